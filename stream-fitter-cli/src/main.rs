@@ -1,35 +1,449 @@
 use std::{
-    fs::File,
+    fs::{read_to_string, File},
     panic::{set_hook, take_hook},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::exit,
+    str::FromStr,
+    time::Duration,
 };
 
-use serde_yaml::from_reader;
+use serde_yaml::{from_reader, from_value, to_string, to_value, Value};
 use structopt::StructOpt;
-use tracing::{error, instrument};
+use tracing::{error, instrument, level_filters::LevelFilter};
+use tracing_subscriber::EnvFilter;
 
 use stream_fitter::{
-    errors::FitterResult,
-    pipe_fitter::{PipeFitter, PipeFitterConfig},
+    errors::{FitterError, FitterErrorKind, FitterResult},
+    pipe_fitter::{
+        isolation::is_isolated_thread,
+        multi::{MultiFitter, MultiFitterConfig},
+        overrides::{apply_overrides, drop_unset, redact_secrets, ConfigOverride},
+        validation::{validate_config, Diagnostic},
+        PipeFitter,
+    },
 };
 
+/// Time the clients get to take the messages relayed to them when the process is stopped.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(StructOpt)]
 struct StreamFitterCli {
     #[structopt(parse(from_os_str))]
-    config_file: PathBuf,
+    config_file: Option<PathBuf>,
+    /// Reload the config whenever the file changes.
+    #[structopt(long)]
+    watch: bool,
+    /// Override a config value, e.g. `--set 'stream_configs[0].channels=["test"]'`.
+    #[structopt(long = "set", number_of_values = 1)]
+    overrides: Vec<ConfigOverride>,
+    /// Print the config as loaded, with overrides applied, unknown keys dropped, aliases
+    /// resolved, unset options left out and secrets redacted, then exit.
+    #[structopt(long, alias = "print-config")]
+    print_effective_config: bool,
+    /// Print the clients the config would load, then exit without connecting.
+    #[structopt(long)]
+    dry_run: bool,
+    /// With `--dry-run`, also check each client's credentials with its platform.
+    #[structopt(long, requires = "dry-run")]
+    test_connectivity: bool,
+    /// Signal flushing relayed messages before exiting, e.g. `TERM`, `INT` or `HUP`, can be
+    /// repeated. Defaults to `TERM` and `INT`, other signals keep their default behavior.
+    #[structopt(long = "shutdown-signal", number_of_values = 1)]
+    shutdown_signals: Vec<ShutdownSignal>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn entrypoint() -> FitterResult<()> {
-    pretty_env_logger::try_init()?;
+#[derive(StructOpt)]
+enum Command {
+    /// Manage tokens stored in the OS keyring.
+    Secret(SecretCommand),
+    /// Check a config file, reporting mistakes by line and suspicious settings, then exit.
+    Validate {
+        #[structopt(parse(from_os_str))]
+        config_file: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+enum SecretCommand {
+    /// Store a secret read from stdin under a `<service>/<name>` keyring entry.
+    Set { entry: String },
+    /// Remove a `<service>/<name>` keyring entry.
+    Rm { entry: String },
+}
+
+#[cfg(feature = "keyring")]
+fn secret_command(command: SecretCommand) -> FitterResult<()> {
+    use std::io::{stdin, BufRead};
+
+    use stream_fitter::secret::{delete_keyring_secret, set_keyring_secret, Secret};
+
+    match command {
+        SecretCommand::Set { entry } => {
+            eprintln!("Enter secret for {}:", entry);
+            let mut value = String::new();
+            stdin().lock().read_line(&mut value)?;
+            set_keyring_secret(&entry, &Secret::new(value.trim_end().to_string()))
+        }
+        SecretCommand::Rm { entry } => delete_keyring_secret(&entry),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+fn secret_command(command: SecretCommand) -> FitterResult<()> {
+    let entry = match command {
+        SecretCommand::Set { entry } | SecretCommand::Rm { entry } => entry,
+    };
+    Err(FitterErrorKind::KeyringErr(format!(
+        "Cannot manage keyring entry {}, stream-fitter was built without the keyring feature",
+        entry
+    ))
+    .into())
+}
+
+/// Sets up logging from `RUST_LOG`, with the config's log levels taking precedence.
+fn init_logging(log_directives: &[String]) -> FitterResult<()> {
+    let mut filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::ERROR.into())
+        .from_env_lossy();
+    for directive in log_directives {
+        filter = filter.add_directive(directive.parse()?);
+    }
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .try_init()
+        .map_err(|err| FitterErrorKind::GenericErr(err.to_string()))?;
+    Ok(())
+}
+
+fn load_config_value(config_file: &Path, overrides: &[ConfigOverride]) -> FitterResult<Value> {
+    let mut config = from_reader(File::open(config_file)?)?;
+    apply_overrides(&mut config, overrides)?;
+    Ok(config)
+}
+
+fn load_config(
+    config_file: &Path,
+    overrides: &[ConfigOverride],
+) -> FitterResult<MultiFitterConfig> {
+    Ok(from_value(load_config_value(config_file, overrides)?)?)
+}
+
+#[cfg(feature = "watch")]
+fn run_watching(
+    config_file: &Path,
+    overrides: &[ConfigOverride],
+    mut fitter: PipeFitter,
+) -> FitterResult<()> {
+    use std::{sync::mpsc::channel, thread::sleep, time::Duration};
+
+    use notify::{recommended_watcher, RecursiveMode, Watcher};
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let _guard = runtime.enter();
+    fitter.start();
+
+    // Watch the directory so editors replacing the file are noticed too.
+    let (tx, rx) = channel();
+    let mut watcher = recommended_watcher(tx)?;
+    let watch_dir = match config_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    watcher.watch(watch_dir, RecursiveMode::NonRecursive)?;
+
+    while let Ok(event) = rx.recv() {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                error!("Error watching config: {}", err);
+                continue;
+            }
+        };
+        if !(event.kind.is_modify() || event.kind.is_create())
+            || !event
+                .paths
+                .iter()
+                .any(|path| path.file_name() == config_file.file_name())
+        {
+            continue;
+        }
+
+        // Let the write settle and coalesce the burst of events it produces.
+        sleep(Duration::from_millis(200));
+        while rx.try_recv().is_ok() {}
+
+        let reload = load_config(config_file, overrides).and_then(|config| match config {
+            MultiFitterConfig::Single(config) => runtime.block_on(fitter.reload_config(*config)),
+            MultiFitterConfig::Multi { .. } => Err(FitterErrorKind::GenericErr(
+                "Can't reload a single fitter with several fitters".to_string(),
+            )
+            .into()),
+        });
+        match reload {
+            Ok(summary) => eprintln!("Config reloaded: {}", summary),
+            Err(err) => error!("Invalid config, keeping the current one: {}", err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "watch"))]
+fn run_watching(
+    _config_file: &Path,
+    _overrides: &[ConfigOverride],
+    _fitter: PipeFitter,
+) -> FitterResult<()> {
+    Err(FitterErrorKind::GenericErr(
+        "stream-fitter was built without the watch feature".to_string(),
+    )
+    .into())
+}
+
+/// Signals a shutdown can be requested with, see `--shutdown-signal`.
+#[cfg(unix)]
+const SHUTDOWN_SIGNALS: &[&str] = &["TERM", "INT", "HUP", "QUIT", "USR1", "USR2"];
+/// Signals a shutdown can be requested with, see `--shutdown-signal`.
+#[cfg(not(unix))]
+const SHUTDOWN_SIGNALS: &[&str] = &["INT"];
+
+/// Signals requesting a shutdown unless others are given.
+#[cfg(unix)]
+const DEFAULT_SHUTDOWN_SIGNALS: &[&str] = &["TERM", "INT"];
+/// Signals requesting a shutdown unless others are given.
+#[cfg(not(unix))]
+const DEFAULT_SHUTDOWN_SIGNALS: &[&str] = &["INT"];
 
+/// Signal requesting a shutdown, named without its `SIG` prefix.
+#[derive(Clone, Copy)]
+struct ShutdownSignal(&'static str);
+
+impl FromStr for ShutdownSignal {
+    type Err = FitterError;
+
+    fn from_str(s: &str) -> FitterResult<Self> {
+        let name = s.to_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        SHUTDOWN_SIGNALS
+            .iter()
+            .find(|signal| **signal == name)
+            .map(|signal| ShutdownSignal(signal))
+            .ok_or_else(|| {
+                FitterErrorKind::GenericErr(format!(
+                    "Unknown shutdown signal {}, expected one of {}",
+                    s,
+                    SHUTDOWN_SIGNALS.join(", ")
+                ))
+                .into()
+            })
+    }
+}
+
+/// Waits for the process to be asked to stop, returning the signal's name.
+///
+/// # Arguments
+///
+/// * `signals` - The signals asking to stop.
+#[cfg(unix)]
+async fn shutdown_signal(signals: &[ShutdownSignal]) -> FitterResult<&'static str> {
+    use futures::future::select_all;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut streams = Vec::new();
+    for ShutdownSignal(name) in signals {
+        let kind = match *name {
+            "TERM" => SignalKind::terminate(),
+            "INT" => SignalKind::interrupt(),
+            "HUP" => SignalKind::hangup(),
+            "QUIT" => SignalKind::quit(),
+            "USR1" => SignalKind::user_defined1(),
+            _ => SignalKind::user_defined2(),
+        };
+        streams.push((*name, signal(kind)?));
+    }
+
+    let received = streams.iter_mut().map(|(name, stream)| {
+        Box::pin(async move {
+            stream.recv().await;
+            *name
+        })
+    });
+    let (name, _, _) = select_all(received).await;
+    Ok(name)
+}
+
+/// Waits for the process to be asked to stop, returning the signal's name.
+///
+/// # Arguments
+///
+/// * `_signals` - The signals asking to stop, only `INT` off Unix.
+#[cfg(not(unix))]
+async fn shutdown_signal(_signals: &[ShutdownSignal]) -> FitterResult<&'static str> {
+    tokio::signal::ctrl_c().await?;
+    Ok("INT")
+}
+
+/// Runs the fitter until it stops, draining it instead when the process is asked to stop.
+fn run_until_signal(mut fitter: PipeFitter, signals: &[ShutdownSignal]) -> FitterResult<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let signal = tokio::select! {
+            result = fitter.serve() => return result,
+            signal = shutdown_signal(signals) => signal?,
+        };
+        let flushed = fitter.drain(DRAIN_TIMEOUT).await;
+        eprintln!(
+            "Received SIG{}, flushed {} message{} before exiting",
+            signal,
+            flushed,
+            if flushed == 1 { "" } else { "s" }
+        );
+        Ok(())
+    })
+}
+
+fn test_connectivity(config: &MultiFitterConfig) -> FitterResult<()> {
+    let mut failed = 0;
+    for (fitter_name, fitter_config) in config.get_fitters() {
+        let fitter = PipeFitter::from_config(fitter_config.clone())?;
+        for (name, result) in fitter.run_connectivity_test()? {
+            match result {
+                Ok(()) => println!("{}: {}: ok", fitter_name, name),
+                Err(err) => {
+                    println!("{}: {}: {}", fitter_name, name, err);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(FitterErrorKind::GenericErr(format!(
+            "{} client{} failed the connectivity test",
+            failed,
+            if failed == 1 { "" } else { "s" }
+        ))
+        .into()),
+    }
+}
+
+/// Prints a finding of `validate`, located in the config file when possible.
+///
+/// # Arguments
+///
+/// * `config_file` - The config file.
+/// * `severity` - How bad the finding is, e.g. `error`.
+/// * `diagnostic` - The finding.
+fn print_diagnostic(config_file: &Path, severity: &str, diagnostic: &Diagnostic) {
+    match (diagnostic.line, diagnostic.column) {
+        (Some(line), Some(column)) => println!(
+            "{}:{}:{}: {}: {}",
+            config_file.display(),
+            line,
+            column,
+            severity,
+            diagnostic.message
+        ),
+        _ => println!(
+            "{}: {}: {}",
+            config_file.display(),
+            severity,
+            diagnostic.message
+        ),
+    }
+}
+
+fn validate_command(config_file: &Path) -> FitterResult<()> {
+    let report = validate_config(&read_to_string(config_file)?);
+    for diagnostic in &report.errors {
+        print_diagnostic(config_file, "error", diagnostic);
+    }
+    for diagnostic in &report.warnings {
+        print_diagnostic(config_file, "warning", diagnostic);
+    }
+
+    match report.errors.len() {
+        0 => {
+            println!("{}: ok", config_file.display());
+            Ok(())
+        }
+        count => Err(FitterErrorKind::ConfigParseError(format!(
+            "{} has {} error{}",
+            config_file.display(),
+            count,
+            if count == 1 { "" } else { "s" }
+        ))
+        .into()),
+    }
+}
+
+fn entrypoint() -> FitterResult<()> {
     let cli = StreamFitterCli::from_args();
 
-    let fitter_config: PipeFitterConfig = from_reader(File::open(cli.config_file)?)?;
+    match cli.command {
+        Some(Command::Secret(command)) => {
+            init_logging(&[])?;
+            return secret_command(command);
+        }
+        Some(Command::Validate { config_file }) => return validate_command(&config_file),
+        None => (),
+    }
+
+    let config_file = cli
+        .config_file
+        .ok_or_else(|| FitterErrorKind::GenericErr("A config file is required".to_string()))?;
 
-    let mut fitter = PipeFitter::from_config(fitter_config)?;
+    let fitter_config = load_config(&config_file, &cli.overrides)?;
+    if cli.print_effective_config {
+        let mut config = to_value(&fitter_config)?;
+        drop_unset(&mut config);
+        redact_secrets(&mut config);
+        print!("{}", to_string(&config)?);
+        return Ok(());
+    }
+    init_logging(&fitter_config.get_log_directives()?)?;
 
-    fitter.run()
+    if cli.dry_run {
+        for (fitter_name, config) in fitter_config.get_fitters() {
+            for stream_config in config.get_stream_configs() {
+                println!("{}: {}", fitter_name, stream_config);
+            }
+        }
+        if cli.test_connectivity {
+            return test_connectivity(&fitter_config);
+        }
+        return Ok(());
+    }
+
+    let config = match fitter_config {
+        MultiFitterConfig::Single(config) => *config,
+        MultiFitterConfig::Multi { .. } if cli.watch => {
+            return Err(FitterErrorKind::GenericErr(
+                "--watch only supports a single fitter config".to_string(),
+            )
+            .into())
+        }
+        multi_config => return MultiFitter::from_config(multi_config)?.run(),
+    };
+    let fitter = PipeFitter::from_config(config)?;
+
+    if cli.watch {
+        return run_watching(&config_file, &cli.overrides, fitter);
+    }
+
+    let signals = match cli.shutdown_signals.is_empty() {
+        true => DEFAULT_SHUTDOWN_SIGNALS
+            .iter()
+            .map(|signal| ShutdownSignal(signal))
+            .collect(),
+        false => cli.shutdown_signals,
+    };
+    run_until_signal(fitter, &signals)
 }
 
 #[instrument]
@@ -37,12 +451,17 @@ fn main() {
     let panic_default = take_hook();
     set_hook(Box::new(move |info| {
         panic_default(info);
-        exit(1);
+        // Panics of clients run with `panic_isolation` are contained by the stream manager.
+        if !is_isolated_thread() {
+            exit(1);
+        }
     }));
 
     exit(match entrypoint() {
         Ok(_) => 0,
         Err(err) => {
+            // Errors before the config is loaded are logged from RUST_LOG alone.
+            let _ = init_logging(&[]);
             for e in err.iter_chain() {
                 error!("{}", e);
             }
@@ -0,0 +1,230 @@
+//! Compares relaying between mock clients in full-mesh and hub-and-spoke topologies.
+//!
+//! Full-mesh is the `PipeFitter` wiring, where every client's relay holds a stream to every
+//! other client. Hub-and-spoke routes all clients through a single relay. Besides criterion's
+//! throughput, the memory used by the wiring and the p99 relay latency are printed.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::join_all;
+use stream_fitter::{
+    clients::{
+        client::{Client, Message},
+        mock::{MockClient, MockHandle},
+    },
+    pipe_fitter::PipeFitter,
+};
+use tikv_jemalloc_ctl::{epoch, stats};
+use tikv_jemallocator::Jemalloc;
+use tokio::{
+    runtime::Runtime,
+    sync::mpsc::{channel, Sender},
+    task::JoinHandle,
+};
+
+#[global_allocator]
+static GLOBAL: Jemalloc = Jemalloc;
+
+/// Numbers of clients to compare.
+const CLIENT_COUNTS: [usize; 4] = [2, 4, 8, 16];
+/// Messages injected by each client per round.
+const MESSAGES_PER_CLIENT: usize = 100;
+
+/// How clients are interconnected.
+#[derive(Clone, Copy, Debug)]
+enum Topology {
+    FullMesh,
+    HubAndSpoke,
+}
+
+/// Running clients wired in a topology.
+struct Wiring {
+    handles: Vec<MockHandle>,
+    fitter: Option<PipeFitter>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Wiring {
+    fn drop(&mut self) {
+        if let Some(fitter) = &mut self.fitter {
+            fitter.stop();
+        }
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Builds mock clients.
+///
+/// # Arguments
+///
+/// * `n` - The number of clients.
+fn mock_clients(n: usize) -> (Vec<Client>, Vec<MockHandle>) {
+    (0..n)
+        .map(|i| MockClient::build(&format!("mock-{}", i)))
+        .unzip()
+}
+
+/// Wires and starts mock clients, must be called within the runtime.
+///
+/// # Arguments
+///
+/// * `topology` - How to interconnect the clients.
+/// * `n` - The number of clients.
+fn wire(topology: Topology, n: usize) -> Wiring {
+    let (mut clients, handles) = mock_clients(n);
+
+    match topology {
+        Topology::FullMesh => {
+            let mut fitter = PipeFitter::from_clients(clients).unwrap();
+            fitter.start();
+            Wiring {
+                handles,
+                fitter: Some(fitter),
+                tasks: Vec::new(),
+            }
+        }
+        Topology::HubAndSpoke => {
+            let (hub_tx, mut hub_rx) = channel::<Message>(100);
+            let spokes = clients
+                .iter_mut()
+                .map(|client| {
+                    client.add_stream(hub_tx.clone()).unwrap();
+                    (client.get_name().to_string(), client.get_stream().unwrap())
+                })
+                .collect::<HashMap<String, Sender<Message>>>();
+
+            let mut tasks = vec![tokio::spawn(async move {
+                while let Some(msg) = hub_rx.recv().await {
+                    for (name, spoke) in &spokes {
+                        if name != msg.get_client() {
+                            spoke.send(msg.clone()).await.unwrap();
+                        }
+                    }
+                }
+            })];
+            tasks.extend(clients.into_iter().map(|mut client| {
+                tokio::spawn(async move {
+                    client.run().await.unwrap();
+                })
+            }));
+
+            Wiring {
+                handles,
+                fitter: None,
+                tasks,
+            }
+        }
+    }
+}
+
+/// Has every client inject messages and waits until all clients received them.
+///
+/// Returns the latency of every relayed message.
+///
+/// # Arguments
+///
+/// * `handles` - The handles of the wired clients.
+async fn relay_round(handles: &mut [MockHandle]) -> Vec<Duration> {
+    let n = handles.len();
+    let start = Instant::now();
+
+    let injectors = handles
+        .iter()
+        .map(MockHandle::get_injector)
+        .collect::<Vec<Sender<Message>>>();
+    let injections = injectors.into_iter().enumerate().map(|(i, injector)| {
+        async move {
+            for _ in 0..MESSAGES_PER_CLIENT {
+                // The content carries the injection time to measure latency.
+                let msg = Message::new(
+                    format!("mock-{}", i),
+                    "bench".to_string(),
+                    "author".to_string(),
+                    start.elapsed().as_nanos().to_string(),
+                );
+                injector.send(msg).await.unwrap();
+            }
+        }
+    });
+
+    let receptions = handles.iter_mut().map(|handle| async move {
+        let mut latencies = Vec::with_capacity((n - 1) * MESSAGES_PER_CLIENT);
+        for _ in 0..(n - 1) * MESSAGES_PER_CLIENT {
+            let msg = handle.recv().await.unwrap();
+            let sent = Duration::from_nanos(msg.get_content().parse().unwrap());
+            latencies.push(start.elapsed() - sent);
+        }
+        latencies
+    });
+
+    let (_, latencies) = futures::join!(join_all(injections), join_all(receptions));
+    latencies.into_iter().flatten().collect()
+}
+
+/// Gets the number of bytes currently allocated.
+fn allocated() -> usize {
+    epoch::advance().unwrap();
+    stats::allocated::read().unwrap()
+}
+
+/// Prints the wiring's memory usage and the p99 relay latency of every topology.
+fn report(runtime: &Runtime) {
+    for &n in &CLIENT_COUNTS {
+        for &topology in &[Topology::FullMesh, Topology::HubAndSpoke] {
+            let before = allocated();
+            let mut wiring = runtime.block_on(async { wire(topology, n) });
+            let mut latencies = runtime.block_on(relay_round(&mut wiring.handles));
+            let after = allocated();
+            drop(wiring);
+
+            latencies.sort();
+            let p99 = latencies[latencies.len() * 99 / 100];
+            println!(
+                "{:?}/{}: {} KiB allocated, p99 latency {:?}",
+                topology,
+                n,
+                after.saturating_sub(before) / 1024,
+                p99
+            );
+        }
+    }
+}
+
+fn topology_benchmark(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    report(&runtime);
+
+    let mut group = c.benchmark_group("topology");
+    for &n in &CLIENT_COUNTS {
+        group.throughput(Throughput::Elements(
+            (n * (n - 1) * MESSAGES_PER_CLIENT) as u64,
+        ));
+        for &topology in &[Topology::FullMesh, Topology::HubAndSpoke] {
+            let mut wiring = runtime.block_on(async { wire(topology, n) });
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", topology), n),
+                &n,
+                |b, _| {
+                    b.iter_custom(|iters| {
+                        runtime.block_on(async {
+                            let start = Instant::now();
+                            for _ in 0..iters {
+                                relay_round(&mut wiring.handles).await;
+                            }
+                            start.elapsed()
+                        })
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, topology_benchmark);
+criterion_main!(benches);
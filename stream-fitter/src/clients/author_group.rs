@@ -0,0 +1,209 @@
+//! Groups consecutive relayed messages of an author into a single message, so chat from
+//! platforms with flat chat stays readable where every message gets its own header.
+//!
+//! Each channel tracks the message its author's latest messages were appended to, which is
+//! edited to append the next one. Any other message sent or posted to the channel ends the
+//! group.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::clients::client::{Message, MessageKind};
+
+/// Default seconds after an author's latest message during which their next one is grouped.
+const DEFAULT_GROUP_WINDOW_SECONDS: u64 = 60;
+
+/// Message a channel's group of consecutive messages was appended to.
+struct AuthorGroup {
+    client: String,
+    channel: String,
+    author: String,
+    message_id: u64,
+    content: String,
+    updated: Instant,
+}
+
+/// Groups of consecutive messages of an author, by channel.
+pub(crate) struct AuthorGroups {
+    window: Duration,
+    limit: usize,
+    groups: HashMap<String, AuthorGroup>,
+}
+
+impl AuthorGroups {
+    /// Creates the groups of a client, if grouping is enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `group_by_author` - Whether to group consecutive messages of an author.
+    /// * `window_seconds` - The seconds after an author's latest message during which their
+    ///   next one is grouped.
+    /// * `limit` - The maximum number of characters of a grouped message.
+    pub(crate) fn from_config(
+        group_by_author: Option<bool>,
+        window_seconds: Option<u64>,
+        limit: usize,
+    ) -> Option<Self> {
+        match group_by_author.unwrap_or_default() {
+            true => Some(AuthorGroups {
+                window: Duration::from_secs(window_seconds.unwrap_or(DEFAULT_GROUP_WINDOW_SECONDS)),
+                limit,
+                groups: HashMap::new(),
+            }),
+            false => None,
+        }
+    }
+
+    /// Gets the message to edit and its new content to append a message to the channel's
+    /// group, if the message continues it.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel sent to.
+    /// * `msg` - The relayed message.
+    pub(crate) fn append(&self, channel: &str, msg: &Message) -> Option<(u64, String)> {
+        let group = self.groups.get(channel)?;
+        let continues = msg.get_kind() == MessageKind::Chat
+            && group.client == msg.get_client()
+            && group.channel == msg.get_channel()
+            && group.author == msg.get_author()
+            && group.updated.elapsed() <= self.window;
+        if !continues {
+            return None;
+        }
+
+        let content = format!("{}\n{}", group.content, msg.get_content());
+        match content.chars().count() <= self.limit {
+            true => Some((group.message_id, content)),
+            false => None,
+        }
+    }
+
+    /// Records that the channel's group was edited to a new content.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel sent to.
+    /// * `content` - The new content of the group's message.
+    pub(crate) fn appended(&mut self, channel: &str, content: String) {
+        if let Some(group) = self.groups.get_mut(channel) {
+            group.content = content;
+            group.updated = Instant::now();
+        }
+    }
+
+    /// Starts a channel's group with a sent message, if it's chat.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel sent to.
+    /// * `msg` - The relayed message.
+    /// * `message_id` - The ID of the message sent.
+    /// * `content` - The content of the message sent.
+    pub(crate) fn start(&mut self, channel: &str, msg: &Message, message_id: u64, content: String) {
+        if msg.get_kind() != MessageKind::Chat {
+            return;
+        }
+
+        self.groups.insert(
+            channel.to_string(),
+            AuthorGroup {
+                client: msg.get_client().to_string(),
+                channel: msg.get_channel().to_string(),
+                author: msg.get_author().to_string(),
+                message_id,
+                content,
+                updated: Instant::now(),
+            },
+        );
+    }
+
+    /// Ends a channel's group, once another message is sent or posted to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel sent to.
+    pub(crate) fn end(&mut self, channel: &str) {
+        self.groups.remove(channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    /// Channel the messages are sent to.
+    const CHANNEL: &str = "123";
+
+    /// Builds a chat message relayed from Twitch.
+    ///
+    /// # Arguments
+    ///
+    /// * `author` - The message's author.
+    /// * `content` - The message's content.
+    fn message(author: &str, content: &str) -> Message {
+        Message::new(
+            "Twitch".to_string(),
+            "first".to_string(),
+            author.to_string(),
+            content.to_string(),
+        )
+    }
+
+    /// Creates groups started with a message of `viewer` sent as message 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `window_seconds` - The seconds during which the next message is grouped.
+    /// * `limit` - The maximum number of characters of a grouped message.
+    fn started(window_seconds: u64, limit: usize) -> AuthorGroups {
+        let mut groups =
+            AuthorGroups::from_config(Some(true), Some(window_seconds), limit).unwrap();
+        groups.start(CHANNEL, &message("viewer", "hi"), 1, "hi".to_string());
+        groups
+    }
+
+    #[test]
+    fn appends_consecutive_messages_of_the_author() {
+        let mut groups = started(60, 2000);
+        assert_eq!(
+            groups.append(CHANNEL, &message("viewer", "there")),
+            Some((1, "hi\nthere".to_string()))
+        );
+        groups.appended(CHANNEL, "hi\nthere".to_string());
+        assert_eq!(
+            groups.append(CHANNEL, &message("viewer", "again")),
+            Some((1, "hi\nthere\nagain".to_string()))
+        );
+        assert_eq!(groups.append(CHANNEL, &message("other", "hey")), None);
+        assert_eq!(groups.append("456", &message("viewer", "there")), None);
+    }
+
+    #[test]
+    fn stops_grouping_after_the_window() {
+        let groups = started(0, 2000);
+        sleep(Duration::from_millis(10));
+        assert_eq!(groups.append(CHANNEL, &message("viewer", "late")), None);
+    }
+
+    #[test]
+    fn stops_grouping_at_the_size_limit() {
+        let groups = started(60, 8);
+        assert_eq!(
+            groups.append(CHANNEL, &message("viewer", "there")),
+            Some((1, "hi\nthere".to_string()))
+        );
+        assert_eq!(groups.append(CHANNEL, &message("viewer", "there!")), None);
+    }
+
+    #[test]
+    fn ends_groups() {
+        let mut groups = started(60, 2000);
+        groups.end(CHANNEL);
+        assert_eq!(groups.append(CHANNEL, &message("viewer", "there")), None);
+        assert!(AuthorGroups::from_config(None, None, 2000).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! Backfill of the history missed while the bridge was down.
+//!
+//! The last relayed message of every channel is persisted to a state file, so on startup only
+//! messages sent after it are relayed again, and none is relayed twice.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex as StdMutex,
+    },
+    time::Duration,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::errors::FitterResult;
+
+/// Default number of messages fetched per channel.
+const DEFAULT_BACKFILL_COUNT: u64 = 20;
+/// Maximum number of messages fetched per channel.
+const MAX_BACKFILL_COUNT: u64 = 100;
+/// Default maximum age in minutes of backfilled messages.
+const DEFAULT_MAX_AGE_MINUTES: u64 = 60;
+
+/// Config struct for backfilling missed history on startup.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct BackfillConfig {
+    /// Number of latest messages fetched per channel, defaults to 20, at most 100.
+    pub count: Option<u64>,
+    /// Maximum age in minutes of backfilled messages, defaults to 60.
+    pub max_age_minutes: Option<u64>,
+    /// File persisting the last relayed message of every channel.
+    pub state_file: PathBuf,
+}
+
+/// Contents of the state file.
+#[derive(Serialize, Deserialize, Default)]
+struct BackfillStateFile {
+    /// ID of the last relayed message by channel ID.
+    last_relayed: HashMap<u64, u64>,
+}
+
+/// Last relayed message of every channel, persisted to the state file.
+pub(crate) struct BackfillState {
+    path: PathBuf,
+    last_relayed: StdMutex<HashMap<u64, u64>>,
+    dirty: AtomicBool,
+}
+
+impl BackfillState {
+    /// Loads the state file, starting empty when it doesn't exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The state file.
+    fn load(path: &Path) -> FitterResult<Self> {
+        let state = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice::<BackfillStateFile>(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("No state file yet: {}", path.display());
+                BackfillStateFile::default()
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(BackfillState {
+            path: path.to_path_buf(),
+            last_relayed: StdMutex::new(state.last_relayed),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Gets the ID of the last relayed message of a channel, if known.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel ID.
+    pub(crate) fn get_last_relayed(&self, channel: u64) -> Option<u64> {
+        self.last_relayed.lock().unwrap().get(&channel).copied()
+    }
+
+    /// Records a relayed message, unless a later one was already relayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel ID.
+    /// * `message` - The message ID.
+    pub(crate) fn record(&self, channel: u64, message: u64) {
+        let mut last_relayed = self.last_relayed.lock().unwrap();
+        let last = last_relayed.entry(channel).or_insert(message);
+        if *last <= message {
+            *last = message;
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes the state file if anything was recorded since the last write.
+    ///
+    /// The file is replaced atomically, so a crash never leaves it half written.
+    pub(crate) async fn save(&self) -> FitterResult<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let result = self.write().await;
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Writes the state file.
+    async fn write(&self) -> FitterResult<()> {
+        let bytes = serde_json::to_vec(&BackfillStateFile {
+            last_relayed: self.last_relayed.lock().unwrap().clone(),
+        })?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, bytes).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        debug!("Saved state file: {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// Backfill settings of a client, with its loaded state.
+pub(crate) struct Backfill {
+    pub(crate) count: u64,
+    pub(crate) max_age: Duration,
+    pub(crate) state: BackfillState,
+}
+
+impl Backfill {
+    /// Builds the backfill settings, loading the state file.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The backfill config.
+    pub(crate) fn from_config(config: &BackfillConfig) -> FitterResult<Self> {
+        Ok(Backfill {
+            count: config
+                .count
+                .unwrap_or(DEFAULT_BACKFILL_COUNT)
+                .clamp(1, MAX_BACKFILL_COUNT),
+            max_age: Duration::from_secs(
+                config.max_age_minutes.unwrap_or(DEFAULT_MAX_AGE_MINUTES) * 60,
+            ),
+            state: BackfillState::load(&config.state_file)?,
+        })
+    }
+}
@@ -0,0 +1,274 @@
+//! Implements a source-only client injecting lines of an external input.
+//!
+//! Lines are read from a tailed file or a command's stdout and relayed to every other client.
+//! Messages relayed to the client are discarded.
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+    process::Command,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+use tracing::{debug, error, info, instrument};
+
+use crate::{
+    clients::client::{
+        async_trait, Client as FitterClient, ClientConfigSnapshot, DynClientTrait, Message,
+        MessageKind,
+    },
+    errors::{FitterErrorKind, FitterResult},
+};
+
+/// Default channel name shown in injected messages.
+const DEFAULT_BROADCAST_CHANNEL: &str = "events";
+/// Time to wait for a tailed file to grow.
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Input a broadcast client reads lines from.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BroadcastSource {
+    /// A file tailed for new lines, like `tail -f`.
+    File(PathBuf),
+    /// A command and its arguments, whose stdout lines are read until it exits.
+    Command(Vec<String>),
+}
+
+/// Config struct for a broadcast client.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct BroadcastConfig {
+    /// Client name shown in injected messages.
+    pub label: String,
+    /// Input to read lines from.
+    pub source: BroadcastSource,
+    /// Channel name shown in injected messages, defaults to "events".
+    pub channel: Option<String>,
+}
+
+impl BroadcastConfig {
+    /// Gets the settings a running client can change without restarting.
+    ///
+    /// Broadcast clients have no such settings, they are restarted on any change.
+    pub fn get_snapshot(&self) -> ClientConfigSnapshot {
+        ClientConfigSnapshot {
+            display_client: self.label.clone(),
+            isolate_channels: false,
+            profanity_filter: None,
+            pipeline: None,
+            spam_filter: None,
+            sample: None,
+            word_count: None,
+            priority_authors: None,
+            priority_regex: None,
+            user_aliases: None,
+            ignored_authors: None,
+        }
+    }
+}
+
+/// Input of a broadcast client and where its lines are injected.
+struct BroadcastInput {
+    label: String,
+    channel: String,
+    source: BroadcastSource,
+    outer_tx: Vec<Sender<Message>>,
+}
+
+/// Broadcast client struct.
+pub struct Broadcast {
+    id: String,
+    input: BroadcastInput,
+    rx: Receiver<Message>,
+    tx: Sender<Message>,
+}
+
+impl Broadcast {
+    /// Build a broadcast client.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A client's unique ID.
+    /// * `config` - The broadcast config to build from.
+    #[instrument(skip(config))]
+    pub fn from_config(id: String, config: BroadcastConfig) -> FitterResult<FitterClient> {
+        info!("Initializing broadcast client");
+        if let BroadcastSource::Command(command) = &config.source {
+            if command.is_empty() {
+                return Err(
+                    FitterErrorKind::GenericErr("Broadcast command is empty".to_string()).into(),
+                );
+            }
+        }
+
+        let (tx, rx) = channel(100);
+        Ok(Broadcast {
+            id,
+            input: BroadcastInput {
+                label: config.label,
+                channel: config
+                    .channel
+                    .unwrap_or_else(|| DEFAULT_BROADCAST_CHANNEL.to_string()),
+                source: config.source,
+                outer_tx: Vec::new(),
+            },
+            rx,
+            tx,
+        }
+        .into_client())
+    }
+}
+
+impl BroadcastInput {
+    /// Reads the input until it ends, injecting its lines.
+    async fn read(&self) -> FitterResult<()> {
+        match &self.source {
+            BroadcastSource::File(path) => self.tail_file(path).await,
+            BroadcastSource::Command(command) => self.read_command(command).await,
+        }
+    }
+
+    /// Builds the message injected for a line of input.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line read from the input.
+    fn line_to_message(&self, line: &str) -> Message {
+        Message::new(
+            self.label.clone(),
+            self.channel.clone(),
+            self.label.clone(),
+            line.to_string(),
+        )
+        .with_kind(MessageKind::Event)
+    }
+
+    /// Injects a line of input to all connected streams.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line read from the input.
+    async fn inject(&self, line: &str) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            return;
+        }
+
+        let new_msg = self.line_to_message(line);
+        for stream in &self.outer_tx {
+            debug!("Sending message: {}", new_msg);
+            if let Err(err) = stream.send(new_msg.clone()).await {
+                error!("Error sending: {:?}", err);
+            }
+        }
+    }
+
+    /// Tails a file, injecting the lines appended to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to tail.
+    async fn tail_file(&self, path: &Path) -> FitterResult<()> {
+        let mut reader = BufReader::new(File::open(path).await?);
+        let mut position = reader.seek(SeekFrom::End(0)).await?;
+        let mut line = String::new();
+
+        loop {
+            let read = reader.read_line(&mut line).await?;
+            position += read as u64;
+
+            // Wait for partial lines to be completed.
+            if read == 0 || !line.ends_with('\n') {
+                tokio::time::sleep(TAIL_POLL_INTERVAL).await;
+
+                // Start over when the file was truncated.
+                if tokio::fs::metadata(path).await?.len() < position {
+                    info!("{} was truncated, reading from the start", path.display());
+                    position = reader.seek(SeekFrom::Start(0)).await?;
+                    line.clear();
+                }
+                continue;
+            }
+
+            self.inject(&line).await;
+            line.clear();
+        }
+    }
+
+    /// Runs a command, injecting the lines it writes to stdout.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command and its arguments.
+    async fn read_command(&self, command: &[String]) -> FitterResult<()> {
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdout = child.stdout.take().ok_or_else(|| {
+            FitterErrorKind::InternalErr("Broadcast command has no stdout".to_string())
+        })?;
+
+        let mut lines = BufReader::new(stdout).lines();
+        while let Some(line) = lines.next_line().await? {
+            self.inject(&line).await;
+        }
+
+        let status = child.wait().await?;
+        if !status.success() {
+            return Err(FitterErrorKind::GenericErr(format!(
+                "Broadcast command {} exited with {}",
+                command[0], status
+            ))
+            .into());
+        }
+        info!("Broadcast command {} exited", command[0]);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DynClientTrait for Broadcast {
+    fn get_name(&self) -> &str {
+        "Broadcast"
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_stream(&self) -> FitterResult<Sender<Message>> {
+        Ok(self.tx.clone())
+    }
+
+    fn add_stream(&mut self, stream: Sender<Message>) -> FitterResult<()> {
+        self.input.outer_tx.push(stream);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn run(&mut self) -> FitterResult<()> {
+        info!("Starting broadcast client {}", self.id);
+        let Broadcast { input, rx, .. } = self;
+
+        // Nothing is sent, but relayed messages are drained so relays to the client don't block.
+        let discard = async {
+            while rx.recv().await.is_some() {
+                debug!("Broadcast client, discarding message");
+            }
+        };
+
+        tokio::select! {
+            result = input.read() => result,
+            _ = discard => Ok(()),
+        }
+    }
+}
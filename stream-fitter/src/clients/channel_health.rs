@@ -0,0 +1,158 @@
+//! Tracks destination channels that can no longer be sent to.
+//!
+//! A channel that keeps failing with "unknown channel" or "missing access" class errors is
+//! marked dead and skipped, then periodically probed in case it comes back.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+/// Default number of consecutive failures before a channel is marked dead.
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+/// Default seconds between probes of a dead channel.
+const DEFAULT_PROBATION_SECONDS: u64 = 300;
+
+/// Config struct for dead channel detection.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ChannelHealthConfig {
+    /// Consecutive failures before a channel is marked dead.
+    pub failure_threshold: Option<u32>,
+    /// Seconds between probes of a dead channel.
+    pub probation_seconds: Option<u64>,
+}
+
+/// Send state of a single channel.
+#[derive(Default)]
+struct ChannelState {
+    consecutive_failures: u32,
+    dead_since: Option<Instant>,
+    last_attempt: Option<Instant>,
+}
+
+/// Per-channel failure tracker.
+pub struct ChannelHealth {
+    client_name: String,
+    failure_threshold: u32,
+    probation: Duration,
+    channels: HashMap<String, ChannelState>,
+}
+
+impl ChannelHealth {
+    /// Creates a tracker where every channel starts out healthy.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_name` - The name of the client owning the channels.
+    /// * `config` - The dead channel detection config.
+    pub fn new(client_name: &str, config: &ChannelHealthConfig) -> Self {
+        ChannelHealth {
+            client_name: client_name.to_string(),
+            failure_threshold: config
+                .failure_threshold
+                .unwrap_or(DEFAULT_FAILURE_THRESHOLD)
+                .max(1),
+            probation: Duration::from_secs(
+                config
+                    .probation_seconds
+                    .unwrap_or(DEFAULT_PROBATION_SECONDS),
+            ),
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Checks whether a message should be sent to a channel.
+    ///
+    /// Dead channels are only attempted once per probation period.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The destination channel.
+    pub fn should_send(&mut self, channel: &str) -> bool {
+        let probation = self.probation;
+        let state = self.channels.entry(channel.to_string()).or_default();
+        let now = Instant::now();
+
+        if state.dead_since.is_some() {
+            match state.last_attempt {
+                Some(last_attempt) if now.duration_since(last_attempt) < probation => {
+                    return false;
+                }
+                _ => info!("Probing dead channel {}", channel),
+            }
+        }
+
+        state.last_attempt = Some(now);
+        true
+    }
+
+    /// Records a successful send, reviving the channel if it was dead.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The destination channel.
+    pub fn record_success(&mut self, channel: &str) {
+        let state = self.channels.entry(channel.to_string()).or_default();
+        state.consecutive_failures = 0;
+
+        if let Some(dead_since) = state.dead_since.take() {
+            info!(
+                "{} channel {} recovered after {:?}",
+                self.client_name,
+                channel,
+                dead_since.elapsed()
+            );
+            if !self.is_degraded() {
+                info!("{} client no longer degraded", self.client_name);
+            }
+        }
+    }
+
+    /// Records an "unknown channel" or "missing access" class failure.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The destination channel.
+    pub fn record_failure(&mut self, channel: &str) {
+        let was_degraded = self.is_degraded();
+        let failure_threshold = self.failure_threshold;
+        let state = self.channels.entry(channel.to_string()).or_default();
+        state.consecutive_failures += 1;
+
+        if state.dead_since.is_some() {
+            warn!("Dead channel {} still unavailable", channel);
+            return;
+        }
+
+        if state.consecutive_failures >= failure_threshold {
+            state.dead_since = Some(Instant::now());
+            error!(
+                "{} channel {} failed {} times in a row, marking it dead and retrying every {:?}",
+                self.client_name, channel, state.consecutive_failures, self.probation
+            );
+            if !was_degraded {
+                error!("{} client degraded", self.client_name);
+            }
+        }
+    }
+
+    /// Checks whether a channel is currently marked dead.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The destination channel.
+    pub fn is_dead(&self, channel: &str) -> bool {
+        self.channels
+            .get(channel)
+            .is_some_and(|state| state.dead_since.is_some())
+    }
+
+    /// Checks whether any channel is currently marked dead.
+    pub fn is_degraded(&self) -> bool {
+        self.channels
+            .values()
+            .any(|state| state.dead_since.is_some())
+    }
+}
@@ -0,0 +1,183 @@
+//! Periodic digests of received chat, relayed instead of every message.
+//!
+//! Messages are counted per source channel, and each channel with activity gets a single
+//! summary message listing its most active authors and latest messages.
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error, instrument};
+
+use crate::{
+    clients::client::{Message, MessageKind},
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::summary::format_counts,
+    util::catalog::{plural, Catalog, CatalogKey},
+};
+
+/// Number of most active authors listed in a digest.
+const DIGEST_TOP_AUTHORS: usize = 3;
+/// Number of latest messages quoted in a digest.
+const DIGEST_HIGHLIGHTS: usize = 3;
+/// Maximum number of characters of a quoted message.
+const HIGHLIGHT_LIMIT: usize = 100;
+
+/// Activity of a single source channel since the last digest.
+#[derive(Default)]
+struct ChannelActivity {
+    count: u64,
+    authors: HashMap<String, u64>,
+    highlights: VecDeque<String>,
+}
+
+impl ChannelActivity {
+    /// Composes the digest text of the channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `catalog` - The messages to compose the digest with.
+    fn to_digest(&self, catalog: &Catalog) -> String {
+        let mut authors = self
+            .authors
+            .iter()
+            .map(|(author, count)| (author.clone(), *count))
+            .collect::<Vec<(String, u64)>>();
+        authors.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let author_count = authors.len() as u64;
+        catalog.render(
+            CatalogKey::Digest,
+            &[
+                &self.count.to_string(),
+                plural(self.count),
+                &author_count.to_string(),
+                plural(author_count),
+                &format_counts(&authors, DIGEST_TOP_AUTHORS),
+                &self
+                    .highlights
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(" / "),
+            ],
+        )
+    }
+}
+
+/// Accumulator of received messages between digests.
+pub(crate) struct ChatDigest {
+    interval: Duration,
+    catalog: Arc<Catalog>,
+    channels: StdMutex<BTreeMap<(String, String), ChannelActivity>>,
+}
+
+impl ChatDigest {
+    /// Creates the accumulator of a client, if digests are configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `digest_interval` - The configured minutes between digests.
+    /// * `catalog` - The messages to compose digests with.
+    pub(crate) fn from_config(
+        digest_interval: Option<u64>,
+        catalog: Arc<Catalog>,
+    ) -> FitterResult<Option<Arc<Self>>> {
+        match digest_interval {
+            Some(0) => Err(FitterErrorKind::GenericErr(
+                "Digest interval must be at least one minute".to_string(),
+            )
+            .into()),
+            Some(minutes) => Ok(Some(Arc::new(ChatDigest {
+                interval: Duration::from_secs(minutes * 60),
+                catalog,
+                channels: StdMutex::new(BTreeMap::new()),
+            }))),
+            None => Ok(None),
+        }
+    }
+
+    /// Counts a received message towards its channel's digest.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The received message.
+    pub(crate) fn record(&self, msg: &Message) {
+        let mut channels = self.channels.lock().unwrap();
+        let activity = channels
+            .entry((msg.get_client().to_string(), msg.get_channel().to_string()))
+            .or_default();
+
+        activity.count += 1;
+        *activity
+            .authors
+            .entry(msg.get_author().to_string())
+            .or_insert(0) += 1;
+
+        if activity.highlights.len() == DIGEST_HIGHLIGHTS {
+            activity.highlights.pop_front();
+        }
+        activity.highlights.push_back(format!(
+            "[{}] {}",
+            msg.get_author(),
+            msg.get_content()
+                .chars()
+                .take(HIGHLIGHT_LIMIT)
+                .collect::<String>()
+        ));
+    }
+
+    /// Builds a digest message for each channel with activity, and resets the counters.
+    pub(crate) fn take_digests(&self) -> Vec<Message> {
+        let channels = std::mem::take(&mut *self.channels.lock().unwrap());
+        channels
+            .into_iter()
+            .map(|((client, channel), activity)| {
+                Message::new(
+                    client.clone(),
+                    channel,
+                    client,
+                    activity.to_digest(&self.catalog),
+                )
+                .with_kind(MessageKind::Event)
+            })
+            .collect()
+    }
+
+    /// Sends the digests to all connected streams.
+    ///
+    /// # Arguments
+    ///
+    /// * `outer_tx` - The TX channels of other clients.
+    pub(crate) async fn flush(&self, outer_tx: &[Sender<Message>]) {
+        for digest in self.take_digests() {
+            for stream in outer_tx {
+                debug!("Sending digest: {}", digest);
+                if let Err(err) = stream.send(digest.clone()).await {
+                    error!("Error sending digest: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Loop to periodically send digests of the received messages to other clients.
+///
+/// # Arguments
+///
+/// * `digest` - The accumulator of received messages.
+/// * `outer_tx` - The TX channels of other clients.
+#[instrument(skip(digest, outer_tx))]
+pub(crate) async fn digest_loop(digest: Arc<ChatDigest>, outer_tx: Vec<Sender<Message>>) {
+    let mut interval = tokio::time::interval(digest.interval);
+
+    // The first tick completes immediately.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        digest.flush(&outer_tx).await;
+    }
+}
@@ -1,15 +1,85 @@
 //! Client trait and utilities definitions.
-use std::fmt::{Display, Formatter, Result};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result},
+};
 
+use chrono::{DateTime, Utc};
 use futures::{future::Future, task::FutureObj};
 use serde_derive::{Deserialize, Serialize};
-use tokio::sync::mpsc::Sender;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc::Sender, watch};
+use tracing::{error, info};
+
+pub use async_trait::async_trait;
 
 use crate::{
-    clients::{discord, twitch},
-    errors::FitterResult,
+    clients::{broadcast, discord, nats, twitch, user_alias::UserAliases},
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::{
+        filter::WordCountFilter,
+        flush::Flushed,
+        pipeline::{Pipeline, PipelineStage},
+        priority::PRIORITY,
+        profanity::ProfanityFilterMode,
+        readiness::Connected,
+        sample::SampleConfig,
+        spam::SpamFilterConfig,
+        watchdog::Progress,
+    },
+    secret::CredentialProvider,
+    util::{catalog::CatalogKey, template::Template},
 };
 
+/// Marker ending truncated content, see `Message::with_truncated_content`.
+const TRUNCATION_MARKER: char = '…';
+
+/// Kind of a message, describing where it came from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    /// A chat message written by a user.
+    #[default]
+    Chat,
+    /// A platform event described as a message, e.g. a user joining voice.
+    Event,
+    /// A message generated by the bridge itself, never relayed between clients.
+    System,
+    /// A new topic for the receiving client's channels, e.g. a stream title, not shown as chat.
+    Topic,
+}
+
+/// Client specific action that doesn't fit relayed messages, see
+/// `ClientTrait::get_action_stream`.
+///
+/// Clients ignore the actions they don't support with a debug log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAction {
+    /// Deletes a message from the client's channels, by its platform ID.
+    DeleteMessage { native_id: String },
+    /// Replaces the content of a message the client sent, by its platform ID.
+    EditMessage { native_id: String, content: String },
+    /// Sets the topic of one of the client's channels.
+    SetChannelTopic { channel: String, topic: String },
+    /// Action only some clients understand, with a payload they interpret, e.g. JSON.
+    Custom { name: String, payload: String },
+}
+
+/// Wire formats messages can be serialized to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SerializationFormat {
+    /// JSON text.
+    #[default]
+    Json,
+    /// MessagePack binary.
+    Msgpack,
+    /// CBOR binary.
+    Cbor,
+}
+
 /// Message type to use for intercommunication between streams.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Message {
@@ -17,9 +87,33 @@ pub struct Message {
     channel: String,
     author: String,
     content: String,
+    #[serde(default)]
+    kind: MessageKind,
+    #[serde(default)]
+    target_channel: Option<String>,
+    #[serde(default)]
+    avatar_url: Option<String>,
+    #[serde(default)]
+    platform_id: Option<String>,
+    #[serde(default)]
+    author_login: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    #[serde(default = "Utc::now")]
+    timestamp: DateTime<Utc>,
+    #[serde(default)]
+    content_hashed: bool,
+    #[serde(default)]
+    author_hashed: bool,
+    #[serde(default)]
+    id: Option<String>,
 }
 
 impl Message {
+    /// Fields templates rendering messages can use, see `Message::format_with`.
+    pub const TEMPLATE_FIELDS: &'static [&'static str] =
+        &["client", "channel", "author", "content"];
+
     /// Create a new message.
     ///
     /// # Arguments
@@ -34,12 +128,374 @@ impl Message {
             channel,
             author,
             content,
+            kind: MessageKind::Chat,
+            target_channel: None,
+            avatar_url: None,
+            platform_id: None,
+            author_login: None,
+            metadata: HashMap::new(),
+            timestamp: Utc::now(),
+            content_hashed: false,
+            author_hashed: false,
+            id: None,
+        }
+    }
+
+    /// Create a new message generated by the bridge itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    pub fn system(content: String) -> Message {
+        Message {
+            kind: MessageKind::System,
+            ..Message::new(
+                "Stream Fitter".to_string(),
+                String::new(),
+                String::new(),
+                content,
+            )
+        }
+    }
+
+    /// Sets the message's kind.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The message's kind.
+    pub fn with_kind(mut self, kind: MessageKind) -> Message {
+        self.kind = kind;
+        self
+    }
+
+    /// Restricts delivery of the message to a single channel of the receiving client.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel name, or ID for Discord, to deliver to.
+    pub fn with_target_channel(mut self, channel: String) -> Message {
+        self.target_channel = Some(channel);
+        self
+    }
+
+    /// Sets the URL of the author's avatar.
+    ///
+    /// # Arguments
+    ///
+    /// * `avatar_url` - The avatar's URL.
+    pub fn with_avatar_url(mut self, avatar_url: String) -> Message {
+        self.avatar_url = Some(avatar_url);
+        self
+    }
+
+    /// Sets the ID of the message on its original platform, e.g. to deduplicate echoes or link
+    /// back to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `platform_id` - The platform's message ID.
+    pub fn with_platform_id(mut self, platform_id: String) -> Message {
+        self.platform_id = Some(platform_id);
+        self
+    }
+
+    /// Sets the author's login on their original platform, when it differs from how they're
+    /// shown, e.g. the lowercase Twitch login of a capitalized or localized display name.
+    ///
+    /// # Arguments
+    ///
+    /// * `author_login` - The author's login.
+    pub fn with_author_login(mut self, author_login: String) -> Message {
+        self.author_login = Some(author_login);
+        self
+    }
+
+    /// Sets a platform specific value, e.g. `discord::DM_USER_ID` to reply to a direct message.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The value's key.
+    /// * `value` - The value.
+    pub fn with_metadata(mut self, key: &str, value: String) -> Message {
+        self.metadata.insert(key.to_string(), value);
+        self
+    }
+
+    /// Sets the time the message was originally sent, instead of the time it was created.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The original send time.
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> Message {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Gets the name of the client that generated the message.
+    pub fn get_client(&self) -> &str {
+        &self.client
+    }
+
+    /// Gets the message's channel.
+    pub fn get_channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Gets the message's author.
+    pub fn get_author(&self) -> &str {
+        &self.author
+    }
+
+    /// Gets the message's content.
+    pub fn get_content(&self) -> &str {
+        &self.content
+    }
+
+    /// Counts the words of the message's content, separated by whitespace.
+    pub fn word_count(&self) -> usize {
+        self.content.split_whitespace().count()
+    }
+
+    /// Gets the message's kind.
+    pub fn get_kind(&self) -> MessageKind {
+        self.kind
+    }
+
+    /// Checks whether the message is delivered ahead of the other queued messages: events,
+    /// bridge messages and messages flagged with `PRIORITY` metadata.
+    pub fn is_priority(&self) -> bool {
+        self.kind != MessageKind::Chat || self.metadata.contains_key(PRIORITY)
+    }
+
+    /// Gets the channel the message is restricted to, if any.
+    pub fn get_target_channel(&self) -> Option<&str> {
+        self.target_channel.as_deref()
+    }
+
+    /// Gets the URL of the author's avatar, if known.
+    pub fn get_avatar_url(&self) -> Option<&str> {
+        self.avatar_url.as_deref()
+    }
+
+    /// Gets the ID of the message on its original platform, if known.
+    pub fn get_platform_id(&self) -> Option<&str> {
+        self.platform_id.as_deref()
+    }
+
+    /// Gets the author's login on their original platform, defaulting to their name, to match
+    /// authors by.
+    pub fn get_author_login(&self) -> &str {
+        self.author_login.as_deref().unwrap_or(&self.author)
+    }
+
+    /// Gets a platform specific value, if set.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The value's key.
+    pub fn get_metadata(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
+    /// Gets the time the message was originally sent.
+    pub fn get_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    /// Gets the message's ID, derived from its client, its platform ID and its content, so
+    /// the same message gets the same ID when received again, e.g. on a retry.
+    ///
+    /// Messages without a platform ID use their send time instead. The ID is assigned when
+    /// the message is first relayed, and kept when its content changes afterwards.
+    pub fn get_id(&self) -> String {
+        if let Some(id) = &self.id {
+            return id.clone();
+        }
+
+        let source_id = match &self.platform_id {
+            Some(platform_id) => platform_id.clone(),
+            None => self.timestamp.to_rfc3339(),
+        };
+        let mut hasher = Sha256::new();
+        for field in [&self.client, &source_id, &self.content] {
+            hasher.update(field.as_bytes());
+            hasher.update([0]);
+        }
+        hex::encode(&hasher.finalize()[..16])
+    }
+
+    /// Assigns the message's ID, see `Message::get_id`.
+    pub(crate) fn assign_id(&mut self) {
+        self.id = Some(self.get_id());
+    }
+
+    /// Gives the message a new identity, e.g. when replaying it again from a chat log, so it
+    /// isn't dropped as already relayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `platform_id` - The message's new platform ID, unique to this emission of it.
+    pub(crate) fn reidentify(&mut self, platform_id: String) {
+        self.id = None;
+        self.platform_id = Some(platform_id);
+    }
+
+    /// Checks whether the message should be delivered to a channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel name, or ID for Discord.
+    pub fn is_for_channel(&self, channel: &str) -> bool {
+        match &self.target_channel {
+            Some(target) => target == channel,
+            None => true,
+        }
+    }
+
+    /// Serializes the message.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The wire format to serialize to.
+    pub fn to_bytes(&self, format: SerializationFormat) -> FitterResult<Vec<u8>> {
+        Ok(match format {
+            SerializationFormat::Json => serde_json::to_vec(self)?,
+            SerializationFormat::Msgpack => rmp_serde::to_vec_named(self)?,
+            SerializationFormat::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(self, &mut bytes)?;
+                bytes
+            }
+        })
+    }
+
+    /// Deserializes a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The serialized message.
+    /// * `format` - The wire format to deserialize from.
+    pub fn from_bytes(bytes: &[u8], format: SerializationFormat) -> FitterResult<Message> {
+        Ok(match format {
+            SerializationFormat::Json => serde_json::from_slice(bytes)?,
+            SerializationFormat::Msgpack => rmp_serde::from_slice(bytes)?,
+            SerializationFormat::Cbor => ciborium::de::from_reader(bytes)?,
+        })
+    }
+
+    /// Sets the message's content.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The new content.
+    pub fn set_content(&mut self, content: String) {
+        self.content = content;
+    }
+
+    /// Copies the message with its content truncated to a number of bytes, e.g. a platform's
+    /// message size limit.
+    ///
+    /// Content is cut at a character boundary and ends with `…` when truncated, which counts
+    /// towards the limit. Limits below the 3 bytes of `…` leave the content empty instead.
+    ///
+    /// ```
+    /// use stream_fitter::clients::client::Message;
+    ///
+    /// let msg = Message::new(
+    ///     "Twitch".to_string(),
+    ///     "#channel".to_string(),
+    ///     "author".to_string(),
+    ///     "café au lait".to_string(),
+    /// );
+    /// assert_eq!(msg.with_truncated_content(8).get_content(), "café…");
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bytes` - The longest content in bytes.
+    pub fn with_truncated_content(&self, max_bytes: usize) -> Message {
+        let mut msg = self.clone();
+        if msg.content.len() > max_bytes {
+            let mut end = max_bytes.saturating_sub(TRUNCATION_MARKER.len_utf8());
+            while !msg.content.is_char_boundary(end) {
+                end -= 1;
+            }
+            msg.content.truncate(end);
+            if max_bytes >= TRUNCATION_MARKER.len_utf8() {
+                msg.content.push(TRUNCATION_MARKER);
+            }
+        }
+        msg
+    }
+
+    /// Replaces the message's content with its hash, see `Message::is_content_hashed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The content's hash.
+    pub fn set_content_hash(&mut self, hash: String) {
+        self.content = hash;
+        self.content_hashed = true;
+    }
+
+    /// Replaces the message's author with their hash, see `Message::is_author_hashed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The author's hash.
+    pub fn set_author_hash(&mut self, hash: String) {
+        self.author = hash;
+        self.author_login = None;
+        // Platform specific values can identify the author too, e.g. their user ID.
+        self.metadata.clear();
+        self.author_hashed = true;
+    }
+
+    /// Checks whether the message's content was replaced with its hash.
+    pub fn is_content_hashed(&self) -> bool {
+        self.content_hashed
+    }
+
+    /// Checks whether the message's author was replaced with their hash.
+    pub fn is_author_hashed(&self) -> bool {
+        self.author_hashed
+    }
+
+    /// Renders the message with a template parsed with `Message::TEMPLATE_FIELDS`.
+    ///
+    /// Messages other than chat are rendered as usual, since they don't follow the template's
+    /// shape.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The template to render.
+    pub fn format_with(&self, template: &Template) -> String {
+        if self.kind != MessageKind::Chat {
+            return self.to_string();
         }
+
+        template.render(&[&self.client, &self.channel, &self.author, &self.content])
     }
 }
 
 impl Display for Message {
     fn fmt(&self, f: &mut Formatter) -> Result {
+        match self.kind {
+            MessageKind::System => return write!(f, "{}", self.content),
+            // Events describe their author in the content.
+            MessageKind::Event => {
+                return write!(f, "[{}: {}] {}", self.client, self.channel, self.content)
+            }
+            MessageKind::Topic => {
+                return write!(
+                    f,
+                    "[{}: {}] Topic: {}",
+                    self.client, self.channel, self.content
+                )
+            }
+            MessageKind::Chat => {}
+        }
+
         write!(
             f,
             "[{}: {}] [{}] {}",
@@ -69,6 +525,92 @@ pub trait ClientTrait {
     /// * `stream` - The other client's TX stream.
     fn add_stream(&mut self, stream: Sender<Message>) -> FitterResult<()>;
 
+    /// Gets the TX side of the client's config watch, if it applies config changes while
+    /// running instead of being restarted.
+    fn get_config_watch(&self) -> Option<watch::Sender<ClientConfigSnapshot>> {
+        None
+    }
+
+    /// Checks whether the client only forwards its platform's messages to other clients,
+    /// without receiving theirs.
+    fn is_forward_only(&self) -> bool {
+        false
+    }
+
+    /// Gets the TX side of the client's action stream, if it handles actions, see
+    /// `ClientAction`.
+    fn get_action_stream(&self) -> Option<Sender<ClientAction>> {
+        None
+    }
+
+    /// Gets the platform's ID of a channel, if the client resolved it, e.g. the numeric ID of
+    /// a Twitch channel resolved with the Helix API.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel's name.
+    fn channel_id(&self, _channel: &str) -> Option<&str> {
+        None
+    }
+
+    /// Hands the client the tracker of its progress, watched for stalls.
+    ///
+    /// Messages the client relays are tracked by the stream manager, clients record other
+    /// progress like sent messages or received keepalives.
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - The client's progress tracker.
+    fn set_progress(&mut self, _progress: Progress) {}
+
+    /// Hands the client the handle it marks once connected to its platform, e.g. logged in.
+    ///
+    /// Clients without a connection of their own are connected right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `connected` - The client's connected handle.
+    fn set_connected(&mut self, connected: Connected) {
+        connected.mark();
+    }
+
+    /// Hands the client the handle signaled once the relay drained, to flush the messages it
+    /// took before it's stopped, e.g. pending digests, then mark it.
+    ///
+    /// Clients without messages of their own to flush are flushed right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `flushed` - The client's flushed handle.
+    fn set_flushed(&mut self, flushed: Flushed) {
+        flushed.mark();
+    }
+
+    /// Probes the client's platform with its credentials, without joining any channel.
+    ///
+    /// Clients without credentials to check succeed right away.
+    fn test_connectivity(&self) -> FutureObj<'static, FitterResult<()>> {
+        FutureObj::new(Box::new(async { Ok(()) }))
+    }
+
+    /// Exports the client's runtime state to keep across restarts, see
+    /// `PipeFitter::export_state`.
+    ///
+    /// Clients without state to keep export none. The state is exported while the client runs
+    /// or after it stopped, so clients keep it shared with their running loop.
+    fn export_state(&self) -> Option<Value> {
+        None
+    }
+
+    /// Imports the runtime state exported by the client's previous run, before it runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The exported state.
+    fn import_state(&mut self, _state: Value) -> FitterResult<()> {
+        Ok(())
+    }
+
     /// Run the client's main loop.
     fn run(&mut self) -> Self::FutType;
 }
@@ -76,14 +618,277 @@ pub trait ClientTrait {
 /// Client type alias to implement for.
 pub type Client = Box<dyn ClientTrait<FutType = FutureObj<'static, FitterResult<()>>> + Send>;
 
+/// Object safe client trait with an `async` main loop, for writing clients without `FutureObj`.
+///
+/// Implementors are turned into a `Client` with `DynClientTrait::into_client`.
+///
+/// # Migrating from `ClientTrait`
+///
+/// 1. Replace `impl ClientTrait for MyClient` with `#[async_trait] impl DynClientTrait for
+///    MyClient` and remove `type FutType`.
+/// 2. Turn `fn run(&mut self) -> Self::FutType` into `async fn run(&mut self) ->
+///    FitterResult<()>`, and replace the `FutureObj::new(Box::new(async move { ... }))` wrapper
+///    with its body. Values no longer need to be cloned out of `self` before the `async` block,
+///    since `run` can borrow `self` directly.
+/// 3. Return `Box::new(client)` as `client.into_client()` from the client's `from_config`.
+///
+/// ```ignore
+/// #[async_trait]
+/// impl DynClientTrait for MyClient {
+///     // `get_name`, `get_id`, `get_stream` and `add_stream` are unchanged.
+///
+///     async fn run(&mut self) -> FitterResult<()> {
+///         while let Some(msg) = self.rx.recv().await {
+///             self.send(msg).await?;
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait DynClientTrait: Send {
+    /// Gets the name of the client.
+    fn get_name(&self) -> &str;
+
+    /// Gets the unique ID of the client.
+    fn get_id(&self) -> &str;
+
+    /// Gets a copy of the TX stream for this client.
+    fn get_stream(&self) -> FitterResult<Sender<Message>>;
+
+    /// Adds a TX stream to send to for this client.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The other client's TX stream.
+    fn add_stream(&mut self, stream: Sender<Message>) -> FitterResult<()>;
+
+    /// Gets the TX side of the client's config watch, if it applies config changes while
+    /// running instead of being restarted.
+    fn get_config_watch(&self) -> Option<watch::Sender<ClientConfigSnapshot>> {
+        None
+    }
+
+    /// Checks whether the client only forwards its platform's messages to other clients,
+    /// without receiving theirs.
+    fn is_forward_only(&self) -> bool {
+        false
+    }
+
+    /// Gets the TX side of the client's action stream, if it handles actions, see
+    /// `ClientAction`.
+    fn get_action_stream(&self) -> Option<Sender<ClientAction>> {
+        None
+    }
+
+    /// Gets the platform's ID of a channel, if the client resolved it, e.g. the numeric ID of
+    /// a Twitch channel resolved with the Helix API.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel's name.
+    fn channel_id(&self, _channel: &str) -> Option<&str> {
+        None
+    }
+
+    /// Hands the client the tracker of its progress, watched for stalls.
+    ///
+    /// Messages the client relays are tracked by the stream manager, clients record other
+    /// progress like sent messages or received keepalives.
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - The client's progress tracker.
+    fn set_progress(&mut self, _progress: Progress) {}
+
+    /// Hands the client the handle it marks once connected to its platform, e.g. logged in.
+    ///
+    /// Clients without a connection of their own are connected right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `connected` - The client's connected handle.
+    fn set_connected(&mut self, connected: Connected) {
+        connected.mark();
+    }
+
+    /// Hands the client the handle signaled once the relay drained, to flush the messages it
+    /// took before it's stopped, e.g. pending digests, then mark it.
+    ///
+    /// Clients without messages of their own to flush are flushed right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `flushed` - The client's flushed handle.
+    fn set_flushed(&mut self, flushed: Flushed) {
+        flushed.mark();
+    }
+
+    /// Probes the client's platform with its credentials, without joining any channel.
+    ///
+    /// Clients without credentials to check succeed right away.
+    fn test_connectivity(&self) -> FutureObj<'static, FitterResult<()>> {
+        FutureObj::new(Box::new(async { Ok(()) }))
+    }
+
+    /// Exports the client's runtime state to keep across restarts, see
+    /// `PipeFitter::export_state`.
+    ///
+    /// Clients without state to keep export none. Unlike `ClientTrait::export_state`, the
+    /// state is only exported before the client runs, since its main loop borrows it.
+    fn export_state(&self) -> Option<Value> {
+        None
+    }
+
+    /// Imports the runtime state exported by the client's previous run, before it runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The exported state.
+    fn import_state(&mut self, _state: Value) -> FitterResult<()> {
+        Ok(())
+    }
+
+    /// Run the client's main loop.
+    async fn run(&mut self) -> FitterResult<()>;
+
+    /// Wraps the client for use by the stream manager.
+    fn into_client(self) -> Client
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(DynClient {
+            name: self.get_name().to_string(),
+            id: self.get_id().to_string(),
+            config_watch: self.get_config_watch(),
+            action_stream: self.get_action_stream(),
+            forward_only: self.is_forward_only(),
+            inner: Some(self),
+        })
+    }
+}
+
+/// Adapter running a `DynClientTrait` implementor as a `ClientTrait` one.
+struct DynClient<T> {
+    name: String,
+    id: String,
+    config_watch: Option<watch::Sender<ClientConfigSnapshot>>,
+    action_stream: Option<Sender<ClientAction>>,
+    forward_only: bool,
+    inner: Option<T>,
+}
+
+impl<T> DynClient<T> {
+    /// Gets the wrapped client, as long as it isn't running.
+    fn get_inner(&self) -> FitterResult<&T> {
+        self.inner.as_ref().ok_or_else(|| {
+            FitterErrorKind::InternalErr("Client already running".to_string()).into()
+        })
+    }
+}
+
+impl<T: DynClientTrait + 'static> ClientTrait for DynClient<T> {
+    type FutType = FutureObj<'static, FitterResult<()>>;
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_stream(&self) -> FitterResult<Sender<Message>> {
+        self.get_inner()?.get_stream()
+    }
+
+    fn add_stream(&mut self, stream: Sender<Message>) -> FitterResult<()> {
+        match &mut self.inner {
+            Some(inner) => inner.add_stream(stream),
+            None => Err(FitterErrorKind::InternalErr("Client already running".to_string()).into()),
+        }
+    }
+
+    fn get_config_watch(&self) -> Option<watch::Sender<ClientConfigSnapshot>> {
+        self.config_watch.clone()
+    }
+
+    fn is_forward_only(&self) -> bool {
+        self.forward_only
+    }
+
+    fn get_action_stream(&self) -> Option<Sender<ClientAction>> {
+        self.action_stream.clone()
+    }
+
+    fn channel_id(&self, channel: &str) -> Option<&str> {
+        self.inner.as_ref()?.channel_id(channel)
+    }
+
+    fn set_progress(&mut self, progress: Progress) {
+        if let Some(inner) = &mut self.inner {
+            inner.set_progress(progress);
+        }
+    }
+
+    fn set_connected(&mut self, connected: Connected) {
+        match &mut self.inner {
+            Some(inner) => inner.set_connected(connected),
+            None => connected.mark(),
+        }
+    }
+
+    fn set_flushed(&mut self, flushed: Flushed) {
+        match &mut self.inner {
+            Some(inner) => inner.set_flushed(flushed),
+            None => flushed.mark(),
+        }
+    }
+
+    fn test_connectivity(&self) -> FutureObj<'static, FitterResult<()>> {
+        match self.get_inner() {
+            Ok(inner) => inner.test_connectivity(),
+            Err(err) => FutureObj::new(Box::new(async move { Err(err) })),
+        }
+    }
+
+    fn export_state(&self) -> Option<Value> {
+        self.inner.as_ref().and_then(|inner| inner.export_state())
+    }
+
+    fn import_state(&mut self, state: Value) -> FitterResult<()> {
+        match &mut self.inner {
+            Some(inner) => inner.import_state(state),
+            None => Err(FitterErrorKind::InternalErr("Client already running".to_string()).into()),
+        }
+    }
+
+    fn run(&mut self) -> Self::FutType {
+        let inner = self.inner.take();
+
+        FutureObj::new(Box::new(async move {
+            match inner {
+                Some(mut inner) => inner.run().await,
+                None => {
+                    Err(FitterErrorKind::InternalErr("Client already running".to_string()).into())
+                }
+            }
+        }))
+    }
+}
+
 /// Client configuration enum for deserializing.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum ClientConfig {
     #[serde(rename = "discord")]
     DiscordConfig(discord::DiscordConfig),
     #[serde(rename = "twitch")]
     TwitchConfig(twitch::TwitchConfig),
+    #[serde(rename = "nats")]
+    NatsConfig(nats::NatsConfig),
+    #[serde(rename = "broadcast")]
+    BroadcastConfig(broadcast::BroadcastConfig),
 }
 
 impl ClientConfig {
@@ -97,6 +902,309 @@ impl ClientConfig {
         match config {
             ClientConfig::DiscordConfig(cfg) => discord::Discord::from_config(id, cfg),
             ClientConfig::TwitchConfig(cfg) => twitch::Twitch::from_config(id, cfg),
+            ClientConfig::NatsConfig(cfg) => nats::Nats::from_config(id, cfg),
+            ClientConfig::BroadcastConfig(cfg) => broadcast::Broadcast::from_config(id, cfg),
+        }
+    }
+
+    /// Gets the name of the client built from the config.
+    pub fn get_name(&self) -> &str {
+        match self {
+            ClientConfig::DiscordConfig(cfg) => cfg.get_name(),
+            ClientConfig::TwitchConfig(cfg) => cfg.get_name(),
+            ClientConfig::NatsConfig(_) => "NATS",
+            ClientConfig::BroadcastConfig(_) => "Broadcast",
+        }
+    }
+
+    /// Gets whether the client built from the config forwards to other clients without
+    /// listening to them.
+    pub fn is_forward_only(&self) -> bool {
+        match self {
+            ClientConfig::DiscordConfig(cfg) => cfg.forward_only.unwrap_or_default(),
+            ClientConfig::TwitchConfig(cfg) => cfg.forward_only.unwrap_or_default(),
+            _ => false,
+        }
+    }
+
+    /// Applies the stream manager's locale and messages to a client that doesn't set its own,
+    /// the client's messages taking precedence over the stream manager's.
+    ///
+    /// # Arguments
+    ///
+    /// * `locale` - The stream manager's locale, if any.
+    /// * `messages` - The stream manager's messages, if any.
+    pub(crate) fn inherit_messages(
+        &mut self,
+        locale: Option<&String>,
+        messages: Option<&HashMap<CatalogKey, String>>,
+    ) {
+        let (client_locale, client_messages) = match self {
+            ClientConfig::DiscordConfig(cfg) => (&mut cfg.locale, &mut cfg.messages),
+            ClientConfig::TwitchConfig(cfg) => (&mut cfg.locale, &mut cfg.messages),
+            _ => return,
+        };
+
+        if client_locale.is_none() {
+            *client_locale = locale.cloned();
+        }
+        if let Some(messages) = messages {
+            let mut merged = messages.clone();
+            merged.extend(client_messages.take().unwrap_or_default());
+            *client_messages = Some(merged);
+        }
+    }
+
+    /// Replaces the client's references to credentials with their values.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The provider to fetch the credentials from.
+    pub(crate) async fn fetch_credentials(
+        &mut self,
+        provider: &dyn CredentialProvider,
+    ) -> FitterResult<()> {
+        match self {
+            ClientConfig::DiscordConfig(cfg) => cfg.token.fetch_ref(provider).await?,
+            ClientConfig::TwitchConfig(cfg) => {
+                if let Some(token) = &mut cfg.token {
+                    token.fetch_ref(provider).await?;
+                }
+                for account in cfg.accounts.iter_mut().flatten() {
+                    account.token.fetch_ref(provider).await?;
+                }
+                if let Some(helix) = &mut cfg.helix {
+                    helix.client_secret.fetch_ref(provider).await?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Gets the settings a running client can change without restarting.
+    pub fn get_snapshot(&self) -> ClientConfigSnapshot {
+        match self {
+            ClientConfig::DiscordConfig(cfg) => cfg.get_snapshot(),
+            ClientConfig::TwitchConfig(cfg) => cfg.get_snapshot(),
+            ClientConfig::NatsConfig(cfg) => cfg.get_snapshot(),
+            ClientConfig::BroadcastConfig(cfg) => cfg.get_snapshot(),
         }
     }
+
+    /// Gets the config without the settings a running client can change, so two configs
+    /// only needing a config snapshot to turn into each other compare equal.
+    pub(crate) fn without_snapshot(&self) -> ClientConfig {
+        let mut config = self.clone();
+        match &mut config {
+            ClientConfig::DiscordConfig(cfg) => {
+                cfg.display_client = None;
+                cfg.user_aliases = None;
+                cfg.isolate_channels = None;
+                cfg.profanity_filter = None;
+                cfg.pipeline = None;
+                cfg.spam_filter = None;
+                cfg.sample = None;
+                cfg.word_count = None;
+                cfg.priority_authors = None;
+                cfg.priority_regex = None;
+                cfg.ignored_authors = None;
+            }
+            ClientConfig::TwitchConfig(cfg) => {
+                cfg.display_client = None;
+                cfg.user_aliases = None;
+                cfg.isolate_channels = None;
+                cfg.profanity_filter = None;
+                cfg.pipeline = None;
+                cfg.spam_filter = None;
+                cfg.sample = None;
+                cfg.word_count = None;
+                cfg.priority_authors = None;
+                cfg.priority_regex = None;
+                cfg.ignored_authors = None;
+            }
+            ClientConfig::NatsConfig(cfg) => {
+                cfg.profanity_filter = None;
+                cfg.pipeline = None;
+                cfg.spam_filter = None;
+                cfg.sample = None;
+                cfg.word_count = None;
+                cfg.priority_authors = None;
+                cfg.priority_regex = None;
+            }
+            ClientConfig::BroadcastConfig(_) => (),
+        }
+        config
+    }
+}
+
+/// Formats a channel count.
+///
+/// # Arguments
+///
+/// * `count` - The number of channels.
+fn channel_count(count: usize) -> String {
+    format!("{} channel{}", count, if count == 1 { "" } else { "s" })
+}
+
+impl Display for ClientConfig {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            ClientConfig::DiscordConfig(cfg) => {
+                write!(f, "Discord({})", channel_count(cfg.channel_ids.len()))
+            }
+            ClientConfig::TwitchConfig(cfg) => match (&cfg.name, &cfg.accounts) {
+                (Some(name), _) => write!(
+                    f,
+                    "Twitch(name={}, {})",
+                    name,
+                    channel_count(cfg.channels.len())
+                ),
+                (None, Some(accounts)) => write!(
+                    f,
+                    "Twitch(accounts={}, {})",
+                    accounts
+                        .iter()
+                        .map(|account| account.name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(","),
+                    channel_count(cfg.channels.len())
+                ),
+                (None, None) => write!(f, "Twitch({})", channel_count(cfg.channels.len())),
+            },
+            ClientConfig::NatsConfig(cfg) => write!(
+                f,
+                "NATS(publish={}, subscribe={})",
+                cfg.publish_subject, cfg.subscribe_subject
+            ),
+            ClientConfig::BroadcastConfig(cfg) => write!(f, "Broadcast(label={})", cfg.label),
+        }
+    }
+}
+
+/// Settings of a client that can change while it runs, sent through its config watch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientConfigSnapshot {
+    /// Client name shown in relayed messages.
+    pub display_client: String,
+    /// Don't forward between channels.
+    pub isolate_channels: bool,
+    /// Drop or censor received messages containing profanity.
+    pub profanity_filter: Option<ProfanityFilterMode>,
+    /// Transformation stages applied to received messages, in order.
+    pub pipeline: Option<Vec<PipelineStage>>,
+    /// Clean up or drop received spam.
+    pub spam_filter: Option<SpamFilterConfig>,
+    /// Only relay a sample of received chat messages.
+    pub sample: Option<SampleConfig>,
+    /// Only relay received chat messages within a word count range.
+    pub word_count: Option<WordCountFilter>,
+    /// Logins of the authors whose received messages are flagged high priority.
+    pub priority_authors: Option<Vec<String>>,
+    /// Pattern of the received messages flagged high priority.
+    pub priority_regex: Option<String>,
+    /// Canonical names shown for users, keyed by their username on the platform.
+    pub user_aliases: Option<HashMap<String, String>>,
+    /// Usernames on the platform of the authors whose received messages aren't forwarded.
+    pub ignored_authors: Option<Vec<String>>,
+}
+
+/// Settings applied by a running client, built from a config snapshot.
+pub(crate) struct LiveSettings {
+    pub(crate) display_client: String,
+    pub(crate) isolate_channels: bool,
+    pub(crate) pipeline: Pipeline,
+    pub(crate) user_aliases: UserAliases,
+    pub(crate) ignored_authors: Vec<String>,
+}
+
+impl LiveSettings {
+    /// Builds the settings of a config snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The config snapshot.
+    pub(crate) fn from_snapshot(snapshot: &ClientConfigSnapshot) -> FitterResult<Self> {
+        Ok(LiveSettings {
+            display_client: snapshot.display_client.clone(),
+            isolate_channels: snapshot.isolate_channels,
+            pipeline: Pipeline::from_config(
+                snapshot.pipeline.as_deref(),
+                snapshot.profanity_filter,
+                snapshot.spam_filter.as_ref(),
+                snapshot.sample.as_ref(),
+                snapshot.word_count.as_ref(),
+                snapshot.priority_authors.as_deref(),
+                snapshot.priority_regex.as_deref(),
+            )?,
+            user_aliases: UserAliases::from_config(snapshot.user_aliases.as_ref()),
+            ignored_authors: snapshot.ignored_authors.clone().unwrap_or_default(),
+        })
+    }
+
+    /// Builds the settings of the latest config snapshot received by a config watch.
+    ///
+    /// # Arguments
+    ///
+    /// * `config_rx` - The RX side of the config watch.
+    pub(crate) fn from_watch(
+        config_rx: &mut watch::Receiver<ClientConfigSnapshot>,
+    ) -> FitterResult<Self> {
+        let snapshot = config_rx.borrow_and_update().clone();
+        LiveSettings::from_snapshot(&snapshot)
+    }
+
+    /// Replaces the settings with the latest config snapshot received by a config watch.
+    ///
+    /// Invalid snapshots are logged and ignored, keeping the current settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `config_rx` - The RX side of the config watch.
+    pub(crate) fn update(&mut self, config_rx: &mut watch::Receiver<ClientConfigSnapshot>) {
+        match LiveSettings::from_watch(config_rx) {
+            Ok(settings) => {
+                info!("Applied config change");
+                *self = settings;
+            }
+            Err(err) => error!("Invalid config change, keeping the current one: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a chat message.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn message(content: &str) -> Message {
+        Message::new(
+            "Twitch".to_string(),
+            "#channel".to_string(),
+            "author".to_string(),
+            content.to_string(),
+        )
+    }
+
+    #[test]
+    fn truncates_at_character_boundaries() {
+        let msg = message("café au lait");
+        // `é` takes the 4th and 5th bytes, it doesn't fit beside the 3 bytes of `…`.
+        assert_eq!(msg.with_truncated_content(7).get_content(), "caf…");
+        assert_eq!(msg.with_truncated_content(8).get_content(), "café…");
+        assert_eq!(msg.with_truncated_content(13).get_content(), "café au lait");
+    }
+
+    #[test]
+    fn truncates_without_marker_below_its_size() {
+        let msg = message("café");
+        assert_eq!(msg.with_truncated_content(3).get_content(), "…");
+        assert_eq!(msg.with_truncated_content(2).get_content(), "");
+        assert_eq!(msg.with_truncated_content(0).get_content(), "");
+        assert_eq!(message("ab").with_truncated_content(2).get_content(), "ab");
+    }
 }
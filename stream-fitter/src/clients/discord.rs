@@ -1,31 +1,259 @@
 //! Implements a Discord client for relaying.
 //!
 //! Built on the serenity library for Discord API intercommunication.
-use std::{option::Option, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    option::Option,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, Instant},
+};
 
-use futures::task::FutureObj;
-use serde_derive::Deserialize;
+use chrono::Utc;
+use futures::{future::join, stream, task::FutureObj, StreamExt};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value};
 use serenity::{
     async_trait,
-    model::{channel::Message as SMessage, gateway::Ready, id::ChannelId},
+    builder::CreateMessage,
+    client::{bridge::gateway::ChunkGuildFilter, ClientError},
+    gateway::GatewayError,
+    http::{error::Error as HttpError, Http, StatusCode},
+    model::{
+        channel::{Channel, Message as SMessage, MessageFlags},
+        event::{ChannelPinsUpdateEvent, GuildMembersChunkEvent, ResumedEvent},
+        gateway::{Activity, Ready},
+        id::{ChannelId, GuildId, MessageId, RoleId, UserId},
+        voice::VoiceState,
+        webhook::Webhook,
+        ModelError,
+    },
     prelude::*,
+    utils::hashmap_to_json_map,
+    Error as SerenityError,
+};
+use tokio::{
+    sync::{
+        mpsc::{channel, Receiver, Sender, UnboundedReceiver},
+        watch,
+    },
+    time::timeout,
 };
-use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
-    clients::client::{Client as FitterClient, ClientTrait, Message},
-    errors::{FitterErrorKind, FitterResult},
+    clients::{
+        author_group::AuthorGroups,
+        backfill::{Backfill, BackfillConfig},
+        channel_health::{ChannelHealth, ChannelHealthConfig},
+        chat_digest::{digest_loop, ChatDigest},
+        client::{
+            Client as FitterClient, ClientAction, ClientConfigSnapshot, ClientTrait, LiveSettings,
+            Message, MessageKind,
+        },
+        embed_digest::{DigestBatch, EmbedDigest, EmbedDigestConfig},
+        forward_policy::{ForwardDecision, ForwardPolicy, IgnoreReason, IncomingMeta},
+        send_queue::{
+            check_max_concurrent_sends, check_max_per_minute, ChannelQueues, SendErrorStrategy,
+            SendErrors,
+        },
+        timestamp::TimestampFormat,
+    },
+    errors::{FitterError, FitterErrorKind, FitterResult},
+    pipe_fitter::{
+        filter::{FilterAction, MessageFilter, WordCountFilter},
+        flush::Flushed,
+        pipeline::PipelineStage,
+        priority::PRIORITY,
+        profanity::ProfanityFilterMode,
+        readiness::Connected,
+        sample::SampleConfig,
+        spam::SpamFilterConfig,
+        watchdog::Progress,
+    },
+    secret::{Secret, TokenConfig},
+    util::{
+        catalog::{Catalog, CatalogKey},
+        template::Template,
+    },
 };
 
+/// Builds the Discord message to send for a relayed message.
+///
+/// # Arguments
+///
+/// * `msg` - The relayed message.
+fn message_to_discord_embed(msg: &Message) -> CreateMessage<'static> {
+    let mut create_message = CreateMessage::default();
+    create_message.content(msg);
+    create_message
+}
+
+/// Channel of relayed direct messages, see `DiscordConfig::monitor_dm_users`.
+pub const DM_CHANNEL: &str = "dm";
+/// Metadata key of the ID of the user a direct message was received from, or is sent to when
+/// relaying a message with the `DM_CHANNEL` channel.
+pub const DM_USER_ID: &str = "dm_user_id";
+
+/// Name of the webhooks created to post relayed messages.
+const WEBHOOK_NAME: &str = "Stream Fitter";
+/// Maximum number of characters of a webhook username.
+const WEBHOOK_USERNAME_LIMIT: usize = 80;
+
+/// Builds the webhook username to post a relayed message as.
+///
+/// # Arguments
+///
+/// * `msg` - The relayed message.
+fn message_to_webhook_username(msg: &Message) -> String {
+    format!("{} ({})", msg.get_author(), msg.get_client())
+        .chars()
+        .take(WEBHOOK_USERNAME_LIMIT)
+        .collect()
+}
+
+/// Maximum number of characters of a thread name.
+const THREAD_NAME_LIMIT: usize = 100;
+
+/// Builds the name of the forum post to start with a relayed message.
+///
+/// # Arguments
+///
+/// * `msg` - The relayed message.
+fn message_to_thread_name(msg: &Message) -> String {
+    let name = match msg.get_kind() {
+        MessageKind::System => msg.get_content().to_string(),
+        _ => format!(
+            "[{}] {}: {}",
+            msg.get_client(),
+            msg.get_author(),
+            msg.get_content()
+        ),
+    };
+    name.chars().take(THREAD_NAME_LIMIT).collect()
+}
+
+/// Time between writes of the backfill state file.
+const BACKFILL_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Maximum number of characters of a channel topic.
+const TOPIC_LIMIT: usize = 1024;
+/// Maximum number of characters of a message.
+const MESSAGE_LIMIT: usize = 2000;
+/// Default longest time to warm the cache up after connecting, see
+/// `DiscordConfig::warm_up_seconds`.
+const DEFAULT_WARM_UP_SECONDS: u64 = 10;
+/// Longest time since the newest pin for a pins update to be a new pin, see
+/// `DiscordConfig::relay_pins`.
+const PIN_WINDOW: Duration = Duration::from_secs(60);
+/// Activity shown as the bot's presence, see `DiscordConfig::keepalive_interval_secs`.
+const KEEPALIVE_ACTIVITY: &str = "the relay";
+
+/// Discord JSON error codes meaning a channel can't be sent to.
+const DEAD_CHANNEL_ERROR_CODES: &[isize] = &[
+    10003, // Unknown channel
+    50001, // Missing access
+    50013, // Missing permissions
+];
+
+/// Checks whether a send error means the channel is gone or inaccessible.
+///
+/// # Arguments
+///
+/// * `err` - The send error.
+fn is_dead_channel_error(err: &SerenityError) -> bool {
+    match err {
+        SerenityError::Http(http_err) => match http_err.as_ref() {
+            HttpError::UnsuccessfulRequest(response) => {
+                DEAD_CHANNEL_ERROR_CODES.contains(&response.error.code)
+            }
+            _ => false,
+        },
+        SerenityError::Model(ModelError::InvalidPermissions(_)) => true,
+        _ => false,
+    }
+}
+
+/// Converts an error into `FitterErrorKind::AuthErr` if Discord rejected the token.
+///
+/// # Arguments
+///
+/// * `err` - The error returned by serenity.
+fn auth_error(err: SerenityError) -> FitterError {
+    let rejected = match &err {
+        SerenityError::Gateway(GatewayError::InvalidAuthentication)
+        | SerenityError::Client(ClientError::InvalidToken) => true,
+        SerenityError::Http(http_err) => matches!(
+            http_err.as_ref(),
+            HttpError::UnsuccessfulRequest(response) if response.status_code == StatusCode::UNAUTHORIZED
+        ),
+        _ => false,
+    };
+
+    match rejected {
+        true => FitterErrorKind::AuthErr("Discord".to_string()).into(),
+        false => err.into(),
+    }
+}
+
+/// Progress of warming the cache up after connecting, see `DiscordConfig::warm_up_seconds`.
+#[derive(Default)]
+struct WarmUp {
+    /// Discord sent every guild, with its channels.
+    cache_ready: bool,
+    /// Guilds whose members are still being sent, with `DiscordConfig::prefetch_members`.
+    pending_guilds: HashSet<GuildId>,
+    /// The client is done warming up, relaying received messages.
+    done: bool,
+}
+
+/// Runtime state of a Discord client kept across restarts, see `ClientTrait::export_state`.
+#[derive(Serialize, Deserialize)]
+struct DiscordState {
+    /// IDs of the pinned messages already relayed, see `DiscordConfig::relay_pins`.
+    relayed_pins: Vec<u64>,
+}
+
 /// Handler struct for receiving and sending Discord messages.
 struct DiscordHandler {
+    settings: StdMutex<Arc<LiveSettings>>,
+    policy: StdMutex<ForwardPolicy>,
+    config_rx: Mutex<watch::Receiver<ClientConfigSnapshot>>,
     ch_ids: Vec<ChannelId>,
     rx: Arc<Mutex<Receiver<Message>>>,
-    tx: Sender<Message>,
+    actions_rx: Mutex<Receiver<ClientAction>>,
     outer_tx: Vec<Sender<Message>>,
-    isolate_channels: bool,
     forward_only: bool,
+    max_concurrent_sends: usize,
+    max_per_minute: Option<usize>,
+    health: StdMutex<ChannelHealth>,
+    send_errors: SendErrors,
+    embed_digest: Option<EmbedDigestConfig>,
+    webhook: bool,
+    webhooks: Mutex<HashMap<ChannelId, Webhook>>,
+    voice_ch_ids: Vec<ChannelId>,
+    relay_pins: bool,
+    relayed_pins: Arc<StdMutex<HashSet<MessageId>>>,
+    chat_digest: Option<Arc<ChatDigest>>,
+    timestamp_format: Option<TimestampFormat>,
+    backfill: Option<Backfill>,
+    same_client_format: Option<Template>,
+    reconnect_message: Option<String>,
+    suppress_embeds: bool,
+    author_groups: Option<StdMutex<AuthorGroups>>,
+    forum_mode: bool,
+    allowed_roles: Option<Vec<RoleId>>,
+    dm_user_ids: Vec<UserId>,
+    catalog: Arc<Catalog>,
+    connected: AtomicBool,
+    connected_signal: Connected,
+    flushed: Flushed,
+    warm_up: watch::Sender<WarmUp>,
+    warm_up_timeout: Duration,
+    prefetch_members: bool,
+    keepalive_interval: Option<Duration>,
+    progress: Progress,
 }
 
 impl DiscordHandler {
@@ -33,24 +261,799 @@ impl DiscordHandler {
     ///
     /// # Arguments
     ///
+    /// * `settings` - The settings that can change while running.
+    /// * `config_rx` - The client's config watch, for settings changing while running.
     /// * `channel_ids` - The Discord channel IDs.
-    /// * `isolate_channels` - Don't forward to other channels.
+    /// * `rx` - The RX channel for the client.
+    /// * `actions_rx` - The RX channel of the actions sent to the client.
     /// * `forward_only` - Forward to other clients, don't listen.
-    fn new(channel_ids: Vec<u64>, isolate_channels: bool, forward_only: bool) -> Self {
-        let (tx, rx) = channel(100);
+    /// * `max_concurrent_sends` - The number of channels sent and forwarded to concurrently.
+    /// * `max_per_minute` - The number of messages sent to each channel per minute, if capped.
+    /// * `health` - The tracker for channels that can't be sent to.
+    /// * `send_errors` - Handles failed sends.
+    /// * `embed_digest` - Batch received messages into embeds instead of sending them.
+    /// * `webhook` - Post relayed messages through webhooks as their author.
+    /// * `voice_channel_ids` - The Discord voice channel IDs to relay join and leave events of.
+    /// * `relay_pins` - Relay the messages pinned in the channels.
+    /// * `chat_digest` - The accumulator of received messages, if they are relayed as digests.
+    /// * `timestamp_format` - Prefixes relayed messages with their original send time.
+    /// * `backfill` - Relays the history missed while down on startup.
+    /// * `same_client_format` - Template of messages forwarded between channels, if configured.
+    /// * `reconnect_message` - Message sent to the channels after reconnecting, if configured.
+    /// * `suppress_embeds` - Suppress the link previews of relayed messages.
+    /// * `author_groups` - Groups consecutive relayed messages of an author, if configured.
+    /// * `forum_mode` - Start a forum post with each relayed message.
+    /// * `allowed_roles` - The roles received messages' authors need one of, if restricted.
+    /// * `dm_user_ids` - The IDs of the users whose direct messages are relayed.
+    /// * `warm_up_timeout` - The longest time to warm the cache up after connecting.
+    /// * `prefetch_members` - Warm the cache up with the members of the channels' guilds.
+    /// * `keepalive_interval` - The time between presence updates, if kept alive.
+    /// * `catalog` - The messages generated by the client.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        settings: LiveSettings,
+        config_rx: watch::Receiver<ClientConfigSnapshot>,
+        channel_ids: Vec<u64>,
+        rx: Receiver<Message>,
+        actions_rx: Receiver<ClientAction>,
+        forward_only: bool,
+        max_concurrent_sends: usize,
+        max_per_minute: Option<usize>,
+        health: ChannelHealth,
+        send_errors: SendErrors,
+        embed_digest: Option<EmbedDigestConfig>,
+        webhook: bool,
+        voice_channel_ids: Vec<u64>,
+        relay_pins: bool,
+        chat_digest: Option<Arc<ChatDigest>>,
+        timestamp_format: Option<TimestampFormat>,
+        backfill: Option<Backfill>,
+        same_client_format: Option<Template>,
+        reconnect_message: Option<String>,
+        suppress_embeds: bool,
+        author_groups: Option<AuthorGroups>,
+        forum_mode: bool,
+        allowed_roles: Option<Vec<u64>>,
+        dm_user_ids: Vec<u64>,
+        warm_up_timeout: Duration,
+        prefetch_members: bool,
+        keepalive_interval: Option<Duration>,
+        catalog: Arc<Catalog>,
+    ) -> Self {
+        let policy = ForwardPolicy::new(channel_ids.iter().map(u64::to_string))
+            .with_isolate_channels(settings.isolate_channels)
+            .with_ignored_authors(&settings.ignored_authors);
         DiscordHandler {
+            settings: StdMutex::new(Arc::new(settings)),
+            policy: StdMutex::new(policy),
+            config_rx: Mutex::new(config_rx),
             ch_ids: channel_ids.into_iter().map(ChannelId).collect(),
             rx: Arc::new(Mutex::new(rx)),
-            tx,
+            actions_rx: Mutex::new(actions_rx),
             outer_tx: Vec::new(),
-            isolate_channels,
             forward_only,
+            max_concurrent_sends,
+            max_per_minute,
+            health: StdMutex::new(health),
+            send_errors,
+            embed_digest,
+            webhook,
+            webhooks: Mutex::new(HashMap::new()),
+            voice_ch_ids: voice_channel_ids.into_iter().map(ChannelId).collect(),
+            relay_pins,
+            relayed_pins: Arc::new(StdMutex::new(HashSet::new())),
+            chat_digest,
+            timestamp_format,
+            backfill,
+            same_client_format,
+            reconnect_message,
+            suppress_embeds,
+            author_groups: author_groups.map(StdMutex::new),
+            forum_mode,
+            allowed_roles: allowed_roles
+                .map(|allowed_roles| allowed_roles.into_iter().map(RoleId).collect()),
+            dm_user_ids: dm_user_ids.into_iter().map(UserId).collect(),
+            catalog,
+            connected: AtomicBool::new(false),
+            connected_signal: Connected::new(),
+            flushed: Flushed::new(),
+            warm_up: watch::Sender::new(WarmUp::default()),
+            warm_up_timeout,
+            prefetch_members,
+            keepalive_interval,
+            progress: Progress::new(),
         }
     }
 
-    /// Returns a copy of the handler's TX stream.
-    fn get_stream(&self) -> Sender<Message> {
-        self.tx.clone()
+    /// Gets the settings currently applied.
+    fn get_settings(&self) -> Arc<LiveSettings> {
+        Arc::clone(&self.settings.lock().unwrap())
+    }
+
+    /// Forwards a message to all connected streams, or counts it towards the next digest.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to forward.
+    async fn forward(&self, msg: &Message) {
+        if let Some(digest) = &self.chat_digest {
+            digest.record(msg);
+            return;
+        }
+
+        for stream in &self.outer_tx {
+            debug!("Sending message: {}", msg);
+            if let Err(err) = stream.send(msg.clone()).await {
+                error!("Error sending: {:?}", err);
+            }
+        }
+    }
+
+    /// Relays a direct message to other clients, if its author is monitored.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The direct message.
+    async fn relay_direct_message(&self, msg: SMessage) {
+        if msg.author.bot || !self.dm_user_ids.contains(&msg.author.id) {
+            debug!("Unmonitored direct message, ignoring");
+            return;
+        }
+
+        let settings = self.get_settings();
+        let new_msg = Message::new(
+            settings.display_client.clone(),
+            DM_CHANNEL.to_string(),
+            settings
+                .user_aliases
+                .resolve(&msg.author.name, msg.author.name.clone()),
+            msg.content,
+        )
+        .with_metadata(DM_USER_ID, msg.author.id.to_string())
+        .with_timestamp(msg.timestamp)
+        .with_platform_id(msg.id.to_string());
+
+        match settings.pipeline.filter(new_msg) {
+            FilterAction::Pass(new_msg) => self.forward(&new_msg).await,
+            FilterAction::Drop => debug!("Dropped by pipeline, ignoring message"),
+        }
+    }
+
+    /// Gets the monitored user a relayed message is a direct message to, if it is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The relayed message.
+    fn get_dm_recipient(&self, msg: &Message) -> Option<UserId> {
+        if msg.get_channel() != DM_CHANNEL {
+            return None;
+        }
+
+        let user_id = UserId(msg.get_metadata(DM_USER_ID)?.parse().ok()?);
+        self.dm_user_ids.contains(&user_id).then_some(user_id)
+    }
+
+    /// Sends a relayed message to a user as a direct message.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `user_id` - The user to send to.
+    /// * `msg` - The message to send.
+    async fn send_direct_message(&self, ctx: &Context, user_id: UserId, msg: &Message) {
+        match user_id.create_dm_channel(ctx).await {
+            Ok(channel) => {
+                self.send_message(ctx, channel.id, message_to_discord_embed(msg))
+                    .await;
+            }
+            Err(err) => error!("Error opening direct messages with {}: {:?}", user_id, err),
+        }
+    }
+
+    /// Waits for Discord to send the guilds, channels and, with `prefetch_members`, members to
+    /// cache after connecting, for at most the warm-up timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    async fn warm_up(&self, ctx: &Context) {
+        let started = Instant::now();
+        let mut warm_up_rx = self.warm_up.subscribe();
+        if timeout(
+            self.warm_up_timeout,
+            warm_up_rx.wait_for(|warm_up| warm_up.cache_ready),
+        )
+        .await
+        .is_err()
+        {
+            warn!("Guilds not cached after {:?}", self.warm_up_timeout);
+        }
+
+        if self.prefetch_members {
+            let mut guild_ids = HashSet::new();
+            for ch_id in &self.ch_ids {
+                match ctx.cache.guild_channel(*ch_id).await {
+                    Some(channel) => {
+                        guild_ids.insert(channel.guild_id);
+                    }
+                    None => warn!("Channel {} not cached, not prefetching its members", ch_id),
+                }
+            }
+            self.warm_up
+                .send_modify(|warm_up| warm_up.pending_guilds = guild_ids.clone());
+            for guild_id in guild_ids {
+                ctx.shard
+                    .chunk_guild(guild_id, None, ChunkGuildFilter::None, None);
+            }
+
+            let left = self.warm_up_timeout.saturating_sub(started.elapsed());
+            if timeout(
+                left,
+                warm_up_rx.wait_for(|warm_up| warm_up.pending_guilds.is_empty()),
+            )
+            .await
+            .is_err()
+            {
+                warn!("Members not cached after {:?}", self.warm_up_timeout);
+            }
+        }
+
+        self.warm_up.send_modify(|warm_up| warm_up.done = true);
+        info!("Warmed up in {:?}", started.elapsed());
+    }
+
+    /// Waits for the client to be done warming up after connecting, see
+    /// `DiscordHandler::warm_up`.
+    async fn wait_warmed_up(&self) {
+        let mut warm_up_rx = self.warm_up.subscribe();
+        // The warm-up stops on its own after the timeout, this only guards against it never
+        // starting.
+        let _ = timeout(
+            self.warm_up_timeout,
+            warm_up_rx.wait_for(|warm_up| warm_up.done),
+        )
+        .await;
+    }
+
+    /// Checks whether a received message's author has one of the allowed roles, if restricted.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `guild_id` - The guild the message was sent in, to fetch the author's roles from.
+    /// * `msg` - The received message.
+    async fn has_allowed_role(
+        &self,
+        ctx: &Context,
+        guild_id: Option<GuildId>,
+        msg: &SMessage,
+    ) -> bool {
+        let allowed_roles = match &self.allowed_roles {
+            Some(allowed_roles) => allowed_roles,
+            None => return true,
+        };
+
+        // Messages from the gateway come with their author's roles, history doesn't.
+        let roles = match (&msg.member, guild_id) {
+            (Some(member), _) => member.roles.clone(),
+            (None, Some(guild_id)) => match guild_id.member(ctx, msg.author.id).await {
+                Ok(member) => member.roles,
+                Err(err) => {
+                    error!("Error fetching roles of {}: {:?}", msg.author.name, err);
+                    return false;
+                }
+            },
+            (None, None) => return false,
+        };
+        roles.iter().any(|role| allowed_roles.contains(role))
+    }
+
+    /// Relays the messages sent to the channels since their last relayed message, oldest first.
+    ///
+    /// Backfilled messages are annotated, and only relayed to other clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `backfill` - The backfill settings and state.
+    async fn backfill_history(&self, ctx: &Context, backfill: &Backfill) {
+        let settings = self.get_settings();
+        for ch_id in &self.ch_ids {
+            // Without a relayed message, there's no telling what was missed.
+            let last_relayed = match backfill.state.get_last_relayed(ch_id.0) {
+                Some(last_relayed) => last_relayed,
+                None => {
+                    info!("No relayed message known, not backfilling: {}", ch_id);
+                    continue;
+                }
+            };
+
+            let mut messages = match ch_id
+                .messages(&ctx.http, |retriever| retriever.limit(backfill.count))
+                .await
+            {
+                Ok(messages) => messages,
+                Err(err) => {
+                    error!("Error fetching history of {}: {:?}", ch_id, err);
+                    continue;
+                }
+            };
+            messages.retain(|msg| {
+                let recent = Utc::now()
+                    .signed_duration_since(msg.timestamp)
+                    .to_std()
+                    .map_or(true, |age| age <= backfill.max_age);
+                msg.id.0 > last_relayed && !msg.author.bot && recent
+            });
+            messages.sort_by_key(|msg| msg.id);
+            info!("Backfilling {} messages of {}", messages.len(), ch_id);
+
+            let ch_name = ch_id.name(ctx).await.unwrap_or_else(|| ch_id.to_string());
+            let guild_id = match ch_id.to_channel(ctx).await {
+                Ok(Channel::Guild(channel)) => Some(channel.guild_id),
+                _ => None,
+            };
+            for msg in messages {
+                let msg_id = msg.id.0;
+                if !self.has_allowed_role(ctx, guild_id, &msg).await {
+                    debug!("Author lacks an allowed role, not backfilling message");
+                    backfill.state.record(ch_id.0, msg_id);
+                    continue;
+                }
+
+                let new_msg = Message::new(
+                    settings.display_client.clone(),
+                    ch_name.clone(),
+                    settings
+                        .user_aliases
+                        .resolve(&msg.author.name, msg.author.name.clone()),
+                    msg.content,
+                )
+                .with_timestamp(msg.timestamp)
+                .with_platform_id(msg.id.to_string());
+
+                if let FilterAction::Pass(mut new_msg) = settings.pipeline.filter(new_msg) {
+                    let content = self
+                        .catalog
+                        .render(CatalogKey::Backfill, &[new_msg.get_content()]);
+                    new_msg.set_content(content);
+                    self.forward(&new_msg).await;
+                }
+                backfill.state.record(ch_id.0, msg_id);
+            }
+        }
+
+        if let Err(err) = backfill.state.save().await {
+            error!("Error saving backfill state: {}", err);
+        }
+    }
+
+    /// Sends the reconnect message to every channel, if configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    async fn send_reconnect_message(&self, ctx: &Context) {
+        let text = match &self.reconnect_message {
+            Some(text) => text,
+            None => return,
+        };
+
+        for ch_id in &self.ch_ids {
+            let mut create_message = CreateMessage::default();
+            create_message.content(text);
+            match self.forum_mode {
+                true => {
+                    let name = text.chars().take(THREAD_NAME_LIMIT).collect();
+                    self.send_forum_post(ctx, *ch_id, name, create_message)
+                        .await
+                }
+                false => {
+                    self.send_message(ctx, *ch_id, create_message).await;
+                }
+            }
+        }
+    }
+
+    /// Sends a message to a channel unless it's marked dead.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `ch_id` - The channel to send to.
+    /// * `msg` - The message to send.
+    async fn send_to_channel(&self, ctx: &Context, ch_id: ChannelId, msg: &Message) {
+        if self.forum_mode {
+            let mut create_message = message_to_discord_embed(msg);
+            self.suppress_link_previews(&mut create_message);
+            self.send_forum_post(ctx, ch_id, message_to_thread_name(msg), create_message)
+                .await;
+            return;
+        }
+
+        if self.webhook && msg.get_kind() != MessageKind::System {
+            self.send_webhook_message(ctx, ch_id, msg).await;
+            return;
+        }
+
+        let groups = match &self.author_groups {
+            Some(groups) => groups,
+            None => {
+                let mut create_message = message_to_discord_embed(msg);
+                self.suppress_link_previews(&mut create_message);
+                self.send_message(ctx, ch_id, create_message).await;
+                return;
+            }
+        };
+
+        // Append to the author's previous message if it continues their group.
+        let channel = ch_id.to_string();
+        let append = groups.lock().unwrap().append(&channel, msg);
+        if let Some((message_id, content)) = append {
+            if self
+                .edit_message(ctx, ch_id, MessageId(message_id), &content)
+                .await
+            {
+                groups.lock().unwrap().appended(&channel, content);
+                return;
+            }
+        }
+
+        let content = msg.to_string();
+        let mut create_message = message_to_discord_embed(msg);
+        self.suppress_link_previews(&mut create_message);
+        if let Some(message_id) = self.send_message(ctx, ch_id, create_message).await {
+            groups
+                .lock()
+                .unwrap()
+                .start(&channel, msg, message_id.0, content);
+        }
+    }
+
+    /// Edits a message sent to a channel, unless the channel is marked dead.
+    ///
+    /// Returns whether the message was edited.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `ch_id` - The channel the message was sent to.
+    /// * `message_id` - The message to edit.
+    /// * `content` - The message's new content.
+    async fn edit_message(
+        &self,
+        ctx: &Context,
+        ch_id: ChannelId,
+        message_id: MessageId,
+        content: &str,
+    ) -> bool {
+        let channel = ch_id.to_string();
+        if !self.health.lock().unwrap().should_send(&channel) {
+            debug!("Dead channel, not editing message for: {}", channel);
+            return false;
+        }
+
+        let result = ch_id
+            .edit_message(&ctx.http, message_id, |m| m.content(content))
+            .await
+            .map(|_| ());
+        let edited = result.is_ok();
+        self.record_send_result(&channel, result);
+        edited
+    }
+
+    /// Suppresses the link previews of a relayed message, if configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `create_message` - The message to send.
+    fn suppress_link_previews(&self, create_message: &mut CreateMessage<'static>) {
+        if self.suppress_embeds {
+            create_message.flags(MessageFlags::SUPPRESS_EMBEDS);
+        }
+    }
+
+    /// Gets the webhook to post to a channel with, creating it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `ch_id` - The channel to post to.
+    async fn get_webhook(&self, ctx: &Context, ch_id: ChannelId) -> Result<Webhook, SerenityError> {
+        let mut webhooks = self.webhooks.lock().await;
+        if let Some(webhook) = webhooks.get(&ch_id) {
+            return Ok(webhook.clone());
+        }
+
+        let existing = ch_id
+            .webhooks(&ctx.http)
+            .await?
+            .into_iter()
+            .find(|webhook| {
+                webhook.name.as_deref() == Some(WEBHOOK_NAME) && webhook.token.is_some()
+            });
+        let webhook = match existing {
+            Some(webhook) => webhook,
+            None => {
+                info!("Creating webhook for channel {}", ch_id);
+                ch_id.create_webhook(&ctx.http, WEBHOOK_NAME).await?
+            }
+        };
+
+        webhooks.insert(ch_id, webhook.clone());
+        Ok(webhook)
+    }
+
+    /// Posts a message to a channel as its author through a webhook, unless it's marked dead.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `ch_id` - The channel to post to.
+    /// * `msg` - The message to post.
+    async fn send_webhook_message(&self, ctx: &Context, ch_id: ChannelId, msg: &Message) {
+        let channel = ch_id.to_string();
+        if !self.health.lock().unwrap().should_send(&channel) {
+            debug!("Dead channel, dropping message for: {}", channel);
+            return;
+        }
+
+        let result = match self.get_webhook(ctx, ch_id).await {
+            Ok(webhook) => self
+                .send_errors
+                .send(
+                    || {
+                        webhook.execute(&ctx.http, false, |w| {
+                            w.username(message_to_webhook_username(msg))
+                                .content(msg.get_content());
+                            if self.suppress_embeds {
+                                w.flags(MessageFlags::SUPPRESS_EMBEDS);
+                            }
+                            if let Some(avatar_url) = msg.get_avatar_url() {
+                                w.avatar_url(avatar_url);
+                            }
+                            w
+                        })
+                    },
+                    |err| !is_dead_channel_error(err),
+                )
+                .await
+                .map(|_| ()),
+            Err(err) => Err(err),
+        };
+        self.record_send_result(&channel, result);
+    }
+
+    /// Gets the actions setting the topic of the channels a topic message is for.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The topic message.
+    fn topic_actions(&self, msg: &Message) -> Vec<ClientAction> {
+        self.ch_ids
+            .iter()
+            .map(ChannelId::to_string)
+            .filter(|channel| msg.is_for_channel(channel))
+            .map(|channel| ClientAction::SetChannelTopic {
+                channel,
+                topic: msg.get_content().to_string(),
+            })
+            .collect()
+    }
+
+    /// Handles an action sent to the client, ignoring the unsupported ones.
+    ///
+    /// Messages are looked up by ID in the configured channels, and channels by ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `action` - The action to handle.
+    #[instrument(skip(self, ctx))]
+    async fn handle_action(&self, ctx: &Context, action: ClientAction) {
+        match action {
+            ClientAction::SetChannelTopic { channel, topic } => {
+                match self
+                    .ch_ids
+                    .iter()
+                    .find(|ch_id| ch_id.to_string() == channel)
+                {
+                    Some(ch_id) => self.set_topic(ctx, *ch_id, &topic).await,
+                    None => debug!("Unknown channel, not setting topic: {}", channel),
+                }
+            }
+            ClientAction::DeleteMessage { native_id } => {
+                if let Some((ch_id, message_id)) = self.find_message(ctx, &native_id).await {
+                    info!("Deleting message {} of channel {}", message_id, ch_id);
+                    if let Err(err) = ch_id.delete_message(&ctx.http, message_id).await {
+                        error!("Error deleting message {}: {:?}", message_id, err);
+                    }
+                }
+            }
+            ClientAction::EditMessage { native_id, content } => {
+                if let Some((ch_id, message_id)) = self.find_message(ctx, &native_id).await {
+                    let content = content.chars().take(MESSAGE_LIMIT).collect::<String>();
+                    if !self.edit_message(ctx, ch_id, message_id, &content).await {
+                        error!("Error editing message {}", message_id);
+                    }
+                }
+            }
+            ClientAction::Custom { name, .. } => {
+                debug!("Unsupported action, ignoring: {}", name)
+            }
+        }
+    }
+
+    /// Finds the configured channel holding a message.
+    ///
+    /// Discord message IDs don't name their channel, so each channel is asked in turn.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `native_id` - The message's ID.
+    async fn find_message(&self, ctx: &Context, native_id: &str) -> Option<(ChannelId, MessageId)> {
+        let message_id = match native_id.parse() {
+            Ok(message_id) => MessageId(message_id),
+            Err(_) => {
+                debug!("Not a Discord message ID: {}", native_id);
+                return None;
+            }
+        };
+
+        for ch_id in &self.ch_ids {
+            if ch_id.message(&ctx.http, message_id).await.is_ok() {
+                return Some((*ch_id, message_id));
+            }
+        }
+        debug!("Message not found in any channel: {}", message_id);
+        None
+    }
+
+    /// Sets the topic of a channel, unless it's already set.
+    ///
+    /// Failures aren't tracked by the channel's health, since a channel can be sent to without
+    /// the permission to manage it.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `ch_id` - The channel to set the topic of.
+    /// * `topic` - The new topic.
+    async fn set_topic(&self, ctx: &Context, ch_id: ChannelId, topic: &str) {
+        let topic = topic.chars().take(TOPIC_LIMIT).collect::<String>();
+        if let Ok(Channel::Guild(channel)) = ch_id.to_channel(ctx).await {
+            if channel.topic.as_deref() == Some(topic.as_str()) {
+                debug!("Topic already set for: {}", ch_id);
+                return;
+            }
+        }
+
+        info!("Setting topic of channel {}", ch_id);
+        if let Err(err) = ch_id.edit(&ctx.http, |c| c.topic(&topic)).await {
+            error!("Error setting topic of {}: {:?}", ch_id, err);
+        }
+    }
+
+    /// Sends a digest to its channels as embeds.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `digest` - The accumulator the digest was taken from.
+    /// * `batch` - The digest to send.
+    async fn send_digest(&self, ctx: &Context, digest: &EmbedDigest, batch: DigestBatch) {
+        for embed in digest.to_embeds(&batch) {
+            for ch_id in &self.ch_ids {
+                if !batch.is_for_channel(&ch_id.to_string()) {
+                    continue;
+                }
+
+                let mut create_message = CreateMessage::default();
+                create_message.set_embed(embed.clone());
+                self.send_message(ctx, *ch_id, create_message).await;
+            }
+        }
+    }
+
+    /// Sends a built message to a channel unless it's marked dead.
+    ///
+    /// Returns the ID of the message sent, if it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `ch_id` - The channel to send to.
+    /// * `create_message` - The message to send.
+    async fn send_message(
+        &self,
+        ctx: &Context,
+        ch_id: ChannelId,
+        create_message: CreateMessage<'static>,
+    ) -> Option<MessageId> {
+        let channel = ch_id.to_string();
+        if !self.health.lock().unwrap().should_send(&channel) {
+            debug!("Dead channel, dropping message for: {}", channel);
+            return None;
+        }
+
+        // Any message sent ends the author's group, which must be consecutive.
+        if let Some(groups) = &self.author_groups {
+            groups.lock().unwrap().end(&channel);
+        }
+        let result = self
+            .send_errors
+            .send(
+                || {
+                    ch_id.send_message(&ctx.http, |m| {
+                        *m = create_message.clone();
+                        m
+                    })
+                },
+                |err| !is_dead_channel_error(err),
+            )
+            .await;
+        let message_id = result.as_ref().ok().map(|sent| sent.id);
+        self.record_send_result(&channel, result.map(|_| ()));
+        message_id
+    }
+
+    /// Starts a post in a forum channel with a built message, unless it's marked dead.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The Discord context.
+    /// * `ch_id` - The forum channel to post to.
+    /// * `name` - The name of the post.
+    /// * `create_message` - The post's opening message.
+    async fn send_forum_post(
+        &self,
+        ctx: &Context,
+        ch_id: ChannelId,
+        name: String,
+        create_message: CreateMessage<'static>,
+    ) {
+        let channel = ch_id.to_string();
+        if !self.health.lock().unwrap().should_send(&channel) {
+            debug!("Dead channel, dropping message for: {}", channel);
+            return;
+        }
+
+        let mut post = JsonMap::new();
+        post.insert("name".to_string(), Value::String(name));
+        post.insert(
+            "message".to_string(),
+            Value::Object(hashmap_to_json_map(create_message.0)),
+        );
+        // Forum posts are threads started with a message, created on the same endpoint as
+        // threads without one.
+        let result = ctx
+            .http
+            .create_private_thread(ch_id.0, &post)
+            .await
+            .map(|_| ());
+        self.record_send_result(&channel, result);
+    }
+
+    /// Tracks the channel's health from the outcome of a send.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel sent to.
+    /// * `result` - The outcome of the send.
+    fn record_send_result(&self, channel: &str, result: Result<(), SerenityError>) {
+        match result {
+            Ok(_) => {
+                self.health.lock().unwrap().record_success(channel);
+                self.progress.record();
+            }
+            Err(err) => {
+                if is_dead_channel_error(&err) {
+                    self.health.lock().unwrap().record_failure(channel);
+                }
+                error!("Error sending: {:?}", err);
+            }
+        }
     }
 
     /// Adds a TX stream to send to on message receipt.
@@ -67,90 +1070,562 @@ impl DiscordHandler {
 impl EventHandler for DiscordHandler {
     #[instrument(skip(self, ctx, msg))]
     async fn message(&self, ctx: Context, msg: SMessage) {
-        // Only forward if it's not a bot message.
-        if msg.author.bot {
-            debug!("Bot, ignoring message");
+        self.progress.record();
+        self.wait_warmed_up().await;
+
+        // Direct messages aren't sent in a guild.
+        if msg.guild_id.is_none() {
+            self.relay_direct_message(msg).await;
             return;
         }
+        let channel = msg.channel_id.to_string();
+        // Messages posted in the channel by anyone else end the author's group there too.
+        if let Some(groups) = &self.author_groups {
+            if !msg.is_own(&ctx).await {
+                groups.lock().unwrap().end(&channel);
+            }
+        }
+        let incoming = IncomingMeta {
+            author: &msg.author.name,
+            author_is_bot: msg.author.bot,
+            channel: &channel,
+            content: &msg.content,
+        };
+        let decision = self.policy.lock().unwrap().decide(&incoming);
+        let to_other_channels = match decision {
+            ForwardDecision::Forward { to_other_channels } => to_other_channels,
+            ForwardDecision::Ignore(IgnoreReason::Bot) => {
+                debug!("Bot, ignoring message");
+                return;
+            }
+            ForwardDecision::Ignore(IgnoreReason::UnknownChannel) => {
+                debug!("Unrecognized channel, ignoring: {}", msg.channel_id);
+                return;
+            }
+            ForwardDecision::Ignore(IgnoreReason::IgnoredAuthor) => {
+                debug!("Ignored author, ignoring message: {}", msg.author.name);
+                return;
+            }
+        };
 
-        // Only forward if it's coming from a channel we are handling.
-        if let None = self.ch_ids.iter().find(|ch_id| ch_id == &&msg.channel_id) {
-            debug!("Unrecognized channel, ignoring: {}", msg.channel_id);
+        if !self.has_allowed_role(&ctx, msg.guild_id, &msg).await {
+            debug!("Author lacks an allowed role, ignoring message");
             return;
         }
 
+        let settings = self.get_settings();
         let new_msg = Message::new(
-            "Discord".to_string(),
+            settings.display_client.clone(),
             msg.channel_id.name(&ctx).await.unwrap(),
-            msg.author.name,
+            settings
+                .user_aliases
+                .resolve(&msg.author.name, msg.author.name.clone()),
             msg.content,
-        );
+        )
+        .with_timestamp(msg.timestamp)
+        .with_platform_id(msg.id.to_string());
 
-        if !self.isolate_channels {
-            // Forward message to other connected channels.
-            for ch_id in &self.ch_ids {
-                // Skip if same channel.
-                if ch_id == &msg.channel_id {
-                    continue;
-                }
+        let new_msg = match settings.pipeline.filter(new_msg) {
+            FilterAction::Pass(msg) => msg,
+            FilterAction::Drop => {
+                debug!("Dropped by pipeline, ignoring message");
+                return;
+            }
+        };
+
+        if to_other_channels {
+            // Forward message to other connected channels, skipping the same channel.
+            let ctx = &ctx;
+            let new_msg = &new_msg;
+            let source_id = msg.channel_id;
+            stream::iter(self.ch_ids.iter().filter(|ch_id| **ch_id != source_id))
+                .for_each_concurrent(self.max_concurrent_sends, |ch_id| async move {
+                    match &self.same_client_format {
+                        Some(format) if !self.webhook => {
+                            let mut create_message = CreateMessage::default();
+                            create_message.content(new_msg.format_with(format));
+                            self.suppress_link_previews(&mut create_message);
+                            self.send_message(ctx, *ch_id, create_message).await;
+                        }
+                        _ => self.send_to_channel(ctx, *ch_id, new_msg).await,
+                    }
+                })
+                .await;
+        }
 
-                if let Err(err) = ch_id.say(&ctx.http, new_msg.clone()).await {
-                    error!("Error sending: {:?}", err);
+        self.forward(&new_msg).await;
+
+        if let Some(backfill) = &self.backfill {
+            backfill.state.record(msg.channel_id.0, msg.id.0);
+        }
+    }
+
+    #[instrument(skip(self, ctx, old, new))]
+    async fn voice_state_update(
+        &self,
+        ctx: Context,
+        _guild_id: Option<GuildId>,
+        old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        self.wait_warmed_up().await;
+        let old_ch_id = old.and_then(|state| state.channel_id);
+        // Mutes, deafens and streams don't change the channel.
+        if old_ch_id == new.channel_id {
+            return;
+        }
+
+        let left = old_ch_id.filter(|ch_id| self.voice_ch_ids.contains(ch_id));
+        let joined = new
+            .channel_id
+            .filter(|ch_id| self.voice_ch_ids.contains(ch_id));
+        if left.is_none() && joined.is_none() {
+            return;
+        }
+
+        let settings = self.get_settings();
+        let (user_name, bot) = match &new.member {
+            Some(member) => (
+                settings
+                    .user_aliases
+                    .resolve(&member.user.name, member.display_name().to_string()),
+                member.user.bot,
+            ),
+            None => match new.user_id.to_user(&ctx).await {
+                Ok(user) => (
+                    settings.user_aliases.resolve(&user.name, user.name.clone()),
+                    user.bot,
+                ),
+                Err(err) => {
+                    error!("Error getting voice user {}: {:?}", new.user_id, err);
+                    return;
                 }
-            }
+            },
+        };
+        if bot {
+            debug!("Bot, ignoring voice event");
+            return;
         }
 
-        // Forward message to all connected streams.
-        for stream in &self.outer_tx {
-            debug!("Sending message: {}", new_msg);
-            if let Err(err) = stream.send(new_msg.clone()).await {
-                error!("Error sending: {:?}", err);
+        let events = left
+            .map(|ch_id| (ch_id, CatalogKey::VoiceLeft))
+            .into_iter()
+            .chain(joined.map(|ch_id| (ch_id, CatalogKey::VoiceJoined)));
+        for (ch_id, key) in events {
+            let ch_name = ch_id.name(&ctx).await.unwrap_or_else(|| ch_id.to_string());
+            let new_msg = Message::new(
+                settings.display_client.clone(),
+                ch_name.clone(),
+                user_name.clone(),
+                self.catalog.render(key, &[&user_name, &ch_name]),
+            )
+            .with_kind(MessageKind::Event);
+
+            self.forward(&new_msg).await;
+        }
+    }
+
+    #[instrument(skip(self, ctx))]
+    async fn channel_pins_update(&self, ctx: Context, pin: ChannelPinsUpdateEvent) {
+        if !self.relay_pins || !self.ch_ids.contains(&pin.channel_id) {
+            return;
+        }
+        self.wait_warmed_up().await;
+
+        // Unpins also update the pins, with the time the newest pin left was pinned.
+        let pinned_now = pin.last_pin_timestamp.is_some_and(|pinned_at| {
+            Utc::now()
+                .signed_duration_since(pinned_at)
+                .to_std()
+                .map_or(true, |age| age <= PIN_WINDOW)
+        });
+        if !pinned_now {
+            debug!("No new pin, ignoring pins update");
+            return;
+        }
+
+        // The event doesn't tell which message was pinned, the newest pin comes first.
+        let pinned = match pin.channel_id.pins(&ctx.http).await {
+            Ok(pins) => match pins.into_iter().next() {
+                Some(pinned) => pinned,
+                None => return,
+            },
+            Err(err) => {
+                error!("Error fetching pins of {}: {:?}", pin.channel_id, err);
+                return;
             }
+        };
+        if !self.relayed_pins.lock().unwrap().insert(pinned.id) {
+            debug!("Pin already relayed, ignoring pins update");
+            return;
+        }
+
+        let settings = self.get_settings();
+        let ch_name = pin
+            .channel_id
+            .name(&ctx)
+            .await
+            .unwrap_or_else(|| pin.channel_id.to_string());
+        let new_msg = Message::new(
+            settings.display_client.clone(),
+            ch_name,
+            settings
+                .user_aliases
+                .resolve(&pinned.author.name, pinned.author.name.clone()),
+            pinned.content,
+        )
+        .with_timestamp(pinned.timestamp)
+        .with_platform_id(pinned.id.to_string());
+
+        // Annotated after the pipeline, like backfilled messages, and delivered ahead of chat.
+        if let FilterAction::Pass(mut new_msg) = settings.pipeline.filter(new_msg) {
+            let content = self
+                .catalog
+                .render(CatalogKey::Pinned, &[new_msg.get_content()]);
+            new_msg.set_content(content);
+            self.forward(&new_msg.with_metadata(PRIORITY, "true".to_string()))
+                .await;
+        }
+    }
+
+    async fn cache_ready(&self, _ctx: Context, _guilds: Vec<GuildId>) {
+        self.warm_up
+            .send_modify(|warm_up| warm_up.cache_ready = true);
+    }
+
+    async fn guild_members_chunk(&self, _ctx: Context, chunk: GuildMembersChunkEvent) {
+        if chunk.chunk_index + 1 == chunk.chunk_count {
+            self.warm_up.send_modify(|warm_up| {
+                warm_up.pending_guilds.remove(&chunk.guild_id);
+            });
         }
     }
 
+    #[instrument(skip(self, ctx, _resumed))]
+    async fn resume(&self, ctx: Context, _resumed: ResumedEvent) {
+        info!("Reconnected, resumed the session");
+        self.progress.record();
+        self.send_reconnect_message(&ctx).await;
+    }
+
     #[instrument(skip(self, ctx, ready))]
     async fn ready(&self, ctx: Context, ready: Ready) {
         debug!("{} is connected!", ready.user.name);
+        self.progress.record();
 
-        if !self.forward_only {
-            // Start up the RX channel.
-            let mut locked_rx = self.rx.lock().await;
-            debug!("Lock acquired!");
+        // Only new sessions after the first one are reconnects, with the cache still warm.
+        if self.connected.swap(true, Ordering::SeqCst) {
+            info!("Reconnected with a new session");
+            self.send_reconnect_message(&ctx).await;
+        } else {
+            self.warm_up(&ctx).await;
+        }
+        self.connected_signal.mark();
+
+        if let Some(backfill) = &self.backfill {
+            self.backfill_history(&ctx, backfill).await;
+        }
+
+        // Start up the RX channels, and the config watch.
+        let mut locked_rx = self.rx.lock().await;
+        let mut locked_actions = self.actions_rx.lock().await;
+        let mut config_rx = self.config_rx.lock().await;
+        debug!("Lock acquired!");
+
+        let ctx = &ctx;
+        let (queues, workers) = ChannelQueues::new(
+            self.ch_ids.clone(),
+            self.max_concurrent_sends,
+            self.max_per_minute,
+            Arc::clone(&self.catalog),
+            |ch_id: ChannelId, msg: Message| async move {
+                self.send_to_channel(ctx, ch_id, &msg).await;
+            },
+        );
 
-            // Poll for new message.
-            while let Some(msg) = locked_rx.recv().await {
+        let dispatch = async move {
+            let mut digest = self.embed_digest.as_ref().map(EmbedDigest::new);
+            let mut flush_interval = tokio::time::interval(Duration::from_secs(1));
+            let mut save_interval = tokio::time::interval(BACKFILL_SAVE_INTERVAL);
+            let mut keepalive_interval =
+                tokio::time::interval(self.keepalive_interval.unwrap_or(Duration::from_secs(1)));
+            let mut flushing = false;
+
+            loop {
+                // Poll for new message, flushing digests as they expire and applying config changes.
+                let msg = tokio::select! {
+                    msg = locked_rx.recv(), if !self.forward_only => match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                    Ok(()) = config_rx.changed() => {
+                        match LiveSettings::from_watch(&mut config_rx) {
+                            Ok(settings) => {
+                                info!("Applied config change");
+                                let mut policy = self.policy.lock().unwrap();
+                                policy.set_isolate_channels(settings.isolate_channels);
+                                policy.set_ignored_authors(&settings.ignored_authors);
+                                *self.settings.lock().unwrap() = Arc::new(settings);
+                            }
+                            Err(err) => error!("Invalid config change, keeping the current one: {}", err),
+                        }
+                        continue;
+                    }
+                    Some(action) = locked_actions.recv() => {
+                        self.handle_action(ctx, action).await;
+                        continue;
+                    }
+                    _ = flush_interval.tick(), if digest.is_some() => {
+                        if let Some(digest) = &mut digest {
+                            for batch in digest.take_expired() {
+                                self.send_digest(ctx, digest, batch).await;
+                            }
+                        }
+                        continue;
+                    }
+                    _ = save_interval.tick(), if self.backfill.is_some() => {
+                        if let Some(backfill) = &self.backfill {
+                            if let Err(err) = backfill.state.save().await {
+                                error!("Error saving backfill state: {}", err);
+                            }
+                        }
+                        continue;
+                    }
+                    _ = keepalive_interval.tick(), if self.keepalive_interval.is_some() => {
+                        ctx.set_activity(Activity::watching(KEEPALIVE_ACTIVITY)).await;
+                        continue;
+                    }
+                    // Nothing more is relayed, so the loop ends once the messages taken are.
+                    () = self.flushed.flushing(), if !flushing => {
+                        flushing = true;
+                        locked_rx.close();
+                        if self.forward_only {
+                            break;
+                        }
+                        continue;
+                    }
+                    else => break,
+                };
                 debug!("Received message! {}", msg);
 
-                // Send received message to channels.
+                // Topics change the channels instead of being sent.
+                if msg.get_kind() == MessageKind::Topic {
+                    for action in self.topic_actions(&msg) {
+                        self.handle_action(ctx, action).await;
+                    }
+                    continue;
+                }
+
+                let msg = match &self.timestamp_format {
+                    Some(timestamp_format) => timestamp_format.apply(msg),
+                    None => msg,
+                };
+
+                // Direct messages go to their user instead of the channels.
+                if let Some(user_id) = self.get_dm_recipient(&msg) {
+                    self.send_direct_message(ctx, user_id, &msg).await;
+                    continue;
+                }
+
+                // Batch chat into digests, bridge messages are sent as is.
+                if let Some(digest) = &mut digest {
+                    if msg.get_kind() != MessageKind::System {
+                        if let Some(batch) = digest.push(&msg) {
+                            self.send_digest(ctx, digest, batch).await;
+                        }
+                        continue;
+                    }
+                }
+
+                // Queue received message to channels.
                 for ch_id in &self.ch_ids {
-                    if let Err(err) = ch_id.say(&ctx.http, msg.clone()).await {
-                        error!("Error sending: {:?}", err);
+                    if !msg.is_for_channel(&ch_id.to_string()) {
+                        continue;
                     }
+
+                    queues.push(ch_id, msg.clone()).await;
                 }
             }
-        }
+
+            // Flush what's left on shutdown.
+            if let Some(digest) = &mut digest {
+                for batch in digest.take_all() {
+                    self.send_digest(ctx, digest, batch).await;
+                }
+            }
+            if let Some(backfill) = &self.backfill {
+                if let Err(err) = backfill.state.save().await {
+                    error!("Error saving backfill state: {}", err);
+                }
+            }
+        };
+
+        join(dispatch, workers).await;
+        self.flushed.mark();
     }
 }
 
 /// Config struct for a Discord client.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct DiscordConfig {
     /// Bot's token.
-    pub token: String,
+    pub token: TokenConfig,
     /// Vec of channel IDs to connect to.
     pub channel_ids: Vec<u64>,
     /// Don't forward between channels.
     pub isolate_channels: Option<bool>,
     /// Only forward to other clients, doesn't listen.
     pub forward_only: Option<bool>,
+    /// Number of channels sent to concurrently, both relaying and forwarding between
+    /// channels, at least 1. Defaults to 4.
+    #[serde(alias = "send_concurrency")]
+    pub max_concurrent_sends: Option<usize>,
+    /// Number of messages relayed to each channel per minute, dropping the overflow and
+    /// periodically posting how many messages were suppressed. Not capped by default.
+    pub max_per_minute: Option<usize>,
+    /// What to do when sending a message fails, defaults to logging it.
+    pub send_error_strategy: Option<SendErrorStrategy>,
+    /// Detection of channels that can no longer be sent to.
+    pub channel_health: Option<ChannelHealthConfig>,
+    /// Client name shown in relayed messages, defaults to the client's name.
+    pub display_client: Option<String>,
+    /// Names shown for users instead of their Discord name, keyed by username, e.g. to show
+    /// members with the same name as on other platforms.
+    pub user_aliases: Option<HashMap<String, String>>,
+    /// Name of the client, used to refer to it elsewhere in the config, e.g. as a summary
+    /// target, and shown in relayed messages unless `display_client` is set. Defaults to
+    /// "Discord", must be unique when set.
+    pub client_name_override: Option<String>,
+    /// Locale of the messages generated by the client, e.g. stream status announcements,
+    /// defaults to the stream manager's.
+    pub locale: Option<String>,
+    /// Messages generated by the client, taking precedence over the stream manager's, see
+    /// `util::catalog`.
+    pub messages: Option<HashMap<CatalogKey, String>>,
+    /// Batch relayed messages into embeds instead of sending them one by one.
+    pub embed_digest: Option<EmbedDigestConfig>,
+    /// Drop or censor received messages containing profanity.
+    pub profanity_filter: Option<ProfanityFilterMode>,
+    /// Transformation stages applied to received messages, in order, defaults to
+    /// `[priority, spam, profanity, word_count, sample]`. Messages relayed from other clients
+    /// are sent without going through it.
+    pub pipeline: Option<Vec<PipelineStage>>,
+    /// Clean up or drop received spam, like all caps or repeated characters.
+    pub spam_filter: Option<SpamFilterConfig>,
+    /// Only relay a sample of received chat messages, e.g. during busy events.
+    pub sample: Option<SampleConfig>,
+    /// Only relay received chat messages within a word count range, e.g. to skip one-word
+    /// reactions.
+    pub word_count: Option<WordCountFilter>,
+    /// Logins of the authors whose received messages are delivered ahead of queued chat by
+    /// the other clients, e.g. moderators.
+    pub priority_authors: Option<Vec<String>>,
+    /// Pattern of the received messages delivered ahead of queued chat by the other clients,
+    /// e.g. `(?i)raid incoming`.
+    pub priority_regex: Option<String>,
+    /// Usernames of the authors whose messages aren't forwarded, ignoring case, e.g. other
+    /// bots in the channels.
+    pub ignored_authors: Option<Vec<String>>,
+    /// Post relayed messages through webhooks as their author, needs the manage webhooks
+    /// permission.
+    pub webhook: Option<bool>,
+    /// Relay users joining and leaving the voice channels in `voice_channel_ids`.
+    pub relay_voice_events: Option<bool>,
+    /// Vec of voice channel IDs to relay join and leave events of.
+    pub voice_channel_ids: Option<Vec<u64>>,
+    /// Relay the messages pinned in the channels to other clients, prefixed with
+    /// "📌 pinned", see the `pinned` message. Needs the read message history permission.
+    pub relay_pins: Option<bool>,
+    /// Minutes between digests of received messages, relayed to other clients instead of
+    /// every message.
+    pub digest_interval: Option<u64>,
+    /// Prefix relayed messages with the time they were originally sent.
+    pub show_timestamp: Option<bool>,
+    /// Format of the timestamps, see `chrono::format::strftime`, defaults to `%Y-%m-%d %H:%M`.
+    pub timestamp_format: Option<String>,
+    /// Timezone of the timestamps, `UTC`, `local` or an offset like `+02:00`, defaults to UTC.
+    pub timestamp_timezone: Option<String>,
+    /// Relay the history missed while the bridge was down on startup.
+    pub backfill: Option<BackfillConfig>,
+    /// Template of messages forwarded between channels, e.g. `{author} (from #{channel}):
+    /// {content|truncate:200}`, with the `{client}`, `{channel}`, `{author}` and `{content}`
+    /// fields, see `util::template` for modifiers and conditional sections.
+    /// Defaults to the format of messages relayed from other clients, ignored with `webhook`.
+    pub same_client_format: Option<String>,
+    /// Message sent to the channels after reconnecting, so chat knows relaying resumed.
+    pub reconnect_message: Option<String>,
+    /// Suppress the link previews of relayed messages.
+    pub suppress_embeds: Option<bool>,
+    /// Append consecutive relayed messages of an author to a single message, editing it
+    /// instead of sending a message each. Ignored with `webhook`, as Discord already groups
+    /// the messages of a webhook username.
+    pub group_by_author: Option<bool>,
+    /// Seconds after an author's latest relayed message during which their next one is
+    /// grouped with it, defaults to 60.
+    pub group_window_seconds: Option<u64>,
+    /// Start a post with each relayed message instead, the channels being forum channels.
+    /// Posts are named `[{client}] {author}: {content}`, truncated to Discord's limit, and
+    /// need the manage threads permission. Ignores `webhook` and `group_by_author`, and can't
+    /// be combined with `embed_digest`.
+    pub forum_mode: Option<bool>,
+    /// Role IDs received messages' authors need one of to be relayed, relaying every author's
+    /// messages by default.
+    pub allowed_roles: Option<Vec<u64>>,
+    /// User IDs whose direct messages to the bot are relayed, with the `dm` channel. Relayed
+    /// messages with the `dm` channel and a `dm_user_id` metadata value of one of them are
+    /// sent to that user as direct messages, see `Message::get_metadata`.
+    pub monitor_dm_users: Option<Vec<u64>>,
+    /// Longest time in seconds to wait after connecting for Discord to send the guilds and
+    /// channels to cache, and their members with `prefetch_members`, defaults to 10. Messages
+    /// received meanwhile are relayed once done, so they aren't missing channel names.
+    pub warm_up_seconds: Option<u64>,
+    /// Also cache the members of the channels' guilds after connecting, e.g. for role checks
+    /// with `allowed_roles`. Needs the server members intent.
+    pub prefetch_members: Option<bool>,
+    /// Seconds between updates of the bot's presence to "Watching the relay", so it doesn't
+    /// look idle in member lists, disabled by default.
+    pub keepalive_interval_secs: Option<u64>,
+}
+
+impl DiscordConfig {
+    /// Gets the name of the client.
+    pub fn get_name(&self) -> &str {
+        self.client_name_override.as_deref().unwrap_or("Discord")
+    }
+
+    /// Gets the settings a running client can change without restarting.
+    pub fn get_snapshot(&self) -> ClientConfigSnapshot {
+        ClientConfigSnapshot {
+            display_client: self
+                .display_client
+                .clone()
+                .unwrap_or_else(|| self.get_name().to_string()),
+            isolate_channels: self.isolate_channels.unwrap_or_default(),
+            profanity_filter: self.profanity_filter,
+            pipeline: self.pipeline.clone(),
+            spam_filter: self.spam_filter.clone(),
+            sample: self.sample.clone(),
+            word_count: self.word_count.clone(),
+            priority_authors: self.priority_authors.clone(),
+            priority_regex: self.priority_regex.clone(),
+            user_aliases: self.user_aliases.clone(),
+            ignored_authors: self.ignored_authors.clone(),
+        }
+    }
 }
 
 /// Discord client struct.
 pub struct Discord {
     id: String,
-    token: String,
+    name: String,
+    token: Secret,
+    tx: Sender<Message>,
+    actions_tx: Sender<ClientAction>,
+    config_tx: watch::Sender<ClientConfigSnapshot>,
+    forward_only: bool,
+    relayed_pins: Arc<StdMutex<HashSet<MessageId>>>,
     handler: Option<DiscordHandler>,
+    send_failures: Option<UnboundedReceiver<FitterError>>,
 }
 
 impl Discord {
@@ -163,20 +1638,99 @@ impl Discord {
     #[instrument(skip(config))]
     pub fn from_config(id: String, config: DiscordConfig) -> FitterResult<FitterClient> {
         info!("Initializing Discord client");
+        let name = config.get_name().to_string();
+        let snapshot = config.get_snapshot();
+        let settings = LiveSettings::from_snapshot(&snapshot)?;
+        let config_tx = watch::Sender::new(snapshot);
+
+        let (send_errors, send_failures) =
+            SendErrors::new(config.send_error_strategy.unwrap_or(SendErrorStrategy::Log));
+
+        let keepalive_interval = match config.keepalive_interval_secs {
+            Some(0) => {
+                return Err(FitterErrorKind::GenericErr(
+                    "Discord keepalive_interval_secs must be at least 1".to_string(),
+                )
+                .into())
+            }
+            keepalive_interval => keepalive_interval,
+        };
+
+        let forum_mode = config.forum_mode.unwrap_or_default();
+        if forum_mode && config.embed_digest.is_some() {
+            return Err(FitterErrorKind::GenericErr(
+                "Discord forum mode can't be combined with embed digests".to_string(),
+            )
+            .into());
+        }
+
+        let health = ChannelHealth::new(&name, &config.channel_health.unwrap_or_default());
+        let catalog = Arc::new(Catalog::from_config(
+            config.locale.as_deref(),
+            config.messages.as_ref(),
+        )?);
+        let (tx, rx) = channel(100);
+        let (actions_tx, actions_rx) = channel(100);
+        let handler = DiscordHandler::new(
+            settings,
+            config_tx.subscribe(),
+            config.channel_ids,
+            rx,
+            actions_rx,
+            config.forward_only.unwrap_or_default(),
+            check_max_concurrent_sends(config.max_concurrent_sends)?,
+            check_max_per_minute(config.max_per_minute)?,
+            health,
+            send_errors,
+            config.embed_digest,
+            config.webhook.unwrap_or_default(),
+            match config.relay_voice_events.unwrap_or_default() {
+                true => config.voice_channel_ids.unwrap_or_default(),
+                false => Vec::new(),
+            },
+            config.relay_pins.unwrap_or_default(),
+            ChatDigest::from_config(config.digest_interval, Arc::clone(&catalog))?,
+            TimestampFormat::from_config(
+                config.show_timestamp,
+                config.timestamp_format.as_deref(),
+                config.timestamp_timezone.as_deref(),
+            )?,
+            config
+                .backfill
+                .as_ref()
+                .map(Backfill::from_config)
+                .transpose()?,
+            config
+                .same_client_format
+                .as_deref()
+                .map(|format| Template::parse(format, Message::TEMPLATE_FIELDS))
+                .transpose()?,
+            config.reconnect_message,
+            config.suppress_embeds.unwrap_or_default(),
+            AuthorGroups::from_config(
+                config.group_by_author,
+                config.group_window_seconds,
+                MESSAGE_LIMIT,
+            ),
+            forum_mode,
+            config.allowed_roles,
+            config.monitor_dm_users.unwrap_or_default(),
+            Duration::from_secs(config.warm_up_seconds.unwrap_or(DEFAULT_WARM_UP_SECONDS)),
+            config.prefetch_members.unwrap_or_default(),
+            keepalive_interval.map(Duration::from_secs),
+            catalog,
+        );
         Ok(Box::new(Discord {
             id,
-            token: config.token,
-            handler: Some(DiscordHandler::new(
-                config.channel_ids,
-                match config.isolate_channels {
-                    Some(setting) => setting,
-                    None => false,
-                },
-                match config.forward_only {
-                    Some(setting) => setting,
-                    None => false,
-                },
-            )),
+            name,
+            token: config.token.resolve()?,
+            tx,
+            actions_tx,
+            forward_only: config.forward_only.unwrap_or_default(),
+            relayed_pins: Arc::clone(&handler.relayed_pins),
+            handler: Some(handler),
+            config_tx,
+            send_failures: Some(send_failures),
         }))
     }
 }
@@ -185,7 +1739,7 @@ impl ClientTrait for Discord {
     type FutType = FutureObj<'static, FitterResult<()>>;
 
     fn get_name(&self) -> &str {
-        "Discord"
+        &self.name
     }
 
     fn get_id(&self) -> &str {
@@ -193,10 +1747,7 @@ impl ClientTrait for Discord {
     }
 
     fn get_stream(&self) -> FitterResult<Sender<Message>> {
-        match &self.handler {
-            Some(handler) => Ok(handler.get_stream()),
-            None => Err(FitterErrorKind::GenericErr("No handler".to_string()).into()),
-        }
+        Ok(self.tx.clone())
     }
 
     fn add_stream(&mut self, stream: Sender<Message>) -> FitterResult<()> {
@@ -209,17 +1760,112 @@ impl ClientTrait for Discord {
         }
     }
 
+    fn get_config_watch(&self) -> Option<watch::Sender<ClientConfigSnapshot>> {
+        Some(self.config_tx.clone())
+    }
+
+    fn is_forward_only(&self) -> bool {
+        self.forward_only
+    }
+
+    fn get_action_stream(&self) -> Option<Sender<ClientAction>> {
+        Some(self.actions_tx.clone())
+    }
+
+    fn set_progress(&mut self, progress: Progress) {
+        if let Some(handler) = &mut self.handler {
+            handler.progress = progress;
+        }
+    }
+
+    fn set_connected(&mut self, connected: Connected) {
+        if let Some(handler) = &mut self.handler {
+            handler.connected_signal = connected;
+        }
+    }
+
+    fn set_flushed(&mut self, flushed: Flushed) {
+        if let Some(handler) = &mut self.handler {
+            handler.flushed = flushed;
+        }
+    }
+
+    fn test_connectivity(&self) -> FutureObj<'static, FitterResult<()>> {
+        let token = self.token.clone();
+
+        FutureObj::new(Box::new(async move {
+            let user = Http::new_with_token(token.expose())
+                .get_current_user()
+                .await
+                .map_err(auth_error)?;
+            debug!("Authenticated as {}", user.name);
+            Ok(())
+        }))
+    }
+
+    fn export_state(&self) -> Option<Value> {
+        let mut relayed_pins = self
+            .relayed_pins
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|msg_id| msg_id.0)
+            .collect::<Vec<u64>>();
+        relayed_pins.sort_unstable();
+        serde_json::to_value(DiscordState { relayed_pins }).ok()
+    }
+
+    fn import_state(&mut self, state: Value) -> FitterResult<()> {
+        let state = serde_json::from_value::<DiscordState>(state)?;
+        self.relayed_pins
+            .lock()
+            .unwrap()
+            .extend(state.relayed_pins.into_iter().map(MessageId));
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     fn run(&mut self) -> Self::FutType {
         info!("Starting Discord client {}", self.get_id());
         let handler = self.handler.take().unwrap();
+        let mut send_failures = self.send_failures.take().unwrap();
         let token = self.token.clone();
+        let digest = handler
+            .chat_digest
+            .clone()
+            .map(|digest| (digest, handler.outer_tx.clone()));
 
         FutureObj::new(Box::new(async move {
-            let mut client = Client::builder(token).event_handler(handler).await?;
+            let mut client = Client::builder(token.expose())
+                .event_handler(handler)
+                .await
+                .map_err(auth_error)?;
 
-            client.start().await?;
-            Ok(())
+            // Sends failing with `SendErrorStrategy::Fail` stop the client.
+            let shard_manager = Arc::clone(&client.shard_manager);
+            let failed = async {
+                let err = send_failures.recv().await;
+                shard_manager.lock().await.shutdown_all().await;
+                err
+            };
+
+            match digest {
+                Some((digest, outer_tx)) => {
+                    let result = tokio::select! {
+                        result = client.start() => result.map_err(auth_error),
+                        Some(err) = failed => Err(err),
+                        _ = digest_loop(Arc::clone(&digest), outer_tx.clone()) => Ok(()),
+                    };
+
+                    // Send what's left on shutdown.
+                    digest.flush(&outer_tx).await;
+                    result
+                }
+                None => tokio::select! {
+                    result = client.start() => result.map_err(auth_error),
+                    Some(err) = failed => Err(err),
+                },
+            }
         }))
     }
 }
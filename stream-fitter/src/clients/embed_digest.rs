@@ -0,0 +1,189 @@
+//! Batches relayed messages into compact Discord embeds.
+//!
+//! Messages are accumulated per source channel, so each embed is attributed to a single
+//! channel, and flushed once enough lines or time have accumulated.
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+use serenity::builder::CreateEmbed;
+
+use crate::clients::client::Message;
+
+/// Default number of lines accumulated before a digest is flushed.
+const DEFAULT_MAX_LINES: usize = 10;
+/// Default seconds a digest accumulates before it is flushed.
+const DEFAULT_MAX_AGE_SECONDS: u64 = 20;
+/// Default digest title, see `EmbedDigestConfig::title_template`.
+const DEFAULT_TITLE_TEMPLATE: &str = "{client} #{channel}";
+/// Maximum number of characters of an embed title.
+const EMBED_TITLE_LIMIT: usize = 256;
+/// Maximum number of characters of an embed description.
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+
+/// Config struct for batching relayed messages into embeds.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct EmbedDigestConfig {
+    /// Lines accumulated before a digest is flushed.
+    pub max_lines: Option<usize>,
+    /// Seconds a digest accumulates before it is flushed.
+    pub max_age_seconds: Option<u64>,
+    /// Embed color as an RGB integer.
+    pub color: Option<u32>,
+    /// Embed title, `{client}` and `{channel}` are replaced by the message source.
+    pub title_template: Option<String>,
+}
+
+/// Lines accumulated from a single source channel.
+pub struct DigestBatch {
+    client: String,
+    channel: String,
+    target_channel: Option<String>,
+    lines: Vec<String>,
+    started: Instant,
+}
+
+impl DigestBatch {
+    /// Checks whether the digest should be delivered to a channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel ID.
+    pub fn is_for_channel(&self, channel: &str) -> bool {
+        match &self.target_channel {
+            Some(target) => target == channel,
+            None => true,
+        }
+    }
+}
+
+/// Accumulator of digests for every source channel.
+pub struct EmbedDigest {
+    max_lines: usize,
+    max_age: Duration,
+    color: Option<u32>,
+    title_template: String,
+    batches: Vec<DigestBatch>,
+}
+
+impl EmbedDigest {
+    /// Creates an empty accumulator.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The embed digest config.
+    pub fn new(config: &EmbedDigestConfig) -> Self {
+        EmbedDigest {
+            max_lines: config.max_lines.unwrap_or(DEFAULT_MAX_LINES).max(1),
+            max_age: Duration::from_secs(config.max_age_seconds.unwrap_or(DEFAULT_MAX_AGE_SECONDS)),
+            color: config.color,
+            title_template: config
+                .title_template
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TITLE_TEMPLATE.to_string()),
+            batches: Vec::new(),
+        }
+    }
+
+    /// Adds a message to its source's digest, returning the digest if it is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The relayed message.
+    pub fn push(&mut self, msg: &Message) -> Option<DigestBatch> {
+        let line = format!("**{}** {}", msg.get_author(), msg.get_content());
+        let target_channel = msg.get_target_channel().map(str::to_string);
+        let idx = match self.batches.iter().position(|batch| {
+            batch.client == msg.get_client()
+                && batch.channel == msg.get_channel()
+                && batch.target_channel == target_channel
+        }) {
+            Some(idx) => idx,
+            None => {
+                self.batches.push(DigestBatch {
+                    client: msg.get_client().to_string(),
+                    channel: msg.get_channel().to_string(),
+                    target_channel,
+                    lines: Vec::new(),
+                    started: Instant::now(),
+                });
+                self.batches.len() - 1
+            }
+        };
+
+        self.batches[idx].lines.push(line);
+        if self.batches[idx].lines.len() >= self.max_lines {
+            Some(self.batches.remove(idx))
+        } else {
+            None
+        }
+    }
+
+    /// Removes the digests that have accumulated for too long.
+    pub fn take_expired(&mut self) -> Vec<DigestBatch> {
+        let max_age = self.max_age;
+        let (expired, kept) = self
+            .batches
+            .drain(..)
+            .partition(|batch| batch.started.elapsed() >= max_age);
+        self.batches = kept;
+        expired
+    }
+
+    /// Removes all digests.
+    pub fn take_all(&mut self) -> Vec<DigestBatch> {
+        self.batches.drain(..).collect()
+    }
+
+    /// Builds the embeds for a digest, split to respect Discord's embed limits.
+    ///
+    /// Lines go in the description rather than fields, so the field limit never applies.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - The digest to build embeds for.
+    pub fn to_embeds(&self, batch: &DigestBatch) -> Vec<CreateEmbed> {
+        let title = truncate(
+            &self
+                .title_template
+                .replace("{client}", &batch.client)
+                .replace("{channel}", &batch.channel),
+            EMBED_TITLE_LIMIT,
+        );
+
+        let mut descriptions = vec![String::new()];
+        for line in &batch.lines {
+            let line = truncate(line, EMBED_DESCRIPTION_LIMIT);
+            let description = descriptions.last_mut().unwrap();
+            if description.is_empty() {
+                description.push_str(&line);
+            } else if description.chars().count() + line.chars().count() < EMBED_DESCRIPTION_LIMIT {
+                description.push('\n');
+                description.push_str(&line);
+            } else {
+                descriptions.push(line);
+            }
+        }
+
+        descriptions
+            .into_iter()
+            .map(|description| {
+                let mut embed = CreateEmbed::default();
+                embed.title(&title).description(description);
+                if let Some(color) = self.color {
+                    embed.color(color);
+                }
+                embed
+            })
+            .collect()
+    }
+}
+
+/// Truncates text to a maximum number of characters.
+///
+/// # Arguments
+///
+/// * `text` - The text to truncate.
+/// * `limit` - The maximum number of characters.
+fn truncate(text: &str, limit: usize) -> String {
+    text.chars().take(limit).collect()
+}
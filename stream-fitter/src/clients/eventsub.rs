@@ -0,0 +1,261 @@
+//! Twitch EventSub websocket client, receiving a stream going live or offline as it happens.
+//!
+//! A session subscribes to the events once welcomed. When Twitch asks to reconnect, the
+//! subscriptions carry over to the new session, otherwise a lost session is replaced by a new
+//! one subscribing again.
+use std::{
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_tungstenite::{tokio::connect_async, tungstenite::Message as WsMessage};
+use futures::StreamExt;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    clients::helix::HelixClient,
+    errors::{FitterErrorKind, FitterResult},
+    secret::Secret,
+    util::backoff::Backoff,
+};
+
+/// EventSub websocket endpoint.
+const EVENTSUB_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+/// Subscription types for a stream going live and offline.
+const STREAM_ONLINE: &str = "stream.online";
+const STREAM_OFFLINE: &str = "stream.offline";
+/// Seconds to wait for a message before the welcome sets the keepalive timeout.
+const DEFAULT_KEEPALIVE_SECONDS: u64 = 10;
+/// Time added to the keepalive timeout before the session is considered lost.
+const KEEPALIVE_MARGIN: Duration = Duration::from_secs(5);
+
+/// A stream going live or offline.
+pub(crate) struct StreamEvent {
+    pub(crate) live: bool,
+    pub(crate) broadcaster_login: String,
+    pub(crate) broadcaster_name: String,
+}
+
+/// Message received from an EventSub session.
+#[derive(Deserialize)]
+struct EventSubMessage {
+    metadata: EventSubMetadata,
+    payload: Value,
+}
+
+/// Metadata of a message received from an EventSub session.
+#[derive(Deserialize)]
+struct EventSubMetadata {
+    message_type: String,
+    #[serde(default)]
+    subscription_type: Option<String>,
+}
+
+/// Payload of welcome and reconnect messages.
+#[derive(Deserialize)]
+struct SessionPayload {
+    session: EventSubSession,
+}
+
+/// An EventSub session.
+#[derive(Deserialize)]
+struct EventSubSession {
+    id: String,
+    #[serde(default)]
+    keepalive_timeout_seconds: Option<u64>,
+    #[serde(default)]
+    reconnect_url: Option<String>,
+}
+
+/// Payload of notification messages.
+#[derive(Deserialize)]
+struct NotificationPayload {
+    event: StreamEventPayload,
+}
+
+/// Event of `stream.online` and `stream.offline` notifications.
+#[derive(Deserialize)]
+struct StreamEventPayload {
+    broadcaster_user_login: String,
+    broadcaster_user_name: String,
+}
+
+/// How a session ended.
+enum SessionEnd {
+    /// Twitch asked to move to a new session, keeping the subscriptions.
+    Reconnect(String),
+    /// The session was lost, a new one needs to subscribe again.
+    Lost,
+}
+
+/// An EventSub websocket client for a broadcaster's stream events.
+struct EventSub {
+    broadcaster_id: String,
+    client: Arc<StdMutex<HelixClient>>,
+    user_token: Secret,
+    events: UnboundedSender<StreamEvent>,
+}
+
+impl EventSub {
+    /// Subscribes a session to the stream going live and offline.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - The session's ID.
+    async fn subscribe(&self, session_id: &str) -> FitterResult<()> {
+        for kind in [STREAM_ONLINE, STREAM_OFFLINE] {
+            let client = Arc::clone(&self.client);
+            let broadcaster_id = self.broadcaster_id.clone();
+            let session_id = session_id.to_string();
+            let user_token = self.user_token.clone();
+            tokio::task::spawn_blocking(move || {
+                client.lock().unwrap().create_eventsub_subscription(
+                    kind,
+                    &broadcaster_id,
+                    &session_id,
+                    &user_token,
+                )
+            })
+            .await
+            .map_err(|err| FitterErrorKind::InternalErr(err.to_string()))??;
+            debug!("Subscribed to {}", kind);
+        }
+        Ok(())
+    }
+
+    /// Runs a session until it ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The session's URL.
+    /// * `subscribe` - Whether to subscribe once welcomed, false when reconnecting.
+    /// * `welcomed` - Called once the session is welcomed.
+    async fn run_session(
+        &self,
+        url: &str,
+        subscribe: bool,
+        mut welcomed: impl FnMut(),
+    ) -> FitterResult<SessionEnd> {
+        let (mut ws, _) = connect_async(url).await?;
+        let mut keepalive = Duration::from_secs(DEFAULT_KEEPALIVE_SECONDS) + KEEPALIVE_MARGIN;
+
+        loop {
+            let text = match tokio::time::timeout(keepalive, ws.next()).await {
+                Ok(Some(Ok(WsMessage::Text(text)))) => text,
+                Ok(Some(Ok(WsMessage::Close(frame)))) => {
+                    warn!("EventSub session closed: {:?}", frame);
+                    return Ok(SessionEnd::Lost);
+                }
+                Ok(Some(Ok(_))) => continue,
+                Ok(Some(Err(err))) => return Err(err.into()),
+                Ok(None) => return Ok(SessionEnd::Lost),
+                Err(_) => {
+                    warn!("EventSub keepalive timed out");
+                    return Ok(SessionEnd::Lost);
+                }
+            };
+
+            let msg = serde_json::from_str::<EventSubMessage>(&text)?;
+            match msg.metadata.message_type.as_str() {
+                "session_welcome" => {
+                    let session = serde_json::from_value::<SessionPayload>(msg.payload)?.session;
+                    info!("EventSub session started: {}", session.id);
+                    if let Some(seconds) = session.keepalive_timeout_seconds {
+                        keepalive = Duration::from_secs(seconds) + KEEPALIVE_MARGIN;
+                    }
+                    if subscribe {
+                        self.subscribe(&session.id).await?;
+                    }
+                    welcomed();
+                }
+                "session_keepalive" => debug!("EventSub keepalive"),
+                "session_reconnect" => {
+                    let session = serde_json::from_value::<SessionPayload>(msg.payload)?.session;
+                    return match session.reconnect_url {
+                        Some(url) => Ok(SessionEnd::Reconnect(url)),
+                        None => Ok(SessionEnd::Lost),
+                    };
+                }
+                "notification" => {
+                    let live = match msg.metadata.subscription_type.as_deref() {
+                        Some(STREAM_ONLINE) => true,
+                        Some(STREAM_OFFLINE) => false,
+                        kind => {
+                            debug!("Ignoring EventSub notification: {:?}", kind);
+                            continue;
+                        }
+                    };
+                    let event = serde_json::from_value::<NotificationPayload>(msg.payload)?.event;
+                    let _ = self.events.send(StreamEvent {
+                        live,
+                        broadcaster_login: event.broadcaster_user_login,
+                        broadcaster_name: event.broadcaster_user_name,
+                    });
+                }
+                "revocation" => warn!(
+                    "EventSub subscription revoked: {:?}",
+                    msg.metadata.subscription_type
+                ),
+                message_type => debug!("Ignoring EventSub message: {}", message_type),
+            }
+        }
+    }
+}
+
+/// Loop receiving a stream going live and offline, reconnecting as needed.
+///
+/// # Arguments
+///
+/// * `broadcaster_id` - The broadcaster's numeric ID.
+/// * `client` - The Helix API client, to subscribe with.
+/// * `user_token` - A user access token of the Helix API client's application.
+/// * `events` - The TX channel of received events.
+#[instrument(skip(client, user_token, events))]
+pub(crate) async fn eventsub_loop(
+    broadcaster_id: String,
+    client: Arc<StdMutex<HelixClient>>,
+    user_token: Secret,
+    events: UnboundedSender<StreamEvent>,
+) {
+    let eventsub = EventSub {
+        broadcaster_id,
+        client,
+        user_token,
+        events,
+    };
+    let backoff = Backoff::new();
+    let mut delays = backoff.delays();
+    let mut url = EVENTSUB_URL.to_string();
+    let mut subscribe = true;
+
+    loop {
+        let mut welcomed = false;
+        match eventsub
+            .run_session(&url, subscribe, || welcomed = true)
+            .await
+        {
+            Ok(SessionEnd::Reconnect(reconnect_url)) => {
+                info!("EventSub asked to reconnect");
+                url = reconnect_url;
+                subscribe = false;
+                continue;
+            }
+            Ok(SessionEnd::Lost) => (),
+            Err(err) => error!("EventSub session error: {}", err),
+        }
+
+        // Start over with a new session.
+        url = EVENTSUB_URL.to_string();
+        subscribe = true;
+        if welcomed {
+            delays = backoff.delays();
+        }
+        if let Some(delay) = delays.next() {
+            info!("Reconnecting to EventSub in {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
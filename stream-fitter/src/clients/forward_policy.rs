@@ -0,0 +1,231 @@
+//! Platform agnostic decisions on whether and where clients forward the messages they receive.
+//!
+//! Clients describe received messages as `IncomingMeta`, so the decisions don't depend on
+//! platform types.
+use std::collections::HashSet;
+
+/// Description of a message received by a client.
+#[derive(Clone, Copy, Debug)]
+pub struct IncomingMeta<'a> {
+    /// The message's author.
+    pub author: &'a str,
+    /// Whether the author is a bot, including the client's own accounts.
+    pub author_is_bot: bool,
+    /// The channel the message was received in.
+    pub channel: &'a str,
+    /// The message's content.
+    pub content: &'a str,
+}
+
+/// Why a received message isn't forwarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IgnoreReason {
+    /// The author is a bot.
+    Bot,
+    /// The channel isn't handled by the client.
+    UnknownChannel,
+    /// The author is on the client's ignore list.
+    IgnoredAuthor,
+}
+
+/// What to do with a received message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardDecision {
+    /// Don't forward the message.
+    Ignore(IgnoreReason),
+    /// Forward the message to other clients.
+    Forward {
+        /// Also forward to the client's other channels.
+        to_other_channels: bool,
+    },
+}
+
+/// Policy deciding whether and where a client forwards the messages it receives.
+#[derive(Clone, Debug)]
+pub struct ForwardPolicy {
+    channels: HashSet<String>,
+    isolate_channels: bool,
+    ignored_authors: HashSet<String>,
+}
+
+impl ForwardPolicy {
+    /// Creates a policy forwarding messages from a client's channels.
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The channels handled by the client.
+    pub fn new<I: IntoIterator<Item = String>>(channels: I) -> Self {
+        ForwardPolicy {
+            channels: channels.into_iter().collect(),
+            isolate_channels: false,
+            ignored_authors: HashSet::new(),
+        }
+    }
+
+    /// Sets whether messages are kept from the client's other channels.
+    ///
+    /// # Arguments
+    ///
+    /// * `isolate_channels` - Don't forward between channels.
+    pub fn with_isolate_channels(mut self, isolate_channels: bool) -> Self {
+        self.isolate_channels = isolate_channels;
+        self
+    }
+
+    /// Changes whether messages are kept from the client's other channels, e.g. on a config
+    /// change.
+    ///
+    /// # Arguments
+    ///
+    /// * `isolate_channels` - Don't forward between channels.
+    pub fn set_isolate_channels(&mut self, isolate_channels: bool) {
+        self.isolate_channels = isolate_channels;
+    }
+
+    /// Sets the authors whose messages aren't forwarded, matched ignoring case.
+    ///
+    /// # Arguments
+    ///
+    /// * `authors` - The ignored authors' usernames on the platform.
+    pub fn with_ignored_authors<'a, I: IntoIterator<Item = &'a String>>(
+        mut self,
+        authors: I,
+    ) -> Self {
+        self.set_ignored_authors(authors);
+        self
+    }
+
+    /// Changes the authors whose messages aren't forwarded, e.g. on a config change.
+    ///
+    /// # Arguments
+    ///
+    /// * `authors` - The ignored authors' usernames on the platform.
+    pub fn set_ignored_authors<'a, I: IntoIterator<Item = &'a String>>(&mut self, authors: I) {
+        self.ignored_authors = authors
+            .into_iter()
+            .map(|author| author.to_lowercase())
+            .collect();
+    }
+
+    /// Decides what to do with a received message.
+    ///
+    /// # Arguments
+    ///
+    /// * `incoming` - The received message.
+    pub fn decide(&self, incoming: &IncomingMeta) -> ForwardDecision {
+        // Only forward if it's not a bot message.
+        if incoming.author_is_bot {
+            return ForwardDecision::Ignore(IgnoreReason::Bot);
+        }
+
+        // Only forward if it's coming from a channel the client is handling.
+        if !self.channels.contains(incoming.channel) {
+            return ForwardDecision::Ignore(IgnoreReason::UnknownChannel);
+        }
+
+        // Only forward if the author isn't ignored.
+        if self
+            .ignored_authors
+            .contains(&incoming.author.to_lowercase())
+        {
+            return ForwardDecision::Ignore(IgnoreReason::IgnoredAuthor);
+        }
+
+        ForwardDecision::Forward {
+            to_other_channels: !self.isolate_channels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Channel handled by the tested policies.
+    const CHANNEL: &str = "handled";
+    /// Author on the ignore list of the tested policies.
+    const IGNORED: &str = "Ignored_User";
+
+    /// Builds a policy handling `CHANNEL` and ignoring `IGNORED`.
+    ///
+    /// # Arguments
+    ///
+    /// * `isolate_channels` - Don't forward between channels.
+    fn policy(isolate_channels: bool) -> ForwardPolicy {
+        ForwardPolicy::new(vec![CHANNEL.to_string(), "other".to_string()])
+            .with_isolate_channels(isolate_channels)
+            .with_ignored_authors(&[IGNORED.to_string()])
+    }
+
+    #[test]
+    fn decides_every_flag_combination() {
+        for author_is_bot in [false, true] {
+            for known_channel in [false, true] {
+                for isolate_channels in [false, true] {
+                    for ignored in [false, true] {
+                        let incoming = IncomingMeta {
+                            author: if ignored { IGNORED } else { "viewer" },
+                            author_is_bot,
+                            channel: if known_channel { CHANNEL } else { "unknown" },
+                            content: "hello",
+                        };
+                        let expected = if author_is_bot {
+                            ForwardDecision::Ignore(IgnoreReason::Bot)
+                        } else if !known_channel {
+                            ForwardDecision::Ignore(IgnoreReason::UnknownChannel)
+                        } else if ignored {
+                            ForwardDecision::Ignore(IgnoreReason::IgnoredAuthor)
+                        } else {
+                            ForwardDecision::Forward {
+                                to_other_channels: !isolate_channels,
+                            }
+                        };
+                        assert_eq!(
+                            policy(isolate_channels).decide(&incoming),
+                            expected,
+                            "bot={} known_channel={} isolate={} ignored={}",
+                            author_is_bot,
+                            known_channel,
+                            isolate_channels,
+                            ignored
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_ignored_authors_ignoring_case() {
+        let incoming = IncomingMeta {
+            author: "IGNORED_user",
+            author_is_bot: false,
+            channel: CHANNEL,
+            content: "hello",
+        };
+        assert_eq!(
+            policy(false).decide(&incoming),
+            ForwardDecision::Ignore(IgnoreReason::IgnoredAuthor)
+        );
+    }
+
+    #[test]
+    fn applies_setting_changes() {
+        let mut policy = policy(false);
+        let incoming = IncomingMeta {
+            author: IGNORED,
+            author_is_bot: false,
+            channel: CHANNEL,
+            content: "hello",
+        };
+
+        policy.set_ignored_authors(&[]);
+        policy.set_isolate_channels(true);
+        assert_eq!(
+            policy.decide(&incoming),
+            ForwardDecision::Forward {
+                to_other_channels: false
+            }
+        );
+    }
+}
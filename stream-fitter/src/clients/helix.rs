@@ -0,0 +1,532 @@
+//! Minimal Twitch Helix API client, used to look up users, streams and channels, to send chat
+//! announcements and to subscribe to EventSub events.
+//!
+//! Requests are blocking, so they are kept out of the message loops: avatars are looked up in
+//! batches by a background loop and cached for later messages.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex as StdMutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    clients::twitch::TwitchHelixConfig,
+    errors::{FitterError, FitterErrorKind, FitterResult},
+    secret::Secret,
+};
+
+/// Twitch OAuth endpoint issuing app access tokens.
+const TWITCH_TOKEN_URL: &str = "https://id.twitch.tv/oauth2/token";
+/// Twitch OAuth endpoint validating access tokens.
+const TWITCH_VALIDATE_URL: &str = "https://id.twitch.tv/oauth2/validate";
+/// Helix API endpoint to look up users.
+const HELIX_USERS_URL: &str = "https://api.twitch.tv/helix/users";
+/// Helix API endpoint to look up live streams.
+const HELIX_STREAMS_URL: &str = "https://api.twitch.tv/helix/streams";
+/// Helix API endpoint to look up channel information.
+const HELIX_CHANNELS_URL: &str = "https://api.twitch.tv/helix/channels";
+/// Helix API endpoint to send chat announcements.
+const HELIX_ANNOUNCEMENTS_URL: &str = "https://api.twitch.tv/helix/chat/announcements";
+/// Helix API endpoint to subscribe to EventSub events.
+const HELIX_EVENTSUB_URL: &str = "https://api.twitch.tv/helix/eventsub/subscriptions";
+/// Maximum number of characters of a chat announcement.
+const ANNOUNCEMENT_LIMIT: usize = 500;
+/// Maximum number of users per Helix users lookup.
+const HELIX_USERS_PER_REQUEST: usize = 100;
+/// Default seconds a looked up avatar is cached.
+const DEFAULT_AVATAR_TTL_SECONDS: u64 = 3600;
+/// Time to wait for more users to look up before sending a batch.
+const AVATAR_BATCH_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait for the Helix rate limit to reset, in case of a skewed clock.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// App access token issued by Twitch.
+#[derive(Deserialize)]
+struct HelixToken {
+    access_token: String,
+}
+
+/// Access token validated by Twitch.
+#[derive(Deserialize)]
+struct TokenValidation {
+    #[serde(default)]
+    login: Option<String>,
+}
+
+/// Helix lookup response.
+#[derive(Deserialize)]
+struct HelixData<T> {
+    data: Vec<T>,
+}
+
+/// A user returned by Helix.
+#[derive(Deserialize)]
+pub(crate) struct HelixUser {
+    pub(crate) id: String,
+    pub(crate) login: String,
+    #[serde(default)]
+    pub(crate) profile_image_url: String,
+}
+
+/// A live stream returned by Helix.
+#[derive(Deserialize)]
+pub(crate) struct HelixStream {
+    #[serde(default)]
+    pub(crate) title: String,
+}
+
+/// A channel's information returned by Helix.
+#[derive(Deserialize)]
+pub(crate) struct HelixChannel {
+    pub(crate) broadcaster_login: String,
+    pub(crate) broadcaster_name: String,
+    #[serde(default)]
+    pub(crate) title: String,
+}
+
+/// Chat announcement sent to Helix.
+#[derive(Serialize)]
+struct HelixAnnouncement {
+    message: String,
+}
+
+/// EventSub subscription sent to Helix.
+#[derive(Serialize)]
+struct HelixEventSubSubscription<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    version: &'a str,
+    condition: HelixEventSubCondition<'a>,
+    transport: HelixEventSubTransport<'a>,
+}
+
+/// Condition of an EventSub subscription.
+#[derive(Serialize)]
+struct HelixEventSubCondition<'a> {
+    broadcaster_user_id: &'a str,
+}
+
+/// Transport of an EventSub subscription.
+#[derive(Serialize)]
+struct HelixEventSubTransport<'a> {
+    method: &'a str,
+    session_id: &'a str,
+}
+
+/// Key to look Helix users up by.
+#[derive(Clone, Copy)]
+pub(crate) enum HelixUserKey {
+    /// The user's numeric ID.
+    Id,
+    /// The user's login name.
+    Login,
+}
+
+/// Helix API client authenticated with an app access token.
+pub(crate) struct HelixClient {
+    client_id: String,
+    client_secret: Secret,
+    token: String,
+    rate_limit_reset: Option<SystemTime>,
+}
+
+impl HelixClient {
+    /// Creates a client, requesting an app access token.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Helix API config.
+    pub(crate) fn new(config: &TwitchHelixConfig) -> FitterResult<Self> {
+        let mut client = HelixClient {
+            client_id: config.client_id.clone(),
+            client_secret: config.client_secret.clone().resolve()?,
+            token: String::new(),
+            rate_limit_reset: None,
+        };
+        client.refresh_token()?;
+        Ok(client)
+    }
+
+    /// Requests a new app access token.
+    fn refresh_token(&mut self) -> FitterResult<()> {
+        self.token = ureq::post(TWITCH_TOKEN_URL)
+            .send_form(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.expose()),
+                ("grant_type", "client_credentials"),
+            ])?
+            .into_json::<HelixToken>()?
+            .access_token;
+        Ok(())
+    }
+
+    /// Looks users up, batching requests and renewing an expired token.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - What the users are identified by.
+    /// * `values` - The IDs or login names of the users.
+    pub(crate) fn get_users(
+        &mut self,
+        key: HelixUserKey,
+        values: &[String],
+    ) -> FitterResult<Vec<HelixUser>> {
+        let param = match key {
+            HelixUserKey::Id => "id",
+            HelixUserKey::Login => "login",
+        };
+
+        let mut users = Vec::new();
+        for batch in values.chunks(HELIX_USERS_PER_REQUEST) {
+            users.extend(self.get(HELIX_USERS_URL, param, batch)?);
+        }
+        Ok(users)
+    }
+
+    /// Looks a user's live stream up.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The broadcaster's numeric ID.
+    pub(crate) fn get_stream(&mut self, user_id: &str) -> FitterResult<Option<HelixStream>> {
+        let streams = self.get(HELIX_STREAMS_URL, "user_id", &[user_id.to_string()])?;
+        Ok(streams.into_iter().next())
+    }
+
+    /// Looks a channel's information up.
+    ///
+    /// # Arguments
+    ///
+    /// * `broadcaster_id` - The broadcaster's numeric ID.
+    pub(crate) fn get_channel(
+        &mut self,
+        broadcaster_id: &str,
+    ) -> FitterResult<Option<HelixChannel>> {
+        let channels = self.get(
+            HELIX_CHANNELS_URL,
+            "broadcaster_id",
+            &[broadcaster_id.to_string()],
+        )?;
+        Ok(channels.into_iter().next())
+    }
+
+    /// Sends a chat announcement as a moderator of a channel.
+    ///
+    /// Announcements are sent with the moderator's user access token, which needs the
+    /// `moderator:manage:announcements` scope, instead of the app access token.
+    ///
+    /// # Arguments
+    ///
+    /// * `broadcaster_id` - The channel's numeric ID.
+    /// * `moderator_id` - The moderator's numeric ID.
+    /// * `moderator_token` - The moderator's user access token.
+    /// * `message` - The announcement's text.
+    pub(crate) fn send_announcement(
+        &self,
+        broadcaster_id: &str,
+        moderator_id: &str,
+        moderator_token: &Secret,
+        message: &str,
+    ) -> FitterResult<()> {
+        let token = moderator_token.expose();
+        ureq::post(HELIX_ANNOUNCEMENTS_URL)
+            .set("Client-Id", &self.client_id)
+            .set(
+                "Authorization",
+                &format!("Bearer {}", token.strip_prefix("oauth:").unwrap_or(token)),
+            )
+            .query("broadcaster_id", broadcaster_id)
+            .query("moderator_id", moderator_id)
+            .send_json(HelixAnnouncement {
+                message: message.chars().take(ANNOUNCEMENT_LIMIT).collect(),
+            })?;
+        Ok(())
+    }
+
+    /// Subscribes an EventSub websocket session to a broadcaster's events.
+    ///
+    /// Websocket subscriptions are created with a user access token instead of the app access
+    /// token.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The subscription type, e.g. `stream.online`.
+    /// * `broadcaster_id` - The broadcaster's numeric ID.
+    /// * `session_id` - The websocket session's ID.
+    /// * `user_token` - A user access token of the same application.
+    pub(crate) fn create_eventsub_subscription(
+        &self,
+        kind: &str,
+        broadcaster_id: &str,
+        session_id: &str,
+        user_token: &Secret,
+    ) -> FitterResult<()> {
+        let token = user_token.expose();
+        ureq::post(HELIX_EVENTSUB_URL)
+            .set("Client-Id", &self.client_id)
+            .set(
+                "Authorization",
+                &format!("Bearer {}", token.strip_prefix("oauth:").unwrap_or(token)),
+            )
+            .send_json(HelixEventSubSubscription {
+                kind,
+                version: "1",
+                condition: HelixEventSubCondition {
+                    broadcaster_user_id: broadcaster_id,
+                },
+                transport: HelixEventSubTransport {
+                    method: "websocket",
+                    session_id,
+                },
+            })?;
+        Ok(())
+    }
+
+    /// Sends a lookup, renewing an expired token and waiting out the rate limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The Helix API endpoint.
+    /// * `param` - The query parameter identifying what to look up.
+    /// * `values` - The values of the query parameter.
+    fn get<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        param: &str,
+        values: &[String],
+    ) -> FitterResult<Vec<T>> {
+        self.wait_rate_limit();
+        let response = match self.request(url, param, values) {
+            Err(err) if matches!(*err, ureq::Error::Status(401, _)) => {
+                info!("Helix token expired, renewing it");
+                self.refresh_token()?;
+                self.request(url, param, values)
+            }
+            Err(err) if matches!(*err, ureq::Error::Status(429, _)) => {
+                if let ureq::Error::Status(_, response) = *err {
+                    self.track_rate_limit(&response);
+                }
+                self.wait_rate_limit();
+                self.request(url, param, values)
+            }
+            response => response,
+        };
+        let response = response?;
+        self.track_rate_limit(&response);
+        Ok(response.into_json::<HelixData<T>>()?.data)
+    }
+
+    /// Remembers when the rate limit resets if a response used it up.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The Helix API response, with its rate limit headers.
+    fn track_rate_limit(&mut self, response: &ureq::Response) {
+        let exhausted =
+            response.status() == 429 || response.header("Ratelimit-Remaining") == Some("0");
+        self.rate_limit_reset = response
+            .header("Ratelimit-Reset")
+            .and_then(|reset| reset.parse().ok())
+            .filter(|_| exhausted)
+            .map(|reset| UNIX_EPOCH + Duration::from_secs(reset));
+    }
+
+    /// Waits for the rate limit to reset if it was used up.
+    fn wait_rate_limit(&mut self) {
+        let wait = self
+            .rate_limit_reset
+            .take()
+            .and_then(|reset| reset.duration_since(SystemTime::now()).ok());
+        if let Some(wait) = wait {
+            let wait = wait.min(MAX_RATE_LIMIT_WAIT);
+            warn!("Helix rate limit reached, waiting {:?}", wait);
+            thread::sleep(wait);
+        }
+    }
+
+    /// Sends a single lookup.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The Helix API endpoint.
+    /// * `param` - The query parameter identifying what to look up.
+    /// * `values` - The values of the query parameter.
+    fn request(
+        &self,
+        url: &str,
+        param: &str,
+        values: &[String],
+    ) -> Result<ureq::Response, Box<ureq::Error>> {
+        values
+            .iter()
+            .fold(
+                ureq::get(url)
+                    .set("Client-Id", &self.client_id)
+                    .set("Authorization", &format!("Bearer {}", self.token)),
+                |request, value| request.query(param, value),
+            )
+            .call()
+            .map_err(Box::new)
+    }
+}
+
+/// Validates a user access token, returning the login name of its user.
+///
+/// # Arguments
+///
+/// * `token` - The user access token, with or without the `oauth:` prefix.
+pub(crate) fn validate_token(token: &Secret) -> FitterResult<String> {
+    let token = token.expose();
+    let validation = ureq::get(TWITCH_VALIDATE_URL)
+        .set(
+            "Authorization",
+            &format!("OAuth {}", token.strip_prefix("oauth:").unwrap_or(token)),
+        )
+        .call()
+        .map_err(|err| match err {
+            ureq::Error::Status(401, _) => FitterErrorKind::AuthErr("Twitch".to_string()).into(),
+            err => FitterError::from(err),
+        })?
+        .into_json::<TokenValidation>()?;
+    validation.login.ok_or_else(|| {
+        FitterErrorKind::GenericErr("Twitch token isn't a user access token".to_string()).into()
+    })
+}
+
+/// Cache of user avatars, looked up in the background on misses.
+pub(crate) struct AvatarCache {
+    ttl: Duration,
+    avatars: StdMutex<HashMap<String, (Option<String>, Instant)>>,
+    pending: StdMutex<HashSet<String>>,
+    lookups: UnboundedSender<String>,
+}
+
+impl AvatarCache {
+    /// Creates an empty cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Helix API config.
+    /// * `lookups` - The TX channel of the lookup loop.
+    pub(crate) fn new(config: &TwitchHelixConfig, lookups: UnboundedSender<String>) -> Self {
+        AvatarCache {
+            ttl: Duration::from_secs(
+                config
+                    .avatar_ttl_seconds
+                    .unwrap_or(DEFAULT_AVATAR_TTL_SECONDS),
+            ),
+            avatars: StdMutex::new(HashMap::new()),
+            pending: StdMutex::new(HashSet::new()),
+            lookups,
+        }
+    }
+
+    /// Gets a user's avatar URL, queuing a lookup if it isn't cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user's numeric ID.
+    pub(crate) fn get(&self, user_id: &str) -> Option<String> {
+        if let Some((avatar_url, fetched)) = self.avatars.lock().unwrap().get(user_id) {
+            if fetched.elapsed() < self.ttl {
+                return avatar_url.clone();
+            }
+        }
+
+        if self.pending.lock().unwrap().insert(user_id.to_string()) {
+            if let Err(err) = self.lookups.send(user_id.to_string()) {
+                error!("Error queuing avatar lookup: {:?}", err);
+            }
+        }
+        None
+    }
+
+    /// Stores looked up avatars, dropping expired ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The looked up users' IDs.
+    /// * `users` - The users found by Helix.
+    fn insert(&self, user_ids: &[String], users: Vec<HelixUser>) {
+        let now = Instant::now();
+        let ttl = self.ttl;
+        let mut avatars = self.avatars.lock().unwrap();
+        avatars.retain(|_, (_, fetched)| fetched.elapsed() < ttl);
+
+        // Users missing from the response are cached too, so they aren't looked up again.
+        for user_id in user_ids {
+            avatars.insert(user_id.clone(), (None, now));
+        }
+        for user in users {
+            let avatar_url = Some(user.profile_image_url).filter(|url| !url.is_empty());
+            avatars.insert(user.id, (avatar_url, now));
+        }
+
+        self.clear_pending(user_ids);
+    }
+
+    /// Allows users to be looked up again.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The users' IDs.
+    fn clear_pending(&self, user_ids: &[String]) {
+        let mut pending = self.pending.lock().unwrap();
+        for user_id in user_ids {
+            pending.remove(user_id);
+        }
+    }
+}
+
+/// Loop looking up queued avatars in batches.
+///
+/// # Arguments
+///
+/// * `lookups` - The RX channel of queued user IDs.
+/// * `cache` - The cache to store avatars in.
+/// * `client` - The Helix API client.
+#[instrument(skip(lookups, cache, client))]
+pub(crate) async fn avatar_lookup_loop(
+    mut lookups: UnboundedReceiver<String>,
+    cache: Arc<AvatarCache>,
+    client: Arc<StdMutex<HelixClient>>,
+) {
+    while let Some(user_id) = lookups.recv().await {
+        // Give other users a chance to join the batch.
+        tokio::time::sleep(AVATAR_BATCH_DELAY).await;
+        let mut user_ids = vec![user_id];
+        while user_ids.len() < HELIX_USERS_PER_REQUEST {
+            match lookups.try_recv() {
+                Ok(user_id) => user_ids.push(user_id),
+                Err(_) => break,
+            }
+        }
+
+        debug!("Looking up {} avatars", user_ids.len());
+        let client = Arc::clone(&client);
+        let lookup_ids = user_ids.clone();
+        let users = tokio::task::spawn_blocking(move || {
+            client
+                .lock()
+                .unwrap()
+                .get_users(HelixUserKey::Id, &lookup_ids)
+        })
+        .await
+        .map_err(|err| FitterErrorKind::InternalErr(err.to_string()).into())
+        .and_then(|users| users);
+
+        match users {
+            Ok(users) => cache.insert(&user_ids, users),
+            Err(err) => {
+                error!("Error looking up avatars: {}", err);
+                cache.clear_pending(&user_ids);
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Implements an in-memory client for tests and benchmarks.
+//!
+//! Messages are injected and observed through a `MockHandle` instead of a chat platform.
+//! Relayed messages go through the same send queue as the real clients', with a single
+//! channel, and can be held back to build a backlog.
+use std::{
+    iter::once,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use futures::future::join;
+use nanoid::nanoid;
+use tokio::sync::{
+    mpsc::{channel, Receiver, Sender},
+    watch,
+};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    clients::{
+        client::{async_trait, Client, DynClientTrait, Message},
+        send_queue::ChannelQueues,
+    },
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::flush::Flushed,
+    util::catalog::Catalog,
+};
+
+/// Size of the mock client's channels.
+const MOCK_CHANNEL_SIZE: usize = 100;
+
+/// In-memory client struct.
+pub struct MockClient {
+    id: String,
+    name: String,
+    rx: Receiver<Message>,
+    tx: Sender<Message>,
+    outer_tx: Vec<Sender<Message>>,
+    injected: Receiver<Message>,
+    received: Sender<Message>,
+    panics: Receiver<()>,
+    stops: Receiver<()>,
+    held: watch::Receiver<bool>,
+    forwarded: Arc<AtomicUsize>,
+    flushed: Flushed,
+}
+
+/// Handle to drive a mock client.
+pub struct MockHandle {
+    injected: Sender<Message>,
+    received: Receiver<Message>,
+    panics: Sender<()>,
+    stops: Sender<()>,
+    held: watch::Sender<bool>,
+    forwarded: Arc<AtomicUsize>,
+}
+
+impl MockClient {
+    /// Build a mock client and the handle driving it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The client's name.
+    pub fn build(name: &str) -> (Client, MockHandle) {
+        let (tx, rx) = channel(MOCK_CHANNEL_SIZE);
+        let (injected_tx, injected_rx) = channel(MOCK_CHANNEL_SIZE);
+        let (received_tx, received_rx) = channel(MOCK_CHANNEL_SIZE);
+        let (panics_tx, panics_rx) = channel(1);
+        let (stops_tx, stops_rx) = channel(1);
+        let (held_tx, held_rx) = watch::channel(false);
+        let forwarded = Arc::new(AtomicUsize::new(0));
+
+        let client = MockClient {
+            id: nanoid!(),
+            name: name.to_string(),
+            rx,
+            tx,
+            outer_tx: Vec::new(),
+            injected: injected_rx,
+            received: received_tx,
+            panics: panics_rx,
+            stops: stops_rx,
+            held: held_rx,
+            forwarded: Arc::clone(&forwarded),
+            flushed: Flushed::new(),
+        };
+        let handle = MockHandle {
+            injected: injected_tx,
+            received: received_rx,
+            panics: panics_tx,
+            stops: stops_tx,
+            held: held_tx,
+            forwarded,
+        };
+        (client.into_client(), handle)
+    }
+}
+
+#[async_trait]
+impl DynClientTrait for MockClient {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_stream(&self) -> FitterResult<Sender<Message>> {
+        Ok(self.tx.clone())
+    }
+
+    fn add_stream(&mut self, stream: Sender<Message>) -> FitterResult<()> {
+        self.outer_tx.push(stream);
+        Ok(())
+    }
+
+    fn set_flushed(&mut self, flushed: Flushed) {
+        self.flushed = flushed;
+    }
+
+    #[instrument(skip(self))]
+    async fn run(&mut self) -> FitterResult<()> {
+        info!("Starting mock client {}", self.id);
+        let MockClient {
+            name,
+            rx,
+            outer_tx,
+            injected,
+            received,
+            panics,
+            stops,
+            held,
+            forwarded,
+            flushed,
+            ..
+        } = self;
+
+        // Both directions are handled independently, so a full channel can't block the other.
+        let external = async {
+            while let Some(msg) = injected.recv().await {
+                // Forward message to all connected streams.
+                for stream in outer_tx.iter() {
+                    stream.send(msg.clone()).await?;
+                }
+                forwarded.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        };
+        let (received, held) = (&*received, &*held);
+        let (queues, workers) = ChannelQueues::new(
+            once(()),
+            1,
+            None,
+            Arc::new(Catalog::default()),
+            |(), msg: Message| async move {
+                let mut held = held.clone();
+                while *held.borrow() {
+                    if held.changed().await.is_err() {
+                        break;
+                    }
+                }
+                if received.send(msg).await.is_err() {
+                    debug!("Handle dropped, discarding message");
+                }
+            },
+        );
+        let flushed = &*flushed;
+        let dispatch = async move {
+            let mut flushing = false;
+            loop {
+                tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Some(msg) => queues.push(&(), msg).await,
+                        None => break,
+                    },
+                    // Nothing more is relayed, so the loop ends once the messages taken are.
+                    () = flushed.flushing(), if !flushing => {
+                        flushing = true;
+                        rx.close();
+                    }
+                }
+            }
+        };
+        let internal = async move {
+            join(dispatch, workers).await;
+            flushed.mark();
+        };
+
+        tokio::select! {
+            (result, ()) = join(external, internal) => result,
+            Some(()) = panics.recv() => panic!("Mock client {} panicked on request", name),
+            Some(()) = stops.recv() => {
+                info!("Mock client {} stopped on request", name);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl MockHandle {
+    /// Injects a message, as if the client received it from its platform.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to inject.
+    pub async fn inject(&self, msg: Message) -> FitterResult<()> {
+        self.injected
+            .send(msg)
+            .await
+            .map_err(|_| FitterErrorKind::GenericErr("Mock client stopped".to_string()).into())
+    }
+
+    /// Gets a TX stream injecting messages, to inject while receiving.
+    pub fn get_injector(&self) -> Sender<Message> {
+        self.injected.clone()
+    }
+
+    /// Makes the client panic, as a client with a bug would.
+    pub async fn panic(&self) -> FitterResult<()> {
+        self.panics
+            .send(())
+            .await
+            .map_err(|_| FitterErrorKind::GenericErr("Mock client stopped".to_string()).into())
+    }
+
+    /// Gets the number of injected messages the client forwarded to its streams.
+    pub fn forwarded(&self) -> usize {
+        self.forwarded.load(Ordering::Relaxed)
+    }
+
+    /// Makes the client stop without an error, dropping its stream as a client that quit
+    /// would.
+    pub async fn stop(&self) -> FitterResult<()> {
+        self.stops
+            .send(())
+            .await
+            .map_err(|_| FitterErrorKind::GenericErr("Mock client stopped".to_string()).into())
+    }
+
+    /// Holds back the delivery of relayed messages, which queue up until released.
+    pub fn hold(&self) {
+        self.held.send(true).ok();
+    }
+
+    /// Resumes the delivery of relayed messages, starting with the queued ones.
+    pub fn release(&self) {
+        self.held.send(false).ok();
+    }
+
+    /// Waits for the next message relayed to the client.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.received.recv().await
+    }
+
+    /// Gets the next message relayed to the client, if one is waiting.
+    pub fn try_recv(&mut self) -> Option<Message> {
+        self.received.try_recv().ok()
+    }
+}
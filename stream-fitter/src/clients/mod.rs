@@ -1,4 +1,23 @@
 //! Clients module.
+pub mod author_group;
+pub mod backfill;
+pub mod broadcast;
+pub mod channel_health;
+pub mod chat_digest;
 pub mod client;
 pub mod discord;
+pub mod embed_digest;
+pub mod eventsub;
+pub mod forward_policy;
+pub mod helix;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod multiline;
+pub mod nats;
+pub mod replay;
+pub mod send_queue;
+pub mod stream_status;
+pub mod timestamp;
 pub mod twitch;
+pub mod twitch_transport;
+pub mod user_alias;
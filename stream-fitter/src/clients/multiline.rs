@@ -0,0 +1,77 @@
+//! Outbound formatting of relayed messages spanning several lines.
+//!
+//! Line-oriented platforms like Twitch can't send a message with newlines, so multi-line
+//! content, e.g. Discord paragraphs, is either split into one message per line or joined into
+//! a single line with a separator. Blank lines are dropped either way.
+use serde_derive::{Deserialize, Serialize};
+
+use crate::clients::client::Message;
+
+/// Default separator of joined lines.
+const DEFAULT_SEPARATOR: &str = " | ";
+
+/// How a client sends relayed messages spanning several lines.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MultilineMode {
+    /// Send each line as its own message, in order.
+    Split,
+    /// Send the lines as a single message, separated by the client's separator.
+    Join,
+}
+
+/// Formatting of a client's multi-line messages.
+pub(crate) struct MultilineFormat {
+    mode: MultilineMode,
+    separator: String,
+}
+
+impl MultilineFormat {
+    /// Builds the multi-line formatting of a client.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The configured mode, defaults to joining the lines.
+    /// * `separator` - The configured separator of joined lines, defaults to ` | `.
+    pub(crate) fn from_config(mode: Option<MultilineMode>, separator: Option<&str>) -> Self {
+        MultilineFormat {
+            mode: mode.unwrap_or(MultilineMode::Join),
+            separator: separator.unwrap_or(DEFAULT_SEPARATOR).to_string(),
+        }
+    }
+
+    /// Formats a message into the messages to send, a single one unless split, and none if
+    /// every line is blank.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to format.
+    pub(crate) fn apply(&self, mut msg: Message) -> Vec<Message> {
+        if !msg.get_content().contains('\n') {
+            return vec![msg];
+        }
+
+        let lines = msg
+            .get_content()
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.trim_start().is_empty())
+            .map(str::to_string)
+            .collect::<Vec<String>>();
+        match self.mode {
+            MultilineMode::Split => lines
+                .into_iter()
+                .map(|line| {
+                    let mut part = msg.clone();
+                    part.set_content(line);
+                    part
+                })
+                .collect(),
+            MultilineMode::Join if lines.is_empty() => Vec::new(),
+            MultilineMode::Join => {
+                msg.set_content(lines.join(&self.separator));
+                vec![msg]
+            }
+        }
+    }
+}
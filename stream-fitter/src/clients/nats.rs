@@ -0,0 +1,318 @@
+//! Implements a NATS client for relaying to and from an event bus.
+//!
+//! Built on the async-nats library. Messages are exchanged as JSON, or in the configured
+//! `SerializationFormat`.
+use std::{path::PathBuf, sync::Arc};
+
+use async_nats::{Client as NatsClient, ConnectOptions, Subscriber};
+use futures::{future::join, task::FutureObj, StreamExt};
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{channel, Receiver, Sender},
+    watch, Mutex,
+};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    clients::client::{
+        Client as FitterClient, ClientConfigSnapshot, ClientTrait, LiveSettings, Message,
+        SerializationFormat,
+    },
+    errors::FitterResult,
+    pipe_fitter::{
+        filter::{FilterAction, MessageFilter, WordCountFilter},
+        pipeline::PipelineStage,
+        privacy::{Privacy, PrivacyConfig},
+        profanity::ProfanityFilterMode,
+        readiness::Connected,
+        sample::SampleConfig,
+        spam::SpamFilterConfig,
+    },
+};
+
+/// Default size limit of received payloads, in bytes.
+const DEFAULT_MAX_INBOUND_BYTES: usize = 64 * 1024;
+
+/// Loop to broadcast messages received on the subscribed subject.
+///
+/// # Arguments
+///
+/// * `subscriber` - The subscription to the subscribed subject.
+/// * `outer_tx` - The TX channels of other clients.
+/// * `config_rx` - The client's config watch, for settings changing while running.
+/// * `max_inbound_bytes` - Size limit of received payloads, larger ones are dropped unread.
+/// * `format` - The wire format of received payloads.
+#[instrument(skip(subscriber, outer_tx, config_rx))]
+async fn external_message_loop(
+    mut subscriber: Subscriber,
+    outer_tx: Vec<Sender<Message>>,
+    mut config_rx: watch::Receiver<ClientConfigSnapshot>,
+    max_inbound_bytes: usize,
+    format: SerializationFormat,
+) {
+    let mut settings = match LiveSettings::from_watch(&mut config_rx) {
+        Ok(settings) => settings,
+        Err(err) => {
+            error!("Invalid config: {}", err);
+            return;
+        }
+    };
+
+    loop {
+        // Poll for new message, applying config changes as they come.
+        let nats_msg = tokio::select! {
+            nats_msg = subscriber.next() => match nats_msg {
+                Some(nats_msg) => nats_msg,
+                None => break,
+            },
+            Ok(()) = config_rx.changed() => {
+                settings.update(&mut config_rx);
+                continue;
+            }
+        };
+
+        // Checked before deserializing, so oversized payloads are never parsed.
+        if nats_msg.payload.len() > max_inbound_bytes {
+            warn!(
+                "Dropped {} byte message on {}, over the {} byte limit",
+                nats_msg.payload.len(),
+                nats_msg.subject,
+                max_inbound_bytes
+            );
+            continue;
+        }
+
+        let new_msg = match Message::from_bytes(&nats_msg.payload, format) {
+            Ok(msg) => msg,
+            Err(err) => {
+                error!("Invalid message on {}: {}", nats_msg.subject, err);
+                continue;
+            }
+        };
+
+        let new_msg = match settings.pipeline.filter(new_msg) {
+            FilterAction::Pass(msg) => msg,
+            FilterAction::Drop => {
+                debug!("Dropped by pipeline, ignoring message");
+                continue;
+            }
+        };
+
+        // Forward message to all connected streams.
+        for stream in &outer_tx {
+            debug!("Sending message: {}", new_msg);
+            if let Err(err) = stream.send(new_msg.clone()).await {
+                error!("Error sending: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Loop to publish received internal messages.
+///
+/// # Arguments
+///
+/// * `rx` - The RX channel for the client.
+/// * `client` - The NATS client to publish with.
+/// * `subject` - The subject to publish to.
+/// * `privacy` - Hashes or drops fields of published messages, if configured.
+/// * `format` - The wire format of published payloads.
+#[instrument(skip(rx, client, privacy))]
+async fn internal_message_loop(
+    rx: Arc<Mutex<Receiver<Message>>>,
+    client: NatsClient,
+    subject: String,
+    privacy: Option<Privacy>,
+    format: SerializationFormat,
+) {
+    let mut locked_rx = rx.lock().await;
+    debug!("Lock acquired!");
+
+    // Poll for new message.
+    while let Some(msg) = locked_rx.recv().await {
+        debug!("Received message! {}", msg);
+
+        let msg = match &privacy {
+            Some(privacy) => privacy.apply(msg),
+            None => msg,
+        };
+        let payload = match msg.to_bytes(format) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("Error serializing: {:?}", err);
+                continue;
+            }
+        };
+
+        if let Err(err) = client.publish(subject.clone(), payload.into()).await {
+            error!("Error publishing: {:?}", err);
+        }
+    }
+}
+
+/// Config struct for a NATS client.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct NatsConfig {
+    /// URL of the NATS server.
+    pub url: String,
+    /// Subject relayed messages are published to.
+    pub publish_subject: String,
+    /// Subject to forward messages from.
+    pub subscribe_subject: String,
+    /// Path to a NATS credentials file.
+    pub credentials: Option<PathBuf>,
+    /// Drop or censor received messages containing profanity.
+    pub profanity_filter: Option<ProfanityFilterMode>,
+    /// Transformation stages applied to received messages, in order, defaults to
+    /// `[priority, spam, profanity, word_count, sample]`.
+    pub pipeline: Option<Vec<PipelineStage>>,
+    /// Clean up or drop received spam, like all caps or repeated characters.
+    pub spam_filter: Option<SpamFilterConfig>,
+    /// Only relay a sample of received chat messages, e.g. during busy events.
+    pub sample: Option<SampleConfig>,
+    /// Only relay received chat messages within a word count range, e.g. to skip one-word
+    /// reactions.
+    pub word_count: Option<WordCountFilter>,
+    /// Logins of the authors whose received messages are delivered ahead of queued chat by
+    /// the other clients, e.g. moderators.
+    pub priority_authors: Option<Vec<String>>,
+    /// Pattern of the received messages delivered ahead of queued chat by the other clients,
+    /// e.g. `(?i)raid incoming`.
+    pub priority_regex: Option<String>,
+    /// Hash or drop the content and authors of published messages, published with
+    /// `content_hashed` and `author_hashed` fields telling consumers what they got.
+    pub privacy: Option<PrivacyConfig>,
+    /// Size limit of received messages in bytes, larger ones are dropped before being
+    /// deserialized, defaults to 64 KiB.
+    pub max_inbound_bytes: Option<usize>,
+    /// Wire format of published and received messages, defaults to JSON.
+    #[serde(default)]
+    pub format: SerializationFormat,
+}
+
+impl NatsConfig {
+    /// Gets the settings a running client can change without restarting.
+    pub fn get_snapshot(&self) -> ClientConfigSnapshot {
+        ClientConfigSnapshot {
+            display_client: "NATS".to_string(),
+            isolate_channels: false,
+            profanity_filter: self.profanity_filter,
+            pipeline: self.pipeline.clone(),
+            spam_filter: self.spam_filter.clone(),
+            sample: self.sample.clone(),
+            word_count: self.word_count.clone(),
+            priority_authors: self.priority_authors.clone(),
+            priority_regex: self.priority_regex.clone(),
+            user_aliases: None,
+            ignored_authors: None,
+        }
+    }
+}
+
+/// NATS client struct.
+pub struct Nats {
+    id: String,
+    config: NatsConfig,
+    rx: Arc<Mutex<Receiver<Message>>>,
+    tx: Sender<Message>,
+    outer_tx: Vec<Sender<Message>>,
+    config_tx: watch::Sender<ClientConfigSnapshot>,
+    connected: Connected,
+}
+
+impl Nats {
+    /// Build a NATS client.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A client's unique ID.
+    /// * `config` - The NATS config to build from.
+    #[instrument(skip(config))]
+    pub fn from_config(id: String, config: NatsConfig) -> FitterResult<FitterClient> {
+        info!("Initializing NATS client");
+        let snapshot = config.get_snapshot();
+        LiveSettings::from_snapshot(&snapshot)?;
+
+        let (tx, rx) = channel(100);
+        Ok(Box::new(Nats {
+            id,
+            config_tx: watch::Sender::new(snapshot),
+            config,
+            rx: Arc::new(Mutex::new(rx)),
+            tx,
+            outer_tx: Vec::new(),
+            connected: Connected::new(),
+        }))
+    }
+}
+
+impl ClientTrait for Nats {
+    type FutType = FutureObj<'static, FitterResult<()>>;
+
+    fn get_name(&self) -> &str {
+        "NATS"
+    }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_stream(&self) -> FitterResult<Sender<Message>> {
+        Ok(self.tx.clone())
+    }
+
+    fn add_stream(&mut self, stream: Sender<Message>) -> FitterResult<()> {
+        self.outer_tx.push(stream);
+        Ok(())
+    }
+
+    fn get_config_watch(&self) -> Option<watch::Sender<ClientConfigSnapshot>> {
+        Some(self.config_tx.clone())
+    }
+
+    fn set_connected(&mut self, connected: Connected) {
+        self.connected = connected;
+    }
+
+    #[instrument(skip(self))]
+    fn run(&mut self) -> Self::FutType {
+        info!("Starting NATS client {}", self.get_id());
+        let config = self.config.clone();
+        let rx = Arc::clone(&self.rx);
+        let outer_tx = self.outer_tx.drain(..).collect::<Vec<Sender<Message>>>();
+        let config_rx = self.config_tx.subscribe();
+        let privacy = Privacy::from_config(config.privacy.as_ref());
+        let connected = self.connected.clone();
+        let max_inbound_bytes = config
+            .max_inbound_bytes
+            .unwrap_or(DEFAULT_MAX_INBOUND_BYTES);
+
+        FutureObj::new(Box::new(async move {
+            // Don't receive our own publishes back when both subjects overlap.
+            let mut options = ConnectOptions::new().no_echo();
+            if let Some(credentials) = &config.credentials {
+                options = options.credentials_file(credentials).await?;
+            }
+
+            let client = options.connect(config.url.as_str()).await?;
+            debug!("Connected to {}", config.url);
+            connected.mark();
+
+            let subscriber = client.subscribe(config.subscribe_subject).await?;
+
+            join(
+                external_message_loop(
+                    subscriber,
+                    outer_tx,
+                    config_rx,
+                    max_inbound_bytes,
+                    config.format,
+                ),
+                internal_message_loop(rx, client, config.publish_subject, privacy, config.format),
+            )
+            .await;
+
+            Ok(())
+        }))
+    }
+}
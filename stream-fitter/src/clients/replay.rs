@@ -0,0 +1,101 @@
+//! Replay of a recorded chat log, standing in for a live stream during development.
+//!
+//! The log holds one JSON `Message` per line, emitted at their original intervals, so load
+//! tests and UI work get realistic traffic without connecting to a platform.
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc::Sender,
+    time::sleep,
+};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    clients::client::{Message, SerializationFormat},
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::watchdog::Progress,
+};
+
+/// Config struct for replaying a recorded chat log instead of connecting.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReplayConfig {
+    /// Path of the log, holding one JSON message per line.
+    pub log_path: String,
+    /// Speed of the replay, e.g. 2.0 to emit messages twice as fast as they were recorded.
+    pub speed_multiplier: f32,
+}
+
+impl ReplayConfig {
+    /// Checks the replay speed is usable.
+    pub fn validate(&self) -> FitterResult<()> {
+        if !self.speed_multiplier.is_finite() || self.speed_multiplier <= 0.0 {
+            return Err(FitterErrorKind::GenericErr(format!(
+                "Replay speed multiplier must be positive, got {}",
+                self.speed_multiplier
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Loop to emit the messages of a recorded chat log to other clients, returning once the log
+/// is replayed.
+///
+/// Lines that aren't messages are skipped with a warning. Messages get new IDs on each replay,
+/// so replaying the log again, e.g. after a config reload, relays them again.
+///
+/// # Arguments
+///
+/// * `config` - The replay config.
+/// * `outer_tx` - The TX channels of other clients.
+/// * `progress` - The client's progress tracker, recording emitted messages.
+#[instrument(skip(config, outer_tx, progress))]
+pub async fn replay_loop(
+    config: ReplayConfig,
+    outer_tx: Vec<Sender<Message>>,
+    progress: Progress,
+) -> FitterResult<()> {
+    info!("Replaying chat log: {}", config.log_path);
+    let mut lines = BufReader::new(File::open(&config.log_path).await?).lines();
+    let mut previous: Option<DateTime<Utc>> = None;
+    let mut line_number = 0;
+    let started = Utc::now().to_rfc3339();
+
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut msg = match Message::from_bytes(line.as_bytes(), SerializationFormat::Json) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("Skipping invalid line {} of chat log: {}", line_number, err);
+                continue;
+            }
+        };
+        msg.reidentify(format!("replay-{}-{}", started, line_number));
+
+        // Messages recorded out of order are emitted right away.
+        if let Some(previous) = previous {
+            if let Ok(interval) = (msg.get_timestamp() - previous).to_std() {
+                sleep(interval.div_f32(config.speed_multiplier)).await;
+            }
+        }
+        previous = Some(msg.get_timestamp());
+
+        progress.record();
+        for stream in &outer_tx {
+            debug!("Sending message: {}", msg);
+            if let Err(err) = stream.send(msg.clone()).await {
+                error!("Error sending: {:?}", err);
+            }
+        }
+    }
+
+    info!("Replayed {} lines of chat log", line_number);
+    Ok(())
+}
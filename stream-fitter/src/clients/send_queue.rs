@@ -0,0 +1,539 @@
+//! Per-channel send queues, so one slow channel doesn't delay sending to the others.
+//!
+//! Every channel gets its own ordered queue drained by its own worker. Workers send to
+//! different channels concurrently, up to a limit, and to the same channel one message at a
+//! time, in the order the messages were queued.
+//!
+//! Each queue has two lanes: high priority messages, see `Message::is_priority`, go in a small
+//! separate lane the worker drains before the normal one, so announcements overtake a backlog
+//! of chat. Messages of the same priority are still sent in the order they were queued.
+//!
+//! Channels can be capped to a number of messages per minute, dropping the overflow instead of
+//! delaying it and periodically posting how many messages were suppressed.
+//!
+//! Failed sends are handled by the client's `SendErrorStrategy`, through `SendErrors`.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    future::Future,
+    hash::Hash,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::future::join_all;
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        Semaphore,
+    },
+    time::{interval_at, Instant},
+};
+use tracing::{debug, error};
+
+use crate::{
+    clients::client::{Message, MessageKind},
+    errors::{FitterError, FitterErrorKind, FitterResult},
+    util::{
+        backoff::Backoff,
+        catalog::{plural, Catalog, CatalogKey},
+    },
+};
+
+/// Default number of channels a client sends to concurrently.
+pub const DEFAULT_MAX_CONCURRENT_SENDS: usize = 4;
+/// Size of each channel's queue.
+const SEND_QUEUE_SIZE: usize = 100;
+/// Size of each channel's high priority lane.
+const PRIORITY_QUEUE_SIZE: usize = 20;
+/// Sliding window of the messages per minute cap, also the interval of suppressed notices.
+const CAP_WINDOW: Duration = Duration::from_secs(60);
+
+/// Checks a configured messages per minute cap.
+///
+/// # Arguments
+///
+/// * `max_per_minute` - The configured cap, if any.
+pub(crate) fn check_max_per_minute(max_per_minute: Option<usize>) -> FitterResult<Option<usize>> {
+    match max_per_minute {
+        Some(0) => {
+            Err(FitterErrorKind::GenericErr("max_per_minute must be at least 1".to_string()).into())
+        }
+        max_per_minute => Ok(max_per_minute),
+    }
+}
+
+/// Checks a configured number of channels sent to concurrently, defaulting it to
+/// `DEFAULT_MAX_CONCURRENT_SENDS`.
+///
+/// # Arguments
+///
+/// * `max_concurrent_sends` - The configured number, if any.
+pub(crate) fn check_max_concurrent_sends(
+    max_concurrent_sends: Option<usize>,
+) -> FitterResult<usize> {
+    match max_concurrent_sends {
+        Some(0) => Err(FitterErrorKind::GenericErr(
+            "max_concurrent_sends must be at least 1".to_string(),
+        )
+        .into()),
+        max_concurrent_sends => Ok(max_concurrent_sends.unwrap_or(DEFAULT_MAX_CONCURRENT_SENDS)),
+    }
+}
+
+/// What a client does when sending a message to a channel fails.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SendErrorStrategy {
+    /// Log the error and drop the message.
+    Log,
+    /// Send again, waiting `backoff_ms` before the first retry and twice as long before each
+    /// next one, then log the error. Errors that can't be fixed by retrying, e.g. a missing
+    /// permission, are logged right away.
+    Retry {
+        /// Number of attempts, including the first one.
+        max_attempts: u32,
+        /// Milliseconds to wait before the first retry.
+        backoff_ms: u64,
+    },
+    /// Stop the client with the error, for the stream manager to restart it, or to stop with
+    /// `abort_on_client_error`.
+    Fail,
+}
+
+/// Applies a client's `SendErrorStrategy` to its sends.
+#[derive(Clone)]
+pub(crate) struct SendErrors {
+    strategy: SendErrorStrategy,
+    failures: UnboundedSender<FitterError>,
+}
+
+impl SendErrors {
+    /// Creates the handling of a client's send errors, and the RX side of the errors stopping
+    /// the client with `SendErrorStrategy::Fail`.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - The client's strategy.
+    pub(crate) fn new(strategy: SendErrorStrategy) -> (Self, UnboundedReceiver<FitterError>) {
+        let (failures, failures_rx) = unbounded_channel();
+        (SendErrors { strategy, failures }, failures_rx)
+    }
+
+    /// Sends with the strategy, retrying or reporting the error stopping the client if the send
+    /// fails. Logging the error is left to the caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `send` - Starts an attempt to send.
+    /// * `is_retryable` - Checks whether an error is worth retrying.
+    pub(crate) async fn send<T, E, F, Fut, P>(&self, mut send: F, is_retryable: P) -> Result<T, E>
+    where
+        E: Debug,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        P: FnMut(&E) -> bool,
+    {
+        let result = match self.strategy {
+            SendErrorStrategy::Retry {
+                max_attempts,
+                backoff_ms,
+            } => {
+                Backoff::new()
+                    .with_initial_delay(Duration::from_millis(backoff_ms))
+                    .with_max_attempts(max_attempts)
+                    .retry(send, is_retryable)
+                    .await
+            }
+            SendErrorStrategy::Log | SendErrorStrategy::Fail => send().await,
+        };
+
+        if let (Err(err), SendErrorStrategy::Fail) = (&result, self.strategy) {
+            let err = FitterErrorKind::GenericErr(format!("Error sending: {:?}", err));
+            self.failures.send(err.into()).ok();
+        }
+        result
+    }
+}
+
+/// TX sides of the lanes of a channel's queue.
+struct Lanes {
+    normal: Sender<Message>,
+    priority: Sender<Message>,
+}
+
+/// Handle queuing messages to the channels of a client.
+pub(crate) struct ChannelQueues<K> {
+    queues: HashMap<K, Lanes>,
+}
+
+impl<K: Hash + Eq + Clone> ChannelQueues<K> {
+    /// Creates the queues of channels, and the future running their workers.
+    ///
+    /// The workers future completes once the queues are dropped and drained.
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The channels to send to.
+    /// * `max_concurrent_sends` - The number of channels sent to concurrently.
+    /// * `max_per_minute` - The number of messages sent to each channel per minute, if capped.
+    /// * `catalog` - The messages to compose suppressed notices with.
+    /// * `send` - Sends a message to a channel.
+    pub(crate) fn new<F, Fut>(
+        channels: impl IntoIterator<Item = K>,
+        max_concurrent_sends: usize,
+        max_per_minute: Option<usize>,
+        catalog: Arc<Catalog>,
+        send: F,
+    ) -> (Self, impl Future<Output = ()>)
+    where
+        F: Fn(K, Message) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut queues = HashMap::new();
+        let mut receivers = Vec::new();
+        for ch in channels {
+            let (normal, normal_rx) = channel(SEND_QUEUE_SIZE);
+            let (priority, priority_rx) = channel(PRIORITY_QUEUE_SIZE);
+            queues.insert(ch.clone(), Lanes { normal, priority });
+            receivers.push((ch, normal_rx, priority_rx));
+        }
+
+        let workers = async move {
+            let semaphore = Semaphore::new(max_concurrent_sends.max(1));
+            join_all(receivers.into_iter().map(|(ch, rx, priority_rx)| {
+                send_worker(
+                    ch,
+                    rx,
+                    priority_rx,
+                    &semaphore,
+                    max_per_minute,
+                    &catalog,
+                    &send,
+                )
+            }))
+            .await;
+        };
+
+        (ChannelQueues { queues }, workers)
+    }
+
+    /// Queues a message to a channel, in the lane of its priority, waiting while the lane is
+    /// full.
+    ///
+    /// # Arguments
+    ///
+    /// * `ch` - The channel to send to.
+    /// * `msg` - The message to send.
+    pub(crate) async fn push(&self, ch: &K, msg: Message) {
+        match self.queues.get(ch) {
+            Some(lanes) => {
+                let queue = match msg.is_priority() {
+                    true => &lanes.priority,
+                    false => &lanes.normal,
+                };
+                if queue.send(msg).await.is_err() {
+                    error!("Send queue closed, dropping message");
+                }
+            }
+            None => error!("No send queue for channel, dropping message"),
+        }
+    }
+}
+
+/// Loop sending a channel's queued messages in order, high priority ones first.
+///
+/// # Arguments
+///
+/// * `ch` - The channel to send to.
+/// * `rx` - The channel's queue.
+/// * `priority_rx` - The channel's high priority lane.
+/// * `semaphore` - Limits the number of channels sent to concurrently.
+/// * `max_per_minute` - The number of messages sent per minute, if capped.
+/// * `catalog` - The messages to compose suppressed notices with.
+/// * `send` - Sends a message to a channel.
+async fn send_worker<K, F, Fut>(
+    ch: K,
+    mut rx: Receiver<Message>,
+    mut priority_rx: Receiver<Message>,
+    semaphore: &Semaphore,
+    max_per_minute: Option<usize>,
+    catalog: &Catalog,
+    send: &F,
+) where
+    K: Clone,
+    F: Fn(K, Message) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut sent = VecDeque::new();
+    let mut suppressed = 0;
+    let mut notices = interval_at(Instant::now() + CAP_WINDOW, CAP_WINDOW);
+    loop {
+        let msg = tokio::select! {
+            biased;
+            Some(msg) = priority_rx.recv() => msg,
+            // Notices are bridge messages, so also high priority.
+            _ = notices.tick(), if suppressed > 0 => {
+                let notice = Message::system(catalog.render(
+                    CatalogKey::Suppressed,
+                    &[&suppressed.to_string(), plural(suppressed)],
+                ));
+                suppressed = 0;
+                notice
+            }
+            // Both lanes close together, the priority one may still hold the last messages.
+            msg = rx.recv() => match msg.or_else(|| priority_rx.try_recv().ok()) {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
+
+        // Drop messages over the cap of the last minute, bridge messages like notices aside.
+        if let (Some(max_per_minute), false) =
+            (max_per_minute, msg.get_kind() == MessageKind::System)
+        {
+            let now = Instant::now();
+            while sent
+                .front()
+                .is_some_and(|at| now.duration_since(*at) >= CAP_WINDOW)
+            {
+                sent.pop_front();
+            }
+            if sent.len() >= max_per_minute {
+                debug!(
+                    "Over {} messages per minute, dropping message",
+                    max_per_minute
+                );
+                // The notice comes a window after the first suppressed message, however
+                // long the channel was quiet before.
+                if suppressed == 0 {
+                    notices.reset();
+                }
+                suppressed += 1;
+                continue;
+            }
+            sent.push_back(now);
+        }
+
+        // The semaphore is never closed.
+        let _permit = semaphore.acquire().await;
+        send(ch.clone(), msg).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    /// Time a test send takes.
+    const SEND_TIME: Duration = Duration::from_millis(10);
+
+    /// Builds a chat message.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn message(content: String) -> Message {
+        Message::new(
+            "mock".to_string(),
+            "channel".to_string(),
+            "viewer".to_string(),
+            content,
+        )
+    }
+
+    #[test]
+    fn checks_max_concurrent_sends() {
+        assert_eq!(
+            check_max_concurrent_sends(None).unwrap(),
+            DEFAULT_MAX_CONCURRENT_SENDS
+        );
+        assert_eq!(check_max_concurrent_sends(Some(2)).unwrap(), 2);
+        assert!(check_max_concurrent_sends(Some(0))
+            .unwrap_err()
+            .to_string()
+            .contains("at least 1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_each_channel_in_order() {
+        let sent = Arc::new(StdMutex::new(Vec::new()));
+        let recorded = Arc::clone(&sent);
+        let (queues, workers) = ChannelQueues::new(
+            ["first", "second"],
+            2,
+            None,
+            Arc::new(Catalog::default()),
+            move |ch, msg: Message| {
+                let recorded = Arc::clone(&recorded);
+                async move {
+                    // Sends take longer on one channel, so the channels interleave.
+                    let delay = if ch == "first" {
+                        SEND_TIME
+                    } else {
+                        SEND_TIME * 3
+                    };
+                    sleep(delay).await;
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push((ch, msg.get_content().to_string()));
+                }
+            },
+        );
+
+        let pushes = async move {
+            for idx in 0..10 {
+                queues
+                    .push(&"first", message(format!("first {}", idx)))
+                    .await;
+                queues
+                    .push(&"second", message(format!("second {}", idx)))
+                    .await;
+            }
+        };
+        tokio::join!(workers, pushes);
+
+        let sent = sent.lock().unwrap();
+        for ch in ["first", "second"] {
+            let contents = sent
+                .iter()
+                .filter(|(sent_ch, _)| *sent_ch == ch)
+                .map(|(_, content)| content.clone())
+                .collect::<Vec<String>>();
+            let expected = (0..10)
+                .map(|idx| format!("{} {}", ch, idx))
+                .collect::<Vec<String>>();
+            assert_eq!(contents, expected);
+        }
+        // The fast channel didn't wait for the slow one.
+        assert_eq!(
+            sent[..2],
+            [
+                ("first", "first 0".to_string()),
+                ("first", "first 1".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounds_concurrent_sends() {
+        let active = Arc::new(StdMutex::new((0, 0)));
+        let counted = Arc::clone(&active);
+        let channels = ["a", "b", "c", "d", "e"];
+        let (queues, workers) = ChannelQueues::new(
+            channels,
+            2,
+            None,
+            Arc::new(Catalog::default()),
+            move |_, _| {
+                let counted = Arc::clone(&counted);
+                async move {
+                    {
+                        let mut counts = counted.lock().unwrap();
+                        counts.0 += 1;
+                        counts.1 = counts.1.max(counts.0);
+                    }
+                    sleep(SEND_TIME).await;
+                    counted.lock().unwrap().0 -= 1;
+                }
+            },
+        );
+
+        let pushes = async move {
+            for ch in &channels {
+                for idx in 0..3 {
+                    queues.push(ch, message(idx.to_string())).await;
+                }
+            }
+        };
+        let start = Instant::now();
+        tokio::join!(workers, pushes);
+
+        // 15 sends, 2 at a time.
+        assert_eq!(active.lock().unwrap().1, 2);
+        assert_eq!(start.elapsed(), SEND_TIME * 8);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_messages_over_the_cap() {
+        let sent = Arc::new(StdMutex::new(Vec::new()));
+        let recorded = Arc::clone(&sent);
+        let (queues, workers) = ChannelQueues::new(
+            ["first"],
+            1,
+            Some(2),
+            Arc::new(Catalog::default()),
+            move |_, msg: Message| {
+                recorded.lock().unwrap().push(msg.get_content().to_string());
+                async {}
+            },
+        );
+
+        let pushes = async move {
+            for idx in 0..4 {
+                queues.push(&"first", message(idx.to_string())).await;
+            }
+            // Suppressed messages are reported once the window elapsed.
+            sleep(CAP_WINDOW).await;
+            queues
+                .push(&"first", message("next minute".to_string()))
+                .await;
+        };
+        tokio::join!(workers, pushes);
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent[..2], ["0", "1"]);
+        assert!(sent[2].contains('2'), "{}", sent[2]);
+        assert_eq!(sent[3], "next minute");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_a_raid_after_an_idle_period_once() {
+        let sent = Arc::new(StdMutex::new(Vec::new()));
+        let recorded = Arc::clone(&sent);
+        let (queues, workers) = ChannelQueues::new(
+            ["first"],
+            1,
+            Some(2),
+            Arc::new(Catalog::default()),
+            move |_, msg: Message| {
+                recorded.lock().unwrap().push(msg.get_content().to_string());
+                async {}
+            },
+        );
+
+        let pushes = async move {
+            // Quiet for several windows, the raid's notice must still wait a full window.
+            sleep(CAP_WINDOW * 5).await;
+            for idx in 0..3 {
+                queues.push(&"first", message(idx.to_string())).await;
+            }
+            sleep(CAP_WINDOW / 2).await;
+            queues.push(&"first", message("3".to_string())).await;
+            sleep(CAP_WINDOW).await;
+        };
+        tokio::join!(workers, pushes);
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(*sent, ["0", "1", "(2 messages suppressed)"]);
+    }
+
+    #[test]
+    fn renders_a_single_suppressed_message() {
+        let catalog = Catalog::default();
+        assert_eq!(
+            catalog.render(CatalogKey::Suppressed, &["1", plural(1)]),
+            "(1 message suppressed)"
+        );
+        assert_eq!(
+            catalog.render(CatalogKey::Suppressed, &["3", plural(3)]),
+            "(3 messages suppressed)"
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Twitch stream status, polled with the Helix API and relayed to other clients.
+//!
+//! Stream titles are relayed as topics, e.g. set on Discord channels, and the stream going live
+//! or offline can be announced as events. Those can be received through EventSub as they happen
+//! instead of being noticed by the next poll.
+use std::{
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use futures::future::{pending, Either};
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{unbounded_channel, Sender},
+    watch,
+};
+use tracing::{debug, error, info, instrument};
+
+use crate::{
+    clients::{
+        client::{ClientConfigSnapshot, Message, MessageKind},
+        eventsub::{eventsub_loop, StreamEvent},
+        helix::{HelixChannel, HelixClient, HelixStream},
+    },
+    errors::{FitterErrorKind, FitterResult},
+    secret::Secret,
+    util::catalog::{Catalog, CatalogKey},
+};
+
+/// Time between stream status polls.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Config struct for relaying a Twitch stream's status.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct StreamStatusConfig {
+    /// Numeric ID of the broadcaster whose stream is followed.
+    pub broadcaster_user_id: String,
+    /// Relay the stream title as the topic of other clients' channels.
+    pub update_channel_topic: Option<bool>,
+    /// Announce the stream going live.
+    pub announce_live: Option<bool>,
+    /// Announce the stream going offline.
+    pub announce_offline: Option<bool>,
+    /// Receive the stream going live or offline through EventSub rather than polling.
+    pub eventsub: Option<bool>,
+}
+
+/// Last polled status of a stream.
+#[derive(Default)]
+struct StreamStatus {
+    live: Option<bool>,
+    title: Option<String>,
+}
+
+impl StreamStatus {
+    /// Updates the status, returning the messages relaying what changed.
+    ///
+    /// The stream going live or offline is only announced once its previous state is known.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The stream status config.
+    /// * `catalog` - The messages to announce the stream going live or offline with.
+    /// * `client_name` - The client name shown in relayed messages.
+    /// * `channel` - The polled channel information.
+    /// * `stream` - The polled live stream, if live.
+    /// * `announce` - Whether to announce the stream going live or offline.
+    fn update(
+        &mut self,
+        config: &StreamStatusConfig,
+        catalog: &Catalog,
+        client_name: &str,
+        channel: HelixChannel,
+        stream: Option<HelixStream>,
+        announce: bool,
+    ) -> Vec<Message> {
+        let new_message = |content: String, kind: MessageKind| {
+            Message::new(
+                client_name.to_string(),
+                channel.broadcaster_login.clone(),
+                channel.broadcaster_name.clone(),
+                content,
+            )
+            .with_kind(kind)
+        };
+
+        let mut messages = Vec::new();
+        if config.update_channel_topic.unwrap_or_default()
+            && !channel.title.is_empty()
+            && self.title.as_ref() != Some(&channel.title)
+        {
+            info!("Stream title changed: {}", channel.title);
+            messages.push(new_message(channel.title.clone(), MessageKind::Topic));
+        }
+
+        let live = stream.is_some();
+        match (self.live, stream) {
+            _ if !announce => (),
+            (Some(false), Some(stream)) if config.announce_live.unwrap_or_default() => {
+                let title = match stream.title.is_empty() {
+                    true => &channel.title,
+                    false => &stream.title,
+                };
+                messages.push(new_message(
+                    catalog.render(CatalogKey::WentLive, &[&channel.broadcaster_name, title]),
+                    MessageKind::Event,
+                ));
+            }
+            (Some(true), None) if config.announce_offline.unwrap_or_default() => {
+                messages.push(new_message(
+                    catalog.render(CatalogKey::WentOffline, &[&channel.broadcaster_name]),
+                    MessageKind::Event,
+                ));
+            }
+            _ => (),
+        }
+
+        // Events keep track of the live state when announced from them.
+        if announce {
+            self.live = Some(live);
+        }
+        self.title = Some(channel.title);
+        messages
+    }
+
+    /// Updates the status from an EventSub event, returning the message announcing it if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The stream status config.
+    /// * `catalog` - The messages to announce the stream going live or offline with.
+    /// * `client_name` - The client name shown in relayed messages.
+    /// * `event` - The received event.
+    fn handle_event(
+        &mut self,
+        config: &StreamStatusConfig,
+        catalog: &Catalog,
+        client_name: &str,
+        event: StreamEvent,
+    ) -> Option<Message> {
+        if self.live.replace(event.live) == Some(event.live) {
+            return None;
+        }
+
+        let content = match event.live {
+            true if config.announce_live.unwrap_or_default() => catalog.render(
+                CatalogKey::WentLive,
+                &[
+                    &event.broadcaster_name,
+                    self.title.as_deref().unwrap_or_default(),
+                ],
+            ),
+            false if config.announce_offline.unwrap_or_default() => {
+                catalog.render(CatalogKey::WentOffline, &[&event.broadcaster_name])
+            }
+            _ => return None,
+        };
+        Some(
+            Message::new(
+                client_name.to_string(),
+                event.broadcaster_login,
+                event.broadcaster_name,
+                content,
+            )
+            .with_kind(MessageKind::Event),
+        )
+    }
+}
+
+/// Sends stream status messages to other clients.
+///
+/// # Arguments
+///
+/// * `outer_tx` - The TX channels of other clients.
+/// * `msg` - The message to send.
+async fn send_status(outer_tx: &[Sender<Message>], msg: Message) {
+    for stream in outer_tx {
+        debug!("Sending stream status: {}", msg);
+        if let Err(err) = stream.send(msg.clone()).await {
+            error!("Error sending stream status: {:?}", err);
+        }
+    }
+}
+
+/// Polls a broadcaster's channel information and live stream.
+///
+/// # Arguments
+///
+/// * `client` - The Helix API client.
+/// * `user_id` - The broadcaster's numeric ID.
+async fn poll(
+    client: Arc<StdMutex<HelixClient>>,
+    user_id: String,
+) -> FitterResult<(HelixChannel, Option<HelixStream>)> {
+    tokio::task::spawn_blocking(move || {
+        let mut client = client.lock().unwrap();
+        let channel = client.get_channel(&user_id)?.ok_or_else(|| {
+            FitterErrorKind::GenericErr(format!("Unknown broadcaster: {}", user_id))
+        })?;
+        Ok((channel, client.get_stream(&user_id)?))
+    })
+    .await
+    .map_err(|err| FitterErrorKind::InternalErr(err.to_string()))?
+}
+
+/// Loop to periodically poll a stream's status and relay its changes to other clients.
+///
+/// # Arguments
+///
+/// * `config` - The stream status config.
+/// * `catalog` - The messages to announce the stream going live or offline with.
+/// * `client` - The Helix API client.
+/// * `config_rx` - The client's config watch, for the client name shown in relayed messages.
+/// * `eventsub_token` - A user access token to receive EventSub events with, if enabled.
+/// * `outer_tx` - The TX channels of other clients.
+#[instrument(skip(config, catalog, client, config_rx, eventsub_token, outer_tx))]
+pub(crate) async fn stream_status_loop(
+    config: StreamStatusConfig,
+    catalog: Arc<Catalog>,
+    client: Arc<StdMutex<HelixClient>>,
+    config_rx: watch::Receiver<ClientConfigSnapshot>,
+    eventsub_token: Option<Secret>,
+    outer_tx: Vec<Sender<Message>>,
+) {
+    let mut status = StreamStatus::default();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let (events_tx, mut events_rx) = unbounded_channel();
+    let announce_polled = eventsub_token.is_none();
+    let eventsub = match eventsub_token {
+        Some(token) => Either::Left(eventsub_loop(
+            config.broadcaster_user_id.clone(),
+            Arc::clone(&client),
+            token,
+            events_tx,
+        )),
+        None => Either::Right(pending::<()>()),
+    };
+    tokio::pin!(eventsub);
+
+    loop {
+        tokio::select! {
+            _ = &mut eventsub => (),
+            Some(event) = events_rx.recv() => {
+                let client_name = config_rx.borrow().display_client.clone();
+                if let Some(msg) = status.handle_event(&config, &catalog, &client_name, event) {
+                    send_status(&outer_tx, msg).await;
+                }
+            }
+            _ = interval.tick() => {
+                let (channel, stream) =
+                    match poll(Arc::clone(&client), config.broadcaster_user_id.clone()).await {
+                        Ok(polled) => polled,
+                        Err(err) => {
+                            error!("Error polling stream status: {}", err);
+                            continue;
+                        }
+                    };
+
+                let client_name = config_rx.borrow().display_client.clone();
+                for msg in status.update(
+                    &config,
+                    &catalog,
+                    &client_name,
+                    channel,
+                    stream,
+                    announce_polled,
+                ) {
+                    send_status(&outer_tx, msg).await;
+                }
+            }
+        }
+    }
+}
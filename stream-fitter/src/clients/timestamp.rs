@@ -0,0 +1,106 @@
+//! Prefixes relayed messages with the time they were originally sent.
+//!
+//! Useful when relayed messages aren't live, e.g. digests, replays or moderation logs.
+use chrono::{format::StrftimeItems, FixedOffset, Local, Utc};
+
+use crate::{
+    clients::client::{Message, MessageKind},
+    errors::{FitterErrorKind, FitterResult},
+};
+
+/// Default timestamp format, see `chrono::format::strftime`.
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Timezone timestamps are shown in.
+#[derive(Clone, Copy)]
+enum Timezone {
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    /// Parses a timezone, either `UTC`, `local` or a fixed offset like `+02:00`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timezone` - The configured timezone.
+    fn parse(timezone: &str) -> FitterResult<Self> {
+        match timezone {
+            "UTC" | "utc" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            offset => offset
+                .parse::<FixedOffset>()
+                .map(Timezone::Fixed)
+                .map_err(|_| {
+                    FitterErrorKind::GenericErr(format!(
+                        "Invalid timezone {:?}, expected UTC, local or an offset like +02:00",
+                        timezone
+                    ))
+                    .into()
+                }),
+        }
+    }
+}
+
+/// Format of the timestamps prefixing relayed messages.
+pub(crate) struct TimestampFormat {
+    format: String,
+    timezone: Timezone,
+}
+
+impl TimestampFormat {
+    /// Builds the timestamp format of a client, if timestamps are shown.
+    ///
+    /// # Arguments
+    ///
+    /// * `show_timestamp` - Whether timestamps are shown.
+    /// * `format` - The configured format, defaults to `%Y-%m-%d %H:%M`.
+    /// * `timezone` - The configured timezone, defaults to UTC.
+    pub(crate) fn from_config(
+        show_timestamp: Option<bool>,
+        format: Option<&str>,
+        timezone: Option<&str>,
+    ) -> FitterResult<Option<Self>> {
+        if !show_timestamp.unwrap_or_default() {
+            return Ok(None);
+        }
+
+        let format = format.unwrap_or(DEFAULT_TIMESTAMP_FORMAT);
+        if StrftimeItems::new(format).parse().is_err() {
+            return Err(FitterErrorKind::GenericErr(format!(
+                "Invalid timestamp format: {:?}",
+                format
+            ))
+            .into());
+        }
+
+        Ok(Some(TimestampFormat {
+            format: format.to_string(),
+            timezone: timezone.map_or(Ok(Timezone::Utc), Timezone::parse)?,
+        }))
+    }
+
+    /// Prefixes a message's content with its original send time.
+    ///
+    /// Bridge messages and topics are left as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The relayed message.
+    pub(crate) fn apply(&self, mut msg: Message) -> Message {
+        if matches!(msg.get_kind(), MessageKind::System | MessageKind::Topic) {
+            return msg;
+        }
+
+        let timestamp = msg.get_timestamp();
+        let formatted = match self.timezone {
+            Timezone::Utc => timestamp.with_timezone(&Utc).format(&self.format),
+            Timezone::Local => timestamp.with_timezone(&Local).format(&self.format),
+            Timezone::Fixed(offset) => timestamp.with_timezone(&offset).format(&self.format),
+        };
+        let content = format!("[{}] {}", formatted, msg.get_content());
+        msg.set_content(content);
+        msg
+    }
+}
@@ -1,80 +1,702 @@
 //! Implements a Twitch client for relaying.
 //!
 //! Built on the twitchchat library for Twitch API intercommunication.
-use std::{option::Option, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    option::Option,
+    sync::{Arc, Mutex as StdMutex},
+};
 
-use futures::task::FutureObj;
-use serde_derive::Deserialize;
-use tokio::sync::{
-    mpsc::{channel, Receiver, Sender, UnboundedReceiver},
-    Mutex,
+use futures::{
+    future::{join, pending, try_join, try_join_all},
+    stream,
+    task::FutureObj,
+    FutureExt, StreamExt,
 };
-use tracing::{debug, error, info, instrument};
-use twitch_irc::{
-    login::StaticLoginCredentials, message::ServerMessage, ClientConfig, TCPTransport,
-    TwitchIRCClient,
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver},
+    watch, Mutex,
 };
+use tracing::{debug, error, info, instrument, warn};
+use twitch_irc::{message::ServerMessage, ClientConfig, Error as TwitchError, TwitchIRCClient};
 
 use crate::{
-    clients::client::{Client as FitterClient, ClientTrait, Message},
-    errors::FitterResult,
+    clients::{
+        channel_health::{ChannelHealth, ChannelHealthConfig},
+        chat_digest::{digest_loop, ChatDigest},
+        client::{
+            Client as FitterClient, ClientConfigSnapshot, ClientTrait, LiveSettings, Message,
+            MessageKind,
+        },
+        forward_policy::{ForwardDecision, ForwardPolicy, IgnoreReason, IncomingMeta},
+        helix::{avatar_lookup_loop, validate_token, AvatarCache, HelixClient, HelixUserKey},
+        multiline::{MultilineFormat, MultilineMode},
+        replay::{replay_loop, ReplayConfig},
+        send_queue::{
+            check_max_concurrent_sends, check_max_per_minute, ChannelQueues, SendErrorStrategy,
+            SendErrors,
+        },
+        stream_status::{stream_status_loop, StreamStatusConfig},
+        timestamp::TimestampFormat,
+        twitch_transport::{TwitchLoginCredentials, TwitchTransport},
+    },
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::{
+        filter::{FilterAction, MessageFilter, WordCountFilter},
+        flush::Flushed,
+        pipeline::PipelineStage,
+        profanity::ProfanityFilterMode,
+        readiness::{Connected, ConnectedBarrier},
+        sample::SampleConfig,
+        spam::SpamFilterConfig,
+        watchdog::Progress,
+    },
+    secret::{Secret, TokenConfig},
+    util::{
+        catalog::{Catalog, CatalogKey},
+        template::Template,
+    },
+};
+
+/// Builds the Twitch chat line to send for a relayed message.
+///
+/// # Arguments
+///
+/// * `msg` - The relayed message.
+fn message_to_twitch_string(msg: &Message) -> String {
+    msg.to_string()
+}
+
+/// Metadata key marking relayed messages to send as announcements with any non-empty value,
+/// e.g. set by embedders or another client, see `TwitchConfig::announce_keywords`.
+pub const ANNOUNCE: &str = "twitch_announce";
+
+/// Twitch notice IDs indicating that the bot can't be in a channel.
+const JOIN_FAILURE_NOTICES: &[&str] = &[
+    "msg_banned",
+    "msg_channel_blocked",
+    "msg_channel_suspended",
+    "msg_room_not_found",
+    "tos_ban",
+];
+
+/// Twitch notice texts sent before closing connections with rejected credentials.
+const AUTH_FAILURE_NOTICES: &[&str] = &["Login authentication failed", "Improperly formatted auth"];
+
+/// Checks whether a message rejects the account's credentials.
+///
+/// # Arguments
+///
+/// * `msg` - The message received from Twitch.
+fn is_auth_failure(msg: &ServerMessage) -> bool {
+    match msg {
+        ServerMessage::Notice(msg) => {
+            msg.channel_login.is_none() && AUTH_FAILURE_NOTICES.contains(&msg.message_text.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Resolves channel login names to numeric channel IDs with the Helix API, in batches.
+///
+/// Fails with every login name that doesn't exist, e.g. a typo.
+///
+/// # Arguments
+///
+/// * `client` - The Helix API client.
+/// * `channels` - The channel login names to resolve.
+#[instrument(skip(client))]
+fn resolve_channel_ids(
+    client: &mut HelixClient,
+    channels: &[String],
+) -> FitterResult<HashMap<String, String>> {
+    let mut channel_ids = HashMap::new();
+    for user in client.get_users(HelixUserKey::Login, channels)? {
+        debug!("Resolved channel {} to ID {}", user.login, user.id);
+        channel_ids.insert(user.login, user.id);
+    }
+
+    let missing = channels
+        .iter()
+        .filter(|channel| !channel_ids.contains_key(&channel.to_lowercase()))
+        .map(String::as_str)
+        .collect::<Vec<&str>>();
+    if !missing.is_empty() {
+        return Err(FitterErrorKind::ConfigParseError(format!(
+            "Twitch channels not found: {}",
+            missing.join(", ")
+        ))
+        .into());
+    }
+
+    Ok(channel_ids)
+}
+
+/// Tracks and logs which configured channels the bot actually joined.
+///
+/// # Arguments
+///
+/// * `msg` - The message received from Twitch.
+/// * `client_name` - The client's name.
+/// * `channels` - The configured channels.
+/// * `joined` - The channels joined so far.
+fn track_joins(
+    msg: &ServerMessage,
+    client_name: &str,
+    channels: &[String],
+    joined: &mut HashSet<String>,
+) {
+    match msg {
+        ServerMessage::Join(msg) if msg.user_login == client_name => {
+            info!("Joined channel: {}", msg.channel_login);
+            joined.insert(msg.channel_login.clone());
+        }
+        ServerMessage::Part(msg) if msg.user_login == client_name => {
+            warn!("Parted channel: {}", msg.channel_login);
+            joined.remove(&msg.channel_login);
+        }
+        ServerMessage::Notice(msg)
+            if msg
+                .message_id
+                .as_ref()
+                .is_some_and(|id| JOIN_FAILURE_NOTICES.contains(&id.as_str())) =>
+        {
+            let channel = msg.channel_login.as_deref().unwrap_or("unknown");
+            warn!("Failed to join channel {}: {}", channel, msg.message_text);
+            joined.remove(channel);
+        }
+        _ => return,
+    }
+
+    let missing = channels
+        .iter()
+        .filter(|channel| !joined.contains(*channel))
+        .cloned()
+        .collect::<Vec<String>>();
+    if missing.is_empty() {
+        info!("Joined all {} configured channels", channels.len());
+    } else {
+        info!(
+            "Joined {} of {} configured channels, missing: {}",
+            channels.len() - missing.len(),
+            channels.len(),
+            missing.join(", ")
+        );
+    }
+}
+
+/// Tracks channels that can't be sent to from the server's replies.
+///
+/// Twitch acknowledges sent messages with a `USERSTATE` and rejects them with a `NOTICE`.
+///
+/// # Arguments
+///
+/// * `msg` - The message received from Twitch.
+/// * `health` - The tracker for channels that can't be sent to.
+fn track_channel_health(msg: &ServerMessage, health: &StdMutex<ChannelHealth>) {
+    match msg {
+        ServerMessage::UserState(msg) => health.lock().unwrap().record_success(&msg.channel_login),
+        ServerMessage::Notice(msg) => {
+            if let (Some(channel), Some(id)) = (&msg.channel_login, &msg.message_id) {
+                if JOIN_FAILURE_NOTICES.contains(&id.as_str()) {
+                    health.lock().unwrap().record_failure(channel);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Alias for the IRC connection of a single Twitch account.
+type TwitchConnection = TwitchIRCClient<TwitchTransport, TwitchLoginCredentials>;
+
+/// Default handling of failed sends, retrying while the connection is re-established.
+const DEFAULT_SEND_ERROR_STRATEGY: SendErrorStrategy = SendErrorStrategy::Retry {
+    max_attempts: 3,
+    backoff_ms: 500,
 };
 
-/// Loop to broadcast received Twitch messages.
+/// Checks whether a send error is worth retrying, e.g. while the connection is re-established.
 ///
 /// # Arguments
 ///
-/// * `inner_rx` - The RX channel of the Twitch chat client.
-/// * `client_name` - The clients name to ignore messages from.
-/// * `channels` - The channels to forward messages from.
-/// * `client` - The Twitch client to broadcast to.
+/// * `err` - The send error.
+fn is_retryable_send_error(err: &TwitchError<TwitchTransport, TwitchLoginCredentials>) -> bool {
+    !matches!(
+        err,
+        TwitchError::LoginError(_) | TwitchError::IRCParseError(_)
+    )
+}
+
+/// A channel and the moderator account sending announcements to it.
+#[derive(Clone)]
+struct AnnounceChannel {
+    broadcaster_id: String,
+    moderator_id: String,
+    moderator_token: Secret,
+}
+
+/// Sends relayed messages starting with a keyword, from a listed author or tagged with the
+/// `ANNOUNCE` metadata key as Twitch announcements.
+struct Announcer {
+    keywords: Vec<String>,
+    authors: Vec<String>,
+    helix: Arc<StdMutex<HelixClient>>,
+    channels: HashMap<String, AnnounceChannel>,
+}
+
+impl Announcer {
+    /// Creates an announcer, resolving the accounts' IDs with the Helix API.
+    ///
+    /// Channels whose ID couldn't be resolved get regular messages instead of announcements.
+    ///
+    /// # Arguments
+    ///
+    /// * `keywords` - The keywords starting messages to announce.
+    /// * `authors` - The authors whose messages are announced.
+    /// * `helix` - The Helix API client.
+    /// * `accounts` - The bot accounts, announcing in their own channels.
+    /// * `channel_ids` - The numeric channel IDs keyed by login name.
+    fn new(
+        keywords: Vec<String>,
+        authors: Vec<String>,
+        helix: Arc<StdMutex<HelixClient>>,
+        accounts: &[TwitchAccount],
+        channel_ids: &HashMap<String, String>,
+    ) -> FitterResult<Self> {
+        let logins = accounts
+            .iter()
+            .map(|account| {
+                account
+                    .user_config
+                    .login_credentials
+                    .credentials
+                    .login
+                    .clone()
+            })
+            .collect::<Vec<String>>();
+        let moderator_ids = helix
+            .lock()
+            .unwrap()
+            .get_users(HelixUserKey::Login, &logins)?
+            .into_iter()
+            .map(|user| (user.login, user.id))
+            .collect::<HashMap<String, String>>();
+
+        let mut channels = HashMap::new();
+        for account in accounts {
+            let credentials = &account.user_config.login_credentials.credentials;
+            let moderator_id = match moderator_ids.get(&credentials.login.to_lowercase()) {
+                Some(moderator_id) => moderator_id,
+                None => {
+                    warn!("Couldn't resolve account ID for: {}", credentials.login);
+                    continue;
+                }
+            };
+
+            for channel in &account.channels {
+                match channel_ids.get(&channel.to_lowercase()) {
+                    Some(broadcaster_id) => {
+                        channels.insert(
+                            channel.clone(),
+                            AnnounceChannel {
+                                broadcaster_id: broadcaster_id.clone(),
+                                moderator_id: moderator_id.clone(),
+                                moderator_token: Secret::new(
+                                    credentials.token.clone().unwrap_or_default(),
+                                ),
+                            },
+                        );
+                    }
+                    None => warn!("Announcements disabled for unresolved channel: {}", channel),
+                }
+            }
+        }
+
+        Ok(Announcer {
+            keywords,
+            authors,
+            helix,
+            channels,
+        })
+    }
+
+    /// Checks whether a message is to be announced.
+    ///
+    /// Authors match by name or login, ignoring case.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The relayed message.
+    fn should_announce(&self, msg: &Message) -> bool {
+        self.keywords
+            .iter()
+            .any(|keyword| msg.get_content().starts_with(keyword.as_str()))
+            || self.authors.iter().any(|author| {
+                author.eq_ignore_ascii_case(msg.get_author())
+                    || author.eq_ignore_ascii_case(msg.get_author_login())
+            })
+            || msg
+                .get_metadata(ANNOUNCE)
+                .is_some_and(|value| !value.is_empty())
+    }
+
+    /// Sends a message as an announcement if it's to be announced, see
+    /// `Announcer::should_announce`.
+    ///
+    /// Returns whether the message was announced, failures are logged so a regular message can
+    /// be sent instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel to announce in.
+    /// * `msg` - The relayed message.
+    /// * `text` - The text to announce.
+    async fn announce(&self, channel: &str, msg: &Message, text: &str) -> bool {
+        if !self.should_announce(msg) {
+            return false;
+        }
+
+        let target = match self.channels.get(channel) {
+            Some(target) => target.clone(),
+            None => {
+                warn!("Can't announce in {}, sending a regular message", channel);
+                return false;
+            }
+        };
+
+        let helix = Arc::clone(&self.helix);
+        let text = text.to_string();
+        let result = tokio::task::spawn_blocking(move || {
+            helix.lock().unwrap().send_announcement(
+                &target.broadcaster_id,
+                &target.moderator_id,
+                &target.moderator_token,
+                &text,
+            )
+        })
+        .await
+        .map_err(|err| FitterErrorKind::InternalErr(err.to_string()).into())
+        .and_then(|result| result);
+
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                warn!(
+                    "Error announcing in {}, sending a regular message: {}",
+                    channel, err
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Sends a message to a channel from the account owning it, unless it's marked dead.
+///
+/// # Arguments
+///
+/// * `connections` - The account connections keyed by the channels they own.
+/// * `health` - The tracker for channels that can't be sent to.
+/// * `send_errors` - Handles failed sends.
+/// * `announcer` - Sends some messages as announcements, if configured.
+/// * `channel` - The channel to send to.
+/// * `msg` - The message to send.
+async fn send_to_channel(
+    connections: &HashMap<String, TwitchConnection>,
+    health: &StdMutex<ChannelHealth>,
+    send_errors: &SendErrors,
+    announcer: Option<&Announcer>,
+    channel: &str,
+    msg: &Message,
+) {
+    let text = message_to_twitch_string(msg);
+    if let Some(announcer) = announcer {
+        if connections.contains_key(channel)
+            && health.lock().unwrap().should_send(channel)
+            && announcer.announce(channel, msg, &text).await
+        {
+            return;
+        }
+    }
+
+    send_text_to_channel(connections, health, send_errors, channel, text).await;
+}
+
+/// Sends text to a channel from the account owning it, unless it's marked dead.
+///
+/// # Arguments
+///
+/// * `connections` - The account connections keyed by the channels they own.
+/// * `health` - The tracker for channels that can't be sent to.
+/// * `send_errors` - Handles failed sends.
+/// * `channel` - The channel to send to.
+/// * `text` - The text to send.
+async fn send_text_to_channel(
+    connections: &HashMap<String, TwitchConnection>,
+    health: &StdMutex<ChannelHealth>,
+    send_errors: &SendErrors,
+    channel: &str,
+    text: String,
+) {
+    let client = match connections.get(channel) {
+        Some(client) => client,
+        None => {
+            error!("No account owns channel, dropping message for: {}", channel);
+            return;
+        }
+    };
+
+    if !health.lock().unwrap().should_send(channel) {
+        debug!("Dead channel, dropping message for: {}", channel);
+        return;
+    }
+
+    if let Err(err) = send_errors
+        .send(
+            || client.privmsg(channel.to_string(), text.clone()),
+            is_retryable_send_error,
+        )
+        .await
+    {
+        error!("Error sending: {:?}", err);
+    }
+}
+
+/// Sends a channel's join message, if one is configured.
+///
+/// # Arguments
+///
+/// * `connections` - The account connections keyed by the channels they own.
+/// * `join_messages` - The join messages keyed by channel.
+/// * `channel` - The joined channel.
+async fn send_join_message(
+    connections: &HashMap<String, TwitchConnection>,
+    join_messages: &HashMap<String, String>,
+    channel: &str,
+) {
+    let (client, text) = match (connections.get(channel), join_messages.get(channel)) {
+        (Some(client), Some(text)) => (client, text),
+        _ => return,
+    };
+
+    debug!("Sending join message to: {}", channel);
+    if let Err(err) = client.privmsg(channel.to_string(), text.clone()).await {
+        error!("Error sending join message: {:?}", err);
+    }
+}
+
+/// Loop to broadcast Twitch messages received by one account.
+///
+/// Stops with a `FitterErrorKind::AuthErr` if Twitch rejects the account's token.
+///
+/// # Arguments
+///
+/// * `inner_rx` - The RX channel of the account's Twitch chat client.
+/// * `config_rx` - The client's config watch, for settings changing while running.
+/// * `account` - The account's name.
+/// * `account_channels` - The channels owned by the account.
+/// * `bot_names` - The names of all accounts, to ignore messages from.
+/// * `channels` - All channels of the client, to forward messages to.
+/// * `join_messages` - Messages sent to channels once joined, keyed by channel.
+/// * `same_client_format` - Template of messages forwarded between channels, if configured.
+/// * `reconnect_message` - Message sent to channels rejoined after reconnecting, if configured.
+/// * `prefer_login_names` - Relay authors by their login instead of their display name.
+/// * `max_concurrent_sends` - The number of channels forwarded to concurrently.
+/// * `connections` - The account connections keyed by the channels they own.
 /// * `outer_tx` - The TX channels of other clients.
-/// * `isolate_channels` - Don't forward to other channels.
-#[instrument(skip(inner_rx, outer_tx))]
+/// * `health` - The tracker for channels that can't be sent to.
+/// * `send_errors` - Handles failed sends.
+/// * `avatars` - The cache of authors' avatars, if Helix API access is configured.
+/// * `digest` - The accumulator of received messages, if they are relayed as digests.
+/// * `progress` - The client's progress tracker, recording every message from Twitch
+///   including keepalives.
+/// * `logged_in` - Marked once Twitch accepted the account's login.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(
+    inner_rx,
+    config_rx,
+    bot_names,
+    channels,
+    join_messages,
+    same_client_format,
+    reconnect_message,
+    connections,
+    outer_tx,
+    health,
+    send_errors,
+    avatars,
+    digest,
+    progress,
+    logged_in
+))]
 async fn external_message_loop(
     mut inner_rx: UnboundedReceiver<ServerMessage>,
-    client_name: String,
+    mut config_rx: watch::Receiver<ClientConfigSnapshot>,
+    account: String,
+    account_channels: Vec<String>,
+    bot_names: Arc<HashSet<String>>,
     channels: Vec<String>,
-    client: TwitchIRCClient<TCPTransport, StaticLoginCredentials>,
+    join_messages: Arc<HashMap<String, String>>,
+    same_client_format: Option<Arc<Template>>,
+    reconnect_message: Option<Arc<str>>,
+    prefer_login_names: bool,
+    max_concurrent_sends: usize,
+    connections: Arc<HashMap<String, TwitchConnection>>,
     outer_tx: Vec<Sender<Message>>,
-    isolate_channels: bool,
-) {
-    while let Some(msg) = inner_rx.recv().await {
-        if let ServerMessage::Privmsg(msg) = msg {
-            // Only forward if it's not a bot message.
-            if msg.sender.login == client_name {
-                debug!("Bot, ignoring message");
+    health: Arc<StdMutex<ChannelHealth>>,
+    send_errors: SendErrors,
+    avatars: Option<Arc<AvatarCache>>,
+    digest: Option<Arc<ChatDigest>>,
+    progress: Progress,
+    logged_in: Connected,
+) -> FitterResult<()> {
+    let mut joined = HashSet::new();
+    let mut settings = match LiveSettings::from_watch(&mut config_rx) {
+        Ok(settings) => settings,
+        Err(err) => {
+            error!("Invalid config: {}", err);
+            return Ok(());
+        }
+    };
+    let mut policy = ForwardPolicy::new(account_channels.iter().cloned())
+        .with_isolate_channels(settings.isolate_channels)
+        .with_ignored_authors(&settings.ignored_authors);
+
+    loop {
+        // Poll for new message, applying config changes as they come.
+        let msg = tokio::select! {
+            msg = inner_rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            Ok(()) = config_rx.changed() => {
+                settings.update(&mut config_rx);
+                policy.set_isolate_channels(settings.isolate_channels);
+                policy.set_ignored_authors(&settings.ignored_authors);
                 continue;
             }
+        };
 
-            // Only forward if it's coming from the channel we are handling.
-            if let None = channels
-                .iter()
-                .find(|channel| channel == &&msg.channel_login)
-            {
-                debug!("Unrecognized channel, ignoring: {}", msg.channel_login);
-                continue;
+        progress.record();
+
+        // Twitch keeps closing the connection, reconnecting won't help.
+        if is_auth_failure(&msg) {
+            error!("Twitch rejected the token of account {}", account);
+            return Err(FitterErrorKind::AuthErr("Twitch".to_string()).into());
+        }
+        logged_in.mark();
+
+        // Channels joined again were rejoined after reconnecting.
+        let join = match &msg {
+            ServerMessage::Join(join) if join.user_login == account => Some((
+                join.channel_login.clone(),
+                joined.contains(&join.channel_login),
+            )),
+            _ => None,
+        };
+        track_joins(&msg, &account, &account_channels, &mut joined);
+        track_channel_health(&msg, &health);
+        match join {
+            Some((channel, false)) => {
+                send_join_message(&connections, &join_messages, &channel).await
+            }
+            Some((channel, true)) => {
+                info!("Rejoined channel after reconnecting: {}", channel);
+                if let Some(text) = &reconnect_message {
+                    send_text_to_channel(
+                        &connections,
+                        &health,
+                        &send_errors,
+                        &channel,
+                        text.to_string(),
+                    )
+                    .await;
+                }
             }
+            None => (),
+        }
+
+        if let ServerMessage::Privmsg(msg) = msg {
+            let incoming = IncomingMeta {
+                author: &msg.sender.login,
+                author_is_bot: bot_names.contains(&msg.sender.login),
+                channel: &msg.channel_login,
+                content: &msg.message_text,
+            };
+            let to_other_channels = match policy.decide(&incoming) {
+                ForwardDecision::Forward { to_other_channels } => to_other_channels,
+                ForwardDecision::Ignore(IgnoreReason::Bot) => {
+                    debug!("Bot, ignoring message");
+                    continue;
+                }
+                ForwardDecision::Ignore(IgnoreReason::UnknownChannel) => {
+                    debug!("Unrecognized channel, ignoring: {}", msg.channel_login);
+                    continue;
+                }
+                ForwardDecision::Ignore(IgnoreReason::IgnoredAuthor) => {
+                    debug!("Ignored author, ignoring message: {}", msg.sender.login);
+                    continue;
+                }
+            };
+
+            // Avatars not cached yet are looked up for the next messages.
+            let avatar_url = avatars
+                .as_ref()
+                .and_then(|avatars| avatars.get(&msg.sender.id));
 
-            let new_msg = Message::new(
-                "Twitch".to_string(),
+            // Display names only differ from logins by capitalization, unless localized.
+            let author = match prefer_login_names {
+                true => msg.sender.login.clone(),
+                false => msg.sender.name,
+            };
+            let author = settings.user_aliases.resolve(&msg.sender.login, author);
+            let mut new_msg = Message::new(
+                settings.display_client.clone(),
                 msg.channel_login.clone(),
-                msg.sender.name,
+                author,
                 msg.message_text,
-            );
-
-            if !isolate_channels {
-                // Forward message to other connected channels.
-                for channel in &channels {
-                    // Skip if same channel.
-                    if channel == &msg.channel_login {
-                        continue;
-                    }
+            )
+            .with_author_login(msg.sender.login)
+            .with_timestamp(msg.server_timestamp)
+            .with_platform_id(msg.message_id);
+            if let Some(avatar_url) = avatar_url {
+                new_msg = new_msg.with_avatar_url(avatar_url);
+            }
 
-                    if let Err(err) = client.privmsg(channel.clone(), new_msg.to_string()).await {
-                        error!("Error sending: {:?}", err);
-                    }
+            let new_msg = match settings.pipeline.filter(new_msg) {
+                FilterAction::Pass(msg) => msg,
+                FilterAction::Drop => {
+                    debug!("Dropped by pipeline, ignoring message");
+                    continue;
                 }
+            };
+
+            if to_other_channels {
+                // Forward message to other connected channels, skipping the same channel.
+                let text = match &same_client_format {
+                    Some(format) => new_msg.format_with(format),
+                    None => message_to_twitch_string(&new_msg),
+                };
+                let source_channel = &msg.channel_login;
+                stream::iter(channels.iter().filter(|channel| *channel != source_channel))
+                    .for_each_concurrent(max_concurrent_sends, |channel| {
+                        send_text_to_channel(
+                            &connections,
+                            &health,
+                            &send_errors,
+                            channel,
+                            text.clone(),
+                        )
+                    })
+                    .await;
+            }
+
+            // Count message towards the next digest instead of forwarding it.
+            if let Some(digest) = &digest {
+                digest.record(&new_msg);
+                continue;
             }
 
             // Forward message to all connected streams.
@@ -86,6 +708,8 @@ async fn external_message_loop(
             }
         }
     }
+
+    Ok(())
 }
 
 /// Loop to broadcast to Twitch received internal messages.
@@ -93,55 +717,367 @@ async fn external_message_loop(
 /// # Arguments
 ///
 /// * `rx` - The RX channel for the client.
-/// * `client` - The Twitch client to broadcast to.
+/// * `connections` - The account connections keyed by the channels they own.
 /// * `channels` - The channels to forward messages to.
-#[instrument(skip(rx, client))]
+/// * `health` - The tracker for channels that can't be sent to.
+/// * `send_errors` - Handles failed sends.
+/// * `max_concurrent_sends` - The number of channels sent to concurrently.
+/// * `max_per_minute` - The number of messages sent to each channel per minute, if capped.
+/// * `catalog` - The messages generated by the client.
+/// * `announcer` - Sends some messages as announcements, if configured.
+/// * `timestamp_format` - Prefixes messages with their original send time, if configured.
+/// * `multiline` - Formats messages spanning several lines.
+/// * `progress` - The client's progress tracker, recording sent messages.
+/// * `flushed` - The client's flushed handle, marked once the messages taken are sent.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(
+    rx,
+    connections,
+    health,
+    send_errors,
+    catalog,
+    announcer,
+    timestamp_format,
+    multiline,
+    progress,
+    flushed
+))]
 async fn internal_message_loop(
     rx: Arc<Mutex<Receiver<Message>>>,
-    client: TwitchIRCClient<TCPTransport, StaticLoginCredentials>,
+    connections: Arc<HashMap<String, TwitchConnection>>,
     channels: Vec<String>,
+    health: Arc<StdMutex<ChannelHealth>>,
+    send_errors: SendErrors,
+    max_concurrent_sends: usize,
+    max_per_minute: Option<usize>,
+    catalog: Arc<Catalog>,
+    announcer: Option<Arc<Announcer>>,
+    timestamp_format: Option<Arc<TimestampFormat>>,
+    multiline: Arc<MultilineFormat>,
+    progress: Progress,
+    flushed: Flushed,
 ) {
     let mut locked_rx = rx.lock().await;
     debug!("Lock acquired!");
 
-    // Poll for new message.
-    while let Some(msg) = locked_rx.recv().await {
-        debug!("Received message! {}", msg);
+    let (connections, health, send_errors, announcer, progress, flushed) = (
+        &connections,
+        &health,
+        &send_errors,
+        announcer.as_deref(),
+        &progress,
+        &flushed,
+    );
+    let (queues, workers) = ChannelQueues::new(
+        channels.clone(),
+        max_concurrent_sends,
+        max_per_minute,
+        catalog,
+        |channel: String, msg: Message| async move {
+            send_to_channel(connections, health, send_errors, announcer, &channel, &msg).await;
+            progress.record();
+        },
+    );
 
-        // Send received message to channels.
-        for channel in &channels {
-            if let Err(err) = client.privmsg(channel.clone(), msg.to_string()).await {
-                error!("Error sending: {:?}", err);
+    let dispatch = async move {
+        let mut flushing = false;
+        loop {
+            // Poll for new message.
+            let msg = tokio::select! {
+                Some(msg) = locked_rx.recv() => msg,
+                // Nothing more is relayed, so the loop ends once the messages taken are.
+                () = flushed.flushing(), if !flushing => {
+                    flushing = true;
+                    locked_rx.close();
+                    continue;
+                }
+                else => break,
             };
+            debug!("Received message! {}", msg);
+
+            // Twitch channels have no topic to set.
+            if msg.get_kind() == MessageKind::Topic {
+                debug!("Topic, not sending");
+                continue;
+            }
+
+            // Split before timestamping, so every line sent gets its timestamp.
+            for msg in multiline.apply(msg) {
+                let msg = match &timestamp_format {
+                    Some(timestamp_format) => timestamp_format.apply(msg),
+                    None => msg,
+                };
+
+                // Queue received message to channels.
+                for channel in &channels {
+                    if !msg.is_for_channel(channel) {
+                        continue;
+                    }
+
+                    queues.push(channel, msg.clone()).await;
+                }
+            }
         }
-    }
+    };
+
+    join(dispatch, workers).await;
+    flushed.mark();
 }
 
-/// Config struct for a Twitch client.
-#[derive(Deserialize)]
-pub struct TwitchConfig {
-    /// Bot's token.
-    pub token: String,
+/// Config struct for Twitch Helix API access.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct TwitchHelixConfig {
+    /// Twitch application's client ID.
+    pub client_id: String,
+    /// Twitch application's client secret.
+    pub client_secret: TokenConfig,
+    /// Seconds a looked up author avatar is cached.
+    pub avatar_ttl_seconds: Option<u64>,
+}
+
+/// Config struct for a Twitch bot account sending to some of the channels.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct TwitchAccountConfig {
     /// Bot's name.
     pub name: String,
+    /// Bot's token.
+    pub token: TokenConfig,
+    /// Channels the bot joins and sends to.
+    pub channels: Vec<String>,
+}
+
+/// Config struct for a Twitch client.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct TwitchConfig {
+    /// Bot's token, when a single bot handles all channels.
+    pub token: Option<TokenConfig>,
+    /// Bot's name, when a single bot handles all channels.
+    pub name: Option<String>,
     /// Vec of channels to connect to.
     pub channels: Vec<String>,
+    /// Bots each handling their own channels, instead of a single bot.
+    pub accounts: Option<Vec<TwitchAccountConfig>>,
     /// Don't forward between channels.
     pub isolate_channels: Option<bool>,
     /// Only forward to other clients, doesn't listen.
     pub forward_only: Option<bool>,
+    /// Number of channels sent to concurrently, both relaying and forwarding between
+    /// channels, at least 1. Defaults to 4.
+    #[serde(alias = "send_concurrency")]
+    pub max_concurrent_sends: Option<usize>,
+    /// Number of messages relayed to each channel per minute, dropping the overflow and
+    /// periodically posting how many messages were suppressed. Not capped by default.
+    pub max_per_minute: Option<usize>,
+    /// What to do when sending a message fails, defaults to retrying 3 times 500ms apart.
+    pub send_error_strategy: Option<SendErrorStrategy>,
+    /// Detection of channels that can no longer be sent to.
+    pub channel_health: Option<ChannelHealthConfig>,
+    /// Client name shown in relayed messages, defaults to the client's name.
+    pub display_client: Option<String>,
+    /// Names shown for users instead of their Twitch name, keyed by login, e.g. to show
+    /// members with the same name as on other platforms.
+    pub user_aliases: Option<HashMap<String, String>>,
+    /// Name of the client, used to refer to it elsewhere in the config, e.g. as a summary
+    /// target, and shown in relayed messages unless `display_client` is set. Defaults to
+    /// "Twitch", must be unique when set.
+    pub client_name_override: Option<String>,
+    /// Locale of the messages generated by the client, e.g. stream status announcements,
+    /// defaults to the stream manager's.
+    pub locale: Option<String>,
+    /// Messages generated by the client, taking precedence over the stream manager's, see
+    /// `util::catalog`.
+    pub messages: Option<HashMap<CatalogKey, String>>,
+    /// Drop or censor received messages containing profanity.
+    pub profanity_filter: Option<ProfanityFilterMode>,
+    /// Transformation stages applied to received messages, in order, defaults to
+    /// `[priority, spam, profanity, word_count, sample]`. Messages relayed from other clients
+    /// are sent without going through it.
+    pub pipeline: Option<Vec<PipelineStage>>,
+    /// Clean up or drop received spam, like all caps or repeated characters.
+    pub spam_filter: Option<SpamFilterConfig>,
+    /// Only relay a sample of received chat messages, e.g. during busy events.
+    pub sample: Option<SampleConfig>,
+    /// Only relay received chat messages within a word count range, e.g. to skip one-word
+    /// reactions.
+    pub word_count: Option<WordCountFilter>,
+    /// Logins of the authors whose received messages are delivered ahead of queued chat by
+    /// the other clients, e.g. moderators.
+    pub priority_authors: Option<Vec<String>>,
+    /// Pattern of the received messages delivered ahead of queued chat by the other clients,
+    /// e.g. `(?i)raid incoming`.
+    pub priority_regex: Option<String>,
+    /// Usernames of the authors whose messages aren't forwarded, ignoring case, e.g. other
+    /// bots in the channels.
+    pub ignored_authors: Option<Vec<String>>,
+    /// Helix API access, to resolve channel IDs on startup, failing on channels that don't
+    /// exist, and authors' avatars.
+    pub helix: Option<TwitchHelixConfig>,
+    /// Minutes between digests of received chat, relayed to other clients instead of every
+    /// message.
+    pub digest_interval: Option<u64>,
+    /// Relay the stream's title and live status, needs Helix API access.
+    pub stream_status: Option<StreamStatusConfig>,
+    /// Keywords starting relayed messages sent as announcements, needs Helix API access and
+    /// the `moderator:manage:announcements` scope on the bots' tokens. Relayed messages with
+    /// an `ANNOUNCE` metadata value are announced too, once announcements are configured.
+    pub announce_keywords: Option<Vec<String>>,
+    /// Source authors whose relayed messages are sent as announcements, by name or login,
+    /// with the same requirements as `announce_keywords`.
+    pub announce_authors: Option<Vec<String>>,
+    /// Prefix relayed messages with the time they were originally sent.
+    pub show_timestamp: Option<bool>,
+    /// Format of the timestamps, see `chrono::format::strftime`, defaults to `%Y-%m-%d %H:%M`.
+    pub timestamp_format: Option<String>,
+    /// Timezone of the timestamps, `UTC`, `local` or an offset like `+02:00`, defaults to UTC.
+    pub timestamp_timezone: Option<String>,
+    /// Send relayed messages spanning several lines, e.g. Discord paragraphs, as one message
+    /// per line with `split`, or as a single line with `join`, the default.
+    pub multiline: Option<MultilineMode>,
+    /// Separator of the lines joined with `multiline: join`, defaults to ` | `.
+    pub multiline_separator: Option<String>,
+    /// Messages sent to channels once the bot joined them, keyed by channel.
+    pub join_message: Option<HashMap<String, String>>,
+    /// Template of messages forwarded between channels, e.g. `{author} (from #{channel}):
+    /// {content|truncate:200}`, with the `{client}`, `{channel}`, `{author}` and `{content}`
+    /// fields, see `util::template` for modifiers and conditional sections.
+    /// Defaults to the format of messages relayed from other clients.
+    pub same_client_format: Option<String>,
+    /// Message sent to channels rejoined after reconnecting, so chat knows relaying resumed.
+    pub reconnect_message: Option<String>,
+    /// Relay authors by their login instead of their display name, keeping names ASCII-only
+    /// rather than localized or lookalike display names. Defaults to false.
+    pub prefer_login_names: Option<bool>,
+    /// Address of a plain IRC server connected to instead of Twitch, e.g. `127.0.0.1:6667`,
+    /// for tests.
+    #[doc(hidden)]
+    pub server_override: Option<String>,
+    /// Replay a recorded chat log instead of connecting to Twitch, e.g. for load tests or UI
+    /// development without a live stream. Needs no bot account, messages from other clients
+    /// are dropped.
+    pub replay_mode: Option<ReplayConfig>,
+}
+
+impl TwitchConfig {
+    /// Gets the name of the client.
+    pub fn get_name(&self) -> &str {
+        self.client_name_override.as_deref().unwrap_or("Twitch")
+    }
+
+    /// Gets the settings a running client can change without restarting.
+    pub fn get_snapshot(&self) -> ClientConfigSnapshot {
+        ClientConfigSnapshot {
+            display_client: self
+                .display_client
+                .clone()
+                .unwrap_or_else(|| self.get_name().to_string()),
+            isolate_channels: self.isolate_channels.unwrap_or_default(),
+            profanity_filter: self.profanity_filter,
+            pipeline: self.pipeline.clone(),
+            spam_filter: self.spam_filter.clone(),
+            sample: self.sample.clone(),
+            word_count: self.word_count.clone(),
+            priority_authors: self.priority_authors.clone(),
+            priority_regex: self.priority_regex.clone(),
+            user_aliases: self.user_aliases.clone(),
+            ignored_authors: self.ignored_authors.clone(),
+        }
+    }
+
+    /// Gets the bot accounts, checking every channel is handled by exactly one of them.
+    fn get_accounts(&self) -> FitterResult<Vec<TwitchAccountConfig>> {
+        let accounts = match (&self.accounts, &self.name, &self.token) {
+            (None, Some(name), Some(token)) => {
+                return Ok(vec![TwitchAccountConfig {
+                    name: name.clone(),
+                    token: token.clone(),
+                    channels: self.channels.clone(),
+                }])
+            }
+            (Some(accounts), None, None) => accounts,
+            (Some(_), _, _) => {
+                return Err(FitterErrorKind::GenericErr(
+                    "Twitch accounts can't be combined with a name and token".to_string(),
+                )
+                .into())
+            }
+            (None, _, _) => {
+                return Err(FitterErrorKind::GenericErr(
+                    "Twitch client needs either a name and token or accounts".to_string(),
+                )
+                .into())
+            }
+        };
+
+        for account in accounts {
+            if let Some(channel) = account
+                .channels
+                .iter()
+                .find(|channel| !self.channels.contains(channel))
+            {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Twitch account {} has unconfigured channel: {}",
+                    account.name, channel
+                ))
+                .into());
+            }
+        }
+
+        for channel in &self.channels {
+            let owners = accounts
+                .iter()
+                .filter(|account| account.channels.contains(channel))
+                .count();
+            if owners != 1 {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Twitch channel {} must belong to exactly one account, found {}",
+                    channel, owners
+                ))
+                .into());
+            }
+        }
+
+        Ok(accounts.clone())
+    }
+}
+
+/// A Twitch bot account ready to connect.
+struct TwitchAccount {
+    user_config: ClientConfig<TwitchLoginCredentials>,
+    channels: Vec<String>,
 }
 
 /// Twitch client struct.
 pub struct Twitch {
     id: String,
-    user_config: Option<ClientConfig<StaticLoginCredentials>>,
+    name: String,
+    accounts: Vec<TwitchAccount>,
     channels: Vec<String>,
+    channel_ids: HashMap<String, String>,
     rx: Arc<Mutex<Receiver<Message>>>,
     tx: Sender<Message>,
     outer_tx: Vec<Sender<Message>>,
-    isolate_channels: bool,
+    config_tx: watch::Sender<ClientConfigSnapshot>,
     forward_only: bool,
+    max_concurrent_sends: usize,
+    max_per_minute: Option<usize>,
+    send_error_strategy: SendErrorStrategy,
+    health: Arc<StdMutex<ChannelHealth>>,
+    catalog: Arc<Catalog>,
+    helix: Option<(TwitchHelixConfig, Arc<StdMutex<HelixClient>>)>,
+    chat_digest: Option<Arc<ChatDigest>>,
+    stream_status: Option<StreamStatusConfig>,
+    eventsub_token: Option<Secret>,
+    announcer: Option<Arc<Announcer>>,
+    timestamp_format: Option<Arc<TimestampFormat>>,
+    multiline: Arc<MultilineFormat>,
+    join_messages: Arc<HashMap<String, String>>,
+    same_client_format: Option<Arc<Template>>,
+    reconnect_message: Option<Arc<str>>,
+    prefer_login_names: bool,
+    replay: Option<ReplayConfig>,
+    progress: Progress,
+    connected: Connected,
+    flushed: Flushed,
 }
 
 impl Twitch {
@@ -154,25 +1090,157 @@ impl Twitch {
     #[instrument(skip(config))]
     pub fn from_config(id: String, config: TwitchConfig) -> FitterResult<FitterClient> {
         info!("Initializing Twitch client");
+        let name = config.get_name().to_string();
+        let snapshot = config.get_snapshot();
+        LiveSettings::from_snapshot(&snapshot)?;
+
+        // Replaying doesn't connect, bot accounts aren't needed.
+        if let Some(replay) = &config.replay_mode {
+            replay.validate()?;
+        }
+        let accounts = match config.replay_mode {
+            Some(_) => Vec::new(),
+            None => config.get_accounts()?,
+        };
+        let accounts = accounts
+            .into_iter()
+            .map(|account| {
+                Ok(TwitchAccount {
+                    user_config: ClientConfig::new_simple(TwitchLoginCredentials::new(
+                        account.name,
+                        Some(account.token.resolve()?.expose().to_string()),
+                        config.server_override.clone(),
+                    )),
+                    channels: account.channels,
+                })
+            })
+            .collect::<FitterResult<Vec<TwitchAccount>>>()?;
+
+        let (channel_ids, helix) = match config.helix {
+            Some(helix_config) => {
+                let mut client = HelixClient::new(&helix_config)?;
+                (
+                    resolve_channel_ids(&mut client, &config.channels)?,
+                    Some((helix_config, Arc::new(StdMutex::new(client)))),
+                )
+            }
+            None => {
+                // Features needing channel or user IDs are disabled, logged once for all of them.
+                let disabled = [
+                    ("stream status", config.stream_status.is_some()),
+                    (
+                        "announcements",
+                        config.announce_keywords.is_some() || config.announce_authors.is_some(),
+                    ),
+                ]
+                .iter()
+                .filter(|(_, configured)| *configured)
+                .map(|(feature, _)| *feature)
+                .collect::<Vec<&str>>();
+                match disabled.is_empty() {
+                    true => info!("No Helix API access, channel IDs and avatars are disabled"),
+                    false => warn!(
+                        "No Helix API access, disabling {}, configure `helix` to enable them",
+                        disabled.join(" and ")
+                    ),
+                }
+                (HashMap::new(), None)
+            }
+        };
+
+        let catalog = Arc::new(Catalog::from_config(
+            config.locale.as_deref(),
+            config.messages.as_ref(),
+        )?);
+        let chat_digest = ChatDigest::from_config(config.digest_interval, Arc::clone(&catalog))?;
+        let timestamp_format = TimestampFormat::from_config(
+            config.show_timestamp,
+            config.timestamp_format.as_deref(),
+            config.timestamp_timezone.as_deref(),
+        )?;
+        let same_client_format = config
+            .same_client_format
+            .as_deref()
+            .map(|format| Template::parse(format, Message::TEMPLATE_FIELDS))
+            .transpose()?;
+        let stream_status = config.stream_status.filter(|_| helix.is_some());
+        // EventSub websocket subscriptions need a user access token, the first account's is used.
+        let eventsub_token = match &stream_status {
+            Some(stream_status) if stream_status.eventsub.unwrap_or_default() => accounts
+                .first()
+                .and_then(|account| {
+                    account
+                        .user_config
+                        .login_credentials
+                        .credentials
+                        .token
+                        .clone()
+                })
+                .map(Secret::new),
+            _ => None,
+        };
+        let announcer = match (config.announce_keywords, config.announce_authors, &helix) {
+            (None, None, _) => None,
+            (_, _, None) => None,
+            (keywords, authors, Some((_, client))) => Some(Arc::new(Announcer::new(
+                keywords.unwrap_or_default(),
+                authors.unwrap_or_default(),
+                Arc::clone(client),
+                &accounts,
+                &channel_ids,
+            )?)),
+        };
+        let channels = &config.channels;
+        let join_messages = config.join_message.unwrap_or_default();
+        if let Some(channel) = join_messages
+            .keys()
+            .find(|channel| !channels.contains(channel))
+        {
+            return Err(FitterErrorKind::GenericErr(format!(
+                "Twitch join message for unconfigured channel: {}",
+                channel
+            ))
+            .into());
+        }
+
         let (tx, rx) = channel(100);
+        let health = ChannelHealth::new(&name, &config.channel_health.unwrap_or_default());
         Ok(Box::new(Twitch {
             id,
-            user_config: Some(ClientConfig::new_simple(StaticLoginCredentials::new(
-                config.name,
-                Some(config.token),
-            ))),
+            name,
+            accounts,
             channels: config.channels,
+            channel_ids,
             rx: Arc::new(Mutex::new(rx)),
             tx,
             outer_tx: Vec::new(),
-            isolate_channels: match config.isolate_channels {
-                Some(setting) => setting,
-                None => false,
-            },
-            forward_only: match config.forward_only {
-                Some(setting) => setting,
-                None => false,
-            },
+            config_tx: watch::Sender::new(snapshot),
+            forward_only: config.forward_only.unwrap_or_default(),
+            max_concurrent_sends: check_max_concurrent_sends(config.max_concurrent_sends)?,
+            max_per_minute: check_max_per_minute(config.max_per_minute)?,
+            send_error_strategy: config
+                .send_error_strategy
+                .unwrap_or(DEFAULT_SEND_ERROR_STRATEGY),
+            health: Arc::new(StdMutex::new(health)),
+            catalog,
+            helix,
+            chat_digest,
+            stream_status,
+            eventsub_token,
+            announcer,
+            timestamp_format: timestamp_format.map(Arc::new),
+            multiline: Arc::new(MultilineFormat::from_config(
+                config.multiline,
+                config.multiline_separator.as_deref(),
+            )),
+            join_messages: Arc::new(join_messages),
+            same_client_format: same_client_format.map(Arc::new),
+            reconnect_message: config.reconnect_message.map(Arc::from),
+            prefer_login_names: config.prefer_login_names.unwrap_or_default(),
+            replay: config.replay_mode,
+            progress: Progress::new(),
+            connected: Connected::new(),
+            flushed: Flushed::new(),
         }))
     }
 }
@@ -181,7 +1249,7 @@ impl ClientTrait for Twitch {
     type FutType = FutureObj<'static, FitterResult<()>>;
 
     fn get_name(&self) -> &str {
-        "Twitch"
+        &self.name
     }
 
     fn get_id(&self) -> &str {
@@ -197,53 +1265,277 @@ impl ClientTrait for Twitch {
         Ok(())
     }
 
+    fn get_config_watch(&self) -> Option<watch::Sender<ClientConfigSnapshot>> {
+        Some(self.config_tx.clone())
+    }
+
+    fn is_forward_only(&self) -> bool {
+        self.forward_only
+    }
+
+    fn channel_id(&self, channel: &str) -> Option<&str> {
+        self.channel_ids
+            .get(&channel.to_lowercase())
+            .map(String::as_str)
+    }
+
+    fn set_progress(&mut self, progress: Progress) {
+        self.progress = progress;
+    }
+
+    fn set_connected(&mut self, connected: Connected) {
+        self.connected = connected;
+    }
+
+    fn set_flushed(&mut self, flushed: Flushed) {
+        // Clients not sending to Twitch have nothing of their own to flush.
+        if self.forward_only || self.replay.is_some() {
+            flushed.mark();
+        }
+        self.flushed = flushed;
+    }
+
+    fn test_connectivity(&self) -> FutureObj<'static, FitterResult<()>> {
+        let credentials = self
+            .accounts
+            .iter()
+            .map(|account| {
+                let credentials = &account.user_config.login_credentials.credentials;
+                (
+                    credentials.login.clone(),
+                    Secret::new(credentials.token.clone().unwrap_or_default()),
+                )
+            })
+            .collect::<Vec<(String, Secret)>>();
+
+        FutureObj::new(Box::new(async move {
+            for (name, token) in credentials {
+                let login = tokio::task::spawn_blocking(move || validate_token(&token))
+                    .await
+                    .map_err(|err| FitterErrorKind::InternalErr(err.to_string()))??;
+                if !login.eq_ignore_ascii_case(&name) {
+                    return Err(FitterErrorKind::GenericErr(format!(
+                        "Twitch token of account {} belongs to {}",
+                        name, login
+                    ))
+                    .into());
+                }
+                debug!("Validated token of account {}", name);
+            }
+            Ok(())
+        }))
+    }
+
     #[instrument(skip(self))]
     fn run(&mut self) -> Self::FutType {
         info!("Starting Twitch client {}", self.get_id());
-        let user_config = self.user_config.take().unwrap();
-        let name = user_config.login_credentials.credentials.login.clone();
+        let accounts = self.accounts.drain(..).collect::<Vec<TwitchAccount>>();
         let channels = self.channels.clone();
+        let config_tx = self.config_tx.clone();
         let rx = Arc::clone(&self.rx);
         let outer_tx = self.outer_tx.drain(..).collect::<Vec<Sender<Message>>>();
-        let isolate_channels = self.isolate_channels;
         let forward_only = self.forward_only;
+        let max_concurrent_sends = self.max_concurrent_sends;
+        let max_per_minute = self.max_per_minute;
+        let send_error_strategy = self.send_error_strategy;
+        let health = Arc::clone(&self.health);
+        let catalog = Arc::clone(&self.catalog);
+        let helix = self.helix.clone();
+        let chat_digest = self.chat_digest.clone();
+        let stream_status = self.stream_status.clone();
+        let eventsub_token = self.eventsub_token.clone();
+        let announcer = self.announcer.clone();
+        let timestamp_format = self.timestamp_format.clone();
+        let multiline = Arc::clone(&self.multiline);
+        let join_messages = Arc::clone(&self.join_messages);
+        let same_client_format = self.same_client_format.clone();
+        let reconnect_message = self.reconnect_message.clone();
+        let prefer_login_names = self.prefer_login_names;
+        let replay = self.replay.clone();
+        let progress = self.progress.clone();
+        let connected = self.connected.clone();
+        let flushed = self.flushed.clone();
 
         FutureObj::new(Box::new(async move {
-            let (inner_rx, client) =
-                TwitchIRCClient::<TCPTransport, StaticLoginCredentials>::new(user_config);
-
-            debug!("{} is connected!", name);
-
-            // Spawn thread to handle incoming messages from Twitch.
-            let send_channels = channels.clone();
-            let forward_client = client.clone();
-            let join_send = tokio::spawn(async move {
-                external_message_loop(
-                    inner_rx,
-                    name,
-                    send_channels,
-                    forward_client,
-                    outer_tx,
-                    isolate_channels,
-                )
-                .await;
-            });
+            // The replay stands in for Twitch, the client keeps running once it's done.
+            if let Some(replay) = replay {
+                connected.mark();
+                let dropped = async {
+                    let mut locked_rx = rx.lock().await;
+                    while let Some(msg) = locked_rx.recv().await {
+                        debug!("Replaying, dropping message: {}", msg);
+                    }
+                };
+                return tokio::select! {
+                    Err(err) = replay_loop(replay, outer_tx, progress) => Err(err),
+                    _ = dropped => Ok(()),
+                };
+            }
 
-            // Join the specified channel.
-            channels
-                .iter()
-                .for_each(|channel| client.join(channel.clone()));
+            // Look avatars up and poll the stream status in the background when Helix API
+            // access is configured.
+            let (avatars, avatar_lookups, status_polls) = match helix {
+                Some((helix_config, client)) => {
+                    let (lookups_tx, lookups_rx) = unbounded_channel();
+                    let avatars = Arc::new(AvatarCache::new(&helix_config, lookups_tx));
+                    let status_polls = stream_status.map(|stream_status| {
+                        stream_status_loop(
+                            stream_status,
+                            Arc::clone(&catalog),
+                            Arc::clone(&client),
+                            config_tx.subscribe(),
+                            eventsub_token,
+                            outer_tx.clone(),
+                        )
+                    });
+                    (
+                        Some(Arc::clone(&avatars)),
+                        Some(avatar_lookup_loop(lookups_rx, avatars, client)),
+                        status_polls,
+                    )
+                }
+                None => (None, None, None),
+            };
+
+            let bot_names = Arc::new(
+                accounts
+                    .iter()
+                    .map(|account| {
+                        account
+                            .user_config
+                            .login_credentials
+                            .credentials
+                            .login
+                            .clone()
+                    })
+                    .collect::<HashSet<String>>(),
+            );
+
+            // Sends failing with `SendErrorStrategy::Fail` stop the client.
+            let (send_errors, mut send_failures) = SendErrors::new(send_error_strategy);
 
-            if !forward_only {
-                // Handle incoming messages from other clients.
-                let join_read = tokio::spawn(async move {
-                    internal_message_loop(rx, client, channels).await;
-                });
-                join_read.await?;
+            // Each account gets its own connection, reconnecting independently. twitch-irc
+            // reconnects internally with its own backoff, so `Backoff` only paces sends here.
+            let mut connections = HashMap::new();
+            let mut receivers = Vec::new();
+            for account in accounts {
+                let name = account
+                    .user_config
+                    .login_credentials
+                    .credentials
+                    .login
+                    .clone();
+                let (inner_rx, client) = TwitchConnection::new(account.user_config);
+
+                debug!("{} is connected!", name);
+
+                // Join the account's channels.
+                for channel in &account.channels {
+                    client.join(channel.clone());
+                    connections.insert(channel.clone(), client.clone());
+                }
+                receivers.push((inner_rx, name, account.channels));
             }
+            let connections = Arc::new(connections);
+            let logins = ConnectedBarrier::new(receivers.len());
 
-            join_send.await?;
-            Ok(())
+            // Handle incoming messages from Twitch, stopping if an account is rejected.
+            let external = try_join_all(receivers.into_iter().map(
+                |(inner_rx, name, account_channels)| {
+                    external_message_loop(
+                        inner_rx,
+                        config_tx.subscribe(),
+                        name,
+                        account_channels,
+                        Arc::clone(&bot_names),
+                        channels.clone(),
+                        Arc::clone(&join_messages),
+                        same_client_format.clone(),
+                        reconnect_message.clone(),
+                        prefer_login_names,
+                        max_concurrent_sends,
+                        Arc::clone(&connections),
+                        outer_tx.clone(),
+                        Arc::clone(&health),
+                        send_errors.clone(),
+                        avatars.clone(),
+                        chat_digest.clone(),
+                        progress.clone(),
+                        logins.handle(),
+                    )
+                },
+            ));
+
+            let digests = chat_digest
+                .clone()
+                .map(|digest| digest_loop(digest, outer_tx.clone()));
+
+            let relay = async move {
+                if forward_only {
+                    external.await?;
+                } else {
+                    // Handle incoming messages from other clients.
+                    let internal = internal_message_loop(
+                        rx,
+                        connections,
+                        channels,
+                        health,
+                        send_errors,
+                        max_concurrent_sends,
+                        max_per_minute,
+                        catalog,
+                        announcer,
+                        timestamp_format,
+                        multiline,
+                        progress,
+                        flushed,
+                    );
+                    try_join(external, internal.map(Ok)).await?;
+                }
+                Ok(())
+            };
+
+            // The background loops never end, they stop along with the relay.
+            let avatar_lookups = async {
+                match avatar_lookups {
+                    Some(avatar_lookups) => avatar_lookups.await,
+                    None => pending().await,
+                }
+            };
+            let digests = async {
+                match digests {
+                    Some(digests) => digests.await,
+                    None => pending().await,
+                }
+            };
+            let status_polls = async {
+                match status_polls {
+                    Some(status_polls) => status_polls.await,
+                    None => pending().await,
+                }
+            };
+            // The client is connected once every account logged in.
+            let logins = async {
+                logins.wait().await;
+                connected.mark();
+                pending::<()>().await
+            };
+
+            let result: FitterResult<()> = tokio::select! {
+                result = relay => result,
+                Some(err) = send_failures.recv() => Err(err),
+                _ = avatar_lookups => Ok(()),
+                _ = digests => Ok(()),
+                _ = status_polls => Ok(()),
+                _ = logins => Ok(()),
+            };
+
+            // Send what's left on shutdown.
+            if let Some(digest) = &chat_digest {
+                digest.flush(&outer_tx).await;
+            }
+
+            result
         }))
     }
 }
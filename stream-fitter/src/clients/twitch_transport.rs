@@ -0,0 +1,153 @@
+//! Transport of the Twitch chat connections.
+//!
+//! Connects to Twitch over TLS, or to a plain IRC server instead when overridden, e.g. a fake
+//! Twitch server in tests. The chat library creates the transport without any context, but
+//! fetches the login credentials in the same task right before, so a client's credentials hand
+//! its overriding server to the transport by the task's ID.
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io,
+    sync::Mutex as StdMutex,
+};
+
+use async_trait::async_trait;
+use either::Either;
+use futures::{future, sink, stream, StreamExt, TryStreamExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::{self, Id as TaskId},
+};
+use tracing::debug;
+use twitch_irc::{
+    login::{CredentialsPair, LoginCredentials},
+    message::{AsRawIRC, IRCMessage},
+    TCPTransport, Transport,
+};
+
+/// Overriding servers of the connections being opened, by the ID of the task opening them.
+static PENDING_SERVERS: StdMutex<Option<HashMap<TaskId, String>>> = StdMutex::new(None);
+
+/// Login credentials of a Twitch account, handing the client's overriding server, if any, to
+/// the transport of each connection opened with them.
+#[derive(Debug, Clone)]
+pub struct TwitchLoginCredentials {
+    /// The credentials logged in with.
+    pub credentials: CredentialsPair,
+    /// Address of the IRC server connected to instead of Twitch, see
+    /// `TwitchConfig::server_override`.
+    server: Option<String>,
+}
+
+impl TwitchLoginCredentials {
+    /// Creates the credentials of an account.
+    ///
+    /// # Arguments
+    ///
+    /// * `login` - The account's login.
+    /// * `token` - The account's OAuth token.
+    /// * `server` - The address of the server to connect to instead of Twitch, if any.
+    pub fn new(login: String, token: Option<String>, server: Option<String>) -> Self {
+        TwitchLoginCredentials {
+            credentials: CredentialsPair { login, token },
+            server,
+        }
+    }
+}
+
+#[async_trait]
+impl LoginCredentials for TwitchLoginCredentials {
+    type Error = Infallible;
+
+    async fn get_credentials(&self) -> Result<CredentialsPair, Infallible> {
+        if let (Some(server), Some(task_id)) = (&self.server, task::try_id()) {
+            PENDING_SERVERS
+                .lock()
+                .unwrap()
+                .get_or_insert_with(HashMap::new)
+                .insert(task_id, server.clone());
+        }
+        Ok(self.credentials.clone())
+    }
+}
+
+/// Transport connecting to Twitch, or to the server overriding it for the client connecting.
+pub struct TwitchTransport {
+    incoming: <TCPTransport as Transport>::Incoming,
+    outgoing: <TCPTransport as Transport>::Outgoing,
+}
+
+impl TwitchTransport {
+    /// Connects to a plain IRC server, without TLS.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server's address.
+    async fn connect_plain(server: &str) -> io::Result<Self> {
+        debug!("Connecting to overriding server: {}", server);
+        let (read_half, write_half) = TcpStream::connect(server).await?.into_split();
+
+        let lines = BufReader::new(read_half).lines();
+        let incoming = stream::unfold(lines, |mut lines| async move {
+            lines
+                .next_line()
+                .await
+                .transpose()
+                .map(|line| (line, lines))
+        })
+        .try_filter(|line| future::ready(!line.is_empty()))
+        .map_err(Either::Left)
+        .and_then(|line| future::ready(IRCMessage::parse(&line).map_err(Either::Right)))
+        .fuse();
+
+        let outgoing = sink::unfold(write_half, |mut write_half, msg: IRCMessage| async move {
+            let mut line = msg.as_raw_irc();
+            line.push_str("\r\n");
+            write_half.write_all(line.as_bytes()).await?;
+            Ok::<_, io::Error>(write_half)
+        });
+
+        Ok(TwitchTransport {
+            incoming: Box::new(Box::pin(incoming)),
+            outgoing: Box::new(Box::pin(outgoing)),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for TwitchTransport {
+    type ConnectError = <TCPTransport as Transport>::ConnectError;
+    type IncomingError = <TCPTransport as Transport>::IncomingError;
+    type OutgoingError = <TCPTransport as Transport>::OutgoingError;
+    type Incoming = <TCPTransport as Transport>::Incoming;
+    type Outgoing = <TCPTransport as Transport>::Outgoing;
+
+    async fn new() -> Result<Self, Self::ConnectError> {
+        let server = task::try_id().and_then(|task_id| {
+            PENDING_SERVERS
+                .lock()
+                .unwrap()
+                .as_mut()
+                .and_then(|servers| servers.remove(&task_id))
+        });
+        match server {
+            Some(server) => Ok(TwitchTransport::connect_plain(&server).await?),
+            None => {
+                let (incoming, outgoing) = TCPTransport::new().await?.split();
+                Ok(TwitchTransport { incoming, outgoing })
+            }
+        }
+    }
+
+    fn split(self) -> (Self::Incoming, Self::Outgoing) {
+        (self.incoming, self.outgoing)
+    }
+}
+
+impl Debug for TwitchTransport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("TwitchTransport").finish()
+    }
+}
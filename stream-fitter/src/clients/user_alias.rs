@@ -0,0 +1,42 @@
+//! Canonical names of users, so members known by different usernames on each platform are
+//! shown with the same name in relayed messages.
+//!
+//! Each client maps the usernames of its platform, e.g. Twitch logins, to the names shown.
+//! Usernames match regardless of case, since platforms like Twitch treat them that way.
+use std::collections::HashMap;
+
+/// Canonical names keyed by lowercase platform username.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UserAliases {
+    aliases: HashMap<String, String>,
+}
+
+impl UserAliases {
+    /// Builds the aliases of a client.
+    ///
+    /// # Arguments
+    ///
+    /// * `aliases` - The configured canonical names keyed by platform username, if any.
+    pub(crate) fn from_config(aliases: Option<&HashMap<String, String>>) -> Self {
+        UserAliases {
+            aliases: aliases
+                .into_iter()
+                .flatten()
+                .map(|(username, name)| (username.to_lowercase(), name.clone()))
+                .collect(),
+        }
+    }
+
+    /// Gets the name to show for a user, their canonical name if mapped.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The user's name on the platform, matched against the aliases.
+    /// * `shown` - The name shown for unmapped users, e.g. their display name.
+    pub(crate) fn resolve(&self, username: &str, shown: String) -> String {
+        match self.aliases.get(&username.to_lowercase()) {
+            Some(name) => name.clone(),
+            None => shown,
+        }
+    }
+}
@@ -1,4 +1,6 @@
 //! Error utilities used throughout this crate.
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
 use failure::Fail;
 
 /// Error type used throughout this crate.
@@ -7,10 +9,68 @@ pub type FitterError = ::failure::Error;
 pub type FitterResult<T> = ::std::result::Result<T, FitterError>;
 
 /// Error kidn enum used throughout this crate.
-#[derive(Fail, Debug)]
+#[derive(Debug)]
 pub enum FitterErrorKind {
-    #[fail(display = "Internal error: {}", _0)]
     InternalErr(String),
-    #[fail(display = "Generic error: {}", _0)]
     GenericErr(String),
+    KeyringErr(String),
+    /// A platform rejected the configured credentials, naming the platform.
+    AuthErr(String),
+    /// The config can be read but can't work as written.
+    ConfigParseError(String),
+    /// A client isolated with `panic_isolation` panicked, naming the client and the reason.
+    ClientPanic(String),
+    /// An operation didn't complete in time, describing it.
+    Timeout(String),
+    /// Errors accumulated instead of stopping at the first one, see `collect_errors`.
+    MultiError(Vec<FitterError>),
+}
+
+impl Display for FitterErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            FitterErrorKind::InternalErr(err) => write!(f, "Internal error: {}", err),
+            FitterErrorKind::GenericErr(err) => write!(f, "Generic error: {}", err),
+            FitterErrorKind::KeyringErr(err) => write!(f, "Keyring error: {}", err),
+            FitterErrorKind::AuthErr(platform) => {
+                write!(f, "{} authentication failed — check your token", platform)
+            }
+            FitterErrorKind::ConfigParseError(err) => write!(f, "Config error: {}", err),
+            FitterErrorKind::ClientPanic(err) => write!(f, "Client panic: {}", err),
+            FitterErrorKind::Timeout(err) => write!(f, "Timeout: {}", err),
+            FitterErrorKind::MultiError(errors) => {
+                write!(f, "{} errors:", errors.len())?;
+                for err in errors {
+                    write!(f, "\n- {}", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Fail for FitterErrorKind {}
+
+/// Collects results, accumulating every error instead of stopping at the first one.
+///
+/// A single error is returned as is, multiple ones as a `FitterErrorKind::MultiError`.
+///
+/// # Arguments
+///
+/// * `results` - The results to collect.
+pub fn collect_errors<T>(results: Vec<FitterResult<T>>) -> FitterResult<Vec<T>> {
+    let mut values = Vec::with_capacity(results.len());
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    match errors.len() {
+        0 => Ok(values),
+        1 => Err(errors.remove(0)),
+        _ => Err(FitterErrorKind::MultiError(errors).into()),
+    }
 }
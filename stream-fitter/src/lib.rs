@@ -2,6 +2,8 @@
 pub mod clients;
 pub mod errors;
 pub mod pipe_fitter;
+pub mod secret;
+pub mod util;
 
 /// Lifted error type used throughout this crate.
 pub type Error = errors::FitterError;
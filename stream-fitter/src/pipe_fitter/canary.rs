@@ -0,0 +1,75 @@
+//! Canary messages smoke testing the relay between two clients, see
+//! `PipeFitter::test_message_roundtrip`.
+//!
+//! A canary is sent into the relay as if a source client received it, and observed by a tap
+//! when the relay hands it to the target client. Canaries are never delivered to clients, so
+//! they are never posted to a platform.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use tokio::sync::oneshot;
+
+/// Metadata key of canary messages, holding their token.
+pub const CANARY: &str = "canary";
+
+/// Tap waiting for a canary.
+struct Tap {
+    target_id: String,
+    tx: oneshot::Sender<()>,
+}
+
+/// Shared taps waiting for canaries, by token.
+#[derive(Clone, Default)]
+pub(crate) struct CanaryTaps {
+    taps: Arc<StdMutex<HashMap<String, Tap>>>,
+}
+
+impl CanaryTaps {
+    /// Taps the relay to a client for a canary, returning the RX side notified once the
+    /// canary reaches it.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The canary's token.
+    /// * `target_id` - The ID of the client the canary is expected at.
+    pub(crate) fn tap(&self, token: &str, target_id: &str) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.taps.lock().unwrap().insert(
+            token.to_string(),
+            Tap {
+                target_id: target_id.to_string(),
+                tx,
+            },
+        );
+        rx
+    }
+
+    /// Removes a canary's tap, e.g. once it timed out.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The canary's token.
+    pub(crate) fn remove(&self, token: &str) {
+        self.taps.lock().unwrap().remove(token);
+    }
+
+    /// Records a canary reaching a client, notifying its tap if the client is its target.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The canary's token.
+    /// * `client_id` - The ID of the client the canary reached.
+    pub(crate) fn observe(&self, token: &str, client_id: &str) {
+        let mut taps = self.taps.lock().unwrap();
+        if taps
+            .get(token)
+            .is_some_and(|tap| tap.target_id == client_id)
+        {
+            if let Some(tap) = taps.remove(token) {
+                tap.tx.send(()).ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Spam scoring of the messages relayed between clients.
+//!
+//! Unlike a client's `spam_filter`, which cleans up messages with fixed rules, a classifier
+//! scores each chat message between 0 and 1, and messages scoring at least the `spam_classifier`
+//! threshold are dropped or flagged. `HeuristicClassifier` is used by default, embedders can
+//! plug in their own, e.g. an ML model, with `PipeFitter::with_spam_classifier`.
+use std::{collections::HashSet, sync::Arc};
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    clients::client::{async_trait, Message, MessageKind},
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::filter::FilterAction,
+};
+
+/// Metadata key of the score of messages flagged as spam, see `SpamAction::Flag`.
+pub const SPAM_SCORE: &str = "spam_score";
+
+/// Default score from which a message is spam.
+const DEFAULT_THRESHOLD: f32 = 0.8;
+/// Score added by each link, a message with 3 links being spam on its own.
+const LINK_WEIGHT: f32 = 0.35;
+/// Weight of the ratio of uppercase letters.
+const CAPS_WEIGHT: f32 = 0.5;
+/// Minimum number of letters for capitals to count.
+const CAPS_MIN_LETTERS: usize = 10;
+/// Weight of the ratio of repeated words.
+const REPETITION_WEIGHT: f32 = 0.8;
+/// Minimum number of words for repetition to count.
+const REPETITION_MIN_WORDS: usize = 4;
+
+/// What to do with messages classified as spam.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpamAction {
+    /// Stop relaying the message.
+    Drop,
+    /// Relay the message with its score as `SPAM_SCORE` metadata, e.g. for moderators' sinks.
+    Flag,
+}
+
+/// Config struct for the spam classification of relayed messages.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpamClassifierConfig {
+    /// Score from which a message is spam, above 0 and at most 1, defaults to 0.8.
+    pub threshold: Option<f32>,
+    /// What to do with spam, defaults to `drop`.
+    pub action: Option<SpamAction>,
+}
+
+impl SpamClassifierConfig {
+    /// Checks that the threshold is a valid score.
+    pub fn validate(&self) -> FitterResult<()> {
+        match self.threshold {
+            Some(threshold) if !(threshold > 0.0 && threshold <= 1.0) => {
+                Err(FitterErrorKind::GenericErr(format!(
+                    "Spam classifier threshold must be above 0 and at most 1, got {}",
+                    threshold
+                ))
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Classifier trait to implement for spam scorers.
+#[async_trait]
+pub trait SpamClassifier: Send + Sync {
+    /// Scores how likely a message is spam, from 0 for legitimate to 1 for spam.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to score.
+    async fn score(&self, msg: &Message) -> f32;
+}
+
+/// Default classifier, scoring links, capitals and repeated words.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeuristicClassifier;
+
+impl HeuristicClassifier {
+    /// Counts the links in a message's content.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn links(content: &str) -> usize {
+        content
+            .split_whitespace()
+            .filter(|word| {
+                let word = word.to_lowercase();
+                word.starts_with("http://")
+                    || word.starts_with("https://")
+                    || word.starts_with("www.")
+            })
+            .count()
+    }
+
+    /// Gets the ratio of uppercase letters of a message's content, if it has enough letters.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn caps_ratio(content: &str) -> f32 {
+        let (letters, uppercase) = content
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .fold((0, 0), |(letters, uppercase), c| {
+                (letters + 1, uppercase + usize::from(c.is_uppercase()))
+            });
+        match letters >= CAPS_MIN_LETTERS {
+            true => uppercase as f32 / letters as f32,
+            false => 0.0,
+        }
+    }
+
+    /// Gets the ratio of words repeating an earlier one, if the message has enough words.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn repetition_ratio(content: &str) -> f32 {
+        let words = content
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<String>>();
+        if words.len() < REPETITION_MIN_WORDS {
+            return 0.0;
+        }
+
+        let unique = words.iter().collect::<HashSet<&String>>().len();
+        (words.len() - unique) as f32 / words.len() as f32
+    }
+}
+
+#[async_trait]
+impl SpamClassifier for HeuristicClassifier {
+    async fn score(&self, msg: &Message) -> f32 {
+        let content = msg.get_content();
+        let score = HeuristicClassifier::links(content) as f32 * LINK_WEIGHT
+            + HeuristicClassifier::caps_ratio(content) * CAPS_WEIGHT
+            + HeuristicClassifier::repetition_ratio(content) * REPETITION_WEIGHT;
+        score.min(1.0)
+    }
+}
+
+/// A classifier along with what to do with the messages it classifies as spam.
+pub(crate) struct SpamClassification {
+    classifier: Arc<dyn SpamClassifier>,
+    threshold: f32,
+    action: SpamAction,
+}
+
+impl SpamClassification {
+    /// Builds the spam classification of relayed messages, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configured threshold and action.
+    /// * `classifier` - The classifier plugged in by the embedder, defaulting to
+    ///   `HeuristicClassifier` when only configured.
+    pub(crate) fn new(
+        config: Option<&SpamClassifierConfig>,
+        classifier: Option<Arc<dyn SpamClassifier>>,
+    ) -> Option<Self> {
+        if config.is_none() && classifier.is_none() {
+            return None;
+        }
+
+        Some(SpamClassification {
+            classifier: classifier.unwrap_or_else(|| Arc::new(HeuristicClassifier)),
+            threshold: config
+                .and_then(|config| config.threshold)
+                .unwrap_or(DEFAULT_THRESHOLD),
+            action: config
+                .and_then(|config| config.action)
+                .unwrap_or(SpamAction::Drop),
+        })
+    }
+
+    /// Scores a chat message, dropping or flagging it if it is spam.
+    ///
+    /// Messages other than chat, e.g. events, are never classified.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to classify.
+    pub(crate) async fn apply(&self, msg: Message) -> FilterAction {
+        if msg.get_kind() != MessageKind::Chat {
+            return FilterAction::Pass(msg);
+        }
+
+        let score = self.classifier.score(&msg).await;
+        if score < self.threshold {
+            return FilterAction::Pass(msg);
+        }
+
+        debug!("Message scored {} as spam", score);
+        match self.action {
+            SpamAction::Drop => FilterAction::Drop,
+            SpamAction::Flag => {
+                FilterAction::Pass(msg.with_metadata(SPAM_SCORE, score.to_string()))
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Message filters applied centrally while relaying between clients.
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    clients::client::{Message, MessageKind},
+    errors::{FitterErrorKind, FitterResult},
+};
+
+/// Outcome of running a message through a filter.
+// Most messages pass, boxing them would only add an allocation each.
+#[allow(clippy::large_enum_variant)]
+pub enum FilterAction {
+    /// Keep relaying the (possibly modified) message.
+    Pass(Message),
+    /// Stop relaying the message.
+    Drop,
+}
+
+/// Filter trait to implement for message filters.
+pub trait MessageFilter: Send + Sync {
+    /// Filters a message about to be relayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to filter.
+    fn filter(&self, msg: Message) -> FilterAction;
+
+    /// Gets the filter's name, e.g. for listing the active filters.
+    ///
+    /// Defaults to the filter's type name.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Gets the filter's settings as JSON, e.g. for listing the active filters.
+    ///
+    /// Defaults to `null` for filters without settings.
+    fn config(&self) -> Value {
+        Value::Null
+    }
+}
+
+/// Ordered chain of message filters.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn MessageFilter>>,
+}
+
+impl FilterChain {
+    /// Create an empty filter chain.
+    pub fn new() -> Self {
+        FilterChain::default()
+    }
+
+    /// Appends a filter to the end of the chain.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter to append.
+    pub fn push(&mut self, filter: Box<dyn MessageFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// Gets the filters, in the order they run.
+    pub fn get_filters(&self) -> Vec<&dyn MessageFilter> {
+        self.filters.iter().map(|filter| filter.as_ref()).collect()
+    }
+
+    /// Runs a message through the filters in order, stopping at the first drop.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to filter.
+    pub fn apply(&self, msg: Message) -> FilterAction {
+        let mut msg = msg;
+        for filter in &self.filters {
+            msg = match filter.filter(msg) {
+                FilterAction::Pass(msg) => msg,
+                FilterAction::Drop => return FilterAction::Drop,
+            };
+        }
+        FilterAction::Pass(msg)
+    }
+}
+
+/// Filter dropping chat messages outside a word count range, e.g. one-word reactions.
+///
+/// Messages other than chat, e.g. events, are always kept.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WordCountFilter {
+    /// Fewest words of the messages kept.
+    pub min_words: usize,
+    /// Most words of the messages kept, unlimited if unset.
+    pub max_words: Option<usize>,
+}
+
+impl WordCountFilter {
+    /// Checks that the range keeps some messages.
+    pub fn validate(&self) -> FitterResult<()> {
+        match self.max_words {
+            Some(max_words) if max_words < self.min_words => {
+                Err(FitterErrorKind::GenericErr(format!(
+                    "Word count filter max_words {} is below min_words {}",
+                    max_words, self.min_words
+                ))
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl MessageFilter for WordCountFilter {
+    fn filter(&self, msg: Message) -> FilterAction {
+        if msg.get_kind() != MessageKind::Chat {
+            return FilterAction::Pass(msg);
+        }
+
+        let words = msg.word_count();
+        if words < self.min_words || self.max_words.is_some_and(|max_words| words > max_words) {
+            return FilterAction::Drop;
+        }
+        FilterAction::Pass(msg)
+    }
+
+    fn name(&self) -> &str {
+        "word_count"
+    }
+
+    fn config(&self) -> Value {
+        json!({ "min_words": self.min_words, "max_words": self.max_words })
+    }
+}
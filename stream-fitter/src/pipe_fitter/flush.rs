@@ -0,0 +1,117 @@
+//! Tracks when every client flushed the messages it took, so draining can wait for them
+//! before stopping the clients.
+//!
+//! Each client gets a handle signaled once the relay drained, e.g. for Discord to send its
+//! pending digests. Clients finish what they took, then mark it. Clients without messages
+//! of their own to flush mark it right away.
+use std::{
+    future::pending,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::{watch, Notify};
+
+/// Barrier released once every client it counts flushed.
+#[derive(Debug)]
+pub struct FlushedBarrier {
+    remaining: AtomicUsize,
+    notify: Notify,
+    flushing: watch::Sender<bool>,
+}
+
+impl FlushedBarrier {
+    /// Creates a barrier counting clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `clients` - The number of clients to wait for, released right away when zero.
+    pub fn new(clients: usize) -> Arc<Self> {
+        Arc::new(FlushedBarrier {
+            remaining: AtomicUsize::new(clients),
+            notify: Notify::new(),
+            flushing: watch::Sender::new(false),
+        })
+    }
+
+    /// Gets the handle of one of the counted clients.
+    pub fn handle(self: &Arc<Self>) -> Flushed {
+        Flushed {
+            marked: Arc::new(AtomicBool::new(false)),
+            flushing: self.flushing.subscribe(),
+            barrier: Arc::clone(self),
+        }
+    }
+
+    /// Signals the clients to flush, once they're relayed nothing more.
+    pub fn flush(&self) {
+        self.flushing.send_replace(true);
+    }
+
+    /// Checks whether every client flushed.
+    pub fn is_released(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+
+    /// Waits until every client flushed.
+    pub async fn wait(&self) {
+        loop {
+            // Registered before checking, so a release in between isn't missed.
+            let notified = self.notify.notified();
+            if self.is_released() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Counts one more client flushed, releasing the barrier with the last one.
+    fn arrive(&self) {
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.notify.notify_waiters();
+        }
+    }
+}
+
+/// Handle a client watches to flush the messages it took, and marks once flushed.
+///
+/// Clones share the same handle.
+#[derive(Clone, Debug)]
+pub struct Flushed {
+    marked: Arc<AtomicBool>,
+    flushing: watch::Receiver<bool>,
+    barrier: Arc<FlushedBarrier>,
+}
+
+impl Default for Flushed {
+    fn default() -> Self {
+        Flushed::new()
+    }
+}
+
+impl Flushed {
+    /// Creates a handle counted by a barrier of its own, never signaled.
+    pub fn new() -> Self {
+        FlushedBarrier::new(1).handle()
+    }
+
+    /// Waits until the client is signaled to flush, right away if it already was.
+    pub async fn flushing(&self) {
+        let mut flushing = self.flushing.clone();
+        while !*flushing.borrow_and_update() {
+            if flushing.changed().await.is_err() {
+                // The barrier is gone, so it'll never be signaled.
+                pending::<()>().await;
+            }
+        }
+    }
+
+    /// Marks the client flushed, only counted the first time.
+    pub fn mark(&self) {
+        if !self.marked.swap(true, Ordering::SeqCst) {
+            self.barrier.arrive();
+        }
+    }
+}
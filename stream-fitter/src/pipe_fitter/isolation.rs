@@ -0,0 +1,86 @@
+//! Runs clients on their own OS thread and Tokio runtime, containing their panics, see
+//! `panic_isolation`.
+//!
+//! Libraries like serenity spawn their own tasks, out of reach of the client's run future, so
+//! panics are attributed to a client by the thread they happen on: every thread of an isolated
+//! client's runtime is named after it, which `is_isolated_thread` checks.
+use std::{any::Any, future::Future, panic::AssertUnwindSafe, thread};
+
+use futures::FutureExt;
+use tokio::sync::oneshot::channel;
+use tracing::{info, instrument};
+
+use crate::errors::{FitterErrorKind, FitterResult};
+
+/// Prefix of the names of the threads running isolated clients.
+const ISOLATED_THREAD_PREFIX: &str = "fitter-client-";
+
+/// Checks whether the current thread runs an isolated client, whose panics are contained
+/// instead of stopping the process.
+pub fn is_isolated_thread() -> bool {
+    thread::current()
+        .name()
+        .is_some_and(|name| name.starts_with(ISOLATED_THREAD_PREFIX))
+}
+
+/// Gets the reason a panic was raised with.
+///
+/// # Arguments
+///
+/// * `panic` - The panic's payload.
+fn panic_reason(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(reason), _) => reason.to_string(),
+        (_, Some(reason)) => reason.clone(),
+        _ => "unknown reason".to_string(),
+    }
+}
+
+/// Runs a client on its own thread and Tokio runtime until it stops, turning a panic into a
+/// `FitterErrorKind::ClientPanic`.
+///
+/// The client stops once the returned future is dropped, e.g. when its task is aborted.
+///
+/// # Arguments
+///
+/// * `name` - The client's name, naming its threads.
+/// * `run` - The client's run future.
+#[instrument(skip(run))]
+pub(crate) async fn run_isolated<F>(name: String, run: F) -> FitterResult<()>
+where
+    F: Future<Output = FitterResult<()>> + Send + 'static,
+{
+    info!("Running client on its own thread");
+    let thread_name = format!("{}{}", ISOLATED_THREAD_PREFIX, name);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .thread_name(thread_name.as_str())
+        .build()?;
+
+    let (result_tx, result_rx) = channel();
+    let (stop_tx, stop_rx) = channel::<()>();
+    thread::Builder::new().name(thread_name).spawn(move || {
+        let result = runtime.block_on(async {
+            tokio::select! {
+                result = AssertUnwindSafe(run).catch_unwind() => Some(result),
+                _ = stop_rx => None,
+            }
+        });
+        if let Some(result) = result {
+            result_tx.send(result).ok();
+        }
+    })?;
+
+    // Dropping the stop channel stops the thread along with the client.
+    let _stop_tx = stop_tx;
+    match result_rx.await {
+        Ok(Ok(result)) => result,
+        Ok(Err(panic)) => Err(FitterErrorKind::ClientPanic(format!(
+            "{} panicked: {}",
+            name,
+            panic_reason(panic.as_ref())
+        ))
+        .into()),
+        Err(_) => Err(FitterErrorKind::InternalErr(format!("{} thread stopped", name)).into()),
+    }
+}
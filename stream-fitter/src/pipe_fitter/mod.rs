@@ -0,0 +1,1848 @@
+//! The central manager to load and interconnect clients.
+pub mod canary;
+pub mod classifier;
+pub mod filter;
+pub mod flush;
+pub mod isolation;
+pub mod multi;
+pub mod overrides;
+pub mod pipeline;
+pub mod priority;
+pub mod privacy;
+pub mod profanity;
+pub mod readiness;
+pub mod sample;
+pub mod spam;
+pub mod state;
+pub mod summary;
+pub mod validation;
+pub mod watchdog;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
+    vec::Vec,
+};
+
+use chrono::Utc;
+use futures::future::{join_all, pending};
+use nanoid::nanoid;
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        mpsc::{
+            channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender,
+            WeakSender,
+        },
+        watch, Mutex,
+    },
+    task::{AbortHandle, JoinSet},
+    time::{sleep, timeout_at, Instant},
+};
+use tracing::{debug, error, info, instrument, warn, Level};
+
+use crate::{
+    clients::client::{
+        Client, ClientAction, ClientConfig, ClientConfigSnapshot, LiveSettings, Message,
+        MessageKind,
+    },
+    errors::{collect_errors, FitterErrorKind, FitterResult},
+    pipe_fitter::{
+        canary::{CanaryTaps, CANARY},
+        classifier::{SpamClassification, SpamClassifier, SpamClassifierConfig},
+        filter::{FilterAction, FilterChain, MessageFilter},
+        flush::{Flushed, FlushedBarrier},
+        isolation::run_isolated,
+        readiness::{Connected, ConnectedBarrier},
+        state::{FitterState, DEFAULT_STATE_MAX_AGE},
+        summary::{summary_loop, SummaryConfig, SummaryStats},
+        watchdog::{watchdog_loop, ClientStalled, Progress, WatchedClient},
+    },
+    secret::{CredentialProvider, EnvCredentialProvider},
+    util::{
+        backoff::{Backoff, Delays},
+        catalog::{Catalog, CatalogKey},
+        emoji::shortcodes_to_unicode,
+    },
+};
+
+/// Default number of relayed messages kept for `PipeFitter::recent_messages`.
+const DEFAULT_RECENT_MESSAGES: usize = 100;
+/// Number of IDs of relayed messages kept to not relay them again, see `Message::get_id`.
+const RELAYED_IDS: usize = 10_000;
+/// Time between checks of the messages left to take by the clients, see `PipeFitter::drain`.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Time a restarted client must run for before failing again restarts it with the first delay.
+const RESTART_RESET: Duration = Duration::from_secs(300);
+
+/// Configuration for pipe manager containing the configs of streams we want to connect.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PipeFitterConfig {
+    stream_configs: Vec<ClientConfig>,
+    /// Number of relayed messages to keep for querying.
+    recent_messages: Option<usize>,
+    /// Periodically post a summary of relay activity.
+    summary: Option<SummaryConfig>,
+    /// Log levels by module path, taking precedence over `RUST_LOG`, e.g.
+    /// `stream_fitter::clients::discord: debug`.
+    log_levels: Option<HashMap<String, String>>,
+    /// Stop every client when one stops with an error, defaults to false, restarting the
+    /// client instead. Clients whose credentials are rejected always stop the stream manager.
+    abort_on_client_error: Option<bool>,
+    /// Restart a client once it made no progress for this many seconds while others did,
+    /// disabled by default. Messages relayed to a client count as its progress, and only
+    /// clients built from the config are restarted.
+    stall_timeout_seconds: Option<u64>,
+    /// Locale of the messages generated by the bridge, e.g. summaries, defaults to `en`.
+    /// Clients use it unless they set their own.
+    locale: Option<String>,
+    /// Messages generated by the bridge, taking precedence over the locale's, see
+    /// `util::catalog`. Clients use them unless they set their own.
+    messages: Option<HashMap<CatalogKey, String>>,
+    /// Convert emoji shortcodes like `:smile:` in relayed messages to Unicode emoji, which
+    /// every platform renders, defaults to false.
+    normalize_emoji: Option<bool>,
+    /// Drop the messages received while paused, see `PipeFitter::pause`, instead of holding
+    /// them until resumed, defaults to false.
+    drop_while_paused: Option<bool>,
+    /// Run each client on its own thread, turning its panics into client errors instead of
+    /// stopping the process, defaults to false. Other clients keep relaying, and the client is
+    /// listed by `PipeFitter::disconnected_clients` until restarted.
+    panic_isolation: Option<bool>,
+    /// Score relayed chat messages as spam, dropping or flagging those at or above a threshold,
+    /// see `classifier`.
+    spam_classifier: Option<SpamClassifierConfig>,
+    /// File keeping the runtime state across restarts, written when drained on shutdown and
+    /// loaded when served again, see `state`.
+    state_file: Option<PathBuf>,
+    /// Longest age in seconds of a loaded state file, older ones being ignored, defaults to 600.
+    state_max_age_seconds: Option<u64>,
+}
+
+impl PipeFitterConfig {
+    /// Gets the configs of the clients to load.
+    pub fn get_stream_configs(&self) -> &[ClientConfig] {
+        &self.stream_configs
+    }
+
+    /// Gets the configs of the clients to load, with the locale and messages they inherit.
+    fn inherited_stream_configs(&self) -> Vec<ClientConfig> {
+        self.stream_configs
+            .iter()
+            .cloned()
+            .map(|mut stream_config| {
+                stream_config.inherit_messages(self.locale.as_ref(), self.messages.as_ref());
+                stream_config
+            })
+            .collect()
+    }
+
+    /// Checks the config for conflicts between clients, i.e. an overridden client name also
+    /// used by another client.
+    pub fn validate(&self) -> FitterResult<()> {
+        for (idx, stream_config) in self.stream_configs.iter().enumerate() {
+            let overridden = match stream_config {
+                ClientConfig::DiscordConfig(cfg) => cfg.client_name_override.is_some(),
+                ClientConfig::TwitchConfig(cfg) => cfg.client_name_override.is_some(),
+                _ => false,
+            };
+            let name = stream_config.get_name();
+            if overridden
+                && self
+                    .stream_configs
+                    .iter()
+                    .enumerate()
+                    .any(|(other, other_config)| other != idx && other_config.get_name() == name)
+            {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Client name {} used by several clients",
+                    name
+                ))
+                .into());
+            }
+        }
+        if let Some(spam_classifier) = &self.spam_classifier {
+            spam_classifier.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Gets the configured log levels as filter directives, e.g.
+    /// `stream_fitter::clients::discord=debug`.
+    pub fn get_log_directives(&self) -> FitterResult<Vec<String>> {
+        let mut directives = Vec::new();
+        for (module, level) in self.log_levels.iter().flatten() {
+            if !level.eq_ignore_ascii_case("off") && Level::from_str(level).is_err() {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Invalid log level for {}: {}",
+                    module, level
+                ))
+                .into());
+            }
+            directives.push(format!("{}={}", module, level.to_lowercase()));
+        }
+        directives.sort();
+        Ok(directives)
+    }
+}
+
+/// Changes applied by `PipeFitter::reload_config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReloadSummary {
+    /// Number of client configs that were added or changed.
+    pub added: usize,
+    /// Number of client configs that were removed or changed.
+    pub removed: usize,
+    /// Number of running clients whose config changes were applied without restarting.
+    pub updated: usize,
+}
+
+impl ReloadSummary {
+    /// Compares the client configs of two configs.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The running config.
+    /// * `new` - The config to load.
+    fn diff(old: &PipeFitterConfig, new: &PipeFitterConfig) -> Self {
+        let mut unmatched = old.stream_configs.iter().collect::<Vec<&ClientConfig>>();
+        let mut added = 0;
+        for stream_config in &new.stream_configs {
+            match unmatched.iter().position(|old| *old == stream_config) {
+                Some(idx) => {
+                    unmatched.remove(idx);
+                }
+                None => added += 1,
+            }
+        }
+
+        ReloadSummary {
+            added,
+            removed: unmatched.len(),
+            updated: 0,
+        }
+    }
+}
+
+impl Display for ReloadSummary {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "added {} client{}, removed {} client{}, updated {} client{}",
+            self.added,
+            if self.added == 1 { "" } else { "s" },
+            self.removed,
+            if self.removed == 1 { "" } else { "s" },
+            self.updated,
+            if self.updated == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// Alias for the client type used by the stream manager.
+type PipeFitterClient = Arc<Mutex<Client>>;
+
+/// Shared ring buffer of the most recently relayed messages.
+#[derive(Clone)]
+struct RecentMessages {
+    capacity: usize,
+    messages: Arc<StdMutex<VecDeque<Message>>>,
+}
+
+impl RecentMessages {
+    /// Creates an empty buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of messages kept.
+    fn new(capacity: usize) -> Self {
+        RecentMessages {
+            capacity,
+            messages: Arc::new(StdMutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Records a relayed message, evicting the oldest one when full.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The relayed message.
+    fn push(&self, msg: Message) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() == self.capacity {
+            messages.pop_front();
+        }
+        messages.push_back(msg);
+    }
+
+    /// Returns up to `n` of the most recent messages, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - The maximum number of messages to return.
+    fn snapshot(&self, n: usize) -> Vec<Message> {
+        let messages = self.messages.lock().unwrap();
+        messages
+            .iter()
+            .skip(messages.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Shared set of the IDs of the most recently relayed messages, see `Message::get_id`.
+#[derive(Clone)]
+struct RelayedIds {
+    ids: Arc<StdMutex<(HashSet<String>, VecDeque<String>)>>,
+}
+
+impl RelayedIds {
+    /// Creates an empty set.
+    fn new() -> Self {
+        RelayedIds {
+            ids: Arc::new(StdMutex::new((HashSet::new(), VecDeque::new()))),
+        }
+    }
+
+    /// Returns the IDs, oldest first.
+    fn snapshot(&self) -> Vec<String> {
+        let ids = self.ids.lock().unwrap();
+        ids.1.iter().cloned().collect()
+    }
+
+    /// Records the ID of a message about to be relayed, evicting the oldest one when full.
+    ///
+    /// Returns false if the message was already relayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The message's ID.
+    fn insert(&self, id: String) -> bool {
+        let mut ids = self.ids.lock().unwrap();
+        let (set, order) = &mut *ids;
+        if !set.insert(id.clone()) {
+            return false;
+        }
+        if order.len() == RELAYED_IDS {
+            if let Some(oldest) = order.pop_front() {
+                set.remove(&oldest);
+            }
+        }
+        order.push_back(id);
+        true
+    }
+}
+
+/// Shared list of the clients that stopped receiving, by ID, see
+/// `PipeFitter::disconnected_clients`.
+#[derive(Clone, Debug, Default)]
+struct DisconnectedClients {
+    clients: Arc<StdMutex<Vec<(String, String)>>>,
+}
+
+impl DisconnectedClients {
+    /// Records a client that stopped receiving.
+    ///
+    /// Returns false if it was already recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The client's ID.
+    /// * `name` - The client's name.
+    fn insert(&self, id: &str, name: &str) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.iter().any(|(other, _)| other == id) {
+            return false;
+        }
+        clients.push((id.to_string(), name.to_string()));
+        true
+    }
+
+    /// Returns the names of the clients, in the order they stopped receiving.
+    fn names(&self) -> Vec<String> {
+        let clients = self.clients.lock().unwrap();
+        clients.iter().map(|(_, name)| name.clone()).collect()
+    }
+
+    /// Checks whether no client stopped receiving.
+    fn is_empty(&self) -> bool {
+        self.clients.lock().unwrap().is_empty()
+    }
+
+    /// Forgets a client that was restarted.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The client's ID.
+    fn remove(&self, id: &str) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|(other, _)| other != id);
+    }
+}
+
+/// A client messages are relayed to.
+struct Destination {
+    id: String,
+    name: String,
+    stream: watch::Receiver<Sender<Message>>,
+    progress: Progress,
+}
+
+impl Destination {
+    /// Sends a message to the client's stream, following the client's new stream when it's
+    /// restarted while sending. Delivered messages count as the client's progress.
+    ///
+    /// Returns false if the client stopped receiving.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to send.
+    async fn send(&mut self, mut msg: Message) -> bool {
+        loop {
+            let tx = self.stream.borrow_and_update().clone();
+            match tx.send(msg).await {
+                Ok(()) => {
+                    self.progress.record();
+                    return true;
+                }
+                Err(err) if self.stream.has_changed().unwrap_or_default() => msg = err.0,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+/// Handles a client is given when wired, given again to the client it's restarted as.
+struct ClientHandles {
+    progress: Progress,
+    connected: Connected,
+    flushed: Flushed,
+}
+
+impl ClientHandles {
+    /// Gives the handles to a client.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The client to give them to.
+    fn hand_to(&self, client: &mut Client) {
+        client.set_progress(self.progress.clone());
+        client.set_connected(self.connected.clone());
+        client.set_flushed(self.flushed.clone());
+    }
+}
+
+/// Builds the destinations a client's messages are relayed to, every other client.
+///
+/// # Arguments
+///
+/// * `id` - The client's ID.
+/// * `clients` - The watched clients.
+/// * `streams` - The watches of the clients' streams, by ID.
+fn destinations_of(
+    id: &str,
+    clients: &[WatchedClient],
+    streams: &HashMap<String, watch::Sender<Sender<Message>>>,
+) -> Vec<Destination> {
+    clients
+        .iter()
+        .filter(|other_client| other_client.id != id)
+        .map(|other_client| {
+            debug!(
+                "Adding client {} {} to client {}",
+                other_client.name, other_client.id, id
+            );
+            Destination {
+                id: other_client.id.clone(),
+                name: other_client.name.clone(),
+                stream: streams[&other_client.id].subscribe(),
+                progress: other_client.progress.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Routes the messages produced by one client to all other clients.
+struct Relay {
+    rx: Receiver<Message>,
+    destinations: Vec<Destination>,
+    progress: Progress,
+}
+
+/// State shared by all relays.
+#[derive(Clone)]
+struct RelayContext {
+    spam_classification: Option<Arc<SpamClassification>>,
+    filters: Arc<FilterChain>,
+    recent: RecentMessages,
+    relayed: RelayedIds,
+    summary: Option<Arc<SummaryStats>>,
+    disconnected: DisconnectedClients,
+    canaries: CanaryTaps,
+    draining: watch::Receiver<bool>,
+    drained: Arc<AtomicUsize>,
+    paused: watch::Receiver<bool>,
+    drop_while_paused: bool,
+    normalize_emoji: bool,
+}
+
+/// Loop to relay a client's messages to the other clients.
+///
+/// Once draining, stops taking new messages from the client and ends after relaying the ones
+/// already received. While paused, leaves the client's messages in its stream until resumed,
+/// or drops them with `drop_while_paused`. A message received as the relay is paused is held
+/// until resumed.
+///
+/// # Arguments
+///
+/// * `relay` - The client's relay.
+/// * `context` - The state shared by all relays.
+#[instrument(skip(relay, context))]
+async fn relay_loop(mut relay: Relay, mut context: RelayContext) {
+    let mut held = None;
+    loop {
+        // Messages held while paused are still relayed when draining.
+        let hold =
+            *context.paused.borrow() && !context.drop_while_paused && !*context.draining.borrow();
+        let mut msg = match held.take() {
+            Some(msg) if !hold => msg,
+            msg => {
+                held = msg;
+                tokio::select! {
+                    msg = relay.rx.recv(), if !hold && held.is_none() => match msg {
+                        Some(msg) => {
+                            relay.progress.record();
+                            msg
+                        }
+                        None => break,
+                    },
+                    Ok(()) = context.draining.changed() => {
+                        relay.rx.close();
+                        continue;
+                    }
+                    Ok(()) = context.paused.changed() => continue,
+                    else => break,
+                }
+            }
+        };
+
+        // Paused while waiting for the message, so it's checked again once received.
+        if *context.paused.borrow() && context.drop_while_paused {
+            debug!("Paused, dropping message");
+            continue;
+        }
+        if *context.paused.borrow() && !*context.draining.borrow() {
+            debug!("Paused, holding message until resumed");
+            held = Some(msg);
+            continue;
+        }
+
+        // Bridge generated messages are never relayed.
+        if msg.get_kind() == MessageKind::System {
+            debug!("System message, not relaying");
+            continue;
+        }
+
+        // Canaries only go as far as the relay to their target, see
+        // `PipeFitter::test_message_roundtrip`.
+        if let Some(token) = msg.get_metadata(CANARY) {
+            for destination in &relay.destinations {
+                context.canaries.observe(token, &destination.id);
+            }
+            continue;
+        }
+
+        // Messages received again, e.g. on a retry, were already relayed. The ID is assigned
+        // first, so content changed by the relay doesn't change it.
+        msg.assign_id();
+        if !context.relayed.insert(msg.get_id()) {
+            debug!("Already relayed {}, not relaying again", msg.get_id());
+            continue;
+        }
+
+        // Normalized before filtering, so filters see the emoji relayed.
+        if context.normalize_emoji {
+            if let Some(content) = shortcodes_to_unicode(msg.get_content()) {
+                msg.set_content(content);
+            }
+        }
+
+        // Classified before filtering, so spam is scored as received.
+        if let Some(spam_classification) = &context.spam_classification {
+            msg = match spam_classification.apply(msg).await {
+                FilterAction::Pass(msg) => msg,
+                FilterAction::Drop => {
+                    debug!("Message classified as spam, not relaying");
+                    continue;
+                }
+            };
+        }
+
+        let msg = match context.filters.apply(msg) {
+            FilterAction::Pass(msg) => msg,
+            FilterAction::Drop => {
+                debug!("Message filtered, not relaying");
+                continue;
+            }
+        };
+
+        context.recent.push(msg.clone());
+        if let Some(summary) = &context.summary {
+            summary.record(&msg);
+        }
+
+        // A closed stream means its client stopped, so it's recorded as disconnected until
+        // it's restarted or the clients are rewired.
+        for destination in &mut relay.destinations {
+            debug!("Relaying message: {}", msg);
+            if destination.send(msg.clone()).await {
+                if *context.draining.borrow() {
+                    context.drained.fetch_add(1, Ordering::Relaxed);
+                }
+            } else if context
+                .disconnected
+                .insert(&destination.id, &destination.name)
+            {
+                // Every relay sees the client stopped, only the first one logs it.
+                warn!(
+                    "Client {} stopped receiving, no longer relaying to it",
+                    destination.name
+                );
+            }
+        }
+    }
+}
+
+/// Stream manager struct.
+pub struct PipeFitter {
+    clients: Vec<PipeFitterClient>,
+    clients_by_id: HashMap<String, PipeFitterClient>,
+    connections: Vec<usize>,
+    config_watches: Vec<Option<watch::Sender<ClientConfigSnapshot>>>,
+    relays: Vec<Relay>,
+    spam_classifier: Option<Arc<dyn SpamClassifier>>,
+    filters: Arc<FilterChain>,
+    recent: RecentMessages,
+    relayed: RelayedIds,
+    summary: Option<(SummaryConfig, Sender<Message>, Catalog)>,
+    disconnected: DisconnectedClients,
+    inputs: HashMap<String, WeakSender<Message>>,
+    canaries: CanaryTaps,
+    watched: Vec<WatchedClient>,
+    stall_tx: UnboundedSender<ClientStalled>,
+    stall_rx: Option<UnboundedReceiver<ClientStalled>>,
+    config: PipeFitterConfig,
+    credentials: Arc<dyn CredentialProvider>,
+    streams: Vec<WeakSender<Message>>,
+    client_configs: HashMap<String, ClientConfig>,
+    client_streams: HashMap<String, watch::Sender<Sender<Message>>>,
+    handles: HashMap<String, ClientHandles>,
+    context: Option<RelayContext>,
+    client_tasks: HashMap<String, AbortHandle>,
+    relay_tasks: Vec<AbortHandle>,
+    draining: watch::Sender<bool>,
+    drained: Arc<AtomicUsize>,
+    paused: watch::Sender<bool>,
+    connected: Arc<ConnectedBarrier>,
+    flushed: Arc<FlushedBarrier>,
+    tasks: JoinSet<FitterResult<()>>,
+}
+
+impl Debug for PipeFitter {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        /// A client as shown in the topology, only read through `Debug`.
+        #[allow(dead_code)]
+        #[derive(Debug)]
+        struct Client {
+            id: String,
+            name: String,
+            connections: usize,
+        }
+
+        // Each lock is only held to copy the client's ID and name, a client locked elsewhere
+        // is shown as such rather than waited for.
+        let clients = self
+            .clients
+            .iter()
+            .zip(&self.connections)
+            .map(|(client, connections)| {
+                let (id, name) = match client.try_lock() {
+                    Ok(client) => (client.get_id().to_string(), client.get_name().to_string()),
+                    Err(_) => ("<locked>".to_string(), "<locked>".to_string()),
+                };
+                Client {
+                    id,
+                    name,
+                    connections: *connections,
+                }
+            })
+            .collect::<Vec<Client>>();
+
+        f.debug_struct("PipeFitter")
+            .field("client_count", &clients.len())
+            .field("clients", &clients)
+            .field("disconnected", &self.disconnected.names())
+            .field("running", &!self.tasks.is_empty())
+            .finish()
+    }
+}
+
+impl PipeFitter {
+    /// Build a stream manager from a config.
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> stream_fitter::errors::FitterResult<()> {
+    /// use stream_fitter::{
+    ///     clients::{client::Message, mock::MockClient},
+    ///     pipe_fitter::{PipeFitter, PipeFitterConfig},
+    /// };
+    ///
+    /// // Clients are usually loaded from `stream_configs`, mock clients are added instead here
+    /// let config: PipeFitterConfig =
+    ///     serde_yaml::from_str("stream_configs: []\nrecent_messages: 10").unwrap();
+    /// let (twitch, twitch_handle) = MockClient::build("twitch");
+    /// let (discord, mut discord_handle) = MockClient::build("discord");
+    ///
+    /// let mut fitter = PipeFitter::from_config_with_clients(config, vec![twitch, discord])?;
+    /// fitter.start();
+    ///
+    /// twitch_handle
+    ///     .inject(Message::new(
+    ///         "twitch".to_string(),
+    ///         "#channel".to_string(),
+    ///         "viewer".to_string(),
+    ///         "Hello!".to_string(),
+    ///     ))
+    ///     .await?;
+    /// let received = discord_handle.recv().await.unwrap();
+    /// assert_eq!(received.get_content(), "Hello!");
+    ///
+    /// fitter.stop();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A stream manager config to load.
+    #[instrument(skip(config))]
+    pub fn from_config(config: PipeFitterConfig) -> FitterResult<Self> {
+        PipeFitter::from_config_with_clients(config, Vec::new())
+    }
+
+    /// Build a stream manager from a config, interconnecting already built clients along with
+    /// the config's.
+    ///
+    /// The clients aren't part of the stream manager's config, so they are dropped by
+    /// `PipeFitter::reload_config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A stream manager config to load.
+    /// * `extra_clients` - The clients to interconnect besides the config's.
+    #[instrument(skip(config, extra_clients))]
+    pub fn from_config_with_clients(
+        config: PipeFitterConfig,
+        extra_clients: Vec<Client>,
+    ) -> FitterResult<Self> {
+        // Credentials referenced by key are read from the environment as the clients are built.
+        let stream_configs = config.inherited_stream_configs();
+        PipeFitter::from_stream_configs(
+            config,
+            stream_configs.clone(),
+            stream_configs,
+            extra_clients,
+            Arc::new(EnvCredentialProvider),
+        )
+    }
+
+    /// Build a stream manager from a config, fetching the credentials its clients reference
+    /// by key, e.g. `token: { token_ref: discord_token }`, from a provider instead of the
+    /// environment.
+    ///
+    /// Credentials are fetched again whenever the clients are restarted, e.g. on
+    /// `PipeFitter::reload_config`, picking up rotated secrets. The extra clients are dropped
+    /// by `PipeFitter::reload_config`, as with `PipeFitter::from_config_with_clients`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A stream manager config to load.
+    /// * `extra_clients` - The clients to interconnect besides the config's.
+    /// * `credentials` - The provider to fetch referenced credentials from.
+    #[instrument(skip(config, extra_clients, credentials))]
+    pub async fn from_config_async(
+        config: PipeFitterConfig,
+        extra_clients: Vec<Client>,
+        credentials: Arc<dyn CredentialProvider>,
+    ) -> FitterResult<Self> {
+        let stream_configs = config.inherited_stream_configs();
+        let fetched = collect_errors(
+            join_all(stream_configs.iter().cloned().map(|mut stream_config| {
+                let credentials = Arc::clone(&credentials);
+                async move {
+                    stream_config
+                        .fetch_credentials(credentials.as_ref())
+                        .await?;
+                    Ok(stream_config)
+                }
+            }))
+            .await,
+        )?;
+        PipeFitter::from_stream_configs(config, stream_configs, fetched, extra_clients, credentials)
+    }
+
+    /// Build a stream manager from a config whose clients' credentials were fetched.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A stream manager config to load.
+    /// * `stream_configs` - The configs of the clients, keeping the references to credentials
+    ///   to restart them.
+    /// * `fetched` - The same configs, with the credentials they reference.
+    /// * `extra_clients` - The clients to interconnect besides the config's.
+    /// * `credentials` - The provider to fetch referenced credentials from on restarts.
+    fn from_stream_configs(
+        config: PipeFitterConfig,
+        stream_configs: Vec<ClientConfig>,
+        fetched: Vec<ClientConfig>,
+        extra_clients: Vec<Client>,
+        credentials: Arc<dyn CredentialProvider>,
+    ) -> FitterResult<Self> {
+        info!("Instantiating PipeFitter");
+        config.validate()?;
+
+        // Build clients, reporting every invalid one at once.
+        let mut client_configs = HashMap::new();
+        let mut clients = collect_errors(
+            stream_configs
+                .into_iter()
+                .zip(fetched)
+                .map(|(stream_config, fetched)| {
+                    info!("Loading client: {}", stream_config);
+                    let id = nanoid!();
+                    client_configs.insert(id.clone(), stream_config);
+                    ClientConfig::from_config(id, fetched)
+                })
+                .collect(),
+        )?;
+        clients.extend(extra_clients);
+
+        if clients.iter().all(|client| client.is_forward_only()) {
+            return Err(FitterErrorKind::ConfigParseError(
+                "All clients are forward_only; relay will be silent".to_string(),
+            )
+            .into());
+        }
+
+        PipeFitter::from_parts(config, clients, client_configs, credentials)
+    }
+
+    /// Build a stream manager interconnecting already built clients.
+    ///
+    /// The clients aren't part of the stream manager's config, so they are dropped by
+    /// `PipeFitter::reload_config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `clients` - The clients to interconnect.
+    #[instrument(skip(clients))]
+    pub fn from_clients(clients: Vec<Client>) -> FitterResult<Self> {
+        info!("Instantiating PipeFitter");
+        PipeFitter::from_parts(
+            PipeFitterConfig::default(),
+            clients,
+            HashMap::new(),
+            Arc::new(EnvCredentialProvider),
+        )
+    }
+
+    /// Interconnects the clients of a stream manager.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The stream manager config.
+    /// * `clients` - The stream manager's clients.
+    /// * `client_configs` - The configs of the clients built from one, by ID, to restart them.
+    /// * `credentials` - The provider to fetch referenced credentials from on restarts.
+    fn from_parts(
+        config: PipeFitterConfig,
+        mut clients: Vec<Client>,
+        client_configs: HashMap<String, ClientConfig>,
+        credentials: Arc<dyn CredentialProvider>,
+    ) -> FitterResult<Self> {
+        let loaded_config = config.clone();
+
+        // Find the client summaries are posted to
+        let summary = match config.summary {
+            Some(summary_config) => {
+                if summary_config.interval_minutes == 0 {
+                    return Err(FitterErrorKind::GenericErr(
+                        "Summary interval must be at least one minute".to_string(),
+                    )
+                    .into());
+                }
+
+                let target = clients
+                    .iter()
+                    .find(|client| client.get_name() == summary_config.target_client)
+                    .ok_or_else(|| {
+                        FitterErrorKind::GenericErr(format!(
+                            "Summary target client not found: {}",
+                            summary_config.target_client
+                        ))
+                    })?
+                    .get_stream()?;
+                let catalog =
+                    Catalog::from_config(config.locale.as_deref(), config.messages.as_ref())?;
+                Some((summary_config, target, catalog))
+            }
+            None => None,
+        };
+
+        if config.stall_timeout_seconds == Some(0) {
+            return Err(FitterErrorKind::GenericErr(
+                "Stall timeout must be at least one second".to_string(),
+            )
+            .into());
+        }
+
+        // Keep the clients' config watches to apply config changes without restarting
+        let config_watches = clients
+            .iter()
+            .map(|client| client.get_config_watch())
+            .collect();
+
+        // Keep the clients' streams to count the messages left to take when draining, weakly
+        // so clients still see them closed
+        let streams = clients
+            .iter()
+            .map(|client| Ok(client.get_stream()?.downgrade()))
+            .collect::<FitterResult<Vec<WeakSender<Message>>>>()?;
+
+        // Keep the clients' streams watched, so relays follow the new stream of a restarted
+        // client
+        let client_streams = clients
+            .iter()
+            .map(|client| {
+                Ok((
+                    client.get_id().to_string(),
+                    watch::Sender::new(client.get_stream()?),
+                ))
+            })
+            .collect::<FitterResult<HashMap<String, watch::Sender<Sender<Message>>>>>()?;
+        let watched = clients
+            .iter()
+            .map(|client| {
+                debug!("Mapping client: {} {}", client.get_name(), client.get_id());
+                WatchedClient {
+                    id: client.get_id().to_string(),
+                    name: client.get_name().to_string(),
+                    progress: Progress::new(),
+                }
+            })
+            .collect::<Vec<WatchedClient>>();
+
+        // Route each client through a relay and construct stream manager clients
+        let mut relays = Vec::new();
+        let mut connections = Vec::new();
+        let mut handles = HashMap::new();
+        let mut inputs = HashMap::new();
+        let connected = ConnectedBarrier::new(clients.len());
+        let flushed = FlushedBarrier::new(clients.len());
+        let pipe_fitter_clients = clients
+            .drain(..)
+            .zip(&watched)
+            .map(|(mut client, watched_client)| {
+                let (tx, rx) = channel(100);
+                // Weakly, so the relay still ends once the client stopped.
+                inputs.insert(watched_client.id.clone(), tx.downgrade());
+                client.add_stream(tx).unwrap();
+                let client_handles = ClientHandles {
+                    progress: watched_client.progress.clone(),
+                    connected: connected.handle(),
+                    flushed: flushed.handle(),
+                };
+                client_handles.hand_to(&mut client);
+                handles.insert(watched_client.id.clone(), client_handles);
+                let destinations = destinations_of(&watched_client.id, &watched, &client_streams);
+                connections.push(destinations.len());
+                relays.push(Relay {
+                    rx,
+                    destinations,
+                    progress: watched_client.progress.clone(),
+                });
+                Arc::new(Mutex::new(client))
+            })
+            .collect::<Vec<PipeFitterClient>>();
+        let (stall_tx, stall_rx) = unbounded_channel();
+
+        let clients_by_id = watched
+            .iter()
+            .map(|watched_client| watched_client.id.clone())
+            .zip(pipe_fitter_clients.iter().map(Arc::clone))
+            .collect();
+
+        Ok(PipeFitter {
+            clients: pipe_fitter_clients,
+            clients_by_id,
+            connections,
+            config_watches,
+            relays,
+            spam_classifier: None,
+            filters: Arc::new(FilterChain::new()),
+            summary,
+            recent: RecentMessages::new(config.recent_messages.unwrap_or(DEFAULT_RECENT_MESSAGES)),
+            relayed: RelayedIds::new(),
+            disconnected: DisconnectedClients::default(),
+            inputs,
+            canaries: CanaryTaps::default(),
+            watched,
+            stall_tx,
+            stall_rx: Some(stall_rx),
+            config: loaded_config,
+            credentials,
+            streams,
+            client_configs,
+            client_streams,
+            handles,
+            context: None,
+            client_tasks: HashMap::new(),
+            relay_tasks: Vec::new(),
+            draining: watch::Sender::new(false),
+            drained: Arc::new(AtomicUsize::new(0)),
+            paused: watch::Sender::new(false),
+            connected,
+            flushed,
+            tasks: JoinSet::new(),
+        })
+    }
+
+    /// Adds a filter applied to every message before it is relayed.
+    ///
+    /// Filters run in the order they were added.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter to add.
+    pub fn with_filter(mut self, filter: Box<dyn MessageFilter>) -> Self {
+        Arc::get_mut(&mut self.filters)
+            .expect("Filters can only be added before running")
+            .push(filter);
+        self
+    }
+
+    /// Scores relayed chat messages with a classifier instead of the default
+    /// `HeuristicClassifier`, with the configured `spam_classifier` threshold and action or
+    /// their defaults.
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> stream_fitter::errors::FitterResult<()> {
+    /// use std::sync::Arc;
+    ///
+    /// use stream_fitter::{
+    ///     clients::{
+    ///         client::{async_trait, Message},
+    ///         mock::MockClient,
+    ///     },
+    ///     pipe_fitter::{classifier::SpamClassifier, PipeFitter, PipeFitterConfig},
+    /// };
+    ///
+    /// struct GiveawayClassifier;
+    ///
+    /// #[async_trait]
+    /// impl SpamClassifier for GiveawayClassifier {
+    ///     async fn score(&self, msg: &Message) -> f32 {
+    ///         match msg.get_content().contains("giveaway") {
+    ///             true => 1.0,
+    ///             false => 0.0,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let config: PipeFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+    /// let (twitch, twitch_handle) = MockClient::build("twitch");
+    /// let (discord, mut discord_handle) = MockClient::build("discord");
+    /// let mut fitter = PipeFitter::from_config_with_clients(config, vec![twitch, discord])?
+    ///     .with_spam_classifier(Arc::new(GiveawayClassifier));
+    /// fitter.start();
+    ///
+    /// for content in ["Free giveaway, click here", "Hello!"] {
+    ///     twitch_handle
+    ///         .inject(Message::new(
+    ///             "twitch".to_string(),
+    ///             "#channel".to_string(),
+    ///             "viewer".to_string(),
+    ///             content.to_string(),
+    ///         ))
+    ///         .await?;
+    /// }
+    /// let received = discord_handle.recv().await.unwrap();
+    /// assert_eq!(received.get_content(), "Hello!");
+    ///
+    /// fitter.stop();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `classifier` - The classifier to score messages with.
+    pub fn with_spam_classifier(mut self, classifier: Arc<dyn SpamClassifier>) -> Self {
+        self.spam_classifier = Some(classifier);
+        self
+    }
+
+    /// Lists the filters applied to every message before it is relayed, in the order they run.
+    pub fn list_filters(&self) -> Vec<&dyn MessageFilter> {
+        self.filters.get_filters()
+    }
+
+    /// Returns a snapshot of the most recently relayed messages, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - The maximum number of messages to return.
+    pub fn recent_messages(&self, n: usize) -> Vec<Message> {
+        self.recent.snapshot(n)
+    }
+
+    /// Gets a client by its ID, without locking the other clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The client's ID.
+    pub fn client_by_id(&self, id: &str) -> Option<Arc<Mutex<Client>>> {
+        self.clients_by_id.get(id).map(Arc::clone)
+    }
+
+    /// Sends an action to a client, e.g. to delete a message or set a channel's topic, see
+    /// `ClientAction`.
+    ///
+    /// Clients that don't handle actions ignore it. Fails if no client has the ID, or if the
+    /// client stopped taking actions.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - The client's ID.
+    /// * `action` - The action to send.
+    #[instrument(skip(self))]
+    pub async fn send_action(&self, client_id: &str, action: ClientAction) -> FitterResult<()> {
+        let client = self.client_by_id(client_id).ok_or_else(|| {
+            FitterErrorKind::GenericErr(format!("No client with ID: {}", client_id))
+        })?;
+        let actions = match client.lock().await.get_action_stream() {
+            Some(actions) => actions,
+            None => {
+                debug!("Client doesn't handle actions, ignoring: {:?}", action);
+                return Ok(());
+            }
+        };
+
+        actions.send(action).await.map_err(|_| {
+            FitterErrorKind::GenericErr(format!("Client {} stopped taking actions", client_id))
+                .into()
+        })
+    }
+
+    /// Returns the names of the clients that stopped receiving messages, which are no longer
+    /// relayed to until the clients are rewired by `PipeFitter::reload_config`.
+    ///
+    /// Clients are told apart by ID, so clients sharing a name are each listed.
+    pub fn disconnected_clients(&self) -> Vec<String> {
+        self.disconnected.names()
+    }
+
+    /// Gets the barrier released once every client connected to its platform, e.g. to wait
+    /// for the relay to be ready after starting it.
+    ///
+    /// Clients rebuilt on a config reload get a new barrier, while a client restarted on its
+    /// own, e.g. once stalled, keeps its handle.
+    pub fn connected_barrier(&self) -> Arc<ConnectedBarrier> {
+        Arc::clone(&self.connected)
+    }
+
+    /// Finds the config snapshots to send when a new config only changes settings the running
+    /// clients can change without restarting.
+    ///
+    /// Returns the index of each changed client with its new snapshot, or `None` when the
+    /// clients need to be restarted.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The config to load.
+    fn get_snapshot_changes(
+        &self,
+        config: &PipeFitterConfig,
+    ) -> Option<Vec<(usize, ClientConfigSnapshot)>> {
+        if config.recent_messages != self.config.recent_messages
+            || config.summary != self.config.summary
+            || config.locale != self.config.locale
+            || config.messages != self.config.messages
+            || config.normalize_emoji != self.config.normalize_emoji
+            || config.drop_while_paused != self.config.drop_while_paused
+            || config.stream_configs.len() != self.config.stream_configs.len()
+            || self.config.stream_configs.len() != self.config_watches.len()
+        {
+            return None;
+        }
+
+        let mut changes = Vec::new();
+        for (idx, (old, new)) in self
+            .config
+            .stream_configs
+            .iter()
+            .zip(&config.stream_configs)
+            .enumerate()
+        {
+            if old == new {
+                continue;
+            }
+            if old.without_snapshot() != new.without_snapshot()
+                || self.config_watches[idx].is_none()
+            {
+                return None;
+            }
+            changes.push((idx, new.get_snapshot()));
+        }
+        Some(changes)
+    }
+
+    /// Replaces the running clients with ones built from a new config.
+    ///
+    /// The new config is fully validated first, so the current clients keep running if it
+    /// is invalid. Clients are only restarted when the config actually changed, and changes
+    /// to settings like the display name or profanity filter are sent to the running clients
+    /// through their config watch instead. Clients are always restarted when some stopped
+    /// receiving messages, to rewire them. Must be called from within the Tokio runtime the
+    /// stream manager was started on.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The stream manager config to load.
+    #[instrument(skip(self, config))]
+    pub async fn reload_config(&mut self, config: PipeFitterConfig) -> FitterResult<ReloadSummary> {
+        let rewire = !self.disconnected.is_empty();
+        if config == self.config && !rewire {
+            debug!("Config unchanged, not reloading");
+            return Ok(ReloadSummary {
+                added: 0,
+                removed: 0,
+                updated: 0,
+            });
+        }
+
+        if let Some(changes) = self.get_snapshot_changes(&config).filter(|_| !rewire) {
+            // Validate every snapshot before applying any.
+            for (_, snapshot) in &changes {
+                LiveSettings::from_snapshot(snapshot)?;
+            }
+
+            info!("Updating PipeFitter clients");
+            for (idx, snapshot) in &changes {
+                if let Some(config_watch) = &self.config_watches[*idx] {
+                    config_watch.send_replace(snapshot.clone());
+                }
+            }
+            self.config = config;
+            return Ok(ReloadSummary {
+                added: 0,
+                removed: 0,
+                updated: changes.len(),
+            });
+        }
+
+        info!("Reloading PipeFitter");
+        let summary = ReloadSummary::diff(&self.config, &config);
+        self.rebuild(config).await?;
+        Ok(summary)
+    }
+
+    /// Replaces the running clients with new ones built from a config, keeping the filters,
+    /// recent messages, runtime state and stall events.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The stream manager config to build from.
+    async fn rebuild(&mut self, config: PipeFitterConfig) -> FitterResult<()> {
+        let mut fitter =
+            PipeFitter::from_config_async(config, Vec::new(), Arc::clone(&self.credentials))
+                .await?;
+        fitter.spam_classifier = self.spam_classifier.clone();
+        fitter.filters = Arc::clone(&self.filters);
+        fitter.recent = self.recent.clone();
+        fitter.relayed = self.relayed.clone();
+        fitter.import_state(self.export_state());
+        fitter.stall_tx = self.stall_tx.clone();
+        fitter.stall_rx = self.stall_rx.take();
+        fitter.paused.send_replace(*self.paused.borrow());
+
+        self.stop();
+        *self = fitter;
+        self.start();
+        Ok(())
+    }
+
+    /// Exports the runtime state of the stream manager and its clients, e.g. to keep it across
+    /// a restart with `PipeFitter::import_state`.
+    ///
+    /// Clients locked elsewhere, e.g. while being started, don't export their state.
+    pub fn export_state(&self) -> FitterState {
+        let mut clients = HashMap::new();
+        for client in &self.clients {
+            match client.try_lock() {
+                Ok(client) => {
+                    if let Some(state) = client.export_state() {
+                        clients.insert(client.get_name().to_string(), state);
+                    }
+                }
+                Err(_) => warn!("Client locked, not exporting its state"),
+            }
+        }
+
+        FitterState {
+            exported_at: Utc::now(),
+            relayed_ids: self.relayed.snapshot(),
+            clients,
+        }
+    }
+
+    /// Imports runtime state exported by `PipeFitter::export_state`, matching clients by name.
+    ///
+    /// Must be called before the clients are started. State a client can't import is ignored
+    /// with a warning.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The exported state.
+    pub fn import_state(&mut self, mut state: FitterState) {
+        for id in state.relayed_ids {
+            self.relayed.insert(id);
+        }
+
+        for client in &self.clients {
+            let mut client = match client.try_lock() {
+                Ok(client) => client,
+                Err(_) => {
+                    warn!("Client locked, not importing its state");
+                    continue;
+                }
+            };
+            if let Some(client_state) = state.clients.remove(client.get_name()) {
+                if let Err(err) = client.import_state(client_state) {
+                    warn!(
+                        "Ignoring the state of {} {}: {}",
+                        client.get_name(),
+                        client.get_id(),
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Spawns the relays and clients on the current Tokio runtime.
+    #[instrument(skip(self))]
+    pub fn start(&mut self) {
+        info!("Starting PipeFitter");
+        let relays = self.relays.drain(..).collect::<Vec<Relay>>();
+        let summary = self.summary.take();
+        let context = RelayContext {
+            spam_classification: SpamClassification::new(
+                self.config.spam_classifier.as_ref(),
+                self.spam_classifier.clone(),
+            )
+            .map(Arc::new),
+            filters: Arc::clone(&self.filters),
+            recent: self.recent.clone(),
+            relayed: self.relayed.clone(),
+            summary: summary.as_ref().map(|_| Arc::new(SummaryStats::default())),
+            disconnected: self.disconnected.clone(),
+            canaries: self.canaries.clone(),
+            draining: self.draining.subscribe(),
+            drained: Arc::clone(&self.drained),
+            paused: self.paused.subscribe(),
+            drop_while_paused: self.config.drop_while_paused.unwrap_or_default(),
+            normalize_emoji: self.config.normalize_emoji.unwrap_or_default(),
+        };
+
+        if let (Some((summary_config, target, catalog)), Some(stats)) = (summary, &context.summary)
+        {
+            let stats = Arc::clone(stats);
+            self.tasks.spawn(async move {
+                summary_loop(summary_config, catalog, stats, target).await;
+                Ok(())
+            });
+        }
+
+        if let Some(timeout) = self.config.stall_timeout_seconds {
+            let watched = self.watched.clone();
+            let stall_tx = self.stall_tx.clone();
+            self.tasks.spawn(async move {
+                watchdog_loop(Duration::from_secs(timeout), watched, stall_tx).await;
+                Ok(())
+            });
+        }
+
+        for relay in relays {
+            self.spawn_relay(relay, context.clone());
+        }
+        self.context = Some(context);
+
+        let clients = self
+            .watched
+            .iter()
+            .map(|watched_client| watched_client.id.clone())
+            .zip(self.clients.iter().map(Arc::clone))
+            .collect::<Vec<(String, PipeFitterClient)>>();
+        for (id, client) in clients {
+            self.spawn_client(id, client);
+        }
+    }
+
+    /// Spawns a client's relay.
+    ///
+    /// # Arguments
+    ///
+    /// * `relay` - The client's relay.
+    /// * `context` - The state shared by all relays.
+    fn spawn_relay(&mut self, relay: Relay, context: RelayContext) {
+        let relay_task = self.tasks.spawn(async move {
+            relay_loop(relay, context).await;
+            Ok(())
+        });
+        self.relay_tasks.push(relay_task);
+    }
+
+    /// Spawns a client's main loop.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The client's ID.
+    /// * `client` - The client.
+    fn spawn_client(&mut self, id: String, client: PipeFitterClient) {
+        let panic_isolation = self.config.panic_isolation.unwrap_or_default();
+        let disconnected = self.disconnected.clone();
+        let client_id = id.clone();
+        let client_task = self.tasks.spawn(async move {
+            let (run, name) = {
+                let mut client = client.lock().await;
+                (client.run(), client.get_name().to_string())
+            };
+            let result = match panic_isolation {
+                true => run_isolated(name.clone(), run).await,
+                false => run.await,
+            };
+            if let Err(err) = &result {
+                error!("Stream error: {:?}", err);
+                if matches!(err.downcast_ref(), Some(FitterErrorKind::ClientPanic(_))) {
+                    disconnected.insert(&client_id, &name);
+                }
+            }
+            result
+        });
+        self.client_tasks.insert(id, client_task);
+    }
+
+    /// Replaces a running client with a new one built from its config, e.g. once it stalled,
+    /// keeping the other clients running.
+    ///
+    /// The new client gets the same ID and handles, and the relays follow its new stream.
+    /// Messages the stopped client hadn't sent yet are lost. Clients not built from the
+    /// config, e.g. passed to `PipeFitter::from_config_with_clients`, can't be rebuilt and
+    /// are left as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The client's ID.
+    #[instrument(skip(self))]
+    async fn restart_client(&mut self, id: &str) -> FitterResult<()> {
+        // Clients are rebuilt with new IDs on a config reload.
+        let idx = match self
+            .watched
+            .iter()
+            .position(|watched_client| watched_client.id == id)
+        {
+            Some(idx) => idx,
+            None => {
+                debug!("Client {} no longer running, not restarting it", id);
+                return Ok(());
+            }
+        };
+        let mut stream_config = match self.client_configs.get(id) {
+            Some(stream_config) => stream_config.clone(),
+            None => {
+                warn!(
+                    "Client {} wasn't built from the config, not restarting it",
+                    id
+                );
+                return Ok(());
+            }
+        };
+        let context = self.context.clone().ok_or_else(|| {
+            FitterErrorKind::InternalErr("Stream manager not started".to_string())
+        })?;
+
+        info!("Restarting client {}", id);
+        stream_config
+            .fetch_credentials(self.credentials.as_ref())
+            .await?;
+        let mut client = ClientConfig::from_config(id.to_string(), stream_config)?;
+        let (tx, rx) = channel(100);
+        self.inputs.insert(id.to_string(), tx.downgrade());
+        client.add_stream(tx)?;
+        let handles = &self.handles[id];
+        handles.hand_to(&mut client);
+        // The restart counts as progress, giving the new client the timeout to catch up.
+        handles.progress.record();
+        let relay = Relay {
+            rx,
+            destinations: destinations_of(id, &self.watched, &self.client_streams),
+            progress: handles.progress.clone(),
+        };
+
+        // Relayed to the new stream before the old client stops, so no relay sees it closed.
+        let stream = client.get_stream()?;
+        self.streams[idx] = stream.downgrade();
+        self.client_streams[id].send_replace(stream);
+        self.config_watches[idx] = client.get_config_watch();
+        if let Some(client_task) = self.client_tasks.remove(id) {
+            client_task.abort();
+        }
+        *self.clients[idx].lock().await = client;
+        self.disconnected.remove(id);
+
+        self.spawn_relay(relay, context);
+        self.spawn_client(id.to_string(), Arc::clone(&self.clients[idx]));
+        Ok(())
+    }
+
+    /// Aborts the running relays and clients.
+    #[instrument(skip(self))]
+    pub fn stop(&mut self) {
+        info!("Stopping PipeFitter");
+        self.relay_tasks.clear();
+        self.client_tasks.clear();
+        self.tasks.abort_all();
+    }
+
+    /// Pauses relaying between all clients, keeping them connected so resuming is instant.
+    ///
+    /// Messages received while paused are held in the clients' streams until resumed, or
+    /// dropped with `drop_while_paused`. Clients whose stream is full wait to send more.
+    #[instrument(skip(self))]
+    pub fn pause(&self) {
+        info!("Pausing PipeFitter");
+        self.paused.send_replace(true);
+    }
+
+    /// Resumes relaying paused by `PipeFitter::pause`.
+    #[instrument(skip(self))]
+    pub fn resume(&self) {
+        info!("Resuming PipeFitter");
+        self.paused.send_replace(false);
+    }
+
+    /// Checks whether relaying is paused, see `PipeFitter::pause`.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Stops taking new messages from the clients, then waits for the messages already
+    /// received to be relayed and taken by the clients they're relayed to before stopping the
+    /// stream manager, e.g. on shutdown. The runtime state is then written to the
+    /// `state_file`, if configured.
+    ///
+    /// Once relayed, the clients are signaled to flush the messages they took, e.g. Discord's
+    /// pending digests, and waited for.
+    ///
+    /// Returns the number of messages the clients took while draining. Messages left when the
+    /// timeout expires are logged with their count by client, and lost along with messages
+    /// still being sent by a client.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest time to wait for the relays and clients.
+    #[instrument(skip(self))]
+    pub async fn drain(&mut self, timeout: Duration) -> usize {
+        info!("Draining PipeFitter");
+        let deadline = Instant::now() + timeout;
+        let pending = self.pending_messages().into_iter().sum::<usize>();
+        self.draining.send_replace(true);
+
+        loop {
+            let relaying = self.relay_tasks.iter().any(|task| !task.is_finished());
+            let remaining = self.pending_messages().into_iter().sum::<usize>();
+            if (!relaying && remaining == 0) || Instant::now() >= deadline {
+                break;
+            }
+            sleep(DRAIN_POLL_INTERVAL).await;
+        }
+
+        if self.relay_tasks.iter().any(|task| !task.is_finished()) {
+            warn!("Received messages left to relay before stopping");
+        }
+        let mut remaining = 0;
+        for (client, count) in self.clients.iter().zip(self.pending_messages()) {
+            if count > 0 {
+                let client = client.lock().await;
+                warn!(
+                    "{} messages to {} {} not taken before stopping",
+                    count,
+                    client.get_name(),
+                    client.get_id()
+                );
+            }
+            remaining += count;
+        }
+
+        // Clients flush what they took, e.g. pending digests, before they're stopped.
+        self.flushed.flush();
+        if timeout_at(deadline, self.flushed.wait()).await.is_err() {
+            warn!("Clients left to flush before stopping");
+        }
+
+        self.stop();
+        if let Some(state_file) = &self.config.state_file {
+            if let Err(err) = self.export_state().save(state_file).await {
+                error!("Error saving state file {}: {}", state_file.display(), err);
+            }
+        }
+        (pending + self.drained.load(Ordering::Relaxed)).saturating_sub(remaining)
+    }
+
+    /// Counts the messages relayed to each client that it hasn't taken yet.
+    fn pending_messages(&self) -> Vec<usize> {
+        self.streams
+            .iter()
+            .map(|stream| match stream.upgrade() {
+                Some(stream) => stream.max_capacity() - stream.capacity(),
+                None => 0,
+            })
+            .collect()
+    }
+
+    /// Sends a canary message from one client to another through the relay, e.g. to smoke
+    /// test a running stream manager, returning the time it took to reach the target.
+    ///
+    /// The canary is sent as if the source client received it, and observed when relayed to
+    /// the target client, without being delivered to it. Fails with a
+    /// `FitterErrorKind::Timeout` if the canary doesn't reach the target in time, e.g. while
+    /// paused or once the target stopped receiving.
+    ///
+    /// # Arguments
+    ///
+    /// * `source_id` - The ID of the client sending the canary.
+    /// * `target_id` - The ID of the client the canary is relayed to.
+    /// * `timeout` - The longest time to wait for the canary.
+    #[instrument(skip(self))]
+    pub async fn test_message_roundtrip(
+        &self,
+        source_id: &str,
+        target_id: &str,
+        timeout: Duration,
+    ) -> FitterResult<Duration> {
+        if source_id == target_id || !self.clients_by_id.contains_key(target_id) {
+            return Err(FitterErrorKind::GenericErr(format!(
+                "No client other than the source with ID: {}",
+                target_id
+            ))
+            .into());
+        }
+        let input = self
+            .inputs
+            .get(source_id)
+            .ok_or_else(|| {
+                FitterErrorKind::GenericErr(format!("No client with ID: {}", source_id))
+            })?
+            .upgrade()
+            .ok_or_else(|| {
+                FitterErrorKind::GenericErr(format!("Client {} stopped sending", source_id))
+            })?;
+
+        let token = nanoid!();
+        let canary = Message::new(
+            "stream-fitter".to_string(),
+            String::new(),
+            "stream-fitter".to_string(),
+            format!("Canary {}", token),
+        )
+        .with_metadata(CANARY, token.clone());
+        let tapped = self.canaries.tap(&token, target_id);
+
+        let start = Instant::now();
+        let roundtrip = async {
+            input.send(canary).await.ok()?;
+            tapped.await.ok()
+        };
+        match tokio::time::timeout(timeout, roundtrip).await {
+            Ok(Some(())) => Ok(start.elapsed()),
+            _ => {
+                self.canaries.remove(&token);
+                Err(FitterErrorKind::Timeout(format!(
+                    "Canary from {} to {} not relayed within {:?}",
+                    source_id, target_id, timeout
+                ))
+                .into())
+            }
+        }
+    }
+
+    /// Probes each client's platform with its credentials, without joining any channel.
+    ///
+    /// Returns each client's name with the result of its probe. Must be called before the
+    /// clients are started.
+    #[instrument(skip(self))]
+    pub async fn test_connectivity(&self) -> Vec<(String, FitterResult<()>)> {
+        let mut probes = Vec::new();
+        for client in &self.clients {
+            let client = client.lock().await;
+            probes.push((client.get_name().to_string(), client.test_connectivity()));
+        }
+
+        let (names, probes): (Vec<String>, Vec<_>) = probes.into_iter().unzip();
+        names.into_iter().zip(join_all(probes).await).collect()
+    }
+
+    /// Probes each client's platform on a new Tokio runtime, see
+    /// `PipeFitter::test_connectivity`.
+    #[instrument(skip(self))]
+    pub fn run_connectivity_test(&self) -> FitterResult<Vec<(String, FitterResult<()>)>> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        Ok(runtime.block_on(self.test_connectivity()))
+    }
+
+    /// Run the stream manager on a new Tokio runtime, see `PipeFitter::serve`.
+    #[instrument(skip(self))]
+    pub fn run(&mut self) -> FitterResult<()> {
+        info!("Running PipeFitter");
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(self.serve())
+    }
+
+    /// Run the stream manager on the current Tokio runtime until every task stopped,
+    /// returning the first client error.
+    ///
+    /// Stops right away with the error of a client whose platform rejected its credentials,
+    /// e.g. a `FitterErrorKind::AuthErr` for a bad token, or of any client with
+    /// `abort_on_client_error`. Restarts a client stopping with any other error, e.g. with
+    /// `SendErrorStrategy::Fail`, after a delay growing while it keeps failing, and a client
+    /// that stalled, with `stall_timeout_seconds`, keeping the other ones running. The runtime state is
+    /// first loaded from the `state_file`, if configured and recent enough.
+    #[instrument(skip(self))]
+    pub async fn serve(&mut self) -> FitterResult<()> {
+        if let Some(state_file) = &self.config.state_file {
+            let max_age = self
+                .config
+                .state_max_age_seconds
+                .map_or(DEFAULT_STATE_MAX_AGE, Duration::from_secs);
+            if let Some(state) = FitterState::load(state_file, max_age) {
+                info!("Loaded state exported at {}", state.exported_at);
+                self.import_state(state);
+            }
+        }
+        self.start();
+        let abort_on_client_error = self.config.abort_on_client_error.unwrap_or_default();
+        let mut stalls = self.stall_rx.take();
+        let backoff = Backoff::new();
+        let mut retries = HashMap::<String, (Delays, Instant)>::new();
+        let mut restarts = JoinSet::<String>::new();
+        let mut first_err = None;
+        let mut announced = false;
+        // Clients waiting to restart keep the stream manager running.
+        while !self.tasks.is_empty() || !restarts.is_empty() {
+            let connected = Arc::clone(&self.connected);
+            let stall = async {
+                match &mut stalls {
+                    Some(stalls) => stalls.recv().await,
+                    None => pending().await,
+                }
+            };
+            let (task_id, result) = tokio::select! {
+                Some(result) = self.tasks.join_next_with_id() => match result {
+                    Ok((task_id, result)) => (Some(task_id), Ok(result)),
+                    Err(err) => (Some(err.id()), Err(err)),
+                },
+                _ = connected.wait(), if !announced => {
+                    info!("All clients connected");
+                    announced = true;
+                    continue;
+                }
+                Some(stalled) = stall => {
+                    warn!("{}, restarting it", stalled);
+                    if let Err(err) = self.restart_client(&stalled.id).await {
+                        error!("Error restarting {} {}: {}", stalled.name, stalled.id, err);
+                    }
+                    continue;
+                }
+                Some(Ok(id)) = restarts.join_next() => {
+                    if let Some((_, restarted_at)) = retries.get_mut(&id) {
+                        *restarted_at = Instant::now();
+                    }
+                    if let Err(err) = self.restart_client(&id).await {
+                        error!("Error restarting {}: {}", id, err);
+                    }
+                    continue;
+                }
+            };
+
+            let err = match result {
+                Ok(Ok(())) => continue,
+                Ok(Err(err)) => err,
+                Err(err) if err.is_cancelled() => continue,
+                Err(err) => FitterErrorKind::InternalErr(format!("Task failed: {}", err)).into(),
+            };
+
+            // Rejected credentials won't fix themselves, the stream manager always stops.
+            let rejected = matches!(err.downcast_ref(), Some(FitterErrorKind::AuthErr(_)));
+            if abort_on_client_error || rejected {
+                self.tasks.abort_all();
+                return Err(err);
+            }
+            let client_id = self
+                .client_tasks
+                .iter()
+                .find(|(_, task)| Some(task.id()) == task_id)
+                .map(|(id, _)| id.clone())
+                .filter(|id| self.client_configs.contains_key(id));
+            if let Some(id) = client_id {
+                let (delays, restarted_at) = retries
+                    .entry(id.clone())
+                    .or_insert_with(|| (backoff.delays(), Instant::now()));
+                if restarted_at.elapsed() > RESTART_RESET {
+                    *delays = backoff.delays();
+                }
+                let delay = delays.next().unwrap_or_default();
+                warn!(
+                    "Client {} stopped with an error, restarting it in {:?}",
+                    id, delay
+                );
+                restarts.spawn(async move {
+                    sleep(delay).await;
+                    id
+                });
+                continue;
+            }
+            first_err.get_or_insert(err);
+        }
+        first_err.map_or(Ok(()), Err)
+    }
+}
@@ -0,0 +1,347 @@
+//! Runs several independent stream managers in one process, sharing one Tokio runtime.
+//!
+//! Each fitter builds and wires its own clients, so clients of different fitters are never
+//! connected, and runs in its own task with its logs in a span named after it, so one fitter
+//! stopping doesn't stop the others.
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use serde::de::Error as DeError;
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml::{from_value, Value};
+use tokio::{
+    sync::oneshot::{channel, Receiver, Sender},
+    task::JoinSet,
+};
+use tracing::{error, info, info_span, instrument, Instrument};
+
+use crate::{
+    errors::{collect_errors, FitterErrorKind, FitterResult},
+    pipe_fitter::{PipeFitter, PipeFitterConfig},
+};
+
+/// Name of the fitter of a single fitter config.
+const DEFAULT_FITTER_NAME: &str = "default";
+
+/// Config of one of several fitters.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct NamedFitterConfig {
+    /// Unique name of the fitter, shown in its logs.
+    pub name: String,
+    /// The fitter's config.
+    #[serde(flatten)]
+    pub config: PipeFitterConfig,
+}
+
+/// Top-level config, either a single fitter or several named ones.
+///
+/// Configs with a `fitters` key are several fitters, others a single one.
+#[derive(Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MultiFitterConfig {
+    /// Several independent fitters, under `fitters`.
+    Multi { fitters: Vec<NamedFitterConfig> },
+    /// A single fitter, named `default`.
+    Single(Box<PipeFitterConfig>),
+}
+
+/// Config of several fitters, under `fitters`.
+#[derive(Deserialize)]
+struct FittersConfig {
+    fitters: Vec<NamedFitterConfig>,
+}
+
+impl<'de> serde::Deserialize<'de> for MultiFitterConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Picking the variant by key, rather than trying each, keeps its errors.
+        let value = <Value as serde::Deserialize>::deserialize(deserializer)?;
+        match value.get("fitters").is_some() {
+            true => from_value::<FittersConfig>(value)
+                .map(|config| MultiFitterConfig::Multi {
+                    fitters: config.fitters,
+                })
+                .map_err(DeError::custom),
+            false => from_value(value)
+                .map(MultiFitterConfig::Single)
+                .map_err(DeError::custom),
+        }
+    }
+}
+
+impl MultiFitterConfig {
+    /// Gets the configs of the fitters to run, with their names.
+    pub fn get_fitters(&self) -> Vec<(&str, &PipeFitterConfig)> {
+        match self {
+            MultiFitterConfig::Multi { fitters } => fitters
+                .iter()
+                .map(|fitter| (fitter.name.as_str(), &fitter.config))
+                .collect(),
+            MultiFitterConfig::Single(config) => vec![(DEFAULT_FITTER_NAME, config)],
+        }
+    }
+
+    /// Gets the log levels of every fitter as filter directives, see
+    /// `PipeFitterConfig::get_log_directives`.
+    pub fn get_log_directives(&self) -> FitterResult<Vec<String>> {
+        let mut directives = Vec::new();
+        for (_, config) in self.get_fitters() {
+            for directive in config.get_log_directives()? {
+                if !directives.contains(&directive) {
+                    directives.push(directive);
+                }
+            }
+        }
+        directives.sort();
+        Ok(directives)
+    }
+}
+
+/// Status of a fitter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FitterStatus {
+    /// Not started yet.
+    Pending,
+    /// Relaying.
+    Running,
+    /// Stopped without error, e.g. shut down.
+    Stopped,
+    /// Stopped with an error.
+    Failed(String),
+}
+
+impl Display for FitterStatus {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            FitterStatus::Pending => write!(f, "pending"),
+            FitterStatus::Running => write!(f, "running"),
+            FitterStatus::Stopped => write!(f, "stopped"),
+            FitterStatus::Failed(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// A fitter and the handles to follow and stop it.
+struct ManagedFitter {
+    name: String,
+    fitter: Option<PipeFitter>,
+    status: Arc<StdMutex<FitterStatus>>,
+    shutdown: Option<Sender<()>>,
+}
+
+/// Manager of several independent fitters.
+pub struct MultiFitter {
+    fitters: Vec<ManagedFitter>,
+    tasks: JoinSet<FitterResult<()>>,
+}
+
+/// Runs a fitter until it stops or is shut down, tracking its status.
+///
+/// # Arguments
+///
+/// * `fitter` - The fitter to run.
+/// * `status` - The fitter's status.
+/// * `shutdown` - Stops the fitter once sent to or dropped.
+async fn fitter_loop(
+    mut fitter: PipeFitter,
+    status: Arc<StdMutex<FitterStatus>>,
+    shutdown: Receiver<()>,
+) -> FitterResult<()> {
+    *status.lock().unwrap() = FitterStatus::Running;
+    let result = tokio::select! {
+        result = fitter.serve() => result,
+        _ = shutdown => {
+            info!("Shutting down");
+            fitter.stop();
+            Ok(())
+        }
+    };
+
+    *status.lock().unwrap() = match &result {
+        Ok(()) => FitterStatus::Stopped,
+        Err(err) => {
+            error!("Fitter stopped: {}", err);
+            FitterStatus::Failed(err.to_string())
+        }
+    };
+    result
+}
+
+impl MultiFitter {
+    /// Builds every fitter of a config, reporting every invalid one at once.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The top-level config.
+    #[instrument(skip(config))]
+    pub fn from_config(config: MultiFitterConfig) -> FitterResult<Self> {
+        info!("Instantiating MultiFitter");
+        let fitters = collect_errors(
+            config
+                .get_fitters()
+                .into_iter()
+                .map(|(name, config)| {
+                    let fitter = PipeFitter::from_config(config.clone()).map_err(|err| {
+                        FitterErrorKind::GenericErr(format!("Fitter {}: {}", name, err))
+                    })?;
+                    Ok((name.to_string(), fitter))
+                })
+                .collect(),
+        )?;
+        MultiFitter::from_fitters(fitters)
+    }
+
+    /// Manages already built fitters, e.g. interconnecting clients not built from a config.
+    ///
+    /// # Arguments
+    ///
+    /// * `fitters` - The fitters, with their unique names.
+    pub fn from_fitters(fitters: Vec<(String, PipeFitter)>) -> FitterResult<Self> {
+        let mut names = HashSet::new();
+        for (name, _) in &fitters {
+            if !names.insert(name) {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Duplicate fitter name: {}",
+                    name
+                ))
+                .into());
+            }
+        }
+
+        Ok(MultiFitter {
+            fitters: fitters
+                .into_iter()
+                .map(|(name, fitter)| ManagedFitter {
+                    name,
+                    fitter: Some(fitter),
+                    status: Arc::new(StdMutex::new(FitterStatus::Pending)),
+                    shutdown: None,
+                })
+                .collect(),
+            tasks: JoinSet::new(),
+        })
+    }
+
+    /// Gets the names of the fitters.
+    pub fn get_names(&self) -> Vec<&str> {
+        self.fitters
+            .iter()
+            .map(|fitter| fitter.name.as_str())
+            .collect()
+    }
+
+    /// Gets the status of every fitter.
+    pub fn statuses(&self) -> Vec<(String, FitterStatus)> {
+        self.fitters
+            .iter()
+            .map(|fitter| (fitter.name.clone(), fitter.status.lock().unwrap().clone()))
+            .collect()
+    }
+
+    /// Spawns every fitter not started yet on the current Tokio runtime.
+    #[instrument(skip(self))]
+    pub fn start(&mut self) {
+        info!("Starting MultiFitter");
+        for managed in &mut self.fitters {
+            let fitter = match managed.fitter.take() {
+                Some(fitter) => fitter,
+                None => continue,
+            };
+
+            let (shutdown_tx, shutdown_rx) = channel();
+            managed.shutdown = Some(shutdown_tx);
+            let span = info_span!("fitter", name = %managed.name);
+            self.tasks.spawn(
+                fitter_loop(fitter, Arc::clone(&managed.status), shutdown_rx).instrument(span),
+            );
+        }
+    }
+
+    /// Stops a running fitter, leaving the others running.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The fitter's name.
+    #[instrument(skip(self))]
+    pub fn shutdown(&mut self, name: &str) -> FitterResult<()> {
+        let managed = self
+            .fitters
+            .iter_mut()
+            .find(|fitter| fitter.name == name)
+            .ok_or_else(|| FitterErrorKind::GenericErr(format!("Unknown fitter: {}", name)))?;
+
+        match managed.shutdown.take() {
+            Some(shutdown) => {
+                let _ = shutdown.send(());
+                Ok(())
+            }
+            None => {
+                Err(FitterErrorKind::GenericErr(format!("Fitter {} isn't running", name)).into())
+            }
+        }
+    }
+
+    /// Waits for every started fitter to stop, returning the errors of the failed ones.
+    pub async fn join(&mut self) -> FitterResult<()> {
+        let mut results = Vec::new();
+        while let Some(result) = self.tasks.join_next().await {
+            results.push(result.unwrap_or_else(|err| {
+                Err(FitterErrorKind::InternalErr(format!("Fitter task failed: {}", err)).into())
+            }));
+        }
+        collect_errors(results).map(|_| ())
+    }
+
+    /// Runs every fitter on a new Tokio runtime until they all stopped.
+    #[instrument(skip(self))]
+    pub fn run(&mut self) -> FitterResult<()> {
+        info!("Running MultiFitter");
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(async {
+            self.start();
+            self.join().await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_single_and_several_fitters() {
+        let single: MultiFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+        assert_eq!(single.get_fitters()[0].0, DEFAULT_FITTER_NAME);
+
+        let multi: MultiFitterConfig = serde_yaml::from_str(
+            "fitters:\n  - name: first\n    stream_configs: []\n  - name: second\n    \
+             stream_configs: []\n",
+        )
+        .unwrap();
+        let names = multi
+            .get_fitters()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["first", "second"]);
+    }
+
+    #[test]
+    fn keeps_the_error_of_the_fitters() {
+        let err = serde_yaml::from_str::<MultiFitterConfig>("fitters:\n  - stream_configs: []\n")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("missing field `name`"), "{}", err);
+
+        let err = serde_yaml::from_str::<MultiFitterConfig>(
+            "stream_configs: []\nrecent_messages: lots\n",
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("expected usize"), "{}", err);
+    }
+}
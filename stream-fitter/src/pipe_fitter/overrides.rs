@@ -0,0 +1,356 @@
+//! Structured overrides applied onto a parsed config, e.g. from command line flags.
+//!
+//! An override is written `<path>=<yaml-value>`. Paths are dotted keys, and list items are
+//! selected with `[<index>]` or `[<name>]`, e.g. `stream_configs[0].channels=["test"]` or
+//! `stream_configs[mybot].isolate_channels=true`.
+use std::str::FromStr;
+
+use serde_yaml::{from_str, from_value, Mapping, Value};
+
+use crate::{
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::PipeFitterConfig,
+};
+
+/// Keys holding secrets, redacted by `redact_secrets`.
+const SECRET_KEYS: &[&str] = &["token", "client_secret"];
+/// Placeholder replacing redacted secrets.
+const REDACTED: &str = "[REDACTED]";
+
+/// A value replacing the one at a path of the config.
+#[derive(Clone, Debug)]
+pub struct ConfigOverride {
+    path: String,
+    segments: Vec<String>,
+    value: Value,
+}
+
+impl ConfigOverride {
+    /// Gets the path as it was written.
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl FromStr for ConfigOverride {
+    type Err = crate::errors::FitterError;
+
+    fn from_str(s: &str) -> FitterResult<Self> {
+        let (path, value) = s.split_once('=').ok_or_else(|| {
+            FitterErrorKind::GenericErr(format!(
+                "Invalid override {:?}, expected <path>=<yaml-value>",
+                s
+            ))
+        })?;
+
+        Ok(ConfigOverride {
+            path: path.to_string(),
+            segments: parse_path(path)?,
+            value: from_str(value).map_err(|err| {
+                FitterErrorKind::GenericErr(format!("Invalid value for override {}: {}", path, err))
+            })?,
+        })
+    }
+}
+
+/// Splits an override path into keys, indices and names.
+///
+/// # Arguments
+///
+/// * `path` - The override path.
+fn parse_path(path: &str) -> FitterResult<Vec<String>> {
+    let invalid = || FitterErrorKind::GenericErr(format!("Invalid override path {:?}", path));
+
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut selectors) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if !key.is_empty() {
+            segments.push(key.to_string());
+        } else if selectors.is_empty() {
+            return Err(invalid().into());
+        }
+
+        while !selectors.is_empty() {
+            let end = match selectors.find(']') {
+                Some(end) if selectors.starts_with('[') && end > 1 => end,
+                _ => return Err(invalid().into()),
+            };
+            segments.push(selectors[1..end].to_string());
+            selectors = &selectors[end + 1..];
+        }
+    }
+    Ok(segments)
+}
+
+/// Checks whether a list item is a mapping with a `name`, `client_name_override` or
+/// `display_client` key.
+///
+/// # Arguments
+///
+/// * `item` - The list item.
+/// * `name` - The name to look for.
+fn has_name(item: &Value, name: &str) -> bool {
+    ["name", "client_name_override", "display_client"]
+        .iter()
+        .any(|key| item.get(*key).and_then(Value::as_str) == Some(name))
+}
+
+/// Gets the value at a segment of a path, adding missing mapping keys.
+///
+/// # Arguments
+///
+/// * `value` - The mapping or list to look in.
+/// * `segment` - The key, index or name to look up.
+/// * `path` - The override path, for errors.
+fn get_segment<'a>(value: &'a mut Value, segment: &str, path: &str) -> FitterResult<&'a mut Value> {
+    if value.is_null() {
+        *value = Value::Mapping(Mapping::new());
+    }
+
+    match value {
+        Value::Mapping(mapping) => {
+            let key = Value::String(segment.to_string());
+            if !mapping.contains_key(&key) {
+                mapping.insert(key.clone(), Value::Null);
+            }
+            Ok(mapping.get_mut(&key).unwrap())
+        }
+        Value::Sequence(sequence) => {
+            let idx = match segment.parse::<usize>() {
+                Ok(idx) => idx,
+                Err(_) => sequence
+                    .iter()
+                    .position(|item| has_name(item, segment))
+                    .ok_or_else(|| {
+                        FitterErrorKind::GenericErr(format!(
+                            "Invalid override path {}: no item named {:?}",
+                            path, segment
+                        ))
+                    })?,
+            };
+            let len = sequence.len();
+            sequence.get_mut(idx).ok_or_else(|| {
+                FitterErrorKind::GenericErr(format!(
+                    "Invalid override path {}: index {} out of {} items",
+                    path, idx, len
+                ))
+                .into()
+            })
+        }
+        _ => Err(FitterErrorKind::GenericErr(format!(
+            "Invalid override path {}: {:?} is not in a mapping or list",
+            path, segment
+        ))
+        .into()),
+    }
+}
+
+/// Applies overrides onto a parsed config, in order.
+///
+/// The config is validated after each override, so an invalid value is reported with its path.
+///
+/// # Arguments
+///
+/// * `config` - The parsed config to modify.
+/// * `overrides` - The overrides to apply.
+pub fn apply_overrides(config: &mut Value, overrides: &[ConfigOverride]) -> FitterResult<()> {
+    for config_override in overrides {
+        let mut target = &mut *config;
+        for segment in &config_override.segments {
+            target = get_segment(target, segment, &config_override.path)?;
+        }
+        *target = config_override.value.clone();
+
+        from_value::<PipeFitterConfig>(config.clone()).map_err(|err| {
+            FitterErrorKind::GenericErr(format!(
+                "Invalid override {}: {}",
+                config_override.path, err
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// Replaces literal secrets of a parsed config with a placeholder, e.g. before printing it.
+///
+/// # Arguments
+///
+/// * `config` - The parsed config to redact.
+pub fn redact_secrets(config: &mut Value) {
+    match config {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                let secret = key.as_str().is_some_and(|key| SECRET_KEYS.contains(&key));
+                if secret && value.is_string() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// Removes the unset options of a serialized config, e.g. before printing it.
+///
+/// # Arguments
+///
+/// * `config` - The serialized config to prune.
+pub fn drop_unset(config: &mut Value) {
+    match config {
+        Value::Mapping(mapping) => {
+            let unset: Vec<Value> = mapping
+                .iter()
+                .filter(|(_, value)| value.is_null())
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in unset {
+                mapping.remove(&key);
+            }
+            mapping.iter_mut().for_each(|(_, value)| drop_unset(value));
+        }
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(drop_unset),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config with two Twitch clients, the second one named `second`.
+    const CONFIG: &str = "\
+recent_messages: 10
+stream_configs:
+  - name: first
+    token: first_token
+    channels: [one]
+  - name: second
+    token: second_token
+    channels: [two]
+";
+
+    /// Applies overrides onto `CONFIG`, returning the resulting config.
+    ///
+    /// # Arguments
+    ///
+    /// * `overrides` - The overrides, written `<path>=<yaml-value>`.
+    fn apply(overrides: &[&str]) -> FitterResult<Value> {
+        let mut config = from_str(CONFIG).unwrap();
+        let overrides = overrides
+            .iter()
+            .map(|config_override| config_override.parse())
+            .collect::<FitterResult<Vec<ConfigOverride>>>()?;
+        apply_overrides(&mut config, &overrides)?;
+        Ok(config)
+    }
+
+    /// Gets the message of an override's error.
+    ///
+    /// # Arguments
+    ///
+    /// * `overrides` - The overrides, written `<path>=<yaml-value>`.
+    fn apply_err(overrides: &[&str]) -> String {
+        apply(overrides).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_paths() {
+        assert_eq!(
+            parse_path("stream_configs[0][second].channels").unwrap(),
+            vec!["stream_configs", "0", "second", "channels"]
+        );
+        assert_eq!(parse_path("[1].name").unwrap(), vec!["1", "name"]);
+        for path in ["", "a..b", "a[0", "a[]", "a[0]b", "a.[]"] {
+            assert!(parse_path(path).is_err(), "{:?} parsed", path);
+        }
+    }
+
+    #[test]
+    fn rejects_overrides_without_value() {
+        assert!("recent_messages".parse::<ConfigOverride>().is_err());
+        assert!("recent_messages=[".parse::<ConfigOverride>().is_err());
+        let config_override = "recent_messages=5".parse::<ConfigOverride>().unwrap();
+        assert_eq!(config_override.get_path(), "recent_messages");
+    }
+
+    #[test]
+    fn replaces_scalars() {
+        let config = apply(&["recent_messages=5"]).unwrap();
+        assert_eq!(config["recent_messages"], Value::from(5));
+    }
+
+    #[test]
+    fn selects_list_items_by_index() {
+        let config = apply(&["stream_configs[0].channels=[test]"]).unwrap();
+        assert_eq!(
+            config["stream_configs"][0]["channels"][0],
+            Value::from("test")
+        );
+        assert_eq!(
+            config["stream_configs"][1]["channels"][0],
+            Value::from("two")
+        );
+    }
+
+    #[test]
+    fn selects_list_items_by_name() {
+        let config = apply(&["stream_configs[second].isolate_channels=true"]).unwrap();
+        assert_eq!(
+            config["stream_configs"][1]["isolate_channels"],
+            Value::from(true)
+        );
+        assert!(config["stream_configs"][0]
+            .get("isolate_channels")
+            .is_none());
+    }
+
+    #[test]
+    fn adds_nested_maps() {
+        let config = apply(&[
+            "log_levels.stream_fitter=debug",
+            "stream_configs[first].user_aliases.some_viewer=Alice",
+        ])
+        .unwrap();
+        assert_eq!(config["log_levels"]["stream_fitter"], Value::from("debug"));
+        assert_eq!(
+            config["stream_configs"][0]["user_aliases"]["some_viewer"],
+            Value::from("Alice")
+        );
+    }
+
+    #[test]
+    fn applies_in_order() {
+        let config = apply(&["recent_messages=5", "recent_messages=6"]).unwrap();
+        assert_eq!(config["recent_messages"], Value::from(6));
+    }
+
+    #[test]
+    fn reports_bad_paths() {
+        assert!(apply_err(&["stream_configs[2].isolate_channels=true"])
+            .contains("index 2 out of 2 items"));
+        assert!(apply_err(&["stream_configs[third].isolate_channels=true"])
+            .contains("no item named \"third\""));
+        assert!(apply_err(&["recent_messages.limit=5"]).contains("not in a mapping or list"));
+    }
+
+    #[test]
+    fn reports_invalid_values_with_their_path() {
+        let err = apply_err(&["recent_messages=many"]);
+        assert!(err.contains("Invalid override recent_messages:"), "{}", err);
+    }
+
+    #[test]
+    fn drops_unset_options() {
+        let mut config: Value =
+            from_str("recent_messages: ~\nstream_configs:\n  - token: abc\n    prefix: ~\n")
+                .unwrap();
+        drop_unset(&mut config);
+        assert_eq!(
+            config,
+            from_str::<Value>("stream_configs:\n  - token: abc\n").unwrap()
+        );
+    }
+}
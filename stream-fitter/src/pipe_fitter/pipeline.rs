@@ -0,0 +1,230 @@
+//! Ordered transformation stages applied by clients to the messages they receive.
+//!
+//! Each client runs its stages in the order of its `pipeline` config, so e.g. sanitizing can
+//! happen before or after profanity filtering. Pipelines are receive-only: the messages a
+//! client sends were already transformed by the pipeline of the client receiving them.
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    clients::client::Message,
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::{
+        filter::{FilterAction, FilterChain, MessageFilter, WordCountFilter},
+        priority::PriorityFlagger,
+        profanity::{ProfanityFilter, ProfanityFilterMode},
+        sample::{SampleConfig, Sampler},
+        spam::{SpamFilter, SpamFilterConfig},
+    },
+};
+
+/// Transformation stage of a client's pipeline.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineStage {
+    /// The client's `profanity_filter`.
+    Profanity,
+    /// Removes control characters.
+    Sanitize,
+    /// Trims surrounding whitespace, dropping empty messages.
+    Trim,
+    /// The client's `spam_filter`.
+    Spam,
+    /// The client's `sample`.
+    Sample,
+    /// The client's `word_count`.
+    WordCount,
+    /// Flags the messages of the client's `priority_authors` or matching its `priority_regex`.
+    Priority,
+}
+
+/// Stage removing control characters.
+struct SanitizeStage;
+
+impl MessageFilter for SanitizeStage {
+    fn filter(&self, mut msg: Message) -> FilterAction {
+        if msg.get_content().chars().any(char::is_control) {
+            let content = msg
+                .get_content()
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect();
+            msg.set_content(content);
+        }
+        FilterAction::Pass(msg)
+    }
+
+    fn name(&self) -> &str {
+        "sanitize"
+    }
+}
+
+/// Stage trimming surrounding whitespace.
+struct TrimStage;
+
+impl MessageFilter for TrimStage {
+    fn filter(&self, mut msg: Message) -> FilterAction {
+        let content = msg.get_content().trim();
+        if content.is_empty() {
+            return FilterAction::Drop;
+        }
+        if content.len() != msg.get_content().len() {
+            let content = content.to_string();
+            msg.set_content(content);
+        }
+        FilterAction::Pass(msg)
+    }
+
+    fn name(&self) -> &str {
+        "trim"
+    }
+}
+
+/// A client's pipeline, built from its config.
+pub struct Pipeline {
+    stages: FilterChain,
+}
+
+impl Pipeline {
+    /// Builds a pipeline.
+    ///
+    /// Without configured stages, only priority flagging, the spam, profanity and word count
+    /// filters and sampling are applied, if configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `stages` - The configured stages, in order.
+    /// * `profanity_filter` - The configured profanity filter mode.
+    /// * `spam_filter` - The configured spam filter thresholds.
+    /// * `sample` - The configured sampling.
+    /// * `word_count` - The configured word count range.
+    /// * `priority_authors` - The configured logins of priority authors.
+    /// * `priority_regex` - The configured pattern of priority messages.
+    pub fn from_config(
+        stages: Option<&[PipelineStage]>,
+        profanity_filter: Option<ProfanityFilterMode>,
+        spam_filter: Option<&SpamFilterConfig>,
+        sample: Option<&SampleConfig>,
+        word_count: Option<&WordCountFilter>,
+        priority_authors: Option<&[String]>,
+        priority_regex: Option<&str>,
+    ) -> FitterResult<Self> {
+        // Flagging first, so priority patterns match the original content, and sampling last,
+        // so dropped spam doesn't take from the budget.
+        let stages = match stages {
+            Some(stages) => stages.to_vec(),
+            None => vec![
+                PipelineStage::Priority,
+                PipelineStage::Spam,
+                PipelineStage::Profanity,
+                PipelineStage::WordCount,
+                PipelineStage::Sample,
+            ],
+        };
+
+        for (idx, stage) in stages.iter().enumerate() {
+            if stages[..idx].contains(stage) {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Pipeline stage {:?} is listed more than once",
+                    stage
+                ))
+                .into());
+            }
+        }
+        if profanity_filter.is_some() && !stages.contains(&PipelineStage::Profanity) {
+            return Err(FitterErrorKind::GenericErr(
+                "Profanity filter is configured but missing from the pipeline".to_string(),
+            )
+            .into());
+        }
+        if spam_filter.is_some() && !stages.contains(&PipelineStage::Spam) {
+            return Err(FitterErrorKind::GenericErr(
+                "Spam filter is configured but missing from the pipeline".to_string(),
+            )
+            .into());
+        }
+        if sample.is_some() && !stages.contains(&PipelineStage::Sample) {
+            return Err(FitterErrorKind::GenericErr(
+                "Sample is configured but missing from the pipeline".to_string(),
+            )
+            .into());
+        }
+        if word_count.is_some() && !stages.contains(&PipelineStage::WordCount) {
+            return Err(FitterErrorKind::GenericErr(
+                "Word count filter is configured but missing from the pipeline".to_string(),
+            )
+            .into());
+        }
+        if (priority_authors.is_some() || priority_regex.is_some())
+            && !stages.contains(&PipelineStage::Priority)
+        {
+            return Err(FitterErrorKind::GenericErr(
+                "Priority flagging is configured but missing from the pipeline".to_string(),
+            )
+            .into());
+        }
+        if let Some(word_count) = word_count {
+            word_count.validate()?;
+        }
+
+        let mut profanity_filter = ProfanityFilter::from_config(profanity_filter)?;
+        let mut spam_filter = SpamFilter::from_config(spam_filter)?;
+        let mut sampler = Sampler::from_config(sample)?;
+        let mut flagger = PriorityFlagger::from_config(priority_authors, priority_regex)?;
+        let mut chain = FilterChain::new();
+        for stage in stages {
+            match stage {
+                // Without a configured filter mode the stage does nothing.
+                PipelineStage::Profanity => {
+                    if let Some(filter) = profanity_filter.take() {
+                        chain.push(Box::new(filter));
+                    }
+                }
+                PipelineStage::Sanitize => chain.push(Box::new(SanitizeStage)),
+                PipelineStage::Trim => chain.push(Box::new(TrimStage)),
+                PipelineStage::Spam => {
+                    if let Some(filter) = spam_filter.take() {
+                        chain.push(Box::new(filter));
+                    }
+                }
+                PipelineStage::Sample => {
+                    if let Some(sampler) = sampler.take() {
+                        chain.push(Box::new(sampler));
+                    }
+                }
+                PipelineStage::WordCount => {
+                    if let Some(word_count) = word_count {
+                        chain.push(Box::new(word_count.clone()));
+                    }
+                }
+                PipelineStage::Priority => {
+                    if let Some(flagger) = flagger.take() {
+                        chain.push(Box::new(flagger));
+                    }
+                }
+            }
+        }
+
+        Ok(Pipeline { stages: chain })
+    }
+}
+
+impl MessageFilter for Pipeline {
+    fn filter(&self, msg: Message) -> FilterAction {
+        self.stages.apply(msg)
+    }
+
+    fn name(&self) -> &str {
+        "pipeline"
+    }
+
+    fn config(&self) -> Value {
+        Value::Array(
+            self.stages
+                .get_filters()
+                .into_iter()
+                .map(|stage| json!({ "name": stage.name(), "config": stage.config() }))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,87 @@
+//! Flagging of high priority messages, delivered ahead of the other queued messages.
+//!
+//! When a client is backed up behind rate limits, announcements like a moderator's "raid
+//! incoming" shouldn't wait behind the queued chat. Clients flag the messages they receive
+//! from their `priority_authors` or matching their `priority_regex`, and destination clients
+//! send the flagged messages, events and bridge messages through a separate lane of each
+//! channel's queue, drained first.
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::{
+    clients::client::{Message, MessageKind},
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::filter::{FilterAction, MessageFilter},
+};
+
+/// Metadata key of the messages flagged high priority, see `Message::is_priority`.
+pub const PRIORITY: &str = "priority";
+
+/// Stage flagging the chat messages of priority authors or matching a priority pattern.
+pub struct PriorityFlagger {
+    authors: Vec<String>,
+    regex: Option<Regex>,
+}
+
+impl PriorityFlagger {
+    /// Builds a flagger if priority authors or a pattern are configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `authors` - The configured priority authors, matched against author logins.
+    /// * `regex` - The configured priority pattern, matched against message contents.
+    pub fn from_config(
+        authors: Option<&[String]>,
+        regex: Option<&str>,
+    ) -> FitterResult<Option<Self>> {
+        if authors.is_none() && regex.is_none() {
+            return Ok(None);
+        }
+
+        let regex = match regex {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|err| {
+                FitterErrorKind::GenericErr(format!("Invalid priority_regex: {}", err))
+            })?),
+            None => None,
+        };
+        Ok(Some(PriorityFlagger {
+            authors: authors
+                .into_iter()
+                .flatten()
+                .map(|author| author.to_lowercase())
+                .collect(),
+            regex,
+        }))
+    }
+}
+
+impl MessageFilter for PriorityFlagger {
+    fn filter(&self, msg: Message) -> FilterAction {
+        // Other kinds, e.g. events, are always high priority.
+        if msg.get_kind() != MessageKind::Chat {
+            return FilterAction::Pass(msg);
+        }
+
+        let login = msg.get_author_login().to_lowercase();
+        let priority = self.authors.contains(&login)
+            || self
+                .regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(msg.get_content()));
+        match priority {
+            true => FilterAction::Pass(msg.with_metadata(PRIORITY, "true".to_string())),
+            false => FilterAction::Pass(msg),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "priority"
+    }
+
+    fn config(&self) -> Value {
+        json!({
+            "priority_authors": self.authors,
+            "priority_regex": self.regex.as_ref().map(Regex::as_str),
+        })
+    }
+}
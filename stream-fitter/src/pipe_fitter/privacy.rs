@@ -0,0 +1,113 @@
+//! Privacy transformations applied to the messages delivered to a client, e.g. an analytics
+//! sink not allowed to store message text.
+//!
+//! Hashes are salted SHA-256 hex digests, so the same author or content hashes the same within
+//! a run without being reversible.
+use rand::{thread_rng, RngCore};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::clients::client::Message;
+
+/// Number of random bytes of a salt generated per run.
+const GENERATED_SALT_BYTES: usize = 16;
+
+/// What to do with the content of delivered messages.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentPrivacy {
+    /// Deliver the content as is.
+    #[default]
+    Keep,
+    /// Replace the content with its hash.
+    Hash,
+    /// Empty the content.
+    Drop,
+}
+
+/// What to do with the author of delivered messages.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthorPrivacy {
+    /// Deliver the author as is.
+    #[default]
+    Keep,
+    /// Replace the author with their hash.
+    Hash,
+}
+
+/// Config struct for the privacy of the messages delivered to a client.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PrivacyConfig {
+    /// What to do with the content, defaults to `keep`.
+    pub content: Option<ContentPrivacy>,
+    /// What to do with the author, defaults to `keep`.
+    pub author: Option<AuthorPrivacy>,
+    /// Salt of the hashes, generated per run by default.
+    pub salt: Option<String>,
+}
+
+/// Privacy transformation of the messages delivered to a client.
+pub struct Privacy {
+    content: ContentPrivacy,
+    author: AuthorPrivacy,
+    salt: Vec<u8>,
+}
+
+impl Privacy {
+    /// Builds a privacy transformation if one is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The privacy config.
+    pub fn from_config(config: Option<&PrivacyConfig>) -> Option<Self> {
+        let config = config?;
+        let salt = match &config.salt {
+            Some(salt) => salt.as_bytes().to_vec(),
+            None => {
+                let mut salt = vec![0; GENERATED_SALT_BYTES];
+                thread_rng().fill_bytes(&mut salt);
+                salt
+            }
+        };
+
+        Some(Privacy {
+            content: config.content.unwrap_or_default(),
+            author: config.author.unwrap_or_default(),
+            salt,
+        })
+    }
+
+    /// Hashes a value with the salt.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to hash.
+    fn hash(&self, value: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update(value.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    /// Applies the transformation to a message about to be delivered.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The client's copy of the message.
+    pub fn apply(&self, mut msg: Message) -> Message {
+        match self.content {
+            ContentPrivacy::Keep => (),
+            ContentPrivacy::Hash => {
+                let hash = self.hash(msg.get_content());
+                msg.set_content_hash(hash);
+            }
+            ContentPrivacy::Drop => msg.set_content(String::new()),
+        }
+        if self.author == AuthorPrivacy::Hash {
+            let hash = self.hash(msg.get_author());
+            msg.set_author_hash(hash);
+        }
+        msg
+    }
+}
@@ -0,0 +1,93 @@
+//! Built-in profanity filter applied by clients to the messages they receive.
+//!
+//! Uses the word lists bundled with the censor library, enabled with the `profanity` feature.
+#[cfg(feature = "profanity")]
+use censor::Censor;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    clients::client::Message,
+    errors::FitterResult,
+    pipe_fitter::filter::{FilterAction, MessageFilter},
+};
+
+/// What to do with messages containing profanity.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfanityFilterMode {
+    /// Don't relay the message.
+    Drop,
+    /// Replace the profane words with asterisks.
+    Censor,
+}
+
+/// Filter dropping or censoring messages containing profanity.
+pub struct ProfanityFilter {
+    mode: ProfanityFilterMode,
+    #[cfg(feature = "profanity")]
+    censor: Censor,
+}
+
+impl ProfanityFilter {
+    /// Builds a profanity filter if one is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The configured filter mode.
+    #[cfg(feature = "profanity")]
+    pub fn from_config(mode: Option<ProfanityFilterMode>) -> FitterResult<Option<Self>> {
+        Ok(mode.map(|mode| ProfanityFilter {
+            mode,
+            censor: Censor::Standard + Censor::Sex,
+        }))
+    }
+
+    /// Builds a profanity filter if one is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The configured filter mode.
+    #[cfg(not(feature = "profanity"))]
+    pub fn from_config(mode: Option<ProfanityFilterMode>) -> FitterResult<Option<Self>> {
+        match mode {
+            Some(_) => Err(crate::errors::FitterErrorKind::GenericErr(
+                "Built without the profanity feature, can't filter profanity".to_string(),
+            )
+            .into()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl MessageFilter for ProfanityFilter {
+    #[cfg(feature = "profanity")]
+    fn filter(&self, mut msg: Message) -> FilterAction {
+        if !self.censor.check(msg.get_content()) {
+            return FilterAction::Pass(msg);
+        }
+
+        match self.mode {
+            ProfanityFilterMode::Drop => FilterAction::Drop,
+            ProfanityFilterMode::Censor => {
+                let content = self.censor.censor(msg.get_content());
+                msg.set_content(content);
+                FilterAction::Pass(msg)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "profanity"))]
+    fn filter(&self, msg: Message) -> FilterAction {
+        // Never built without the feature.
+        FilterAction::Pass(msg)
+    }
+
+    fn name(&self) -> &str {
+        "profanity"
+    }
+
+    fn config(&self) -> Value {
+        json!({ "mode": self.mode })
+    }
+}
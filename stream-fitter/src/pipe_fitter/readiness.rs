@@ -0,0 +1,93 @@
+//! Tracks when every client connected to its platform, so the relay can be waited on until
+//! it's ready.
+//!
+//! Each client gets a handle it marks once connected, e.g. when Discord's gateway is ready.
+//! Clients without a connection of their own mark it right away.
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+/// Barrier released once every client it counts is connected.
+#[derive(Debug)]
+pub struct ConnectedBarrier {
+    remaining: AtomicUsize,
+    notify: Notify,
+}
+
+impl ConnectedBarrier {
+    /// Creates a barrier counting clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `clients` - The number of clients to wait for, released right away when zero.
+    pub fn new(clients: usize) -> Arc<Self> {
+        Arc::new(ConnectedBarrier {
+            remaining: AtomicUsize::new(clients),
+            notify: Notify::new(),
+        })
+    }
+
+    /// Gets the handle of one of the counted clients.
+    pub fn handle(self: &Arc<Self>) -> Connected {
+        Connected {
+            marked: Arc::new(AtomicBool::new(false)),
+            barrier: Arc::clone(self),
+        }
+    }
+
+    /// Checks whether every client is connected.
+    pub fn is_released(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+
+    /// Waits until every client is connected.
+    pub async fn wait(&self) {
+        loop {
+            // Registered before checking, so a release in between isn't missed.
+            let notified = self.notify.notified();
+            if self.is_released() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Counts one more client connected, releasing the barrier with the last one.
+    fn arrive(&self) {
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.notify.notify_waiters();
+        }
+    }
+}
+
+/// Handle a client marks once connected to its platform.
+///
+/// Clones share the same handle.
+#[derive(Clone, Debug)]
+pub struct Connected {
+    marked: Arc<AtomicBool>,
+    barrier: Arc<ConnectedBarrier>,
+}
+
+impl Default for Connected {
+    fn default() -> Self {
+        Connected::new()
+    }
+}
+
+impl Connected {
+    /// Creates a handle counted by a barrier of its own.
+    pub fn new() -> Self {
+        ConnectedBarrier::new(1).handle()
+    }
+
+    /// Marks the client connected, only counted the first time.
+    pub fn mark(&self) {
+        if !self.marked.swap(true, Ordering::SeqCst) {
+            self.barrier.arrive();
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Sampling applied by clients to the messages they receive.
+//!
+//! Keeps mirrors of busy chats readable by only relaying a share of the messages, either a
+//! random ratio or up to a number per minute. Messages matching a priority pattern, e.g.
+//! mentions of the streamer, and messages other than chat, e.g. events, are always kept.
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::{
+    clients::client::{Message, MessageKind},
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::filter::{FilterAction, MessageFilter},
+};
+
+/// Duration of the windows messages are budgeted and sampled-out messages reported over.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Config struct for a client's sampling, with either `ratio` or `max_per_minute`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SampleConfig {
+    /// Share of chat messages kept at random, above 0 and at most 1.
+    pub ratio: Option<f64>,
+    /// Number of chat messages kept per minute, the first ones of each minute.
+    pub max_per_minute: Option<u32>,
+    /// Pattern of chat messages always kept, e.g. `(?i)@streamer`, counting towards
+    /// `max_per_minute`.
+    pub priority_regex: Option<String>,
+    /// Seed of the random selection, for reproducible sampling.
+    pub seed: Option<u64>,
+}
+
+/// Budget of the messages kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Budget {
+    Ratio(f64),
+    PerMinute(u32),
+}
+
+/// Sampling state of the current window.
+struct Window {
+    rng: StdRng,
+    start: Instant,
+    kept: u32,
+    sampled_out: u64,
+}
+
+/// Filter dropping chat messages beyond a budget.
+pub struct Sampler {
+    budget: Budget,
+    priority: Option<Regex>,
+    window: Mutex<Window>,
+    sampled_out: AtomicU64,
+}
+
+impl Sampler {
+    /// Builds a sampler if one is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configured sampling.
+    pub fn from_config(config: Option<&SampleConfig>) -> FitterResult<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let budget = match (config.ratio, config.max_per_minute) {
+            (Some(ratio), None) if ratio > 0.0 && ratio <= 1.0 => Budget::Ratio(ratio),
+            (Some(ratio), None) => {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Sample ratio must be above 0 and at most 1, got {}",
+                    ratio
+                ))
+                .into())
+            }
+            (None, Some(max_per_minute)) => Budget::PerMinute(max_per_minute),
+            _ => {
+                return Err(FitterErrorKind::GenericErr(
+                    "Sample needs either ratio or max_per_minute".to_string(),
+                )
+                .into())
+            }
+        };
+        let priority = match &config.priority_regex {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|err| {
+                FitterErrorKind::GenericErr(format!("Invalid sample priority_regex: {}", err))
+            })?),
+            None => None,
+        };
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Ok(Some(Sampler {
+            budget,
+            priority,
+            window: Mutex::new(Window {
+                rng,
+                start: Instant::now(),
+                kept: 0,
+                sampled_out: 0,
+            }),
+            sampled_out: AtomicU64::new(0),
+        }))
+    }
+
+    /// Gets the number of messages sampled out so far.
+    pub fn get_sampled_out(&self) -> u64 {
+        self.sampled_out.load(Ordering::Relaxed)
+    }
+}
+
+impl MessageFilter for Sampler {
+    fn filter(&self, msg: Message) -> FilterAction {
+        if msg.get_kind() != MessageKind::Chat {
+            return FilterAction::Pass(msg);
+        }
+
+        let mut window = self.window.lock().unwrap();
+        if window.start.elapsed() >= WINDOW {
+            if window.sampled_out > 0 {
+                info!(
+                    "Sampled out {} messages in the last minute",
+                    window.sampled_out
+                );
+            }
+            window.start = Instant::now();
+            window.kept = 0;
+            window.sampled_out = 0;
+        }
+
+        let priority = self
+            .priority
+            .as_ref()
+            .is_some_and(|priority| priority.is_match(msg.get_content()));
+        let keep = priority
+            || match self.budget {
+                Budget::Ratio(ratio) => window.rng.gen::<f64>() < ratio,
+                Budget::PerMinute(max_per_minute) => window.kept < max_per_minute,
+            };
+
+        if keep {
+            window.kept += 1;
+            FilterAction::Pass(msg)
+        } else {
+            window.sampled_out += 1;
+            self.sampled_out.fetch_add(1, Ordering::Relaxed);
+            FilterAction::Drop
+        }
+    }
+
+    fn name(&self) -> &str {
+        "sample"
+    }
+
+    fn config(&self) -> Value {
+        let (ratio, max_per_minute) = match self.budget {
+            Budget::Ratio(ratio) => (Some(ratio), None),
+            Budget::PerMinute(max_per_minute) => (None, Some(max_per_minute)),
+        };
+        json!({
+            "ratio": ratio,
+            "max_per_minute": max_per_minute,
+            "priority_regex": self.priority.as_ref().map(Regex::as_str),
+            "sampled_out": self.get_sampled_out(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a sampler from its YAML config.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The YAML config.
+    fn sampler(config: &str) -> Sampler {
+        let config: SampleConfig = serde_yaml::from_str(config).unwrap();
+        Sampler::from_config(Some(&config)).unwrap().unwrap()
+    }
+
+    /// Builds a chat message.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn message(content: &str) -> Message {
+        Message::new(
+            "mock".to_string(),
+            "channel".to_string(),
+            "viewer".to_string(),
+            content.to_string(),
+        )
+    }
+
+    /// Gets which of a number of chat messages a sampler keeps.
+    ///
+    /// # Arguments
+    ///
+    /// * `sampler` - The sampler.
+    /// * `count` - The number of messages.
+    fn kept(sampler: &Sampler, count: usize) -> Vec<bool> {
+        (0..count)
+            .map(|idx| match sampler.filter(message(&idx.to_string())) {
+                FilterAction::Pass(_) => true,
+                FilterAction::Drop => false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        for config in [
+            "ratio: 0.0",
+            "ratio: 1.5",
+            "{}",
+            "{ratio: 0.5, max_per_minute: 10}",
+            "{ratio: 0.5, priority_regex: \"(\"}",
+        ] {
+            let config: SampleConfig = serde_yaml::from_str(config).unwrap();
+            assert!(Sampler::from_config(Some(&config)).is_err(), "{:?}", config);
+        }
+        assert!(Sampler::from_config(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn samples_ratio_reproducibly_with_seed() {
+        let first = kept(&sampler("{ratio: 0.25, seed: 7}"), 1000);
+        assert_eq!(first, kept(&sampler("{ratio: 0.25, seed: 7}"), 1000));
+        assert_ne!(first, kept(&sampler("{ratio: 0.25, seed: 8}"), 1000));
+
+        let count = first.iter().filter(|kept| **kept).count();
+        assert!((200..300).contains(&count), "kept {} of 1000", count);
+        assert!(kept(&sampler("{ratio: 1.0, seed: 7}"), 100)
+            .into_iter()
+            .all(|kept| kept));
+    }
+
+    #[test]
+    fn keeps_first_messages_of_the_minute() {
+        let kept = kept(&sampler("max_per_minute: 3"), 10);
+        assert_eq!(kept[..3], [true; 3]);
+        assert_eq!(kept[3..], [false; 7]);
+    }
+
+    #[test]
+    fn always_keeps_priority_messages() {
+        let per_minute = sampler("{max_per_minute: 1, priority_regex: \"(?i)@streamer\"}");
+        assert!(matches!(
+            per_minute.filter(message("first")),
+            FilterAction::Pass(_)
+        ));
+        assert!(matches!(
+            per_minute.filter(message("second")),
+            FilterAction::Drop
+        ));
+        assert!(matches!(
+            per_minute.filter(message("hi @Streamer")),
+            FilterAction::Pass(_)
+        ));
+
+        let ratio = sampler("{ratio: 0.01, seed: 7, priority_regex: \"!important\"}");
+        for _ in 0..100 {
+            assert!(matches!(
+                ratio.filter(message("!important")),
+                FilterAction::Pass(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn never_drops_system_or_event_messages() {
+        let sampler = sampler("max_per_minute: 0");
+        assert!(matches!(
+            sampler.filter(Message::system("summary".to_string())),
+            FilterAction::Pass(_)
+        ));
+        assert!(matches!(
+            sampler.filter(message("raid").with_kind(MessageKind::Event)),
+            FilterAction::Pass(_)
+        ));
+        assert!(matches!(
+            sampler.filter(message("chat")),
+            FilterAction::Drop
+        ));
+        assert_eq!(sampler.get_sampled_out(), 1);
+    }
+
+    #[test]
+    fn reports_sampled_out_count() {
+        let sampler = sampler("max_per_minute: 2");
+        kept(&sampler, 5);
+        assert_eq!(sampler.get_sampled_out(), 3);
+        assert_eq!(sampler.config()["sampled_out"], json!(3));
+        assert_eq!(sampler.config()["max_per_minute"], json!(2));
+    }
+}
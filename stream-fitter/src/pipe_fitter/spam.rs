@@ -0,0 +1,192 @@
+//! Anti-spam heuristics applied by clients to the messages they receive.
+//!
+//! Keeps relayed chat readable during raids by de-capitalizing shouting, collapsing repeated
+//! characters and dropping messages repeating a single word.
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    clients::client::Message,
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::filter::{FilterAction, MessageFilter},
+};
+
+/// Default ratio of uppercase letters above which a message is de-capitalized.
+const DEFAULT_CAPS_RATIO: f64 = 0.7;
+/// Default minimum number of letters for a message to be de-capitalized.
+const DEFAULT_CAPS_MIN_LETTERS: usize = 10;
+/// Default number of times a character can repeat in a row.
+const DEFAULT_MAX_REPEATED_CHARS: usize = 3;
+/// Default ratio of words being the same above which a message is dropped.
+const DEFAULT_REPEATED_WORD_RATIO: f64 = 0.8;
+/// Default minimum number of words for a message to be dropped as repeated.
+const DEFAULT_REPEATED_WORD_MIN_WORDS: usize = 4;
+
+/// Config struct for a client's anti-spam heuristics.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpamFilterConfig {
+    /// Ratio of uppercase letters above which a message is de-capitalized, defaults to 0.7.
+    pub caps_ratio: Option<f64>,
+    /// Minimum number of letters for a message to be de-capitalized, defaults to 10.
+    pub caps_min_letters: Option<usize>,
+    /// Number of times a character can repeat in a row before the repeats are collapsed,
+    /// defaults to 3.
+    pub max_repeated_chars: Option<usize>,
+    /// Ratio of words being the same above which a message is dropped, defaults to 0.8.
+    pub repeated_word_ratio: Option<f64>,
+    /// Minimum number of words for a message to be dropped as repeated, defaults to 4.
+    pub repeated_word_min_words: Option<usize>,
+}
+
+/// Filter cleaning up or dropping spam.
+pub struct SpamFilter {
+    caps_ratio: f64,
+    caps_min_letters: usize,
+    max_repeated_chars: usize,
+    repeated_word_ratio: f64,
+    repeated_word_min_words: usize,
+}
+
+impl SpamFilter {
+    /// Builds a spam filter if one is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configured thresholds.
+    pub fn from_config(config: Option<&SpamFilterConfig>) -> FitterResult<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let filter = SpamFilter {
+            caps_ratio: config.caps_ratio.unwrap_or(DEFAULT_CAPS_RATIO),
+            caps_min_letters: config.caps_min_letters.unwrap_or(DEFAULT_CAPS_MIN_LETTERS),
+            max_repeated_chars: config
+                .max_repeated_chars
+                .unwrap_or(DEFAULT_MAX_REPEATED_CHARS),
+            repeated_word_ratio: config
+                .repeated_word_ratio
+                .unwrap_or(DEFAULT_REPEATED_WORD_RATIO),
+            repeated_word_min_words: config
+                .repeated_word_min_words
+                .unwrap_or(DEFAULT_REPEATED_WORD_MIN_WORDS),
+        };
+
+        for (name, ratio) in [
+            ("caps_ratio", filter.caps_ratio),
+            ("repeated_word_ratio", filter.repeated_word_ratio),
+        ] {
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                return Err(FitterErrorKind::GenericErr(format!(
+                    "Spam filter {} must be above 0 and at most 1, got {}",
+                    name, ratio
+                ))
+                .into());
+            }
+        }
+        if filter.max_repeated_chars == 0 {
+            return Err(FitterErrorKind::GenericErr(
+                "Spam filter max_repeated_chars must be at least 1".to_string(),
+            )
+            .into());
+        }
+
+        Ok(Some(filter))
+    }
+
+    /// Checks if a message is mostly the same word repeated.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn is_repeated_word(&self, content: &str) -> bool {
+        let mut counts = HashMap::new();
+        let mut words = 0;
+        for word in content.split_whitespace() {
+            *counts.entry(word.to_lowercase()).or_insert(0) += 1;
+            words += 1;
+        }
+        if words < self.repeated_word_min_words {
+            return false;
+        }
+
+        let most_repeated = counts.values().copied().max().unwrap_or_default();
+        most_repeated as f64 / words as f64 >= self.repeated_word_ratio
+    }
+
+    /// Collapses characters repeated more than allowed in a row, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn collapse_repeats(&self, content: &str) -> Option<String> {
+        let mut collapsed = String::with_capacity(content.len());
+        let mut last = None;
+        let mut run = 0;
+        for c in content.chars() {
+            if last == Some(c) {
+                run += 1;
+            } else {
+                last = Some(c);
+                run = 1;
+            }
+            if run <= self.max_repeated_chars {
+                collapsed.push(c);
+            }
+        }
+
+        match collapsed.len() == content.len() {
+            true => None,
+            false => Some(collapsed),
+        }
+    }
+
+    /// Checks if a message has enough uppercase letters to be de-capitalized.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The message's content.
+    fn is_shouting(&self, content: &str) -> bool {
+        let (letters, uppercase) = content
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .fold((0, 0), |(letters, uppercase), c| {
+                (letters + 1, uppercase + usize::from(c.is_uppercase()))
+            });
+        letters >= self.caps_min_letters && uppercase as f64 / letters as f64 >= self.caps_ratio
+    }
+}
+
+impl MessageFilter for SpamFilter {
+    fn filter(&self, mut msg: Message) -> FilterAction {
+        if self.is_repeated_word(msg.get_content()) {
+            return FilterAction::Drop;
+        }
+
+        if let Some(content) = self.collapse_repeats(msg.get_content()) {
+            msg.set_content(content);
+        }
+        if self.is_shouting(msg.get_content()) {
+            let content = msg.get_content().to_lowercase();
+            msg.set_content(content);
+        }
+        FilterAction::Pass(msg)
+    }
+
+    fn name(&self) -> &str {
+        "spam"
+    }
+
+    fn config(&self) -> Value {
+        json!({
+            "caps_ratio": self.caps_ratio,
+            "caps_min_letters": self.caps_min_letters,
+            "max_repeated_chars": self.max_repeated_chars,
+            "repeated_word_ratio": self.repeated_word_ratio,
+            "repeated_word_min_words": self.repeated_word_min_words,
+        })
+    }
+}
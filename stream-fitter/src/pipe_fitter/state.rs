@@ -0,0 +1,88 @@
+//! Runtime state kept across restarts, e.g. when upgrading.
+//!
+//! With a `state_file`, the stream manager writes its state when drained on shutdown and
+//! loads it back when served again, so the relayed messages aren't relayed twice right after
+//! a restart. A state file that can't be read, is corrupt or is older than
+//! `state_max_age_seconds` is ignored with a warning, the stream manager starting afresh.
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::errors::FitterResult;
+
+/// Default longest age of a loaded state file.
+pub const DEFAULT_STATE_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Runtime state of a stream manager, see `PipeFitter::export_state`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FitterState {
+    /// Time the state was exported, e.g. on the last shutdown.
+    pub exported_at: DateTime<Utc>,
+    /// IDs of the most recently relayed messages, oldest first, see `Message::get_id`.
+    pub relayed_ids: Vec<String>,
+    /// State of the clients exporting one, by client name, see `ClientTrait::export_state`.
+    pub clients: HashMap<String, Value>,
+}
+
+impl FitterState {
+    /// Loads a state file, if it exists, is valid and is recent enough.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The state file.
+    /// * `max_age` - The longest time since the state was exported.
+    pub fn load(path: &Path, max_age: Duration) -> Option<Self> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("No state file yet: {}", path.display());
+                return None;
+            }
+            Err(err) => {
+                warn!("Ignoring unreadable state file {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        let state = match serde_json::from_slice::<FitterState>(&bytes) {
+            Ok(state) => state,
+            Err(err) => {
+                warn!("Ignoring corrupt state file {}: {}", path.display(), err);
+                return None;
+            }
+        };
+
+        // States exported in the future, e.g. after a clock change, aren't trusted either.
+        let fresh = Utc::now()
+            .signed_duration_since(state.exported_at)
+            .to_std()
+            .is_ok_and(|age| age <= max_age);
+        if !fresh {
+            warn!(
+                "Ignoring stale state file {}, exported at {}",
+                path.display(),
+                state.exported_at
+            );
+            return None;
+        }
+        Some(state)
+    }
+
+    /// Writes the state to a file.
+    ///
+    /// The file is replaced atomically, so a crash never leaves it half written.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The state file.
+    pub async fn save(&self, path: &Path) -> FitterResult<()> {
+        let bytes = serde_json::to_vec(self)?;
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, bytes).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        debug!("Saved state file: {}", path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! Periodic summaries of the bridge's relay activity.
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error, instrument};
+
+use crate::{
+    clients::client::Message,
+    util::catalog::{plural, Catalog, CatalogKey},
+};
+
+/// Default number of top authors listed in a summary.
+const DEFAULT_TOP_AUTHORS: usize = 3;
+
+/// Config struct for periodic bridge summaries.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SummaryConfig {
+    /// Minutes between summaries.
+    pub interval_minutes: u64,
+    /// Name of the client to post summaries to.
+    pub target_client: String,
+    /// Channel of the target client to post summaries to.
+    pub target_channel: String,
+    /// Number of most active authors to list.
+    pub top_authors: Option<usize>,
+}
+
+/// Relay counters accumulated between summaries.
+#[derive(Default)]
+pub(crate) struct SummaryStats {
+    platforms: DashMap<String, u64>,
+    authors: DashMap<String, u64>,
+    channels: DashMap<String, u64>,
+}
+
+impl SummaryStats {
+    /// Counts a relayed message.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The relayed message.
+    pub(crate) fn record(&self, msg: &Message) {
+        *self
+            .platforms
+            .entry(msg.get_client().to_string())
+            .or_insert(0) += 1;
+        *self
+            .authors
+            .entry(format!("{} ({})", msg.get_author(), msg.get_client()))
+            .or_insert(0) += 1;
+        *self
+            .channels
+            .entry(format!("{} #{}", msg.get_client(), msg.get_channel()))
+            .or_insert(0) += 1;
+    }
+
+    /// Composes the summary text and resets the counters.
+    ///
+    /// # Arguments
+    ///
+    /// * `top_authors` - Number of most active authors to list.
+    /// * `catalog` - The messages to compose the summary with.
+    fn take_summary(&self, top_authors: usize, catalog: &Catalog) -> String {
+        let platforms = drain_sorted(&self.platforms);
+        let authors = drain_sorted(&self.authors);
+        let channels = drain_sorted(&self.channels);
+
+        let total = platforms.iter().map(|(_, count)| count).sum::<u64>();
+        if total == 0 {
+            return catalog.render(CatalogKey::SummaryEmpty, &[]);
+        }
+
+        catalog.render(
+            CatalogKey::Summary,
+            &[
+                &total.to_string(),
+                plural(total),
+                &format_counts(&platforms, platforms.len()),
+                &format_counts(&authors, top_authors),
+                &format_counts(&channels, channels.len()),
+            ],
+        )
+    }
+}
+
+/// Removes all counters from a map, sorted by descending count.
+///
+/// # Arguments
+///
+/// * `counters` - The counters to drain.
+fn drain_sorted(counters: &DashMap<String, u64>) -> Vec<(String, u64)> {
+    let keys = counters
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<String>>();
+    let mut counts = keys
+        .into_iter()
+        .filter_map(|key| counters.remove(&key))
+        .collect::<Vec<(String, u64)>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+/// Formats the first `n` counters as `name: count` pairs.
+///
+/// # Arguments
+///
+/// * `counts` - The sorted counters.
+/// * `n` - The maximum number of counters to format.
+pub(crate) fn format_counts(counts: &[(String, u64)], n: usize) -> String {
+    counts
+        .iter()
+        .take(n)
+        .map(|(name, count)| format!("{}: {}", name, count))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Loop to periodically post summaries to the target client.
+///
+/// # Arguments
+///
+/// * `config` - The summary config.
+/// * `catalog` - The messages to compose summaries with.
+/// * `stats` - The counters to summarize.
+/// * `target` - The TX stream of the target client.
+#[instrument(skip(config, catalog, stats, target))]
+pub(crate) async fn summary_loop(
+    config: SummaryConfig,
+    catalog: Catalog,
+    stats: Arc<SummaryStats>,
+    target: Sender<Message>,
+) {
+    let top_authors = config.top_authors.unwrap_or(DEFAULT_TOP_AUTHORS);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_minutes * 60));
+
+    // The first tick completes immediately.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let summary = stats.take_summary(top_authors, &catalog);
+        debug!("Posting summary: {}", summary);
+
+        let msg = Message::system(summary).with_target_channel(config.target_channel.clone());
+        if let Err(err) = target.send(msg).await {
+            error!("Error sending summary: {:?}", err);
+        }
+    }
+}
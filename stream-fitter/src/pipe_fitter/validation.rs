@@ -0,0 +1,395 @@
+//! Checks of a config file, reporting mistakes by line and column, and warning about settings
+//! that load but are unlikely to do what was meant.
+//!
+//! Client blocks are an untagged enum, so a mistake in one only yields a generic "did not match
+//! any variant" error. Each failing block is instead read again as the kind of client its keys
+//! suggest, which gives the error of the field at fault with its location.
+use std::fmt::{Formatter, Result as FmtResult};
+
+use serde::de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_yaml::{from_str, from_value, Deserializer, Error as YamlError, Value};
+
+use crate::{
+    clients::{
+        broadcast::BroadcastConfig, client::ClientConfig, discord::DiscordConfig, nats::NatsConfig,
+        twitch::TwitchConfig,
+    },
+    pipe_fitter::{
+        multi::{MultiFitterConfig, NamedFitterConfig},
+        PipeFitterConfig,
+    },
+};
+
+/// A mistake or a suspicious setting found in a config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// Line of the config at fault, starting at 1, if known.
+    pub line: Option<usize>,
+    /// Column of the config at fault, starting at 1, if known.
+    pub column: Option<usize>,
+    /// What's wrong, starting with the path of the value at fault.
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Builds a diagnostic without a location.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - What's wrong.
+    fn new(message: String) -> Self {
+        Diagnostic {
+            line: None,
+            column: None,
+            message,
+        }
+    }
+
+    /// Builds a diagnostic from a parsing error, moving its location out of its message.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The parsing error.
+    fn from_error(err: &YamlError) -> Self {
+        let message = err.to_string();
+        match err.location() {
+            Some(location) => {
+                let suffix = format!(" at line {} column {}", location.line(), location.column());
+                Diagnostic {
+                    line: Some(location.line()),
+                    column: Some(location.column()),
+                    message: message.trim_end_matches(&suffix).to_string(),
+                }
+            }
+            None => Diagnostic::new(message),
+        }
+    }
+}
+
+/// The mistakes and suspicious settings found in a config.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    /// Mistakes keeping the config from loading.
+    pub errors: Vec<Diagnostic>,
+    /// Settings that load but are unlikely to do what was meant.
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Checks a config, as found in a config file.
+///
+/// # Arguments
+///
+/// * `text` - The config's YAML.
+pub fn validate_config(text: &str) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let value: Value = match from_str(text) {
+        Ok(value) => value,
+        Err(err) => {
+            report.errors.push(Diagnostic::from_error(&err));
+            return report;
+        }
+    };
+
+    let config: MultiFitterConfig = match from_value(value.clone()) {
+        Ok(config) => config,
+        Err(_) => {
+            report.errors = diagnose(text, &value);
+            return report;
+        }
+    };
+
+    let multi = matches!(config, MultiFitterConfig::Multi { .. });
+    for (idx, (name, fitter_config)) in config.get_fitters().into_iter().enumerate() {
+        let prefix = match multi {
+            true => format!("fitters[{}].", idx),
+            false => String::new(),
+        };
+        check_fitter(&prefix, name, fitter_config, &mut report);
+    }
+    report
+}
+
+/// Checks the settings of a fitter config that loads.
+///
+/// # Arguments
+///
+/// * `prefix` - The path of the fitter in the config, empty for a single fitter.
+/// * `name` - The fitter's name.
+/// * `config` - The fitter's config.
+/// * `report` - The report to add the findings to.
+fn check_fitter(
+    prefix: &str,
+    name: &str,
+    config: &PipeFitterConfig,
+    report: &mut ValidationReport,
+) {
+    if let Err(err) = config.validate() {
+        report
+            .errors
+            .push(Diagnostic::new(format!("fitter {}: {}", name, err)));
+    }
+
+    let stream_configs = config.get_stream_configs();
+    let forward_only = stream_configs
+        .iter()
+        .filter(|stream_config| stream_config.is_forward_only())
+        .count();
+    match stream_configs.len() {
+        0 => report.warnings.push(Diagnostic::new(format!(
+            "{}stream_configs: fitter {} has no clients",
+            prefix, name
+        ))),
+        1 if forward_only == 1 => report.warnings.push(Diagnostic::new(format!(
+            "{}stream_configs[0]: forward_only with no peers, the client relays nothing",
+            prefix
+        ))),
+        1 => report.warnings.push(Diagnostic::new(format!(
+            "{}stream_configs: fitter {} has a single client, with no peers to relay to",
+            prefix, name
+        ))),
+        count if forward_only == count => report.errors.push(Diagnostic::new(format!(
+            "{}stream_configs: every client is forward_only, the relay would be silent",
+            prefix
+        ))),
+        _ => (),
+    }
+
+    for (idx, stream_config) in stream_configs.iter().enumerate() {
+        let channel_count = match stream_config {
+            ClientConfig::DiscordConfig(cfg) => cfg.channel_ids.len(),
+            ClientConfig::TwitchConfig(cfg) => cfg.channels.len(),
+            _ => continue,
+        };
+        if channel_count == 0 {
+            report.warnings.push(Diagnostic::new(format!(
+                "{}stream_configs[{}]: {} has no channels to relay",
+                prefix,
+                idx,
+                stream_config.get_name()
+            )));
+        }
+    }
+}
+
+/// Finds the mistakes of a config that doesn't load, by client block when they're at fault.
+///
+/// # Arguments
+///
+/// * `text` - The config's YAML.
+/// * `value` - The config, parsed.
+fn diagnose(text: &str, value: &Value) -> Vec<Diagnostic> {
+    let fitters: Vec<(Vec<Segment>, &Value)> = match value.get("fitters") {
+        Some(Value::Sequence(fitters)) => fitters
+            .iter()
+            .enumerate()
+            .map(|(idx, fitter)| (vec![Segment::Key("fitters"), Segment::Index(idx)], fitter))
+            .collect(),
+        _ => vec![(Vec::new(), value)],
+    };
+
+    let mut errors = Vec::new();
+    for (path, fitter) in &fitters {
+        if let Some(Value::Sequence(blocks)) = fitter.get("stream_configs") {
+            for (idx, block) in blocks.iter().enumerate() {
+                if from_value::<ClientConfig>(block.clone()).is_ok() {
+                    continue;
+                }
+                let mut block_path = path.clone();
+                block_path.extend([Segment::Key("stream_configs"), Segment::Index(idx)]);
+                errors.extend(probe(text, &block_path, Target::guess(block)));
+            }
+        }
+    }
+
+    // The client blocks are fine, so the mistake is in the fitter settings.
+    if errors.is_empty() {
+        for (path, _) in &fitters {
+            let target = match path.is_empty() {
+                true => Target::Fitter,
+                false => Target::NamedFitter,
+            };
+            errors.extend(probe(text, path, Target::Fitter).or_else(|| probe(text, path, target)));
+        }
+    }
+
+    if errors.is_empty() {
+        errors.push(Diagnostic::new(
+            "expected a fitter config with stream_configs, or several under fitters".to_string(),
+        ));
+    }
+    errors
+}
+
+/// Reads a part of a config again as a given type, returning its error if it doesn't load.
+///
+/// # Arguments
+///
+/// * `text` - The config's YAML.
+/// * `path` - The path of the part to read.
+/// * `target` - The type to read the part as.
+fn probe(text: &str, path: &[Segment], target: Target) -> Option<Diagnostic> {
+    Probe { path, target }
+        .deserialize(Deserializer::from_str(text))
+        .err()
+        .map(|err| {
+            let mut diagnostic = Diagnostic::from_error(&err);
+            if let Some(kind) = target.client_kind() {
+                diagnostic.message = format!("{} (read as a {} client)", diagnostic.message, kind);
+            }
+            diagnostic
+        })
+}
+
+/// A step of the path to a part of a config.
+#[derive(Clone, Copy)]
+enum Segment {
+    /// A mapping's key.
+    Key(&'static str),
+    /// A sequence's index.
+    Index(usize),
+}
+
+/// A type to read a part of a config as.
+#[derive(Clone, Copy)]
+enum Target {
+    Discord,
+    Twitch,
+    Nats,
+    Broadcast,
+    /// A client block not looking like any kind of client.
+    UnknownClient,
+    Fitter,
+    NamedFitter,
+}
+
+impl Target {
+    /// Guesses the kind of client a block is meant to be from its keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - The client block.
+    fn guess(block: &Value) -> Self {
+        let has = |key: &str| block.get(key).is_some();
+        if has("channel_ids") {
+            Target::Discord
+        } else if has("channels") || has("replay_mode") {
+            Target::Twitch
+        } else if has("url") || has("publish_subject") || has("subscribe_subject") {
+            Target::Nats
+        } else if has("source") || has("label") {
+            Target::Broadcast
+        } else {
+            Target::UnknownClient
+        }
+    }
+
+    /// Gets the name of the kind of client, for client targets.
+    fn client_kind(self) -> Option<&'static str> {
+        match self {
+            Target::Discord => Some("Discord"),
+            Target::Twitch => Some("Twitch"),
+            Target::Nats => Some("NATS"),
+            Target::Broadcast => Some("broadcast"),
+            _ => None,
+        }
+    }
+
+    /// Reads a part of a config as the target type.
+    ///
+    /// # Arguments
+    ///
+    /// * `deserializer` - The deserializer of the part.
+    fn read<'de, D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        use serde::Deserialize;
+
+        match self {
+            Target::Discord => DiscordConfig::deserialize(deserializer).map(drop),
+            Target::Twitch => TwitchConfig::deserialize(deserializer).map(drop),
+            Target::Nats => NatsConfig::deserialize(deserializer).map(drop),
+            Target::Broadcast => BroadcastConfig::deserialize(deserializer).map(drop),
+            Target::UnknownClient => deserializer.deserialize_any(UnknownClient),
+            Target::Fitter => PipeFitterConfig::deserialize(deserializer).map(drop),
+            Target::NamedFitter => NamedFitterConfig::deserialize(deserializer).map(drop),
+        }
+    }
+}
+
+/// Visitor rejecting a client block that doesn't look like any kind of client.
+struct UnknownClient;
+
+impl<'de> Visitor<'de> for UnknownClient {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "a client block with channel_ids (Discord), channels (Twitch), url (NATS) or \
+             source (broadcast)"
+        )
+    }
+}
+
+/// Seed reading the part of a config at a path as a target type, skipping the rest, so its
+/// errors keep their path and location.
+#[derive(Clone, Copy)]
+struct Probe<'a> {
+    path: &'a [Segment],
+    target: Target,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for Probe<'a> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        match self.path.first() {
+            None => self.target.read(deserializer),
+            Some(Segment::Key(_)) => deserializer.deserialize_map(self),
+            Some(Segment::Index(_)) => deserializer.deserialize_seq(self),
+        }
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for Probe<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter) -> FmtResult {
+        match self.path.first() {
+            Some(Segment::Key(key)) => write!(f, "a mapping with {}", key),
+            _ => write!(f, "a sequence"),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let next = Probe {
+            path: &self.path[1..],
+            target: self.target,
+        };
+        while let Some(key) = map.next_key::<String>()? {
+            match self.path.first() {
+                Some(Segment::Key(expected)) if key == *expected => map.next_value_seed(next)?,
+                _ => map.next_value::<IgnoredAny>().map(drop)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let next = Probe {
+            path: &self.path[1..],
+            target: self.target,
+        };
+        let mut idx = 0;
+        loop {
+            let found = match self.path.first() {
+                Some(Segment::Index(expected)) if idx == *expected => {
+                    seq.next_element_seed(next)?.is_some()
+                }
+                _ => seq.next_element::<IgnoredAny>()?.is_some(),
+            };
+            if !found {
+                return Ok(());
+            }
+            idx += 1;
+        }
+    }
+}
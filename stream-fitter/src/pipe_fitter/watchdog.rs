@@ -0,0 +1,237 @@
+//! Detects clients stalled while still running, e.g. a connection that stays open but stops
+//! delivering messages.
+//!
+//! Clients are stalled when they made no progress for the stall timeout while other clients
+//! did, so quiet periods where no client makes progress aren't mistaken for stalls. Clients
+//! are given the stall timeout to catch up once activity resumes after a quiet period. Besides
+//! the progress clients record themselves, the relay records the messages they send and the
+//! ones delivered to their stream.
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+use tracing::{debug, instrument};
+
+/// Shortest interval between checks for stalled clients.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracker of a client's last progress, e.g. a processed message or a received keepalive.
+///
+/// Clones share the same tracker.
+#[derive(Clone, Debug)]
+pub struct Progress {
+    epoch: Instant,
+    last_millis: Arc<AtomicU64>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Progress::new()
+    }
+}
+
+impl Progress {
+    /// Creates a tracker, counting its creation as progress.
+    pub fn new() -> Self {
+        Progress {
+            epoch: Instant::now(),
+            last_millis: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Records progress made now.
+    pub fn record(&self) {
+        let millis = self.epoch.elapsed().as_millis() as u64;
+        self.last_millis.fetch_max(millis, Ordering::Relaxed);
+    }
+
+    /// Gets the time since the last progress.
+    pub fn get_idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last_millis.load(Ordering::Relaxed));
+        self.epoch.elapsed().saturating_sub(last)
+    }
+}
+
+/// Event emitted when a client is detected as stalled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientStalled {
+    /// The client's unique ID.
+    pub id: String,
+    /// The client's name.
+    pub name: String,
+    /// The time since the client's last progress.
+    pub idle: Duration,
+}
+
+impl Display for ClientStalled {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "Client {} {} stalled, no progress for {}s",
+            self.name,
+            self.id,
+            self.idle.as_secs()
+        )
+    }
+}
+
+/// A client watched for stalls.
+#[derive(Clone)]
+pub(crate) struct WatchedClient {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) progress: Progress,
+}
+
+/// Loop to check the clients' progress, emitting an event for each stalled client.
+///
+/// A stalled client's progress is reset as it's emitted, so it isn't emitted again while
+/// it's restarted.
+///
+/// # Arguments
+///
+/// * `timeout` - The time without progress after which a client is stalled.
+/// * `clients` - The watched clients.
+/// * `events` - The TX channel stalls are emitted to.
+#[instrument(skip(clients, events))]
+pub(crate) async fn watchdog_loop(
+    timeout: Duration,
+    clients: Vec<WatchedClient>,
+    events: UnboundedSender<ClientStalled>,
+) {
+    let mut interval = tokio::time::interval((timeout / 4).max(MIN_CHECK_INTERVAL));
+    // Started as a quiet period, so clients get the timeout to start making progress.
+    let mut last_quiet = Instant::now();
+    loop {
+        interval.tick().await;
+
+        let idles = clients
+            .iter()
+            .map(|client| client.progress.get_idle())
+            .collect::<Vec<Duration>>();
+        if idles.iter().all(|idle| *idle > timeout) {
+            last_quiet = Instant::now();
+        }
+        let active = last_quiet.elapsed() > timeout;
+        let stalled = idles.iter().enumerate().find(|(idx, idle)| {
+            active
+                && **idle > timeout
+                && idles
+                    .iter()
+                    .enumerate()
+                    .any(|(other, other_idle)| other != *idx && *other_idle <= timeout)
+        });
+
+        match stalled {
+            Some((idx, idle)) => {
+                let client = &clients[idx];
+                client.progress.record();
+                if events
+                    .send(ClientStalled {
+                        id: client.id.clone(),
+                        name: client.name.clone(),
+                        idle: *idle,
+                    })
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            None => debug!("No stalled client"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{sync::mpsc::unbounded_channel, time::sleep};
+
+    use super::*;
+
+    /// Time without progress after which a client is stalled.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Builds a watched client.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The client's ID, also used as its name.
+    /// * `progress` - The client's progress tracker.
+    fn watched(id: &str, progress: &Progress) -> WatchedClient {
+        WatchedClient {
+            id: id.to_string(),
+            name: id.to_string(),
+            progress: progress.clone(),
+        }
+    }
+
+    /// Waits while one client records progress every second.
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - The progressing client's tracker.
+    /// * `secs` - The number of seconds to wait.
+    async fn progress_for(progress: &Progress, secs: u64) {
+        for _ in 0..secs {
+            sleep(Duration::from_secs(1)).await;
+            progress.record();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flags_clients_quiet_while_others_progress() {
+        let (quiet, busy) = (Progress::new(), Progress::new());
+        let (events, mut stalls) = unbounded_channel();
+        let clients = vec![watched("quiet", &quiet), watched("busy", &busy)];
+        tokio::spawn(watchdog_loop(TIMEOUT, clients, events));
+
+        progress_for(&busy, 9).await;
+        assert!(stalls.try_recv().is_err());
+
+        progress_for(&busy, 5).await;
+        let stalled = stalls.try_recv().unwrap();
+        assert_eq!(stalled.id, "quiet");
+        assert!(stalled.idle > TIMEOUT);
+        assert!(stalls.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ignores_quiet_periods() {
+        let (first, second) = (Progress::new(), Progress::new());
+        let (events, mut stalls) = unbounded_channel();
+        let clients = vec![watched("first", &first), watched("second", &second)];
+        tokio::spawn(watchdog_loop(TIMEOUT, clients, events));
+
+        sleep(TIMEOUT * 6).await;
+        assert!(stalls.try_recv().is_err());
+
+        // Progress after the quiet period doesn't make the other client stalled right away.
+        progress_for(&first, 9).await;
+        assert!(stalls.try_recv().is_err());
+        progress_for(&first, 5).await;
+        assert_eq!(stalls.try_recv().unwrap().id, "second");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flags_stalled_clients_again_after_the_timeout() {
+        let (quiet, busy) = (Progress::new(), Progress::new());
+        let (events, mut stalls) = unbounded_channel();
+        let clients = vec![watched("quiet", &quiet), watched("busy", &busy)];
+        tokio::spawn(watchdog_loop(TIMEOUT, clients, events));
+
+        progress_for(&busy, 14).await;
+        assert_eq!(stalls.try_recv().unwrap().id, "quiet");
+
+        // Reset when flagged, so only flagged again once quiet for the timeout.
+        progress_for(&busy, 9).await;
+        assert!(stalls.try_recv().is_err());
+        progress_for(&busy, 5).await;
+        assert_eq!(stalls.try_recv().unwrap().id, "quiet");
+    }
+}
@@ -0,0 +1,234 @@
+//! Secret handling for tokens and other credentials.
+use std::{
+    env,
+    fmt::{Debug, Display, Formatter, Result},
+};
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::errors::{FitterError, FitterErrorKind, FitterResult};
+
+/// A resolved secret value that is redacted when displayed or debugged.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wraps a secret value.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The secret value.
+    pub fn new(value: String) -> Self {
+        Secret(value)
+    }
+
+    /// Returns the underlying secret value.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+/// Source of the credentials referenced by key in the config, e.g. a secret manager.
+///
+/// Credentials are fetched every time clients are built, including when the stream manager
+/// restarts them, so rotated secrets are picked up without editing the config.
+#[async_trait]
+pub trait CredentialProvider: Send + Sync {
+    /// Fetches a credential.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key the credential is referenced by.
+    async fn fetch(&self, key: &str) -> FitterResult<String>;
+}
+
+/// Credential provider reading credentials from the environment variables named by their keys.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnvCredentialProvider;
+
+#[async_trait]
+impl CredentialProvider for EnvCredentialProvider {
+    async fn fetch(&self, key: &str) -> FitterResult<String> {
+        env_credential(key)
+    }
+}
+
+/// Reads a credential from the environment variable named by its key.
+///
+/// # Arguments
+///
+/// * `key` - The key the credential is referenced by.
+fn env_credential(key: &str) -> FitterResult<String> {
+    env::var(key).map_err(|err| {
+        FitterErrorKind::GenericErr(format!("Environment variable {}: {}", key, err)).into()
+    })
+}
+
+/// Token config, either a literal value or a reference to an OS keyring entry or a credential.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TokenConfig {
+    /// The token itself.
+    Literal(String),
+    /// An OS keyring entry named `<service>/<name>` holding the token.
+    Keyring { keyring: String },
+    /// The key of a credential holding the token, fetched through the stream manager's
+    /// credential provider, see `CredentialProvider`.
+    Ref { token_ref: String },
+}
+
+impl TokenConfig {
+    /// Resolves the token to its secret value, reading referenced credentials from the
+    /// environment.
+    ///
+    /// References to credentials of another provider are replaced with their values first,
+    /// see `TokenConfig::fetch_ref`.
+    pub fn resolve(self) -> FitterResult<Secret> {
+        match self {
+            TokenConfig::Literal(token) => Ok(Secret::new(token)),
+            TokenConfig::Keyring { keyring } => get_keyring_secret(&keyring),
+            TokenConfig::Ref { token_ref } => env_credential(&token_ref)
+                .map(Secret::new)
+                .map_err(|err| credential_error(&token_ref, err)),
+        }
+    }
+
+    /// Replaces a reference to a credential with the credential's value.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The provider to fetch the credential from.
+    pub(crate) async fn fetch_ref(
+        &mut self,
+        provider: &dyn CredentialProvider,
+    ) -> FitterResult<()> {
+        if let TokenConfig::Ref { token_ref } = self {
+            let token = provider
+                .fetch(token_ref)
+                .await
+                .map_err(|err| credential_error(token_ref, err))?;
+            *self = TokenConfig::Literal(token);
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the error fetching a credential with the credential's key.
+///
+/// # Arguments
+///
+/// * `key` - The key the credential is referenced by.
+/// * `err` - The error fetching the credential.
+fn credential_error(key: &str, err: FitterError) -> FitterError {
+    FitterErrorKind::GenericErr(format!("Credential {}: {}", key, err)).into()
+}
+
+/// Splits a keyring entry name into its service and user parts.
+///
+/// # Arguments
+///
+/// * `entry` - The keyring entry, formatted as `<service>/<name>`.
+fn split_entry(entry: &str) -> FitterResult<(&str, &str)> {
+    match entry.find('/') {
+        Some(idx) if idx > 0 && idx < entry.len() - 1 => Ok((&entry[..idx], &entry[idx + 1..])),
+        _ => Err(FitterErrorKind::KeyringErr(format!(
+            "Invalid keyring entry {:?}, expected <service>/<name>",
+            entry
+        ))
+        .into()),
+    }
+}
+
+/// Opens an OS keyring entry.
+///
+/// # Arguments
+///
+/// * `entry` - The keyring entry, formatted as `<service>/<name>`.
+#[cfg(feature = "keyring")]
+fn keyring_entry(entry: &str) -> FitterResult<keyring::Entry> {
+    let (service, name) = split_entry(entry)?;
+    keyring::Entry::new(service, name).map_err(|err| {
+        FitterErrorKind::KeyringErr(format!("Keyring entry {}: {}", entry, err)).into()
+    })
+}
+
+/// Fetches a secret from the OS keyring.
+///
+/// # Arguments
+///
+/// * `entry` - The keyring entry, formatted as `<service>/<name>`.
+#[cfg(feature = "keyring")]
+pub fn get_keyring_secret(entry: &str) -> FitterResult<Secret> {
+    match keyring_entry(entry)?.get_password() {
+        Ok(password) => Ok(Secret::new(password)),
+        Err(keyring::Error::NoEntry) => {
+            Err(FitterErrorKind::KeyringErr(format!("Keyring entry {} not found", entry)).into())
+        }
+        Err(err) => {
+            Err(FitterErrorKind::KeyringErr(format!("Keyring entry {}: {}", entry, err)).into())
+        }
+    }
+}
+
+/// Fetches a secret from the OS keyring.
+///
+/// Always fails as keyring support is disabled.
+///
+/// # Arguments
+///
+/// * `entry` - The keyring entry, formatted as `<service>/<name>`.
+#[cfg(not(feature = "keyring"))]
+pub fn get_keyring_secret(entry: &str) -> FitterResult<Secret> {
+    split_entry(entry)?;
+    Err(FitterErrorKind::KeyringErr(format!(
+        "Keyring entry {} requested but the keyring feature is disabled",
+        entry
+    ))
+    .into())
+}
+
+/// Stores a secret in the OS keyring.
+///
+/// # Arguments
+///
+/// * `entry` - The keyring entry, formatted as `<service>/<name>`.
+/// * `secret` - The secret to store.
+#[cfg(feature = "keyring")]
+pub fn set_keyring_secret(entry: &str, secret: &Secret) -> FitterResult<()> {
+    keyring_entry(entry)?
+        .set_password(secret.expose())
+        .map_err(|err| {
+            FitterErrorKind::KeyringErr(format!("Keyring entry {}: {}", entry, err)).into()
+        })
+}
+
+/// Removes a secret from the OS keyring.
+///
+/// # Arguments
+///
+/// * `entry` - The keyring entry, formatted as `<service>/<name>`.
+#[cfg(feature = "keyring")]
+pub fn delete_keyring_secret(entry: &str) -> FitterResult<()> {
+    match keyring_entry(entry)?.delete_credential() {
+        Ok(_) => Ok(()),
+        Err(keyring::Error::NoEntry) => {
+            Err(FitterErrorKind::KeyringErr(format!("Keyring entry {} not found", entry)).into())
+        }
+        Err(err) => {
+            Err(FitterErrorKind::KeyringErr(format!("Keyring entry {}: {}", entry, err)).into())
+        }
+    }
+}
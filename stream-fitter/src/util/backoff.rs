@@ -0,0 +1,342 @@
+//! Exponential backoff with jitter, for retrying failing operations.
+//!
+//! Delays are computed by pure functions of the attempt number and a jitter sample, and
+//! waited with `tokio::time::sleep`, so retries can be tested with Tokio's paused clock.
+//!
+//! Twitch chat connections aren't reconnected with it: twitch-irc opens and reopens them
+//! internally, with a backoff of its own.
+use std::{future::Future, time::Duration};
+
+use rand::Rng;
+use tracing::debug;
+
+/// Default delay before the first retry.
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+/// Default longest delay between retries.
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Default factor the delay grows by after each retry.
+const DEFAULT_MULTIPLIER: f64 = 2.0;
+/// Default fraction of the delay randomly added or removed.
+const DEFAULT_JITTER: f64 = 0.1;
+
+/// Exponential backoff policy.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), std::io::Error> {
+/// use std::time::Duration;
+///
+/// use stream_fitter::util::backoff::Backoff;
+///
+/// let backoff = Backoff::new()
+///     .with_initial_delay(Duration::from_millis(100))
+///     .with_max_attempts(5);
+/// let contents = backoff
+///     .retry(
+///         || tokio::fs::read_to_string("feed.txt"),
+///         |err| err.kind() == std::io::ErrorKind::NotFound,
+///     )
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            multiplier: DEFAULT_MULTIPLIER,
+            jitter: DEFAULT_JITTER,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Creates a backoff policy with the default settings, retrying forever.
+    pub fn new() -> Self {
+        Backoff::default()
+    }
+
+    /// Sets the delay before the first retry.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial_delay` - The delay.
+    pub fn with_initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    /// Sets the longest delay between retries, before jitter.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_delay` - The delay.
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets the factor the delay grows by after each retry.
+    ///
+    /// # Arguments
+    ///
+    /// * `multiplier` - The factor, at least 1.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Sets the fraction of the delay randomly added or removed, so clients failing together
+    /// don't retry together.
+    ///
+    /// # Arguments
+    ///
+    /// * `jitter` - The fraction, between 0 and 1.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the number of attempts, including the first one, before giving up.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_attempts` - The number of attempts, at least 1.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts.max(1));
+        self
+    }
+
+    /// Gets the delay before a retry, without jitter.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The retry's number, starting at 0 for the first retry.
+    pub fn get_base_delay(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry.min(i32::MAX as u32) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        if !delay.is_finite() || delay >= self.max_delay.as_secs_f64() {
+            self.max_delay
+        } else {
+            Duration::from_secs_f64(delay)
+        }
+    }
+
+    /// Gets the delay before a retry, with jitter.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The retry's number, starting at 0 for the first retry.
+    /// * `sample` - Where the delay falls in the jitter range, between -1 and 1.
+    pub fn get_delay(&self, retry: u32, sample: f64) -> Duration {
+        let delay = self.get_base_delay(retry).as_secs_f64();
+        Duration::from_secs_f64(delay * (1.0 + self.jitter * sample.clamp(-1.0, 1.0)))
+    }
+
+    /// Gets the delays between attempts, with random jitter.
+    ///
+    /// Ends once the attempts are exhausted, or never without a maximum number of attempts.
+    pub fn delays(&self) -> Delays {
+        Delays {
+            backoff: self.clone(),
+            retry: 0,
+        }
+    }
+
+    /// Runs an operation until it succeeds, waiting between attempts.
+    ///
+    /// Gives up with the last error when the error isn't retryable or the attempts are
+    /// exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * `operation` - Starts an attempt of the operation.
+    /// * `is_retryable` - Checks whether an error is worth retrying.
+    pub async fn retry<T, E, F, Fut, P>(
+        &self,
+        mut operation: F,
+        mut is_retryable: P,
+    ) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        P: FnMut(&E) -> bool,
+    {
+        let mut delays = self.delays();
+        loop {
+            let err = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            if !is_retryable(&err) {
+                return Err(err);
+            }
+            match delays.next() {
+                Some(delay) => {
+                    debug!("Attempt failed, retrying in {:?}", delay);
+                    tokio::time::sleep(delay).await;
+                }
+                None => return Err(err),
+            }
+        }
+    }
+}
+
+/// Iterator over the delays between attempts of a `Backoff`.
+#[derive(Clone, Debug)]
+pub struct Delays {
+    backoff: Backoff,
+    retry: u32,
+}
+
+impl Iterator for Delays {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        if let Some(max_attempts) = self.backoff.max_attempts {
+            if self.retry + 1 >= max_attempts {
+                return None;
+            }
+        }
+
+        let sample = rand::thread_rng().gen_range(-1.0..=1.0);
+        let delay = self.backoff.get_delay(self.retry, sample);
+        self.retry = self.retry.saturating_add(1);
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use tokio::time::Instant;
+
+    use super::*;
+
+    /// Backoff without jitter, doubling from 100ms up to 1s.
+    fn backoff() -> Backoff {
+        Backoff::new()
+            .with_initial_delay(Duration::from_millis(100))
+            .with_max_delay(Duration::from_secs(1))
+            .with_jitter(0.0)
+    }
+
+    #[test]
+    fn grows_delay_by_multiplier_up_to_max() {
+        let delays = (0..6)
+            .map(|retry| backoff().get_base_delay(retry).as_millis())
+            .collect::<Vec<u128>>();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+
+        let tripling = backoff().with_multiplier(3.0);
+        assert_eq!(tripling.get_base_delay(2), Duration::from_millis(900));
+        assert_eq!(tripling.get_base_delay(3), Duration::from_secs(1));
+        // Multipliers below 1 would shrink the delay.
+        let constant = backoff().with_multiplier(0.5);
+        assert_eq!(constant.get_base_delay(5), Duration::from_millis(100));
+        assert_eq!(backoff().get_base_delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn bounds_jitter_by_sample() {
+        let backoff = backoff().with_jitter(0.5);
+        assert_eq!(backoff.get_delay(1, 0.0), Duration::from_millis(200));
+        assert_eq!(backoff.get_delay(1, -1.0), Duration::from_millis(100));
+        assert_eq!(backoff.get_delay(1, 1.0), Duration::from_millis(300));
+        // Samples out of range are clamped.
+        assert_eq!(backoff.get_delay(1, -5.0), Duration::from_millis(100));
+        assert_eq!(backoff.get_delay(1, 5.0), Duration::from_millis(300));
+        // The cap applies before jitter.
+        assert_eq!(backoff.get_delay(10, 1.0), Duration::from_millis(1500));
+
+        for delay in backoff.delays().take(100) {
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(1500));
+        }
+    }
+
+    #[test]
+    fn stops_after_max_attempts() {
+        assert_eq!(backoff().with_max_attempts(4).delays().count(), 3);
+        assert_eq!(backoff().with_max_attempts(1).delays().count(), 0);
+        assert_eq!(backoff().with_max_attempts(0).delays().count(), 0);
+        assert_eq!(backoff().delays().take(50).count(), 50);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_until_success() {
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let result = backoff()
+            .retry(
+                || {
+                    attempts.set(attempts.get() + 1);
+                    let attempt = attempts.get();
+                    async move {
+                        match attempt {
+                            3 => Ok(attempt),
+                            _ => Err("failed"),
+                        }
+                    }
+                },
+                |_| true,
+            )
+            .await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_exhausted_attempts() {
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let result: Result<(), &str> = backoff()
+            .with_max_attempts(3)
+            .retry(
+                || {
+                    attempts.set(attempts.get() + 1);
+                    async { Err("failed") }
+                },
+                |_| true,
+            )
+            .await;
+        assert_eq!(result, Err("failed"));
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_non_retryable_errors() {
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let result: Result<(), &str> = backoff()
+            .retry(
+                || {
+                    attempts.set(attempts.get() + 1);
+                    let attempt = attempts.get();
+                    async move {
+                        match attempt {
+                            1 => Err("transient"),
+                            _ => Err("fatal"),
+                        }
+                    }
+                },
+                |err| *err == "transient",
+            )
+            .await;
+        assert_eq!(result, Err("fatal"));
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,206 @@
+//! Catalog of the messages the bridge generates itself, e.g. summaries or stream status
+//! announcements, by locale.
+//!
+//! Messages are templates, see `util::template`, whose fields are the message's parameters.
+//! Messages set in the config under `messages` take precedence over the locale's, and unknown
+//! locales fall back to English with a warning. Counts that need a plural form are given
+//! along with a `*_plural` field, only non-empty when the count isn't one, for use in
+//! conditional sections, e.g. `{count} message{?count_plural}s{/count_plural}`.
+//!
+//! To add a locale, add a table mapping every key to its message, and list it in `LOCALES`.
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    errors::{FitterErrorKind, FitterResult},
+    util::template::Template,
+};
+
+/// Locale used when none is configured, and for unknown ones.
+const DEFAULT_LOCALE: &str = "en";
+
+/// Message generated by the bridge.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogKey {
+    /// Summary of the relay activity.
+    Summary,
+    /// Summary when no message was relayed.
+    SummaryEmpty,
+    /// Digest of a channel's chat.
+    Digest,
+    /// Number of messages dropped over a channel's messages per minute cap.
+    Suppressed,
+    /// Stream went live.
+    WentLive,
+    /// Stream went offline.
+    WentOffline,
+    /// User joined a voice channel.
+    VoiceJoined,
+    /// User left a voice channel.
+    VoiceLeft,
+    /// Message relayed from the history missed while down.
+    Backfill,
+    /// Message pinned in a channel.
+    Pinned,
+}
+
+impl CatalogKey {
+    /// Every key, to build a complete catalog.
+    const ALL: &'static [CatalogKey] = &[
+        CatalogKey::Summary,
+        CatalogKey::SummaryEmpty,
+        CatalogKey::Digest,
+        CatalogKey::Suppressed,
+        CatalogKey::WentLive,
+        CatalogKey::WentOffline,
+        CatalogKey::VoiceJoined,
+        CatalogKey::VoiceLeft,
+        CatalogKey::Backfill,
+        CatalogKey::Pinned,
+    ];
+
+    /// Gets the key's name, as written in the config.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            CatalogKey::Summary => "summary",
+            CatalogKey::SummaryEmpty => "summary_empty",
+            CatalogKey::Digest => "digest",
+            CatalogKey::Suppressed => "suppressed",
+            CatalogKey::WentLive => "went_live",
+            CatalogKey::WentOffline => "went_offline",
+            CatalogKey::VoiceJoined => "voice_joined",
+            CatalogKey::VoiceLeft => "voice_left",
+            CatalogKey::Backfill => "backfill",
+            CatalogKey::Pinned => "pinned",
+        }
+    }
+
+    /// Gets the parameters of the message, in the order their values are given when rendering.
+    pub fn get_fields(&self) -> &'static [&'static str] {
+        match self {
+            CatalogKey::Summary => &["count", "count_plural", "platforms", "authors", "channels"],
+            CatalogKey::SummaryEmpty => &[],
+            CatalogKey::Digest => &[
+                "count",
+                "count_plural",
+                "author_count",
+                "author_count_plural",
+                "authors",
+                "latest",
+            ],
+            CatalogKey::Suppressed => &["count", "count_plural"],
+            CatalogKey::WentLive => &["broadcaster", "title"],
+            CatalogKey::WentOffline => &["broadcaster"],
+            CatalogKey::VoiceJoined | CatalogKey::VoiceLeft => &["user", "channel"],
+            CatalogKey::Backfill | CatalogKey::Pinned => &["content"],
+        }
+    }
+}
+
+/// English messages.
+///
+/// # Arguments
+///
+/// * `key` - The message to get.
+fn en(key: CatalogKey) -> &'static str {
+    match key {
+        CatalogKey::Summary => {
+            "Bridge summary: {count} messages relayed ({platforms}) | Top authors: {authors} | \
+             Channels: {channels}"
+        }
+        CatalogKey::SummaryEmpty => "Bridge summary: no messages relayed",
+        CatalogKey::Digest => {
+            "Digest: {count} message{?count_plural}s{/count_plural} from {author_count} \
+             author{?author_count_plural}s{/author_count_plural} | Top authors: {authors} | \
+             Latest: {latest}"
+        }
+        CatalogKey::Suppressed => "({count} message{?count_plural}s{/count_plural} suppressed)",
+        CatalogKey::WentLive => "{broadcaster} went live{?title}: {title}{/title}",
+        CatalogKey::WentOffline => "{broadcaster} went offline",
+        CatalogKey::VoiceJoined => "{user} joined {channel}",
+        CatalogKey::VoiceLeft => "{user} left {channel}",
+        CatalogKey::Backfill => "[backfill] {content}",
+        CatalogKey::Pinned => "📌 pinned: {content}",
+    }
+}
+
+/// Table mapping every key to its message in a locale.
+type LocaleTable = fn(CatalogKey) -> &'static str;
+
+/// Tables of messages by locale.
+const LOCALES: &[(&str, LocaleTable)] = &[("en", en)];
+
+/// Gets the value of a `*_plural` field for a count.
+///
+/// # Arguments
+///
+/// * `count` - The count.
+pub fn plural(count: u64) -> &'static str {
+    match count {
+        1 => "",
+        _ => "plural",
+    }
+}
+
+/// Messages generated by the bridge, in a locale.
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    templates: HashMap<CatalogKey, Template>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Catalog::from_config(None, None).expect("Default messages are valid")
+    }
+}
+
+impl Catalog {
+    /// Builds the catalog of a locale, validating the configured messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `locale` - The locale, e.g. `en` or `en-US`, defaults to English.
+    /// * `messages` - Messages taking precedence over the locale's.
+    pub fn from_config(
+        locale: Option<&str>,
+        messages: Option<&HashMap<CatalogKey, String>>,
+    ) -> FitterResult<Self> {
+        let locale = locale.unwrap_or(DEFAULT_LOCALE).to_lowercase();
+        // Regional variants fall back to their language, e.g. `pt-BR` to `pt`.
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+        let table = LOCALES
+            .iter()
+            .find(|(name, _)| *name == locale)
+            .or_else(|| LOCALES.iter().find(|(name, _)| *name == language))
+            .map(|(_, table)| *table)
+            .unwrap_or_else(|| {
+                warn!("Unknown locale {}, falling back to English", locale);
+                en
+            });
+
+        let mut templates = HashMap::new();
+        for key in CatalogKey::ALL {
+            let template = match messages.and_then(|messages| messages.get(key)) {
+                Some(message) => Template::parse(message, key.get_fields()).map_err(|err| {
+                    FitterErrorKind::GenericErr(format!("Message {}: {}", key.get_name(), err))
+                })?,
+                None => Template::parse(table(*key), key.get_fields())?,
+            };
+            templates.insert(*key, template);
+        }
+        Ok(Catalog { templates })
+    }
+
+    /// Renders a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The message to render.
+    /// * `values` - The values of the message's parameters, see `CatalogKey::get_fields`.
+    pub fn render(&self, key: CatalogKey, values: &[&str]) -> String {
+        self.templates[&key].render(values)
+    }
+}
@@ -0,0 +1,203 @@
+//! Conversion of emoji shortcodes like `:smile:` to Unicode emoji.
+//!
+//! Platforms disagree on shortcodes while Unicode emoji render everywhere, so relayed messages
+//! are normalized to Unicode. Only common shortcodes are known, others are left as is.
+
+/// Known shortcodes without their colons and their emoji, sorted by shortcode.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("angry", "😠"),
+    ("beers", "🍻"),
+    ("black_heart", "🖤"),
+    ("blue_heart", "💙"),
+    ("blush", "😊"),
+    ("boom", "💥"),
+    ("broken_heart", "💔"),
+    ("cat", "🐱"),
+    ("clap", "👏"),
+    ("clown_face", "🤡"),
+    ("coffee", "☕"),
+    ("cold_face", "🥶"),
+    ("cold_sweat", "😰"),
+    ("confused", "😕"),
+    ("cool", "🆒"),
+    ("crown", "👑"),
+    ("cry", "😢"),
+    ("dog", "🐶"),
+    ("exploding_head", "🤯"),
+    ("expressionless", "😑"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fire", "🔥"),
+    ("fist", "✊"),
+    ("flushed", "😳"),
+    ("ghost", "👻"),
+    ("gift", "🎁"),
+    ("green_heart", "💚"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("handshake", "🤝"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("hot_face", "🥵"),
+    ("hugs", "🤗"),
+    ("innocent", "😇"),
+    ("joy", "😂"),
+    ("kiss", "😘"),
+    ("laughing", "😆"),
+    ("melting_face", "🫠"),
+    ("money_mouth_face", "🤑"),
+    ("moon", "🌙"),
+    ("muscle", "💪"),
+    ("musical_note", "🎵"),
+    ("nerd_face", "🤓"),
+    ("neutral_face", "😐"),
+    ("ok_hand", "👌"),
+    ("orange_heart", "🧡"),
+    ("party_popper", "🎉"),
+    ("partying_face", "🥳"),
+    ("pensive", "😔"),
+    ("pizza", "🍕"),
+    ("pleading_face", "🥺"),
+    ("point_down", "👇"),
+    ("point_left", "👈"),
+    ("point_right", "👉"),
+    ("point_up", "☝️"),
+    ("poop", "💩"),
+    ("pray", "🙏"),
+    ("purple_heart", "💜"),
+    ("rage", "😡"),
+    ("rainbow", "🌈"),
+    ("raised_hand", "✋"),
+    ("raised_hands", "🙌"),
+    ("relieved", "😌"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("salute", "🫡"),
+    ("scream", "😱"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smiling_face_with_tear", "🥲"),
+    ("smirk", "😏"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("star_struck", "🤩"),
+    ("stuck_out_tongue", "😛"),
+    ("sunglasses", "😎"),
+    ("sunny", "☀️"),
+    ("sweat", "😓"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("tired_face", "😫"),
+    ("triumph", "😤"),
+    ("trophy", "🏆"),
+    ("unamused", "😒"),
+    ("upside_down_face", "🙃"),
+    ("v", "✌️"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("weary", "😩"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("x", "❌"),
+    ("yellow_heart", "💛"),
+    ("yum", "😋"),
+    ("zany_face", "🤪"),
+    ("zzz", "💤"),
+];
+
+/// Gets the emoji of a shortcode.
+///
+/// # Arguments
+///
+/// * `shortcode` - The shortcode without its colons, e.g. `smile`.
+pub fn get_emoji(shortcode: &str) -> Option<&'static str> {
+    SHORTCODES
+        .binary_search_by_key(&shortcode, |(shortcode, _)| shortcode)
+        .ok()
+        .map(|idx| SHORTCODES[idx].1)
+}
+
+/// Replaces the known shortcodes of a text with their emoji.
+///
+/// Discord custom emoji, e.g. `<:fire:123>` or `<a:fire:123>` when animated, are left as is.
+/// Returns `None` when the text has no known shortcode.
+///
+/// # Arguments
+///
+/// * `text` - The text to convert.
+pub fn shortcodes_to_unicode(text: &str) -> Option<String> {
+    let mut converted = String::new();
+    let mut copied = 0;
+    let mut start = 0;
+    while let Some(open) = text[start..].find(':').map(|idx| start + idx) {
+        let close = match text[open + 1..].find(':') {
+            Some(idx) => open + 1 + idx,
+            None => break,
+        };
+        let custom = text[..open].ends_with('<') || text[..open].ends_with("<a");
+        match get_emoji(&text[open + 1..close]).filter(|_| !custom) {
+            Some(emoji) => {
+                converted.push_str(&text[copied..open]);
+                converted.push_str(emoji);
+                copied = close + 1;
+                start = close + 1;
+            }
+            // The closing colon may open the next shortcode, e.g. `12:30 :smile:`.
+            None => start = close,
+        }
+    }
+
+    if copied == 0 {
+        return None;
+    }
+    converted.push_str(&text[copied..]);
+    Some(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_known_shortcodes() {
+        assert_eq!(
+            shortcodes_to_unicode(":fire: hype :+1:").as_deref(),
+            Some("🔥 hype 👍")
+        );
+        assert_eq!(
+            shortcodes_to_unicode("back at 12:30 :smile:").as_deref(),
+            Some("back at 12:30 😄")
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_shortcodes() {
+        assert_eq!(shortcodes_to_unicode(":not_an_emoji: or 12:30"), None);
+        assert_eq!(
+            shortcodes_to_unicode(":not_an_emoji: :wave:").as_deref(),
+            Some(":not_an_emoji: 👋")
+        );
+    }
+
+    #[test]
+    fn leaves_discord_custom_emoji() {
+        assert_eq!(shortcodes_to_unicode("<:fire:123456>"), None);
+        assert_eq!(shortcodes_to_unicode("<a:fire:123456>"), None);
+        assert_eq!(
+            shortcodes_to_unicode("<:fire:123456> :fire:").as_deref(),
+            Some("<:fire:123456> 🔥")
+        );
+    }
+}
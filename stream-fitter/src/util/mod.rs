@@ -0,0 +1,5 @@
+//! Utilities shared by clients and the stream manager.
+pub mod backoff;
+pub mod catalog;
+pub mod emoji;
+pub mod template;
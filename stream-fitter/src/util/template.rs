@@ -0,0 +1,397 @@
+//! Templates rendering named fields into text, e.g. the per-client formats of forwarded
+//! messages.
+//!
+//! Templates are parsed once into a `Template` when the config is loaded, so invalid templates
+//! are reported at startup with the position of the offending character. The syntax is:
+//!
+//! * `{field}` inserts a field.
+//! * `{field|modifier|...}` inserts a field transformed by modifiers, applied in order:
+//!   `upper`, `lower` and `truncate:N`, which keeps the first N characters and adds `…` when
+//!   the field is cut.
+//! * `{?field}...{/field}` only renders its contents when the field isn't empty. Sections can
+//!   be nested.
+//! * `{{` and `}}` insert literal braces.
+use std::{iter::Peekable, str::Chars};
+
+use crate::errors::{FitterErrorKind, FitterResult};
+
+/// Marker appended to truncated fields.
+const TRUNCATION_MARKER: char = '…';
+
+/// Transformation of an inserted field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Modifier {
+    Upper,
+    Lower,
+    Truncate(usize),
+}
+
+impl Modifier {
+    /// Parses a modifier, returning an error message if it's invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `modifier` - The modifier, e.g. `truncate:200`.
+    fn parse(modifier: &str) -> Result<Self, String> {
+        match modifier.split_once(':') {
+            None if modifier == "upper" => Ok(Modifier::Upper),
+            None if modifier == "lower" => Ok(Modifier::Lower),
+            Some(("truncate", length)) => length
+                .parse()
+                .map(Modifier::Truncate)
+                .map_err(|_| format!("invalid truncate length {:?}", length)),
+            _ => Err(format!(
+                "unknown modifier {:?}, expected upper, lower or truncate:N",
+                modifier
+            )),
+        }
+    }
+
+    /// Applies the modifier to a field.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The field, possibly transformed by previous modifiers.
+    fn apply(&self, value: String) -> String {
+        match self {
+            Modifier::Upper => value.to_uppercase(),
+            Modifier::Lower => value.to_lowercase(),
+            Modifier::Truncate(length) => match value.char_indices().nth(*length) {
+                Some((end, _)) => {
+                    let mut truncated = value[..end].to_string();
+                    truncated.push(TRUNCATION_MARKER);
+                    truncated
+                }
+                None => value,
+            },
+        }
+    }
+}
+
+/// Part of a parsed template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    /// Literal text.
+    Text(String),
+    /// Field inserted with modifiers, by index in the template's fields.
+    Field {
+        field: usize,
+        modifiers: Vec<Modifier>,
+    },
+    /// Contents only rendered when a field isn't empty.
+    Section { field: usize, body: Vec<Node> },
+}
+
+/// Parser building the nodes of a template.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// Number of characters consumed.
+    position: usize,
+    fields: &'a [&'a str],
+}
+
+/// Parse error, with the position of the offending character.
+type ParseError = (usize, String);
+
+impl<'a> Parser<'a> {
+    /// Consumes the next character.
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.position += 1;
+        Some(c)
+    }
+
+    /// Looks up a field by name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The field's name.
+    /// * `position` - The position of the name, for errors.
+    fn field(&self, name: &str, position: usize) -> Result<usize, ParseError> {
+        self.fields
+            .iter()
+            .position(|field| *field == name)
+            .ok_or_else(|| {
+                (
+                    position,
+                    format!(
+                        "unknown field {:?}, expected one of {}",
+                        name,
+                        self.fields.join(", ")
+                    ),
+                )
+            })
+    }
+
+    /// Parses nodes until the end of the template or of the enclosing section.
+    ///
+    /// # Arguments
+    ///
+    /// * `section` - The field and position of the enclosing section, if any.
+    fn parse_nodes(&mut self, section: Option<(usize, usize)>) -> Result<Vec<Node>, ParseError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        loop {
+            let start = self.position;
+            let c = match self.next() {
+                Some(c) => c,
+                None => break,
+            };
+
+            match c {
+                '{' if self.chars.peek() == Some(&'{') => {
+                    self.next();
+                    text.push('{');
+                }
+                '}' if self.chars.peek() == Some(&'}') => {
+                    self.next();
+                    text.push('}');
+                }
+                '}' => {
+                    return Err((
+                        start,
+                        "unmatched '}', use '}}' for a literal brace".to_string(),
+                    ))
+                }
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match self.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => {
+                                return Err((
+                                    start,
+                                    "unclosed '{', use '{{' for a literal brace".to_string(),
+                                ))
+                            }
+                        }
+                    }
+
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    if let Some(name) = tag.strip_prefix('?') {
+                        let field = self.field(name, start + 2)?;
+                        let body = self.parse_nodes(Some((field, start)))?;
+                        nodes.push(Node::Section { field, body });
+                    } else if let Some(name) = tag.strip_prefix('/') {
+                        let field = self.field(name, start + 2)?;
+                        return match section {
+                            Some((open, _)) if open == field => Ok(nodes),
+                            Some((open, _)) => Err((
+                                start,
+                                format!("expected {{/{}}}, got {{/{}}}", self.fields[open], name),
+                            )),
+                            None => Err((start, format!("unopened section {{/{}}}", name))),
+                        };
+                    } else {
+                        nodes.push(self.parse_field(&tag, start + 1)?);
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        if let Some((field, start)) = section {
+            return Err((
+                start,
+                format!("unclosed section {{?{}}}", self.fields[field]),
+            ));
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(nodes)
+    }
+
+    /// Parses an inserted field and its modifiers.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The contents of the braces, e.g. `content|truncate:200`.
+    /// * `position` - The position of the tag's contents, for errors.
+    fn parse_field(&self, tag: &str, position: usize) -> Result<Node, ParseError> {
+        let mut parts = tag.split('|');
+        let name = parts.next().unwrap_or_default();
+        let field = self.field(name, position)?;
+
+        let mut offset = position + name.chars().count() + 1;
+        let mut modifiers = Vec::new();
+        for part in parts {
+            modifiers.push(Modifier::parse(part).map_err(|err| (offset, err))?);
+            offset += part.chars().count() + 1;
+        }
+        Ok(Node::Field { field, modifiers })
+    }
+}
+
+/// Parsed template, rendered from the values of its fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    /// Parses a template.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The template to parse.
+    /// * `fields` - The names of the fields the template can use, in the order their values
+    ///   are given when rendering.
+    pub fn parse(template: &str, fields: &[&str]) -> FitterResult<Self> {
+        let mut parser = Parser {
+            chars: template.chars().peekable(),
+            position: 0,
+            fields,
+        };
+        match parser.parse_nodes(None) {
+            Ok(nodes) => Ok(Template { nodes }),
+            Err((position, err)) => Err(FitterErrorKind::GenericErr(format!(
+                "Invalid template {:?} at character {}: {}",
+                template,
+                position + 1,
+                err
+            ))
+            .into()),
+        }
+    }
+
+    /// Renders the template.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The values of the fields, in the order they were given when parsing.
+    pub fn render(&self, values: &[&str]) -> String {
+        let mut rendered = String::new();
+        Self::render_nodes(&self.nodes, values, &mut rendered);
+        rendered
+    }
+
+    /// Renders nodes, recursing into sections.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - The nodes to render.
+    /// * `values` - The values of the fields.
+    /// * `rendered` - The text rendered so far.
+    fn render_nodes(nodes: &[Node], values: &[&str], rendered: &mut String) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => rendered.push_str(text),
+                Node::Field { field, modifiers } => {
+                    let value = modifiers
+                        .iter()
+                        .fold(values[*field].to_string(), |value, modifier| {
+                            modifier.apply(value)
+                        });
+                    rendered.push_str(&value);
+                }
+                Node::Section { field, body } => {
+                    if !values[*field].is_empty() {
+                        Self::render_nodes(body, values, rendered);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fields of the tested templates.
+    const FIELDS: &[&str] = &["author", "channel", "content"];
+
+    /// Renders a template.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The template.
+    /// * `values` - The values of `FIELDS`.
+    fn render(template: &str, values: &[&str]) -> String {
+        Template::parse(template, FIELDS).unwrap().render(values)
+    }
+
+    /// Gets the error message of an invalid template.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The template.
+    fn parse_err(template: &str) -> String {
+        Template::parse(template, FIELDS).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn inserts_fields() {
+        assert_eq!(
+            render("[{channel}] {author}: {content}", &["alice", "chat", "hi"]),
+            "[chat] alice: hi"
+        );
+        assert_eq!(render("", &["alice", "chat", "hi"]), "");
+    }
+
+    #[test]
+    fn escapes_literal_braces() {
+        assert_eq!(
+            render("{{{author}}} {{}}", &["alice", "chat", "hi"]),
+            "{alice} {}"
+        );
+    }
+
+    #[test]
+    fn applies_modifiers_in_order() {
+        let values = &["Alice", "chat", "héllo world"];
+        assert_eq!(render("{author|upper}", values), "ALICE");
+        assert_eq!(render("{author|lower}", values), "alice");
+        assert_eq!(render("{content|truncate:2}", values), "hé…");
+        assert_eq!(render("{content|truncate:11}", values), "héllo world");
+        assert_eq!(render("{content|truncate:0}", values), "…");
+        assert_eq!(render("{content|truncate:5|upper}", values), "HÉLLO…");
+        assert_eq!(render("{author|upper|lower}", values), "alice");
+    }
+
+    #[test]
+    fn renders_nested_sections() {
+        let template = "{?author}{author}{?channel} in {channel}{/channel}: {/author}{content}";
+        assert_eq!(
+            render(template, &["alice", "chat", "hi"]),
+            "alice in chat: hi"
+        );
+        assert_eq!(render(template, &["alice", "", "hi"]), "alice: hi");
+        assert_eq!(render(template, &["", "chat", "hi"]), "hi");
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        for (template, position, err) in [
+            ("ab}", 3, "unmatched '}'"),
+            ("ab {author", 4, "unclosed '{'"),
+            ("x {unknown}", 4, "unknown field \"unknown\""),
+            ("{content|shout}", 10, "unknown modifier \"shout\""),
+            (
+                "{content|upper|truncate:x}",
+                16,
+                "invalid truncate length \"x\"",
+            ),
+            ("{?author}hi", 1, "unclosed section {?author}"),
+            (
+                "{?author}{/content}",
+                10,
+                "expected {/author}, got {/content}",
+            ),
+            ("hi {/author}", 4, "unopened section {/author}"),
+            ("{?nobody}{/nobody}", 3, "unknown field \"nobody\""),
+        ] {
+            let message = parse_err(template);
+            assert!(
+                message.contains(&format!("at character {}: {}", position, err)),
+                "{:?}: {}",
+                template,
+                message
+            );
+        }
+    }
+}
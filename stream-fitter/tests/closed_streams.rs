@@ -0,0 +1,150 @@
+//! Integration tests of the relay dropping clients that stopped receiving.
+use std::{
+    io::{Result as IoResult, Write},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use stream_fitter::{
+    clients::{client::Message, mock::MockClient},
+    pipe_fitter::{PipeFitter, PipeFitterConfig},
+};
+use tokio::time::timeout;
+
+/// Time to wait for the clients to do something.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Log of the relay dropping a client that stopped receiving.
+const STOPPED_LOG: &str = "Client obs stopped receiving, no longer relaying to it";
+
+/// Writer appending logs to a shared buffer.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    /// Counts the logs containing a text.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to look for.
+    fn count(&self, text: &str) -> usize {
+        String::from_utf8_lossy(&self.0.lock().unwrap())
+            .lines()
+            .filter(|line| line.contains(text))
+            .count()
+    }
+}
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+/// Captures the logs of every test, which are told apart by client name.
+fn logs() -> &'static Logs {
+    static LOGS: OnceLock<Logs> = OnceLock::new();
+    LOGS.get_or_init(|| {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .init();
+        logs
+    })
+}
+
+/// Builds a message sent on a mock client.
+///
+/// # Arguments
+///
+/// * `content` - The message's content.
+fn message(content: &str) -> Message {
+    Message::new(
+        "mock".to_string(),
+        "#channel".to_string(),
+        "viewer".to_string(),
+        content.to_string(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_relaying_after_client_stops_receiving() {
+    let logs = logs();
+    let config: PipeFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+    let (obs, obs_handle) = MockClient::build("obs");
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, mut discord_handle) = MockClient::build("discord");
+    let mut fitter =
+        PipeFitter::from_config_with_clients(config, vec![obs, twitch, discord]).unwrap();
+    fitter.start();
+
+    obs_handle.stop().await.unwrap();
+    // Messages relayed before the client's stream is dropped are still taken by it.
+    let mut count = 0;
+    while fitter.disconnected_clients().is_empty() {
+        count += 1;
+        let content = format!("before {}", count);
+        twitch_handle.inject(message(&content)).await.unwrap();
+        let received = timeout(TIMEOUT, discord_handle.recv())
+            .await
+            .expect("timed out waiting for a relayed message")
+            .unwrap();
+        assert_eq!(received.get_content(), content);
+    }
+    assert_eq!(fitter.disconnected_clients(), vec!["obs".to_string()]);
+
+    for idx in 0..5 {
+        let content = format!("after {}", idx);
+        twitch_handle.inject(message(&content)).await.unwrap();
+        let received = timeout(TIMEOUT, discord_handle.recv())
+            .await
+            .expect("timed out waiting for a relayed message")
+            .unwrap();
+        assert_eq!(received.get_content(), content);
+    }
+    assert_eq!(logs.count(STOPPED_LOG), 1);
+    assert_eq!(fitter.disconnected_clients(), vec!["obs".to_string()]);
+
+    fitter.stop();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tells_clients_sharing_a_name_apart() {
+    let logs = logs();
+    let config: PipeFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+    let (stopped, stopped_handle) = MockClient::build("cam");
+    let (running, mut running_handle) = MockClient::build("cam");
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, discord_handle) = MockClient::build("discord");
+    let mut fitter =
+        PipeFitter::from_config_with_clients(config, vec![stopped, running, twitch, discord])
+            .unwrap();
+    fitter.start();
+
+    stopped_handle.stop().await.unwrap();
+    // Both relays drop the stopped client, the other one sharing its name keeps receiving.
+    let mut count = 0;
+    while fitter.disconnected_clients().is_empty() || count < 10 {
+        count += 1;
+        for handle in [&twitch_handle, &discord_handle] {
+            handle.inject(message(&count.to_string())).await.unwrap();
+            timeout(TIMEOUT, running_handle.recv())
+                .await
+                .expect("timed out waiting for a relayed message")
+                .unwrap();
+        }
+    }
+    assert_eq!(fitter.disconnected_clients(), vec!["cam".to_string()]);
+    assert_eq!(
+        logs.count("Client cam stopped receiving, no longer relaying to it"),
+        1
+    );
+
+    fitter.stop();
+}
@@ -0,0 +1,66 @@
+//! Integration tests fetching the credentials clients reference from a provider.
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use stream_fitter::{
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::{PipeFitter, PipeFitterConfig},
+    secret::CredentialProvider,
+};
+use tokio::time::sleep;
+
+/// Credential provider answering after a delay, like a remote secret manager.
+#[derive(Default)]
+struct SlowProvider {
+    fetched: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl CredentialProvider for SlowProvider {
+    async fn fetch(&self, key: &str) -> FitterResult<String> {
+        sleep(Duration::from_millis(50)).await;
+        self.fetched.lock().unwrap().push(key.to_string());
+        match key {
+            "twitch_token" => Ok("fake_token".to_string()),
+            _ => Err(FitterErrorKind::GenericErr("Unknown key".to_string()).into()),
+        }
+    }
+}
+
+/// Builds a config with a Twitch client whose token is referenced by key.
+///
+/// # Arguments
+///
+/// * `key` - The key the token is referenced by.
+fn config(key: &str) -> PipeFitterConfig {
+    serde_yaml::from_str(&format!(
+        "stream_configs:\n\
+         \x20 - name: fitter_bot\n\
+         \x20   token: {{ token_ref: {} }}\n\
+         \x20   channels: [first]\n",
+        key
+    ))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn awaits_the_provider_on_the_current_thread() {
+    let provider = Arc::new(SlowProvider::default());
+    PipeFitter::from_config_async(config("twitch_token"), Vec::new(), provider.clone())
+        .await
+        .unwrap();
+    assert_eq!(*provider.fetched.lock().unwrap(), vec!["twitch_token"]);
+}
+
+#[tokio::test]
+async fn reports_credentials_failing_to_fetch() {
+    let provider = Arc::new(SlowProvider::default());
+    let err = match PipeFitter::from_config_async(config("missing"), Vec::new(), provider).await {
+        Ok(_) => panic!("built without its credential"),
+        Err(err) => err,
+    };
+    assert!(err.to_string().contains("Credential missing"), "{}", err);
+}
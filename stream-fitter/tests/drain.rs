@@ -0,0 +1,117 @@
+//! Integration tests of draining the relay on shutdown.
+mod support;
+
+use std::time::Duration;
+
+use stream_fitter::{
+    clients::{
+        client::Message,
+        mock::MockClient,
+        twitch::{Twitch, TwitchConfig},
+    },
+    pipe_fitter::{PipeFitter, PipeFitterConfig},
+};
+use tokio::time::{sleep, timeout};
+
+use support::fake_twitch::{Event, FakeTwitch};
+
+/// Time to wait for the clients to do something.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of messages queued before shutting down.
+const QUEUED: usize = 10;
+
+/// Builds a message sent on a mock client.
+///
+/// # Arguments
+///
+/// * `content` - The message's content.
+fn message(content: &str) -> Message {
+    Message::new(
+        "mock".to_string(),
+        "#channel".to_string(),
+        "viewer".to_string(),
+        content.to_string(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delivers_messages_queued_before_shutdown() {
+    let config: PipeFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, mut discord_handle) = MockClient::build("discord");
+    let mut fitter = PipeFitter::from_config_with_clients(config, vec![twitch, discord]).unwrap();
+    fitter.start();
+
+    // Paused, the messages stay queued in the relay until it drains.
+    fitter.pause();
+    for idx in 0..QUEUED {
+        twitch_handle
+            .inject(message(&format!("queued {}", idx)))
+            .await
+            .unwrap();
+    }
+    timeout(TIMEOUT, async {
+        while twitch_handle.forwarded() < QUEUED {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the messages to be queued");
+    assert!(discord_handle.try_recv().is_none());
+
+    // Drained, the clients delivered what they took before they're stopped.
+    assert_eq!(fitter.drain(TIMEOUT).await, QUEUED);
+    for idx in 0..QUEUED {
+        let received = discord_handle
+            .try_recv()
+            .expect("queued message lost on shutdown");
+        assert_eq!(received.get_content(), format!("queued {}", idx));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_messages_queued_by_clients_before_shutdown() {
+    let mut fake = FakeTwitch::start().await;
+    let twitch_config: TwitchConfig = serde_yaml::from_str(&format!(
+        "name: fitter_bot\n\
+         token: fake_token\n\
+         channels: [first]\n\
+         server_override: \"{}\"\n",
+        fake.address()
+    ))
+    .unwrap();
+    let twitch = Twitch::from_config("twitch".to_string(), twitch_config).unwrap();
+    let config: PipeFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+    let (obs, obs_handle) = MockClient::build("obs");
+    let mut fitter = PipeFitter::from_config_with_clients(config, vec![obs, twitch]).unwrap();
+    fitter.start();
+    fake.wait_joined(&["first"]).await;
+
+    fitter.pause();
+    for idx in 0..QUEUED {
+        obs_handle
+            .inject(message(&format!("queued {}", idx)))
+            .await
+            .unwrap();
+    }
+    timeout(TIMEOUT, async {
+        while obs_handle.forwarded() < QUEUED {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the messages to be queued");
+
+    // Drained, the Twitch send queue sent what it took before the client is stopped.
+    assert_eq!(fitter.drain(TIMEOUT).await, QUEUED);
+    // Sent over several connections, the messages may arrive out of order.
+    let mut missing = (0..QUEUED)
+        .map(|idx| format!("queued {}", idx))
+        .collect::<Vec<String>>();
+    while !missing.is_empty() {
+        if let Event::Sent { channel, text } = fake.next_event().await {
+            assert_eq!(channel, "first");
+            missing.retain(|content| !text.ends_with(content.as_str()));
+        }
+    }
+}
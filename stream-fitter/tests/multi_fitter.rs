@@ -0,0 +1,88 @@
+//! Integration tests of several fitters run side by side in one process.
+use std::time::Duration;
+
+use stream_fitter::{
+    clients::{client::Message, mock::MockClient},
+    pipe_fitter::{
+        multi::{FitterStatus, MultiFitter},
+        PipeFitter, PipeFitterConfig,
+    },
+};
+use tokio::time::{sleep, timeout};
+
+/// Time to wait for the fitters to do something.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds a message sent on a mock client.
+///
+/// # Arguments
+///
+/// * `content` - The message's content.
+fn message(content: &str) -> Message {
+    Message::new(
+        "mock".to_string(),
+        "#channel".to_string(),
+        "viewer".to_string(),
+        content.to_string(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_fitters_apart() {
+    let config: PipeFitterConfig =
+        serde_yaml::from_str("stream_configs: []\nabort_on_client_error: true").unwrap();
+    let (first_twitch, first_twitch_handle) = MockClient::build("twitch");
+    let (first_discord, mut first_discord_handle) = MockClient::build("discord");
+    let (second_twitch, second_twitch_handle) = MockClient::build("twitch");
+    let (second_discord, mut second_discord_handle) = MockClient::build("discord");
+    let first =
+        PipeFitter::from_config_with_clients(config.clone(), vec![first_twitch, first_discord])
+            .unwrap();
+    let second =
+        PipeFitter::from_config_with_clients(config, vec![second_twitch, second_discord]).unwrap();
+    let mut multi = MultiFitter::from_fitters(vec![
+        ("first".to_string(), first),
+        ("second".to_string(), second),
+    ])
+    .unwrap();
+    multi.start();
+
+    // Messages stay within their fitter.
+    first_twitch_handle.inject(message("first")).await.unwrap();
+    let received = timeout(TIMEOUT, first_discord_handle.recv())
+        .await
+        .expect("timed out waiting for a relayed message")
+        .unwrap();
+    assert_eq!(received.get_content(), "first");
+    second_twitch_handle
+        .inject(message("second"))
+        .await
+        .unwrap();
+    let received = timeout(TIMEOUT, second_discord_handle.recv())
+        .await
+        .expect("timed out waiting for a relayed message")
+        .unwrap();
+    assert_eq!(received.get_content(), "second");
+    assert!(first_discord_handle.try_recv().is_none());
+
+    // A failing fitter stops alone.
+    second_twitch_handle.panic().await.unwrap();
+    timeout(TIMEOUT, async {
+        while !matches!(multi.statuses()[1].1, FitterStatus::Failed(_)) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the fitter to fail");
+    assert_eq!(multi.statuses()[0].1, FitterStatus::Running);
+
+    first_twitch_handle.inject(message("after")).await.unwrap();
+    let received = timeout(TIMEOUT, first_discord_handle.recv())
+        .await
+        .expect("timed out waiting for a relayed message")
+        .unwrap();
+    assert_eq!(received.get_content(), "after");
+
+    multi.shutdown("first").unwrap();
+    assert!(multi.join().await.is_err());
+}
@@ -0,0 +1,59 @@
+//! Integration tests containing the panics of clients run with `panic_isolation`.
+use std::time::Duration;
+
+use stream_fitter::{
+    clients::{client::Message, mock::MockClient},
+    pipe_fitter::{PipeFitter, PipeFitterConfig},
+};
+use tokio::time::{sleep, timeout};
+
+/// Time to wait for the clients to do something.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds a message sent on a mock client.
+///
+/// # Arguments
+///
+/// * `content` - The message's content.
+fn message(content: &str) -> Message {
+    Message::new(
+        "mock".to_string(),
+        "#channel".to_string(),
+        "viewer".to_string(),
+        content.to_string(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sibling_keeps_relaying_after_panic() {
+    let config: PipeFitterConfig =
+        serde_yaml::from_str("stream_configs: []\npanic_isolation: true").unwrap();
+    let (buggy, buggy_handle) = MockClient::build("buggy");
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, mut discord_handle) = MockClient::build("discord");
+    let mut fitter =
+        PipeFitter::from_config_with_clients(config, vec![buggy, twitch, discord]).unwrap();
+    fitter.start();
+
+    buggy_handle.panic().await.unwrap();
+    timeout(TIMEOUT, async {
+        while fitter.disconnected_clients().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the panic");
+    assert_eq!(fitter.disconnected_clients(), vec!["buggy".to_string()]);
+
+    twitch_handle
+        .inject(message("after the panic"))
+        .await
+        .unwrap();
+    let received = timeout(TIMEOUT, discord_handle.recv())
+        .await
+        .expect("timed out waiting for a relayed message")
+        .unwrap();
+    assert_eq!(received.get_content(), "after the panic");
+
+    fitter.stop();
+}
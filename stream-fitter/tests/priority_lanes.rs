@@ -0,0 +1,123 @@
+//! Integration tests of the delivery of high priority messages ahead of queued chat.
+use std::time::Duration;
+
+use stream_fitter::{
+    clients::{
+        client::{Message, MessageKind},
+        mock::MockClient,
+    },
+    pipe_fitter::{
+        filter::{FilterAction, MessageFilter},
+        pipeline::{Pipeline, PipelineStage},
+        priority::PRIORITY,
+        PipeFitter, PipeFitterConfig,
+    },
+};
+use tokio::time::{sleep, timeout};
+
+/// Time to wait for the clients to do something.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Time for injected messages to reach the destination's queue.
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// Builds a message sent on a mock client.
+///
+/// # Arguments
+///
+/// * `author` - The message's author.
+/// * `content` - The message's content.
+fn message(author: &str, content: &str) -> Message {
+    Message::new(
+        "mock".to_string(),
+        "#channel".to_string(),
+        author.to_string(),
+        content.to_string(),
+    )
+}
+
+#[test]
+fn flags_priority_authors_and_patterns() {
+    let authors = vec!["Moderator".to_string()];
+    let pipeline = Pipeline::from_config(
+        None,
+        None,
+        None,
+        None,
+        None,
+        Some(&authors),
+        Some("(?i)raid"),
+    )
+    .unwrap();
+    let is_priority = |msg| match pipeline.filter(msg) {
+        FilterAction::Pass(msg) => msg.is_priority(),
+        FilterAction::Drop => panic!("message dropped"),
+    };
+
+    assert!(is_priority(message("moderator", "stream is ending")));
+    assert!(is_priority(message("viewer", "RAID incoming")));
+    assert!(is_priority(
+        message("viewer", "joined voice").with_kind(MessageKind::Event)
+    ));
+    assert!(!is_priority(message("viewer", "hello")));
+}
+
+#[test]
+fn requires_priority_stage_when_configured() {
+    let authors = vec!["moderator".to_string()];
+    let stages = [PipelineStage::Spam];
+    assert!(
+        Pipeline::from_config(Some(&stages), None, None, None, None, Some(&authors), None).is_err()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn priority_messages_overtake_backlog() {
+    let config: PipeFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, mut discord_handle) = MockClient::build("discord");
+    let mut fitter = PipeFitter::from_config_with_clients(config, vec![twitch, discord]).unwrap();
+    fitter.start();
+
+    // Build a backlog behind the first message, stuck sending.
+    discord_handle.hold();
+    for idx in 1..=5 {
+        twitch_handle
+            .inject(message("viewer", &format!("chat {}", idx)))
+            .await
+            .unwrap();
+    }
+    sleep(SETTLE).await;
+    twitch_handle
+        .inject(message("moderator", "raid incoming").with_metadata(PRIORITY, "true".to_string()))
+        .await
+        .unwrap();
+    twitch_handle
+        .inject(message("viewer", "followed").with_kind(MessageKind::Event))
+        .await
+        .unwrap();
+    sleep(SETTLE).await;
+    discord_handle.release();
+
+    let mut received = Vec::new();
+    while received.len() < 7 {
+        let msg = timeout(TIMEOUT, discord_handle.recv())
+            .await
+            .expect("timed out waiting for a relayed message")
+            .unwrap();
+        received.push(msg.get_content().to_string());
+    }
+    assert_eq!(
+        received,
+        vec![
+            "chat 1",
+            "raid incoming",
+            "followed",
+            "chat 2",
+            "chat 3",
+            "chat 4",
+            "chat 5"
+        ]
+    );
+
+    fitter.stop();
+}
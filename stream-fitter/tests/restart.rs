@@ -0,0 +1,39 @@
+//! Integration tests restarting the clients that stop with an error.
+use std::time::Duration;
+
+use stream_fitter::{
+    clients::mock::MockClient,
+    pipe_fitter::{PipeFitter, PipeFitterConfig},
+};
+use tokio::time::timeout;
+
+/// Time to wait for the clients to do something.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restarts_clients_stopping_with_an_error() {
+    let config: PipeFitterConfig = serde_yaml::from_str(
+        r#"
+stream_configs:
+  - label: ticker
+    source:
+      command: [sh, -c, "echo tick; exit 1"]
+"#,
+    )
+    .unwrap();
+    let (discord, mut discord_handle) = MockClient::build("discord");
+    let mut fitter = PipeFitter::from_config_with_clients(config, vec![discord]).unwrap();
+    let serve = tokio::spawn(async move { fitter.serve().await });
+
+    // Each run of the command relays its line once before failing.
+    for _ in 0..3 {
+        let received = timeout(TIMEOUT, discord_handle.recv())
+            .await
+            .expect("timed out waiting for the restarted client")
+            .unwrap();
+        assert_eq!(received.get_content(), "tick");
+    }
+    assert!(!serve.is_finished());
+
+    serve.abort();
+}
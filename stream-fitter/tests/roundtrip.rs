@@ -0,0 +1,87 @@
+//! Integration tests of the canary messages smoke testing the relay between clients.
+use std::time::Duration;
+
+use stream_fitter::{
+    clients::{
+        client::Message,
+        mock::{MockClient, MockHandle},
+    },
+    errors::FitterErrorKind,
+    pipe_fitter::{PipeFitter, PipeFitterConfig},
+};
+use tokio::time::timeout;
+
+/// Time to wait for a canary.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Time to wait for a canary that isn't relayed.
+const SHORT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Builds a started stream manager with two mock clients, returning it with their IDs and
+/// handles.
+fn start_fitter() -> (PipeFitter, String, String, MockHandle, MockHandle) {
+    let config: PipeFitterConfig = serde_yaml::from_str("stream_configs: []").unwrap();
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, discord_handle) = MockClient::build("discord");
+    let (twitch_id, discord_id) = (twitch.get_id().to_string(), discord.get_id().to_string());
+    let mut fitter = PipeFitter::from_config_with_clients(config, vec![twitch, discord]).unwrap();
+    fitter.start();
+    (fitter, twitch_id, discord_id, twitch_handle, discord_handle)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn measures_roundtrip_without_delivering_canary() {
+    let (mut fitter, twitch_id, discord_id, _twitch_handle, mut discord_handle) = start_fitter();
+
+    let latency = fitter
+        .test_message_roundtrip(&twitch_id, &discord_id, TIMEOUT)
+        .await
+        .unwrap();
+    assert!(latency < TIMEOUT);
+    assert!(discord_handle.try_recv().is_none());
+
+    fitter.stop();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_out_while_paused() {
+    let (mut fitter, twitch_id, discord_id, twitch_handle, mut discord_handle) = start_fitter();
+
+    // The message is received after pausing, so it's held with the canary behind it.
+    fitter.pause();
+    twitch_handle
+        .inject(Message::new(
+            "twitch".to_string(),
+            "#channel".to_string(),
+            "viewer".to_string(),
+            "while paused".to_string(),
+        ))
+        .await
+        .unwrap();
+    let err = fitter
+        .test_message_roundtrip(&twitch_id, &discord_id, SHORT_TIMEOUT)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(FitterErrorKind::Timeout(_))
+    ));
+
+    assert!(discord_handle.try_recv().is_none());
+
+    fitter.resume();
+    let held = timeout(TIMEOUT, discord_handle.recv())
+        .await
+        .expect("timed out waiting for the held message")
+        .unwrap();
+    assert_eq!(held.get_content(), "while paused");
+    assert!(fitter
+        .test_message_roundtrip(&twitch_id, &discord_id, TIMEOUT)
+        .await
+        .is_ok());
+    assert!(fitter
+        .test_message_roundtrip(&twitch_id, "unknown", TIMEOUT)
+        .await
+        .is_err());
+
+    fitter.stop();
+}
@@ -0,0 +1,190 @@
+//! Integration tests of the runtime state kept across restarts.
+use std::{path::PathBuf, time::Duration};
+
+use chrono::Utc;
+use futures::task::FutureObj;
+use serde_json::{json, Value};
+use stream_fitter::{
+    clients::{
+        client::{async_trait, DynClientTrait, Message},
+        mock::MockClient,
+    },
+    errors::{FitterErrorKind, FitterResult},
+    pipe_fitter::{
+        state::{FitterState, DEFAULT_STATE_MAX_AGE},
+        PipeFitter, PipeFitterConfig,
+    },
+};
+use tokio::{
+    sync::mpsc::{channel, Sender},
+    time::timeout,
+};
+
+/// Time to wait for the clients to do something.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds a message sent on a mock client, with its platform ID.
+///
+/// # Arguments
+///
+/// * `platform_id` - The message's ID on its platform.
+/// * `content` - The message's content.
+fn message(platform_id: &str, content: &str) -> Message {
+    Message::new(
+        "mock".to_string(),
+        "#channel".to_string(),
+        "viewer".to_string(),
+        content.to_string(),
+    )
+    .with_platform_id(platform_id.to_string())
+}
+
+/// Gets a state file path unique to a test.
+///
+/// # Arguments
+///
+/// * `test` - The test's name.
+fn state_file(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "stream-fitter-{}-{}.json",
+        test,
+        std::process::id()
+    ))
+}
+
+/// Client keeping a counter as its runtime state, run through the `ClientTrait` adapter.
+struct CountingClient {
+    count: u64,
+    tx: Sender<Message>,
+    reachable: bool,
+}
+
+#[async_trait]
+impl DynClientTrait for CountingClient {
+    fn get_name(&self) -> &str {
+        "counting"
+    }
+
+    fn get_id(&self) -> &str {
+        "counting"
+    }
+
+    fn get_stream(&self) -> FitterResult<Sender<Message>> {
+        Ok(self.tx.clone())
+    }
+
+    fn add_stream(&mut self, _stream: Sender<Message>) -> FitterResult<()> {
+        Ok(())
+    }
+
+    fn test_connectivity(&self) -> FutureObj<'static, FitterResult<()>> {
+        let reachable = self.reachable;
+        FutureObj::new(Box::new(async move {
+            match reachable {
+                true => Ok(()),
+                false => Err(FitterErrorKind::GenericErr("Unreachable".to_string()).into()),
+            }
+        }))
+    }
+
+    fn export_state(&self) -> Option<Value> {
+        Some(json!({ "count": self.count }))
+    }
+
+    fn import_state(&mut self, state: Value) -> FitterResult<()> {
+        self.count = state["count"].as_u64().unwrap_or_default();
+        Ok(())
+    }
+
+    async fn run(&mut self) -> FitterResult<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn adapted_clients_forward_their_state() {
+    let (tx, _rx) = channel(1);
+    let mut client = CountingClient {
+        count: 0,
+        tx,
+        reachable: true,
+    }
+    .into_client();
+    client.import_state(json!({ "count": 3 })).unwrap();
+    assert_eq!(client.export_state(), Some(json!({ "count": 3 })));
+    assert!(client.test_connectivity().await.is_ok());
+
+    let (tx, _rx) = channel(1);
+    let client = CountingClient {
+        count: 0,
+        tx,
+        reachable: false,
+    }
+    .into_client();
+    assert!(client.test_connectivity().await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn relayed_ids_survive_restart() {
+    let path = state_file("restart");
+    let config: PipeFitterConfig = serde_yaml::from_str(&format!(
+        "stream_configs: []\nstate_file: {}",
+        path.display()
+    ))
+    .unwrap();
+
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, mut discord_handle) = MockClient::build("discord");
+    let mut fitter =
+        PipeFitter::from_config_with_clients(config.clone(), vec![twitch, discord]).unwrap();
+    fitter.start();
+    twitch_handle.inject(message("1", "before")).await.unwrap();
+    timeout(TIMEOUT, discord_handle.recv())
+        .await
+        .expect("timed out waiting for a relayed message")
+        .unwrap();
+    fitter.drain(TIMEOUT).await;
+
+    let state = FitterState::load(&path, DEFAULT_STATE_MAX_AGE).expect("state file written");
+    assert_eq!(state.relayed_ids.len(), 1);
+    std::fs::remove_file(&path).unwrap();
+
+    // The message received again after the restart, e.g. on a retry, was already relayed.
+    let (twitch, twitch_handle) = MockClient::build("twitch");
+    let (discord, mut discord_handle) = MockClient::build("discord");
+    let mut fitter = PipeFitter::from_config_with_clients(config, vec![twitch, discord]).unwrap();
+    fitter.import_state(state);
+    fitter.start();
+    twitch_handle.inject(message("1", "before")).await.unwrap();
+    twitch_handle.inject(message("2", "after")).await.unwrap();
+    let received = timeout(TIMEOUT, discord_handle.recv())
+        .await
+        .expect("timed out waiting for a relayed message")
+        .unwrap();
+    assert_eq!(received.get_content(), "after");
+
+    fitter.stop();
+}
+
+#[test]
+fn ignores_corrupt_and_stale_state_files() {
+    let path = state_file("ignored");
+
+    std::fs::write(&path, "not a state").unwrap();
+    assert!(FitterState::load(&path, DEFAULT_STATE_MAX_AGE).is_none());
+
+    let mut state = FitterState {
+        exported_at: Utc::now() - chrono::Duration::hours(1),
+        relayed_ids: vec!["id".to_string()],
+        clients: Default::default(),
+    };
+    std::fs::write(&path, serde_json::to_vec(&state).unwrap()).unwrap();
+    assert!(FitterState::load(&path, DEFAULT_STATE_MAX_AGE).is_none());
+
+    state.exported_at = Utc::now();
+    std::fs::write(&path, serde_json::to_vec(&state).unwrap()).unwrap();
+    assert_eq!(FitterState::load(&path, DEFAULT_STATE_MAX_AGE), Some(state));
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(FitterState::load(&path, DEFAULT_STATE_MAX_AGE).is_none());
+}
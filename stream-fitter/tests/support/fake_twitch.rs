@@ -0,0 +1,240 @@
+//! Fake Twitch chat server, speaking enough of the Twitch IRC protocol to drive the real
+//! `Twitch` client pointed at it with `TwitchConfig::server_override`.
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::timeout,
+};
+
+/// Time to wait for the client to do something.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Server time of the injected messages, in milliseconds since the epoch.
+pub const SENT_TIMESTAMP_MILLIS: i64 = 1_594_545_155_039;
+
+/// Something the client did on the fake server.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// The client logged in as an account.
+    LoggedIn(String),
+    /// The client joined a channel.
+    Joined(String),
+    /// The client sent a message to a channel.
+    Sent { channel: String, text: String },
+    /// The client answered a ping.
+    Ponged,
+}
+
+/// A fake Twitch chat server.
+pub struct FakeTwitch {
+    address: String,
+    events: UnboundedReceiver<Event>,
+    connections: Arc<StdMutex<Vec<UnboundedSender<String>>>>,
+    next_id: AtomicUsize,
+}
+
+impl FakeTwitch {
+    /// Starts a fake server on a free local port.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (events_tx, events) = unbounded_channel();
+        let connections = Arc::new(StdMutex::new(Vec::new()));
+
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let (lines_tx, lines_rx) = unbounded_channel();
+                accepted.lock().unwrap().push(lines_tx);
+                tokio::spawn(serve(socket, lines_rx, events_tx.clone()));
+            }
+        });
+
+        FakeTwitch {
+            address,
+            events,
+            connections,
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the address to point `TwitchConfig::server_override` at.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Waits for the next thing the client does, panicking if it takes too long.
+    pub async fn next_event(&mut self) -> Event {
+        timeout(EVENT_TIMEOUT, self.events.recv())
+            .await
+            .expect("timed out waiting for the client")
+            .expect("fake server stopped")
+    }
+
+    /// Waits for the client to join channels, skipping anything else it does meanwhile.
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The channels' login names.
+    pub async fn wait_joined(&mut self, channels: &[&str]) {
+        let mut missing = channels.to_vec();
+        while !missing.is_empty() {
+            if let Event::Joined(channel) = self.next_event().await {
+                missing.retain(|missing| *missing != channel);
+            }
+        }
+    }
+
+    /// Sends a chat message to every connected client, as Twitch does with tags enabled.
+    ///
+    /// Returns the message's ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel's login name.
+    /// * `login` - The author's login name.
+    /// * `display_name` - The author's display name.
+    /// * `text` - The message's text.
+    pub fn privmsg(&self, channel: &str, login: &str, display_name: &str, text: &str) -> String {
+        let n = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let id = format!("00000000-0000-0000-0000-{:012}", n);
+        self.broadcast(format!(
+            "@badge-info=;badges=;color=;display-name={0};emotes=;flags=;id={1};mod=0;\
+             room-id=1000;subscriber=0;tmi-sent-ts={2};turbo=0;user-id={3};user-type= \
+             :{4}!{4}@{4}.tmi.twitch.tv PRIVMSG #{5} :{6}",
+            display_name,
+            id,
+            SENT_TIMESTAMP_MILLIS,
+            2000 + n,
+            login,
+            channel,
+            text
+        ));
+        id
+    }
+
+    /// Pings every connected client, as Twitch does every few minutes.
+    pub fn ping(&self) {
+        self.broadcast("PING :tmi.twitch.tv".to_string());
+    }
+
+    /// Drops every open connection, as Twitch does on restarts.
+    pub fn drop_connections(&self) {
+        self.connections.lock().unwrap().clear();
+    }
+
+    /// Sends a raw line to every connected client.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line to send, without its line ending.
+    fn broadcast(&self, line: String) {
+        for connection in self.connections.lock().unwrap().iter() {
+            connection.send(line.clone()).ok();
+        }
+    }
+}
+
+/// Serves one client connection until either side closes it.
+///
+/// # Arguments
+///
+/// * `socket` - The client's connection.
+/// * `lines_rx` - The lines to send to the client, closing the connection once dropped.
+/// * `events` - The TX channel of what the client does.
+async fn serve(
+    socket: TcpStream,
+    mut lines_rx: UnboundedReceiver<String>,
+    events: UnboundedSender<Event>,
+) {
+    let (read_half, mut write_half) = socket.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    let mut nick = String::new();
+
+    loop {
+        let replies = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => reply(&line, &mut nick, &events),
+                _ => break,
+            },
+            line = lines_rx.recv() => match line {
+                Some(line) => vec![line],
+                None => break,
+            },
+        };
+
+        // Lines the client sent before closing its end are still read.
+        for line in replies {
+            let line = format!("{}\r\n", line);
+            if write_half.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Handles a line sent by the client, returning the lines to reply with.
+///
+/// # Arguments
+///
+/// * `line` - The line sent by the client.
+/// * `nick` - The account logged in on the connection, set once it logs in.
+/// * `events` - The TX channel of what the client does.
+fn reply(line: &str, nick: &mut String, events: &UnboundedSender<Event>) -> Vec<String> {
+    let (command, params) = line.split_once(' ').unwrap_or((line, ""));
+    match command {
+        "CAP" => {
+            let capabilities = params.trim_start_matches("REQ ");
+            vec![format!(":tmi.twitch.tv CAP * ACK {}", capabilities)]
+        }
+        "NICK" => {
+            *nick = params.to_string();
+            events.send(Event::LoggedIn(nick.clone())).ok();
+            vec![
+                format!(":tmi.twitch.tv 001 {} :Welcome, GLHF!", nick),
+                format!(":tmi.twitch.tv 376 {} :>", nick),
+            ]
+        }
+        "JOIN" => params
+            .split(',')
+            .map(|channel| {
+                let channel = channel.trim_start_matches('#');
+                events.send(Event::Joined(channel.to_string())).ok();
+                format!(":{0}!{0}@{0}.tmi.twitch.tv JOIN #{1}", nick, channel)
+            })
+            .collect(),
+        "PRIVMSG" => {
+            let (channel, text) = params.split_once(" :").unwrap_or((params, ""));
+            let channel = channel.trim_start_matches('#');
+            events
+                .send(Event::Sent {
+                    channel: channel.to_string(),
+                    text: text.to_string(),
+                })
+                .ok();
+            vec![format!(
+                "@badge-info=;badges=;color=;display-name={0};emote-sets=0;mod=0;subscriber=0;\
+                 user-type= :tmi.twitch.tv USERSTATE #{1}",
+                nick, channel
+            )]
+        }
+        "PONG" => {
+            events.send(Event::Ponged).ok();
+            Vec::new()
+        }
+        "PING" => vec![format!(
+            ":tmi.twitch.tv PONG tmi.twitch.tv {}",
+            params.trim_start_matches(':')
+        )],
+        _ => Vec::new(),
+    }
+}
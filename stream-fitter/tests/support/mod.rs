@@ -0,0 +1,5 @@
+//! Shared support of the integration tests.
+// Each test crate only uses some of the support.
+#![allow(dead_code)]
+
+pub mod fake_twitch;
@@ -0,0 +1,392 @@
+//! Integration tests driving the Twitch client against a fake Twitch chat server.
+mod support;
+
+use std::{fs, path::Path, time::Duration};
+
+use chrono::{TimeZone, Utc};
+
+use stream_fitter::clients::{
+    client::{Client, Message, SerializationFormat},
+    twitch::{Twitch, TwitchConfig},
+};
+use tokio::{
+    sync::mpsc::{channel, Receiver, Sender},
+    time::timeout,
+};
+
+use support::fake_twitch::{Event, FakeTwitch, SENT_TIMESTAMP_MILLIS};
+
+/// Name of the bot account the client logs in as.
+const BOT_NAME: &str = "fitter_bot";
+/// Time to wait for the client to relay a message.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds a Twitch client pointed at a fake server, listening to `#first` and `#second`.
+///
+/// # Arguments
+///
+/// * `fake` - The fake server.
+/// * `extra_config` - More YAML config lines, each ending with a newline.
+fn build_client(fake: &FakeTwitch, extra_config: &str) -> Client {
+    let config: TwitchConfig = serde_yaml::from_str(&format!(
+        "name: {}\n\
+         token: fake_token\n\
+         channels: [first, second]\n\
+         isolate_channels: true\n\
+         forward_only: true\n\
+         server_override: \"{}\"\n\
+         {}",
+        BOT_NAME,
+        fake.address(),
+        extra_config
+    ))
+    .unwrap();
+    Twitch::from_config("twitch".to_string(), config).unwrap()
+}
+
+/// Starts a Twitch client pointed at a fake server, returning the messages it relays.
+///
+/// Returns once the client joined both its channels.
+///
+/// # Arguments
+///
+/// * `fake` - The fake server.
+/// * `extra_config` - More YAML config lines, each ending with a newline.
+async fn start_client(fake: &mut FakeTwitch, extra_config: &str) -> Receiver<Message> {
+    let mut client = build_client(fake, extra_config);
+    let (tx, rx) = channel(100);
+    client.add_stream(tx).unwrap();
+    tokio::spawn(client.run());
+
+    fake.wait_joined(&["first", "second"]).await;
+    rx
+}
+
+/// Waits for the next message relayed by the client, panicking if it takes too long.
+///
+/// # Arguments
+///
+/// * `rx` - The messages relayed by the client.
+async fn next_relayed(rx: &mut Receiver<Message>) -> Message {
+    timeout(RELAY_TIMEOUT, rx.recv())
+        .await
+        .expect("timed out waiting for a relayed message")
+        .expect("client stopped")
+}
+
+#[tokio::test]
+async fn forwards_tagged_privmsg() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "").await;
+
+    let id = fake.privmsg("first", "some_viewer", "Some_Viewer", "hello there");
+    let msg = next_relayed(&mut rx).await;
+    assert_eq!(msg.get_client(), "Twitch");
+    assert_eq!(msg.get_channel(), "first");
+    assert_eq!(msg.get_author(), "Some_Viewer");
+    assert_eq!(msg.get_author_login(), "some_viewer");
+    assert_eq!(msg.get_content(), "hello there");
+    assert_eq!(msg.get_platform_id(), Some(id.as_str()));
+    assert_eq!(
+        msg.get_timestamp().timestamp_millis(),
+        SENT_TIMESTAMP_MILLIS
+    );
+}
+
+#[tokio::test]
+async fn shows_aliased_authors_by_canonical_name() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "user_aliases:\n  Some_Viewer: Alice\n").await;
+
+    fake.privmsg("first", "some_viewer", "Some_Viewer", "aliased");
+    fake.privmsg("first", "other_viewer", "Other_Viewer", "not aliased");
+    let msg = next_relayed(&mut rx).await;
+    assert_eq!(msg.get_author(), "Alice");
+    assert_eq!(msg.get_author_login(), "some_viewer");
+    assert_eq!(next_relayed(&mut rx).await.get_author(), "Other_Viewer");
+}
+
+#[tokio::test]
+async fn ignores_own_messages() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "").await;
+
+    fake.privmsg("first", BOT_NAME, BOT_NAME, "echo of a relayed message");
+    fake.privmsg("first", "some_viewer", "some_viewer", "after the echo");
+    assert_eq!(next_relayed(&mut rx).await.get_content(), "after the echo");
+}
+
+#[tokio::test]
+async fn ignores_unconfigured_channels() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "").await;
+
+    fake.privmsg("elsewhere", "some_viewer", "some_viewer", "from elsewhere");
+    fake.privmsg("second", "some_viewer", "some_viewer", "from second");
+    let msg = next_relayed(&mut rx).await;
+    assert_eq!(msg.get_channel(), "second");
+    assert_eq!(msg.get_content(), "from second");
+}
+
+#[tokio::test]
+async fn reconnects_after_dropped_connection() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "").await;
+
+    fake.ping();
+    assert_eq!(fake.next_event().await, Event::Ponged);
+
+    fake.drop_connections();
+    assert_eq!(
+        fake.next_event().await,
+        Event::LoggedIn(BOT_NAME.to_string())
+    );
+    fake.wait_joined(&["first", "second"]).await;
+
+    fake.privmsg("first", "some_viewer", "some_viewer", "after reconnecting");
+    assert_eq!(
+        next_relayed(&mut rx).await.get_content(),
+        "after reconnecting"
+    );
+}
+
+#[tokio::test]
+async fn connects_each_client_to_its_own_server() {
+    let mut first_fake = FakeTwitch::start().await;
+    let mut second_fake = FakeTwitch::start().await;
+    let mut first_rx = start_client(&mut first_fake, "").await;
+    let mut second_rx = start_client(&mut second_fake, "").await;
+
+    first_fake.privmsg(
+        "first",
+        "some_viewer",
+        "some_viewer",
+        "from the first server",
+    );
+    second_fake.privmsg(
+        "first",
+        "some_viewer",
+        "some_viewer",
+        "from the second server",
+    );
+    assert_eq!(
+        next_relayed(&mut first_rx).await.get_content(),
+        "from the first server"
+    );
+    assert_eq!(
+        next_relayed(&mut second_rx).await.get_content(),
+        "from the second server"
+    );
+}
+
+#[tokio::test]
+async fn replays_chat_log() {
+    let sent = Utc.timestamp_millis_opt(SENT_TIMESTAMP_MILLIS).unwrap();
+    let mut log = Vec::new();
+    for (offset, content) in [(0, "first line"), (60, "a minute later")] {
+        let msg = Message::new(
+            "Twitch".to_string(),
+            "first".to_string(),
+            "some_viewer".to_string(),
+            content.to_string(),
+        )
+        .with_timestamp(sent + chrono::Duration::seconds(offset));
+        log.extend(msg.to_bytes(SerializationFormat::Json).unwrap());
+        log.push(b'\n');
+    }
+    let log_path = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+    fs::write(&log_path, log).unwrap();
+
+    // A minute between messages, replayed in a fraction of a second.
+    let mut rx = start_replay_client(&log_path);
+    let first = next_relayed(&mut rx).await;
+    assert_eq!(first.get_content(), "first line");
+    assert_eq!(next_relayed(&mut rx).await.get_content(), "a minute later");
+
+    // Replaying the log again, e.g. after a config reload, relays the messages again.
+    let mut rx = start_replay_client(&log_path);
+    assert_ne!(next_relayed(&mut rx).await.get_id(), first.get_id());
+    fs::remove_file(log_path).unwrap();
+}
+
+/// Starts a Twitch client replaying a chat log at 600 times its speed, returning the messages
+/// it relays.
+///
+/// # Arguments
+///
+/// * `log_path` - The chat log's path.
+fn start_replay_client(log_path: &Path) -> Receiver<Message> {
+    let config: TwitchConfig = serde_yaml::from_str(&format!(
+        "channels: [first]\n\
+         replay_mode:\n  log_path: \"{}\"\n  speed_multiplier: 600.0\n",
+        log_path.display()
+    ))
+    .unwrap();
+    let mut client = Twitch::from_config("twitch".to_string(), config).unwrap();
+    let (tx, rx) = channel(100);
+    client.add_stream(tx).unwrap();
+    // Dropping the client closes its input, which ends the replay.
+    tokio::spawn(async move { client.run().await });
+    rx
+}
+
+/// Starts a Twitch client sending to its channels, returning the stream relaying other
+/// clients' messages to it.
+///
+/// Returns once the client joined its channels.
+///
+/// # Arguments
+///
+/// * `fake` - The fake server.
+/// * `channels` - The client's channels.
+/// * `extra_config` - More YAML config lines, each ending with a newline.
+async fn start_sending_client(
+    fake: &mut FakeTwitch,
+    channels: &[&str],
+    extra_config: &str,
+) -> Sender<Message> {
+    let config: TwitchConfig = serde_yaml::from_str(&format!(
+        "name: {}\n\
+         token: fake_token\n\
+         channels: [{}]\n\
+         server_override: \"{}\"\n\
+         {}",
+        BOT_NAME,
+        channels.join(", "),
+        fake.address(),
+        extra_config
+    ))
+    .unwrap();
+    let mut client = Twitch::from_config("twitch".to_string(), config).unwrap();
+    let stream = client.get_stream().unwrap();
+    tokio::spawn(client.run());
+
+    fake.wait_joined(channels).await;
+    stream
+}
+
+/// Waits for the next message the client sends to a channel, skipping other events.
+///
+/// # Arguments
+///
+/// * `fake` - The fake server.
+async fn next_sent(fake: &mut FakeTwitch) -> (String, String) {
+    loop {
+        let event = timeout(RELAY_TIMEOUT, fake.next_event())
+            .await
+            .expect("timed out waiting for a sent message");
+        if let Event::Sent { channel, text } = event {
+            return (channel, text);
+        }
+    }
+}
+
+#[tokio::test]
+async fn joins_multiline_messages() {
+    let mut fake = FakeTwitch::start().await;
+    let stream =
+        start_sending_client(&mut fake, &["first"], "multiline_separator: \" / \"\n").await;
+
+    stream
+        .send(Message::system(
+            "first line\n\n  \nsecond line  ".to_string(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        next_sent(&mut fake).await,
+        ("first".to_string(), "first line / second line".to_string())
+    );
+}
+
+#[tokio::test]
+async fn splits_multiline_messages() {
+    let mut fake = FakeTwitch::start().await;
+    let stream = start_sending_client(&mut fake, &["first"], "multiline: split\n").await;
+
+    stream
+        .send(Message::system("first line\n\nsecond line".to_string()))
+        .await
+        .unwrap();
+    stream
+        .send(Message::system("\n \n".to_string()))
+        .await
+        .unwrap();
+    stream
+        .send(Message::system("single line".to_string()))
+        .await
+        .unwrap();
+    for expected in ["first line", "second line", "single line"] {
+        assert_eq!(next_sent(&mut fake).await.1, expected);
+    }
+}
+
+#[tokio::test]
+async fn relays_localized_display_names() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "").await;
+
+    fake.privmsg("first", "hungry_viewer", "배고픈시청자", "hello");
+    let msg = next_relayed(&mut rx).await;
+    assert_eq!(msg.get_author(), "배고픈시청자");
+    assert_eq!(msg.get_author_login(), "hungry_viewer");
+    assert_eq!(msg.to_string(), "[Twitch: first] [배고픈시청자] hello");
+}
+
+#[tokio::test]
+async fn relays_logins_instead_of_localized_display_names() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "prefer_login_names: true\n").await;
+    fake.privmsg("first", "hungry_viewer", "배고픈시청자", "hello");
+    assert_eq!(next_relayed(&mut rx).await.get_author(), "hungry_viewer");
+}
+
+#[tokio::test]
+async fn aliases_localized_display_names_by_login() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "user_aliases:\n  hungry_viewer: Hungry\n").await;
+
+    fake.privmsg("first", "hungry_viewer", "배고픈시청자", "aliased");
+    let msg = next_relayed(&mut rx).await;
+    assert_eq!(msg.get_author(), "Hungry");
+    assert_eq!(msg.get_author_login(), "hungry_viewer");
+}
+
+#[tokio::test]
+async fn ignores_localized_authors_by_login() {
+    let mut fake = FakeTwitch::start().await;
+    let mut rx = start_client(&mut fake, "ignored_authors: [Hungry_Viewer]\n").await;
+
+    fake.privmsg("first", "hungry_viewer", "배고픈시청자", "ignored");
+    fake.privmsg("first", "other_viewer", "다른시청자", "not ignored");
+    let msg = next_relayed(&mut rx).await;
+    assert_eq!(msg.get_author(), "다른시청자");
+    assert_eq!(msg.get_content(), "not ignored");
+}
+
+#[tokio::test]
+async fn formats_localized_display_names_between_channels() {
+    let mut fake = FakeTwitch::start().await;
+    start_sending_client(&mut fake, &["first", "second"], "").await;
+    fake.privmsg("first", "hungry_viewer", "배고픈시청자", "hello");
+    assert_eq!(
+        next_sent(&mut fake).await,
+        (
+            "second".to_string(),
+            "[Twitch: first] [배고픈시청자] hello".to_string()
+        )
+    );
+}
+
+#[tokio::test]
+async fn formats_localized_display_names_with_template() {
+    let mut fake = FakeTwitch::start().await;
+    start_sending_client(
+        &mut fake,
+        &["first", "second"],
+        "same_client_format: \"{author}: {content}\"\n",
+    )
+    .await;
+    fake.privmsg("first", "hungry_viewer", "배고픈시청자", "hello");
+    assert_eq!(next_sent(&mut fake).await.1, "배고픈시청자: hello");
+}